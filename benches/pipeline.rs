@@ -0,0 +1,71 @@
+//! Processing a synthetic report, see `dedupy generate`.
+//!
+//! - `validate` reads and parses every row.
+//! - `totals` also aggregates, as if no run had seen the report.
+//! - `run/new` is a whole first run: aggregation, writing memory, and writing
+//!   the workbook.
+//! - `run/seen` is a run over a report memory already has, so every row is
+//!   dropped as a duplicate.
+//!
+//! Runs write to a temporary directory, which is removed afterwards.
+
+use std::path::{Path, PathBuf};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use dedupy::{generate, Config, Report};
+
+const ROWS: usize = 20_000;
+
+/// Files a run writes, so every `run/new` starts without memory.
+const MEMORY: [&str; 3] = ["memory", "sku_memory", "near_memory"];
+
+fn report(dir: &Path) -> PathBuf {
+    let path = dir.join("report.csv");
+    let options = generate::Options {
+        rows: ROWS,
+        ..generate::Options::default()
+    };
+    generate::write(&options, std::fs::File::create(&path).unwrap()).unwrap();
+    path
+}
+
+fn pipeline(c: &mut Criterion) {
+    let dir = std::env::temp_dir().join(format!("dedupy-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = report(&dir);
+    // Runs write their output next to memory, in the working directory.
+    std::env::set_current_dir(&dir).unwrap();
+    let config = Config {
+        audit_log: dir.join("audit.jsonl"),
+        ..Config::default()
+    };
+
+    let mut group = c.benchmark_group("pipeline");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(ROWS as u64));
+    group.bench_function("validate", |b| {
+        b.iter(|| Report::validate(&path, &config).unwrap())
+    });
+    group.bench_function("totals", |b| {
+        b.iter(|| Report::totals(&path, &config).unwrap())
+    });
+    group.bench_function(BenchmarkId::new("run", "new"), |b| {
+        b.iter(|| {
+            for memory in MEMORY {
+                let _ = std::fs::remove_file(dir.join(memory));
+            }
+            Report::parse(&path, &config).unwrap()
+        })
+    });
+    Report::parse(&path, &config).unwrap();
+    group.bench_function(BenchmarkId::new("run", "seen"), |b| {
+        b.iter(|| Report::parse(&path, &config).unwrap())
+    });
+    group.finish();
+
+    std::env::set_current_dir(std::env::temp_dir()).unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+criterion_group!(benches, pipeline);
+criterion_main!(benches);
@@ -0,0 +1,76 @@
+//! Sorting the aggregated output by type and description.
+//!
+//! `Sale` is private, so this sorts rows of the same shape, comparing the
+//! clone per key that the output used to be sorted with against comparing
+//! by reference, as it is now.
+
+use std::sync::Arc;
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+
+struct Sale {
+    kind: Arc<str>,
+    sku: Arc<str>,
+    description: Arc<str>,
+    cents: i64,
+}
+
+/// `n` rows with as many distinct descriptions, in no particular order.
+fn sales(n: usize) -> Vec<Sale> {
+    let kinds = ["Order", "Refund", "Service Fee", "Adjustment", "Transfer"].map(Arc::<str>::from);
+    (0..n)
+        .map(|i| {
+            let i = i.wrapping_mul(2654435761) % n;
+            Sale {
+                kind: Arc::clone(&kinds[i % kinds.len()]),
+                sku: Arc::from(format!("SKU-{}", i % 1000)),
+                description: Arc::from(format!("Product description {i}")),
+                cents: i as i64,
+            }
+        })
+        .collect()
+}
+
+fn sort(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sort");
+    for n in [10_000, 100_000] {
+        let input = sales(n);
+        let copy = || {
+            input
+                .iter()
+                .map(|s| Sale {
+                    kind: Arc::clone(&s.kind),
+                    sku: Arc::clone(&s.sku),
+                    description: Arc::clone(&s.description),
+                    cents: s.cents,
+                })
+                .collect::<Vec<_>>()
+        };
+        group.bench_function(BenchmarkId::new("clone key", n), |b| {
+            b.iter_batched(
+                copy,
+                |mut sales| {
+                    sales.sort_unstable_by_key(|s| (s.kind.clone(), s.description.clone()));
+                    black_box(sales)
+                },
+                BatchSize::LargeInput,
+            )
+        });
+        group.bench_function(BenchmarkId::new("by reference", n), |b| {
+            b.iter_batched(
+                copy,
+                |mut sales| {
+                    sales.sort_unstable_by(|a, b| {
+                        (&a.kind, &a.description).cmp(&(&b.kind, &b.description))
+                    });
+                    black_box(sales)
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, sort);
+criterion_main!(benches);
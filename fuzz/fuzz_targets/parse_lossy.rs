@@ -0,0 +1,21 @@
+//! Feeds arbitrary bytes to `Report::try_parse_lossy`, which must neither
+//! panic nor run away, whatever it is given.
+//!
+//! libFuzzer aborts on a panic before it can be caught, so panics are found
+//! even though `try_parse_lossy` would report them as a problem.
+
+#![no_main]
+
+use dedupy::{Config, Format, Limits, Report};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|report: &[u8]| {
+    let limits = Limits {
+        max_bytes: 64 * 1024,
+        max_rows: 1_000,
+        max_problems: 100,
+    };
+    let parsed = Report::try_parse_lossy(report, Format::Csv, &limits, &Config::default());
+    assert!(parsed.problems.len() <= limits.max_problems + 1);
+    assert!(parsed.rows <= limits.max_rows);
+});
@@ -0,0 +1,119 @@
+//! Totals of the output per account of a chart of accounts, see `accounts`.
+//!
+//! Every aggregate is totalled under the account of the first rule that
+//! matches it, or under [`UNMAPPED`], so that the accounts add up to the
+//! output. This is the shape of a journal entry as it is keyed in by hand.
+
+use std::collections::BTreeMap;
+
+use serde::{ser::SerializeStruct as _, Serialize};
+
+use crate::{Cents, Config, Sale};
+
+/// Name of the account of aggregates that no rule matches.
+const UNMAPPED: &str = "Unmapped";
+
+/// Net amount of an account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Total {
+    pub(crate) code: String,
+    pub(crate) name: String,
+    pub(crate) cents: Cents,
+}
+
+impl Serialize for Total {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut s = serializer.serialize_struct("Total", 3)?;
+        s.serialize_field("Code", &self.code)?;
+        s.serialize_field("Name", &self.name)?;
+        s.serialize_field("Total", &(self.cents as f64 / 100.0))?;
+        s.end()
+    }
+}
+
+/// Totals of `sales` per account, by code, followed by [`UNMAPPED`] if any
+/// sale matches no rule. Empty when no accounts are configured.
+pub(crate) fn summarize(sales: &[Sale], config: &Config) -> Vec<Total> {
+    if config.accounts.is_empty() {
+        return Vec::new();
+    }
+    let mut accounts = BTreeMap::<&str, (&str, Cents)>::new();
+    for sale in sales {
+        let (code, name) = account(config, &sale.kind, &sale.description);
+        accounts.entry(code).or_insert((name, 0)).1 += sale.cents;
+    }
+    // Sorted by code, which is empty for UNMAPPED.
+    let (unmapped, mapped) = accounts
+        .into_iter()
+        .map(|(code, (name, cents))| Total {
+            code: code.to_string(),
+            name: name.to_string(),
+            cents,
+        })
+        .partition::<Vec<_>, _>(|total| total.code.is_empty());
+    mapped.into_iter().chain(unmapped).collect()
+}
+
+/// Code and name of the account a transaction is totalled under.
+pub(crate) fn account<'c>(config: &'c Config, kind: &str, description: &str) -> (&'c str, &'c str) {
+    config
+        .account(kind, description)
+        .map_or(("", UNMAPPED), |account| (&account.code, &account.name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn totals_per_account() {
+        let config: Config = toml::from_str(
+            r#"
+            [[accounts]]
+            type = "Order"
+            code = "4000"
+            name = "Sales"
+            [[accounts]]
+            description = "Storage"
+            code = "6200"
+            name = "Storage fees"
+            [[accounts]]
+            type = "Refund"
+            code = "4000"
+            name = "Sales"
+            "#,
+        )
+        .unwrap();
+        let sale = |kind: &str, description: &str, cents| Sale {
+            kind: kind.into(),
+            description: description.into(),
+            cents,
+            ..Sale::default()
+        };
+        let sales = [
+            sale("Order", "Widget", 1_000),
+            sale("Refund", "Widget", -300),
+            sale("FBA Inventory Fee", "Storage Fee", -50),
+            sale("Transfer", "To account", -600),
+        ];
+        assert_eq!(
+            summarize(&sales, &config),
+            [
+                total("4000", "Sales", 700),
+                total("6200", "Storage fees", -50),
+                total("", UNMAPPED, -600),
+            ]
+        );
+    }
+
+    fn total(code: &str, name: &str, cents: Cents) -> Total {
+        Total {
+            code: code.to_string(),
+            name: name.to_string(),
+            cents,
+        }
+    }
+}
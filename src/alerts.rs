@@ -0,0 +1,100 @@
+//! Checks of every report against `[alerts]`, whose alerts are added to its
+//! summary, printed, emailed and posted with it, and make `dedupy` exit
+//! with its own code.
+//!
+//! Refunds and fees are measured like in [`crate::analytics`], and a report
+//! is compared to the runs recorded before it.
+
+use crate::{analytics, Alerts, Summary};
+
+impl Alerts {
+    /// Whether any check is set.
+    pub(crate) fn any(&self) -> bool {
+        self.refund_rate.is_some() || self.new_types || self.fee_increase.is_some()
+    }
+
+    /// What about `summary` is alarming, compared to the `earlier` runs.
+    pub(crate) fn check<'a>(
+        &self,
+        summary: &Summary,
+        earlier: impl Iterator<Item = &'a Summary> + Clone,
+    ) -> Vec<String> {
+        let mut alerts = Vec::new();
+        if let Some(limit) = self.refund_rate {
+            let rate = analytics::refund_rate(&summary.totals).unwrap_or_default() * 100.0;
+            if rate > limit {
+                alerts.push(format!(
+                    "refunds are {rate:.1}% of orders, more than {limit}%"
+                ));
+            }
+        }
+        // Every type is new to the first run.
+        if self.new_types && earlier.clone().next().is_some() {
+            for kind in summary.totals.keys() {
+                if !earlier.clone().any(|run| run.totals.contains_key(kind)) {
+                    alerts.push(format!("`{kind}` was never seen before"));
+                }
+            }
+        }
+        if let Some(limit) = self.fee_increase {
+            if let Some((month, totals)) = analytics::previous_month(summary, earlier) {
+                let now = analytics::fees(&summary.totals).abs();
+                let then = analytics::fees(&totals).abs();
+                let increase = (now - then) as f64 / then.max(1) as f64 * 100.0;
+                if then > 0 && increase > limit {
+                    alerts.push(format!(
+                        "fees are up {increase:.1}% from {month}, more than {limit}%"
+                    ));
+                }
+            }
+        }
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Cents;
+
+    #[test]
+    fn raises_alerts() {
+        let day = |m| chrono::NaiveDate::from_ymd_opt(2024, m, 1).unwrap();
+        let summary = |m, totals: &[(&str, Cents)]| Summary {
+            period: Some((day(m), day(m))),
+            totals: (totals.iter())
+                .map(|(kind, cents)| (kind.to_string(), *cents))
+                .collect(),
+            ..Summary::default()
+        };
+        let earlier = [summary(1, &[("Order", 10_000), ("FBA Fee", -1_000)])];
+        let this = summary(
+            2,
+            &[("Order", 10_000), ("Refund", -600), ("FBA Fee", -1_300)],
+        );
+        let alerts = Alerts {
+            refund_rate: Some(5.0),
+            new_types: true,
+            fee_increase: Some(20.0),
+        };
+        assert_eq!(
+            alerts.check(&this, earlier.iter()),
+            [
+                "refunds are 6.0% of orders, more than 5%",
+                "`Refund` was never seen before",
+                "fees are up 30.0% from 2024-01, more than 20%",
+            ]
+        );
+        assert_eq!(
+            alerts.check(&this, [].iter()),
+            ["refunds are 6.0% of orders, more than 5%"]
+        );
+
+        let calm = Alerts {
+            refund_rate: Some(10.0),
+            new_types: false,
+            fee_increase: Some(50.0),
+        };
+        assert!(calm.check(&this, earlier.iter()).is_empty());
+    }
+}
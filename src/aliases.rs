@@ -0,0 +1,118 @@
+//! Column names of reports exported by Seller Central in other languages, see
+//! `header_aliases`.
+//!
+//! A localized header is read as if it had the English names, so the rest of
+//! dedupy, and the settings naming columns, only know those. Transaction
+//! types are kept as written, such as `Bestellung` for `Order`.
+
+use std::collections::HashMap;
+
+use csv::StringRecord;
+
+use crate::Config;
+
+/// Localized column names, lowercase, with the English name of each.
+const BUILT_IN: &[(&str, &str)] = &[
+    // German
+    ("datum/uhrzeit", "date/time"),
+    ("abrechnungsnummer", "settlement id"),
+    ("typ", "type"),
+    ("bestellnummer", "order id"),
+    ("beschreibung", "description"),
+    ("menge", "quantity"),
+    ("gesamt", "total"),
+    // French
+    ("date/heure", "date/time"),
+    ("numéro de versement", "settlement id"),
+    ("numéro de la commande", "order id"),
+    ("quantité", "quantity"),
+    // Spanish
+    ("fecha y hora", "date/time"),
+    ("identificador de pago", "settlement id"),
+    ("tipo", "type"),
+    ("número de pedido", "order id"),
+    ("descripción", "description"),
+    ("cantidad", "quantity"),
+    ("web de amazon", "marketplace"),
+    // Italian
+    ("data/ora:", "date/time"),
+    ("numero pagamento", "settlement id"),
+    ("numero ordine", "order id"),
+    ("descrizione", "description"),
+    ("quantità", "quantity"),
+    ("totale", "total"),
+    // Japanese
+    ("日付/時間", "date/time"),
+    ("決済番号", "settlement id"),
+    ("トランザクションの種類", "type"),
+    ("注文番号", "order id"),
+    ("説明", "description"),
+    ("数量", "quantity"),
+    ("合計", "total"),
+];
+
+/// Every alias in use, keyed by the lowercase localized name.
+pub(crate) struct Aliases(HashMap<String, String>);
+
+impl Aliases {
+    /// The built-in aliases, with those of `header_aliases` taking precedence.
+    pub(crate) fn new(config: &Config) -> Self {
+        let built_in = BUILT_IN
+            .iter()
+            .map(|(alias, name)| (alias.to_string(), name.to_string()));
+        let configured = config
+            .header_aliases
+            .iter()
+            .map(|(alias, name)| (alias.trim().to_lowercase(), name.clone()));
+        Self(built_in.chain(configured).collect())
+    }
+
+    /// `hdr` with every localized name replaced by its English name. Names
+    /// are matched ignoring case and surrounding whitespace.
+    pub(crate) fn translate(&self, hdr: &StringRecord) -> StringRecord {
+        let mut translated = hdr
+            .iter()
+            .map(|field| {
+                self.0
+                    .get(&field.trim().to_lowercase())
+                    .map_or(field, String::as_str)
+            })
+            .collect::<StringRecord>();
+        translated.set_position(hdr.position().cloned());
+        translated
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn translates_headers() {
+        let config: Config = toml::from_str(
+            r#"
+            [header_aliases]
+            "Artikelnummer" = "sku"
+            "#,
+        )
+        .unwrap();
+        let aliases = Aliases::new(&config);
+        let hdr = StringRecord::from(vec![
+            "Typ",
+            "Artikelnummer",
+            "Beschreibung",
+            "Menge",
+            "Umsätze",
+            " Gesamt ",
+        ]);
+        assert_eq!(
+            aliases.translate(&hdr),
+            vec!["type", "sku", "description", "quantity", "Umsätze", "total"]
+        );
+        let japanese = StringRecord::from(vec!["トランザクションの種類", "数量", "合計"]);
+        assert_eq!(
+            aliases.translate(&japanese),
+            vec!["type", "quantity", "total"]
+        );
+    }
+}
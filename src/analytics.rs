@@ -0,0 +1,289 @@
+//! Figures computed from the output of a report, see `analytics`, added to
+//! its summary.
+//!
+//! Revenue is the total of the `Order` type, which the fees and refunds are
+//! measured against, and a type is a fee if its name has "fee" in it. A
+//! report is compared to the runs recorded before it whose rows start in
+//! the month before its own.
+//!
+//! The totals and period every run records in the audit log are also added
+//! up per month, as [`trends`].
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{Datelike as _, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+use crate::{Cents, Sale, Summary};
+
+/// The transaction type of sales.
+const ORDER: &str = "Order";
+/// The transaction type of refunds.
+const REFUND: &str = "Refund";
+
+/// Analytics of a report, see the [module](crate::analytics).
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Analytics {
+    /// SKUs with the largest total of orders, largest first.
+    pub top_by_revenue: Vec<SkuTotal>,
+    /// SKUs with the most units ordered, most first.
+    pub top_by_units: Vec<SkuTotal>,
+    /// Fees, either way, per unit of revenue. None without revenue.
+    pub fee_ratio: Option<f64>,
+    /// Refunds, either way, per unit of revenue. None without revenue.
+    pub refund_rate: Option<f64>,
+    /// Change of every total from the month before, if it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub month_over_month: Option<MonthOverMonth>,
+}
+
+/// Orders of a SKU, across its descriptions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkuTotal {
+    pub sku: String,
+    pub quantity: i64,
+    pub cents: Cents,
+}
+
+/// A report against the runs of the month before it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MonthOverMonth {
+    /// The month compared to, such as `2024-01`.
+    pub previous: String,
+    /// This report's total less the month before's, per transaction type.
+    pub deltas: BTreeMap<String, Cents>,
+}
+
+/// Revenue, fees, and refunds of the runs whose rows start in a month.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Trend {
+    /// Such as `2024-01`.
+    pub month: String,
+    pub runs: usize,
+    pub revenue: Cents,
+    pub fees: Cents,
+    pub refunds: Cents,
+    /// Across all transaction types.
+    pub net: Cents,
+}
+
+/// Whether a transaction type is a fee.
+pub(crate) fn is_fee(kind: &str) -> bool {
+    kind.to_lowercase().contains("fee")
+}
+
+/// Total of every fee type, see [`is_fee`].
+pub(crate) fn fees(totals: &BTreeMap<String, Cents>) -> Cents {
+    (totals.iter())
+        .filter(|(kind, _)| is_fee(kind))
+        .map(|(_, cents)| cents)
+        .sum()
+}
+
+/// The last `periods` months that `runs` have rows starting in, oldest
+/// first. Runs without a dated row are left out.
+pub fn trends<'a>(runs: impl Iterator<Item = &'a Summary>, periods: usize) -> Vec<Trend> {
+    let mut months = BTreeMap::<(i32, u32), Trend>::new();
+    for run in runs {
+        let Some((first, _)) = run.period else {
+            continue;
+        };
+        let trend = months
+            .entry((first.year(), first.month()))
+            .or_insert_with(|| Trend {
+                month: format!("{}-{:02}", first.year(), first.month()),
+                runs: 0,
+                revenue: 0,
+                fees: 0,
+                refunds: 0,
+                net: 0,
+            });
+        let total = |kind: &str| run.totals.get(kind).copied().unwrap_or_default();
+        trend.runs += 1;
+        trend.revenue += total(ORDER);
+        trend.fees += fees(&run.totals);
+        trend.refunds += total(REFUND);
+        trend.net += run.total();
+    }
+    let skip = months.len().saturating_sub(periods);
+    months.into_values().skip(skip).collect()
+}
+
+impl Analytics {
+    /// The analytics of `summary`, whose output is `sales`, listing `top`
+    /// SKUs and comparing it to the `earlier` runs.
+    pub(crate) fn new<'a>(
+        summary: &Summary,
+        sales: &[Sale],
+        top: usize,
+        earlier: impl Iterator<Item = &'a Summary>,
+    ) -> Self {
+        let mut skus = BTreeMap::<&str, (i64, Cents)>::new();
+        for sale in sales.iter().filter(|sale| &*sale.kind == ORDER) {
+            let (quantity, cents) = skus.entry(&sale.sku).or_default();
+            *quantity += sale.quantity;
+            *cents += sale.cents;
+        }
+        let skus = (skus.into_iter())
+            .map(|(sku, (quantity, cents))| SkuTotal {
+                sku: sku.to_string(),
+                quantity,
+                cents,
+            })
+            .collect::<Vec<_>>();
+        let top_by = |key: fn(&SkuTotal) -> i64| {
+            let mut skus = skus.clone();
+            skus.sort_by(|a, b| key(b).cmp(&key(a)).then_with(|| a.sku.cmp(&b.sku)));
+            skus.truncate(top);
+            skus
+        };
+
+        Self {
+            top_by_revenue: top_by(|sku| sku.cents),
+            top_by_units: top_by(|sku| sku.quantity),
+            fee_ratio: ratio(&summary.totals, fees(&summary.totals)),
+            refund_rate: refund_rate(&summary.totals),
+            month_over_month: month_over_month(summary, earlier),
+        }
+    }
+}
+
+/// `cents`, either way, per unit of the revenue of `totals`.
+fn ratio(totals: &BTreeMap<String, Cents>, cents: Cents) -> Option<f64> {
+    let revenue = totals.get(ORDER).copied().unwrap_or_default();
+    (revenue > 0).then(|| cents.abs() as f64 / revenue as f64)
+}
+
+/// Refunds, either way, per unit of the revenue of `totals`.
+pub(crate) fn refund_rate(totals: &BTreeMap<String, Cents>) -> Option<f64> {
+    ratio(totals, totals.get(REFUND).copied().unwrap_or_default())
+}
+
+/// The month before the one the rows of `summary` start in, such as
+/// `2024-01`, with the totals of the `earlier` runs whose rows start in it,
+/// added together. None if there were none.
+pub(crate) fn previous_month<'a>(
+    summary: &Summary,
+    earlier: impl Iterator<Item = &'a Summary>,
+) -> Option<(String, BTreeMap<String, Cents>)> {
+    let month = |date: NaiveDate| (date.year(), date.month());
+    let (first, _) = summary.period?;
+    let previous = month(first.with_day(1)?.pred_opt()?);
+    let mut totals = BTreeMap::<String, Cents>::new();
+    let mut found = false;
+    for run in earlier.filter(|run| {
+        run.period
+            .is_some_and(|(first, _)| month(first) == previous)
+    }) {
+        found = true;
+        for (kind, cents) in &run.totals {
+            *totals.entry(kind.clone()).or_default() += cents;
+        }
+    }
+    found.then(|| (format!("{}-{:02}", previous.0, previous.1), totals))
+}
+
+/// Compares `summary` to the month before, see [`previous_month`].
+fn month_over_month<'a>(
+    summary: &Summary,
+    earlier: impl Iterator<Item = &'a Summary>,
+) -> Option<MonthOverMonth> {
+    let (previous, totals) = previous_month(summary, earlier)?;
+    let kinds = (summary.totals.keys())
+        .chain(totals.keys())
+        .collect::<BTreeSet<_>>();
+    let deltas = (kinds.into_iter())
+        .map(|kind| {
+            let now = summary.totals.get(kind).copied().unwrap_or_default();
+            let then = totals.get(kind).copied().unwrap_or_default();
+            (kind.clone(), now - then)
+        })
+        .collect();
+    Some(MonthOverMonth { previous, deltas })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn computes_analytics() {
+        let sale = |kind: &str, sku: &str, quantity, cents| Sale {
+            kind: kind.into(),
+            sku: sku.into(),
+            description: "Widget".into(),
+            quantity,
+            cents,
+        };
+        let sales = [
+            sale("Order", "A", 1, 5_000),
+            sale("Order", "B", 4, 2_000),
+            sale("Order", "B", 2, 1_000),
+            sale("Order", "C", 1, 2_000),
+            sale("Refund", "A", -1, -1_000),
+            sale("Service Fee", "", 0, -800),
+        ];
+        let day = |m, d| NaiveDate::from_ymd_opt(2024, m, d).unwrap();
+        let summary = |period, totals: &[(&str, Cents)]| Summary {
+            period: Some(period),
+            totals: (totals.iter())
+                .map(|(kind, cents)| (kind.to_string(), *cents))
+                .collect(),
+            ..Summary::default()
+        };
+        let this = summary(
+            (day(2, 1), day(2, 29)),
+            &[("Order", 10_000), ("Refund", -1_000), ("Service Fee", -800)],
+        );
+        let earlier = [
+            summary((day(1, 1), day(1, 15)), &[("Order", 4_000)]),
+            summary(
+                (day(1, 16), day(1, 31)),
+                &[("Order", 2_000), ("Refund", -500)],
+            ),
+            summary((day(2, 1), day(2, 29)), &[("Order", 1)]),
+        ];
+
+        let analytics = Analytics::new(&this, &sales, 2, earlier.iter());
+        let skus = |skus: &[SkuTotal]| skus.iter().map(|s| s.sku.clone()).collect::<Vec<_>>();
+        assert_eq!(skus(&analytics.top_by_revenue), ["A", "B"]);
+        assert_eq!(skus(&analytics.top_by_units), ["B", "A"]);
+        assert_eq!(analytics.top_by_units[0].cents, 3_000);
+        assert_eq!(analytics.fee_ratio, Some(0.08));
+        assert_eq!(analytics.refund_rate, Some(0.1));
+        assert_eq!(
+            analytics.month_over_month,
+            Some(MonthOverMonth {
+                previous: "2024-01".to_string(),
+                deltas: [
+                    ("Order".to_string(), 4_000),
+                    ("Refund".to_string(), -500),
+                    ("Service Fee".to_string(), -800)
+                ]
+                .into()
+            })
+        );
+
+        let none = Analytics::new(&this, &sales, 2, earlier[2..].iter());
+        assert_eq!(none.month_over_month, None);
+
+        let undated = Summary::default();
+        let trends = trends(earlier.iter().chain([&this, &undated]), 5);
+        assert_eq!(trends.len(), 2, "undated runs are left out");
+        assert_eq!(
+            trends[1],
+            Trend {
+                month: "2024-02".to_string(),
+                runs: 2,
+                revenue: 10_001,
+                fees: -800,
+                refunds: -1_000,
+                net: 8_201,
+            }
+        );
+        assert_eq!((trends[0].runs, trends[0].net), (2, 5_500));
+        let last = super::trends(earlier.iter().chain([&this]), 1);
+        assert_eq!(last.len(), 1);
+        assert_eq!(last[0].month, "2024-02");
+    }
+}
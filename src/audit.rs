@@ -0,0 +1,116 @@
+//! An append-only record of every run, one JSON object per line.
+
+use std::io::{BufRead as _, Write as _};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::{crypt, Config, Outcome, Summary};
+
+/// A single line of the audit log.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Record {
+    pub timestamp: DateTime<Local>,
+    /// Version of dedupy that produced the run.
+    pub version: String,
+    #[serde(flatten)]
+    pub summary: Summary,
+    /// Every report of a batch that failed, which is recorded as a run that
+    /// read and wrote nothing, see [`crate::BatchFailed`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outcomes: Vec<Outcome>,
+}
+
+impl Record {
+    pub fn new(timestamp: DateTime<Local>, summary: Summary) -> Self {
+        Self {
+            timestamp,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            summary,
+            outcomes: Vec::new(),
+        }
+    }
+
+    /// Reads every run, oldest first, from `postgres` if it is set and from
+    /// `audit_log` otherwise.
+    pub fn load(config: &Config) -> eyre::Result<Vec<Self>> {
+        match &config.postgres {
+            None => Self::read_all(config),
+            #[cfg(feature = "postgres")]
+            Some(url) => crate::postgres::records(url),
+            #[cfg(not(feature = "postgres"))]
+            Some(_) => {
+                eyre::bail!("`postgres` is set, but dedupy was built without the postgres feature")
+            }
+        }
+    }
+
+    /// Reads every record in `audit_log`, oldest first.
+    ///
+    /// A missing log is treated as empty.
+    pub fn read_all(config: &Config) -> eyre::Result<Vec<Self>> {
+        let Some(log) = crypt::read(&config.audit_log, config.encryption.as_ref())? else {
+            return Ok(Vec::new());
+        };
+        let mut records = Vec::new();
+        for line in log.lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                records.push(serde_json::from_str(&line)?);
+            }
+        }
+        Ok(records)
+    }
+
+    /// Appends this record to `audit_log`, creating it if needed.
+    ///
+    /// An encrypted log is rewritten as a whole, and is never appended to
+    /// without `encryption`.
+    pub fn append(&self, config: &Config) -> eyre::Result<()> {
+        let mut line = serde_json::to_vec(self)?;
+        line.push(b'\n');
+        if config.encryption.is_some() {
+            let mut log =
+                crypt::read(&config.audit_log, config.encryption.as_ref())?.unwrap_or_default();
+            log.extend_from_slice(&line);
+            return crypt::write(&config.audit_log, config.encryption.as_ref(), &log);
+        }
+        crypt::ensure_plain(&config.audit_log)?;
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.audit_log)?;
+        file.write_all(&line)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Encryption;
+
+    #[test]
+    fn keeps_encrypted_log() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let key_file = dir.join("key");
+        std::fs::write(&key_file, [7; 32]).unwrap();
+        let mut config = Config {
+            audit_log: dir.join("audit.jsonl"),
+            encryption: Some(Encryption {
+                key_file: Some(key_file),
+                passphrase_env: None,
+            }),
+            ..Config::default()
+        };
+        let record = Record::new(Local::now(), Summary::default());
+        record.append(&config).unwrap();
+        let encrypted = std::fs::read(&config.audit_log).unwrap();
+
+        config.encryption = None;
+        let error = record.append(&config).unwrap_err();
+        assert!(error.to_string().contains("is encrypted"), "{error}");
+        assert_eq!(std::fs::read(&config.audit_log).unwrap(), encrypted);
+    }
+}
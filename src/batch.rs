@@ -0,0 +1,148 @@
+//! Finding the reports of a batch in directories given instead of files,
+//! see `ignore`.
+//!
+//! Every file directly in a directory is a report, in order of name, unless
+//! its name matches one of the `ignore` patterns, such as a partly copied
+//! file or the lock file Excel keeps next to a workbook that is open, or its
+//! contents are already in `archive`, as it was processed before. Directories
+//! within are not read. Files given by name are never left out.
+//!
+//! A file modified within the last `stable_seconds` may still be being
+//! copied, such as over a network share, so it is waited for until it goes
+//! that long unmodified, and left out if it was modified in the meantime.
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::{Config, Input};
+
+/// `paths` with every directory replaced by the reports in it.
+pub fn expand(paths: Vec<PathBuf>, config: &Config) -> eyre::Result<Vec<PathBuf>> {
+    let mut files = Vec::with_capacity(paths.len());
+    for path in paths {
+        if !path.is_dir() {
+            files.push(path);
+            continue;
+        }
+        let mut found = Vec::new();
+        for entry in std::fs::read_dir(&path)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                continue;
+            }
+            let file = entry.path();
+            match ignored(&file, config)? {
+                Some(why) => tracing::info!(file = %file.display(), "{why}, skipping it"),
+                None => found.push(file),
+            }
+        }
+        found.sort();
+        files.extend(stable(found, config)?);
+    }
+    Ok(files)
+}
+
+/// Why the file at `path` is left out of a batch, if it is.
+pub(crate) fn ignored(path: &Path, config: &Config) -> eyre::Result<Option<&'static str>> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    if config.ignore.iter().any(|pattern| pattern.matches(&name)) {
+        return Ok(Some("matches `ignore`"));
+    }
+    if config.archive.is_some() && Input::new(path)?.is_archived(config)? {
+        return Ok(Some("already archived"));
+    }
+    Ok(None)
+}
+
+/// `files` less those modified while waiting for every one of them to go
+/// `stable_seconds` unmodified.
+fn stable(files: Vec<PathBuf>, config: &Config) -> eyre::Result<Vec<PathBuf>> {
+    let window = Duration::from_secs(config.stable_seconds);
+    let state = |path: &Path| -> std::io::Result<(u64, SystemTime)> {
+        let metadata = std::fs::metadata(path)?;
+        Ok((metadata.len(), metadata.modified()?))
+    };
+    let before = files
+        .iter()
+        .map(|file| state(file))
+        .collect::<Result<Vec<_>, _>>()?;
+    // Modified in the future is as good as just now.
+    let youngest = (before.iter())
+        .map(|(_, modified)| modified.elapsed().unwrap_or_default())
+        .min();
+    match youngest {
+        Some(age) if age < window => std::thread::sleep(window - age),
+        _ => return Ok(files),
+    }
+    let mut stable = Vec::with_capacity(files.len());
+    for (file, before) in files.into_iter().zip(before) {
+        if state(&file)? == before {
+            stable.push(file);
+        } else {
+            tracing::info!(file = %file.display(), "still being written, skipping it");
+        }
+    }
+    Ok(stable)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write as _;
+
+    use super::*;
+
+    #[test]
+    fn leaves_out_ignored_files() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let archive = dir.join("archive");
+        std::fs::create_dir_all(&archive).unwrap();
+        let report = "type,sku,description,quantity,total\n";
+        for name in [
+            "b.csv",
+            "a.csv",
+            "~$a.xlsx",
+            ".a.csv.swp",
+            "c.csv.part",
+            "old.csv",
+        ] {
+            std::fs::write(dir.join(name), format!("{report}{name}\n")).unwrap();
+        }
+        let old = Input::new(&dir.join("old.csv")).unwrap();
+        std::fs::copy(dir.join("old.csv"), archive.join(old.archive_name())).unwrap();
+
+        let config = Config {
+            archive: Some(archive),
+            stable_seconds: 0,
+            ..Config::default()
+        };
+        let given = dir.join("given.part");
+        let files = expand(vec![dir.to_path_buf(), given.clone()], &config).unwrap();
+        assert_eq!(files, [dir.join("a.csv"), dir.join("b.csv"), given]);
+    }
+
+    #[test]
+    fn leaves_out_files_being_written() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let (done, copying) = (dir.join("done.csv"), dir.join("copying.csv"));
+        std::fs::write(&done, "type,total\n").unwrap();
+        std::fs::write(&copying, "type,total\n").unwrap();
+        let copy = std::thread::spawn({
+            let copying = copying.clone();
+            move || {
+                std::thread::sleep(Duration::from_millis(300));
+                let file = std::fs::OpenOptions::new().append(true).open(copying);
+                file.unwrap().write_all(b"Order,5.00\n").unwrap();
+            }
+        });
+        let config = Config {
+            stable_seconds: 1,
+            ..Config::default()
+        };
+        assert_eq!(expand(vec![dir.to_path_buf()], &config).unwrap(), [done]);
+        copy.join().unwrap();
+    }
+}
@@ -0,0 +1,97 @@
+//! Bundling the output of a report with the files that go with it into a
+//! zip, see `bundle`.
+//!
+//! A bundle is written next to the output, named after the months its rows
+//! are dated in, such as `2024-01.zip`, with `-1`, `-2`, and so on added if
+//! a bundle of that name is already there, so that every settlement is one
+//! file to hand over. The summary of the report is added as `SUMMARY.json`, as it is recorded in
+//! the audit log, and the bundled files are removed.
+
+use std::{io::Write as _, path::PathBuf};
+
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use crate::{alternate_name, sheet::Period, Summary};
+
+/// Writes the bundle of `files`, returning where it was written.
+pub(crate) fn write(summary: &Summary, files: &[PathBuf], date: &str) -> eyre::Result<PathBuf> {
+    let name = match Period::from(summary.period).to_string() {
+        period if period.is_empty() => format!("UNDATED_{date}"),
+        period => period.replace(" to ", "_to_"),
+    };
+    let first = summary.output.with_file_name(format!("{name}.zip"));
+    let mut path = first.clone();
+    let mut n = 0;
+    while path.try_exists()? {
+        n += 1;
+        path = alternate_name(&first, n);
+    }
+
+    let mut zip = ZipWriter::new(std::fs::File::create(&path)?);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    let written = (|| {
+        for file in files {
+            let name = file.file_name().unwrap_or_default().to_string_lossy();
+            zip.start_file(name, options)?;
+            zip.write_all(&std::fs::read(file)?)?;
+        }
+        zip.start_file("SUMMARY.json", options)?;
+        serde_json::to_writer_pretty(&mut zip, summary)?;
+        zip.finish()?;
+        Ok::<_, eyre::Report>(())
+    })();
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&path);
+        return Err(e);
+    }
+    for file in files {
+        std::fs::remove_file(file)?;
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bundles_by_period() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let files = ["AGGREGATED.csv", "POSSIBLE_DUPLICATES.csv"].map(|name| dir.join(name));
+        let day = |m| chrono::NaiveDate::from_ymd_opt(2024, m, 5).unwrap();
+        let summary = Summary {
+            output: files[0].clone(),
+            period: Some((day(1), day(2))),
+            ..Summary::default()
+        };
+        let mut bundles = Vec::new();
+        for _ in 0..2 {
+            for file in &files {
+                std::fs::write(file, "a,b\n").unwrap();
+            }
+            bundles.push(write(&summary, &files, "2024-03-01").unwrap());
+        }
+        let names = (bundles.iter())
+            .map(|bundle| {
+                let zip = zip::ZipArchive::new(std::fs::File::open(bundle).unwrap()).unwrap();
+                let mut names = zip.file_names().map(str::to_string).collect::<Vec<_>>();
+                names.sort();
+                names
+            })
+            .collect::<Vec<_>>();
+        let left = files.iter().any(|file| file.exists());
+        assert_eq!(
+            bundles,
+            [
+                dir.join("2024-01_to_2024-02.zip"),
+                dir.join("2024-01_to_2024-02-1.zip")
+            ]
+        );
+        assert_eq!(
+            names[0],
+            ["AGGREGATED.csv", "POSSIBLE_DUPLICATES.csv", "SUMMARY.json"]
+        );
+        assert!(!left, "bundled files are removed");
+    }
+}
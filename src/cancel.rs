@@ -0,0 +1,51 @@
+//! Stopping a run from another thread, such as when a window is closed or
+//! the client of a server goes away, see [`Report::parse_with`].
+//!
+//! A run checks its [`Cancel`] between batches of rows and once more before
+//! writing its output, and stops with [`Cancelled`] if it was cancelled.
+//! Memory is only written after the output, so a cancelled run leaves it as
+//! it was, like an interrupted one, see [`crate::checkpoint`].
+//!
+//! [`Report::parse_with`]: crate::Report::parse_with
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// Cancels every run it is passed to, and every clone of it, once
+/// [`Cancel::cancel`] is called.
+#[derive(Debug, Clone, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    /// Stops the runs this was passed to at their next check. A run past its
+    /// last check, writing memory, finishes.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn check(&self) -> eyre::Result<()> {
+        match self.is_cancelled() {
+            true => Err(Cancelled.into()),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Error of a run that was cancelled, found with `downcast_ref` on the error
+/// of [`Report::parse_with`](crate::Report::parse_with).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the run was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
@@ -0,0 +1,181 @@
+//! Checkpoints of a run over a very large report, see `checkpoint_rows`.
+//!
+//! Every `checkpoint_rows` rows, what a run has aggregated and memorized so
+//! far is written to [`JOURNAL`]. Memory itself is only written once the run
+//! finishes, so an interrupted run leaves it as it was, and the next run of
+//! the same report, recognized by its checksum, resumes after the last
+//! checkpoint instead of starting over. Rows before the checkpoint are not
+//! passed to hooks or written to `explain` again.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    crypt, ledger::Ledger, memory::Progress, sheet::Period, tax::Taxes, Adjustment, Cents, Config,
+    WithSku,
+};
+
+/// Journal of the last checkpoint, removed once a run finishes.
+pub const JOURNAL: &str = "checkpoint_journal";
+
+/// Everything a run over a report has done, after reading some of its rows.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Checkpoint {
+    /// Data rows read, which a resumed run skips.
+    pub(crate) rows: u64,
+    pub(crate) duplicates: u64,
+    #[serde(default)]
+    pub(crate) duplicate_totals: BTreeMap<String, Cents>,
+    pub(crate) adjustments: Vec<(Adjustment, (Cents, i64))>,
+    pub(crate) with_sku: Vec<(WithSku, Cents)>,
+    pub(crate) taxes: Taxes,
+    pub(crate) currencies: BTreeSet<Arc<str>>,
+    pub(crate) period: Period,
+    pub(crate) days: BTreeSet<NaiveDate>,
+    pub(crate) ledger: Ledger,
+    pub(crate) near_duplicates: Vec<Vec<String>>,
+    pub(crate) near_seen: HashSet<u64>,
+    #[serde(default)]
+    pub(crate) quarantined: Vec<(String, Vec<String>)>,
+    pub(crate) records: Progress,
+    pub(crate) skus: Progress,
+    pub(crate) near: Option<Progress>,
+}
+
+/// Where the checkpoints of a run over a report go.
+pub(crate) struct Journal<'c> {
+    path: PathBuf,
+    config: &'c Config,
+    /// Checksum of the report, see [`crate::Input`], written with every
+    /// checkpoint.
+    checksum: String,
+    every: u64,
+    /// Rows after which the next checkpoint is written.
+    next: u64,
+}
+
+impl<'c> Journal<'c> {
+    /// The journal of a run over the report with `checksum`, if
+    /// `checkpoint_rows` is set.
+    pub(crate) fn new(config: &'c Config, checksum: &str) -> Option<Self> {
+        let every = config.checkpoint_rows.filter(|&every| every > 0)?;
+        Some(Self {
+            path: PathBuf::from(JOURNAL),
+            config,
+            checksum: checksum.to_string(),
+            every,
+            next: every,
+        })
+    }
+
+    /// The last checkpoint of an interrupted run over the same report. A
+    /// checkpoint of another report is ignored, and replaced by the next one.
+    pub(crate) fn resume(&mut self) -> eyre::Result<Option<Checkpoint>> {
+        let Some(bytes) = crypt::read(&self.path, self.config.encryption.as_ref())? else {
+            return Ok(None);
+        };
+        let (checksum, checkpoint) = serde_json::from_slice::<(String, Checkpoint)>(&bytes)?;
+        if checksum != self.checksum {
+            return Ok(None);
+        }
+        self.next = checkpoint.rows + self.every;
+        Ok(Some(checkpoint))
+    }
+
+    /// Whether a checkpoint is due, having read `rows` rows.
+    pub(crate) fn due(&self, rows: u64) -> bool {
+        rows >= self.next
+    }
+
+    /// Replaces the last checkpoint with `checkpoint`.
+    ///
+    /// The journal is written next to itself and then renamed, so that it
+    /// is never left half written.
+    pub(crate) fn write(&mut self, checkpoint: &Checkpoint) -> eyre::Result<()> {
+        self.next = checkpoint.rows + self.every;
+        let partial = self.path.with_extension("partial");
+        let bytes = serde_json::to_vec(&(&self.checksum, checkpoint))?;
+        crypt::write(&partial, self.config.encryption.as_ref(), &bytes)?;
+        std::fs::rename(&partial, &self.path)?;
+        tracing::debug!(rows = checkpoint.rows, "wrote a checkpoint");
+        Ok(())
+    }
+
+    /// Removes the journal, once the run no longer needs to be resumed.
+    pub(crate) fn finish(self) -> eyre::Result<()> {
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{aggregate, hooks::Hooks, Memories};
+
+    #[derive(Default)]
+    struct Lines(Vec<u64>);
+
+    impl Hooks for Lines {
+        fn row(&mut self, row: &crate::hooks::Row<'_>) -> bool {
+            self.0.push(row.line);
+            true
+        }
+    }
+
+    #[test]
+    fn resumes() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let config: Config = toml::from_str("checkpoint_rows = 1").unwrap();
+        let journal = || Journal {
+            path: dir.join(JOURNAL),
+            ..Journal::new(&config, "report").unwrap()
+        };
+        let report = b"type,sku,description,quantity,total\n\
+            Order,A,Widget,2,10.00\n\
+            Service Fee,,Advertising,,-1.50\n\
+            Order,A,Widget,1,5.00\n";
+
+        // Interrupted after the first two rows.
+        let mut memories = Memories::default();
+        let interrupted = &report[..report.len() - "Order,A,Widget,1,5.00\n".len()];
+        aggregate(
+            interrupted,
+            &config,
+            &mut memories,
+            None,
+            Some(&mut journal()),
+            &mut (),
+            None,
+        )
+        .unwrap();
+
+        let mut lines = Lines::default();
+        let aggregation = aggregate(
+            &report[..],
+            &config,
+            &mut Memories::default(),
+            None,
+            Some(&mut journal()),
+            &mut lines,
+            None,
+        )
+        .unwrap();
+        assert_eq!(lines.0, [4], "rows before the checkpoint are skipped");
+        assert_eq!(aggregation.rows, 3);
+        assert_eq!(aggregation.totals()["Order"], 1_500);
+        assert_eq!(aggregation.totals()["Service Fee"], -150);
+
+        journal().finish().unwrap();
+        assert!(!dir.join(JOURNAL).exists());
+    }
+}
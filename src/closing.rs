@@ -0,0 +1,110 @@
+//! Periods that are closed in accounting, see `dedupy close-period`.
+//!
+//! Totals of a closed period were already reported, so a run refuses to
+//! aggregate rows dated in one, unless `reopen_closed_periods` is set.
+
+use std::{collections::BTreeSet, io::ErrorKind, path::Path};
+
+use chrono::{DateTime, Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+use crate::Config;
+
+/// Where closed periods are kept.
+pub const CLOSED: &str = "closed_periods.json";
+
+/// A range of days that is closed, both ends included.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Closed {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    /// When the period was closed.
+    pub closed: DateTime<Local>,
+}
+
+impl Closed {
+    fn contains(&self, day: NaiveDate) -> bool {
+        (self.from..=self.to).contains(&day)
+    }
+}
+
+/// Reads the periods closed in the file at `path`, in the order they were
+/// closed. A missing file closes nothing.
+pub fn load(path: &Path) -> eyre::Result<Vec<Closed>> {
+    match std::fs::read(path) {
+        Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Closes the days from `from` to `to` in the file at `path`, returning every
+/// closed period.
+pub fn close(path: &Path, from: NaiveDate, to: NaiveDate) -> eyre::Result<Vec<Closed>> {
+    if from > to {
+        eyre::bail!("{from} is after {to}");
+    }
+    let mut closed = load(path)?;
+    closed.push(Closed {
+        from,
+        to,
+        closed: Local::now(),
+    });
+    std::fs::write(path, serde_json::to_vec_pretty(&closed)?)?;
+    Ok(closed)
+}
+
+/// Fails if any of `days`, that new rows are dated on, is in a period closed
+/// in [`CLOSED`], or only warns if `reopen_closed_periods` is set.
+pub(crate) fn check(config: &Config, days: &BTreeSet<NaiveDate>) -> eyre::Result<()> {
+    for period in load(Path::new(CLOSED))? {
+        let inside = days
+            .iter()
+            .filter(|day| period.contains(**day))
+            .collect::<Vec<_>>();
+        let (Some(first), Some(last)) = (inside.first(), inside.last()) else {
+            continue;
+        };
+        let dated = match first == last {
+            true => first.to_string(),
+            false => format!("{first} to {last}"),
+        };
+        let closed = period.closed.date_naive();
+        if config.reopen_closed_periods {
+            tracing::warn!(
+                "aggregating rows dated {dated}, in the period {} to {} closed on \
+                 {closed}, as it is reopened",
+                period.from,
+                period.to
+            );
+        } else {
+            eyre::bail!(
+                "rows dated {dated} are new, but the period {} to {} was closed on \
+                 {closed}; pass --reopen to aggregate them anyway",
+                period.from,
+                period.to
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn closes() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let path = dir.join(CLOSED);
+        let day = |d| NaiveDate::from_ymd_opt(2024, 1, d).unwrap();
+
+        assert!(close(&path, day(2), day(1)).is_err());
+        close(&path, day(1), day(31)).unwrap();
+        let closed = load(&path).unwrap();
+        assert_eq!(closed.len(), 1);
+        assert!(closed[0].contains(day(31)));
+        assert!(!closed[0].contains(NaiveDate::from_ymd_opt(2024, 2, 1).unwrap()));
+    }
+}
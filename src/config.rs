@@ -0,0 +1,961 @@
+//! Optional settings, read from [`Config::PATH`] in the working directory.
+
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use glob::Pattern;
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+
+use crate::{HashAlgorithm, Marketplace, Normalize};
+
+/// Settings shared by every run.
+///
+/// Missing keys, or a missing file altogether, fall back to [`Default`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// File that a JSON line is appended to after every run.
+    pub audit_log: PathBuf,
+    /// Directory that every processed report is copied into, named by its
+    /// checksum, so the run can be replayed later.
+    pub archive: Option<PathBuf>,
+    /// Patterns of the names of files that are left out when a directory is
+    /// processed, such as temporary files, see [`crate::batch`].
+    #[serde(deserialize_with = "globs")]
+    pub ignore: Vec<Pattern>,
+    /// Seconds a file in a directory must go unmodified before it is read,
+    /// so that one still being copied is not read half written.
+    pub stable_seconds: u64,
+    /// CSV file that a row is appended to for every row of every report,
+    /// explaining how it was aggregated.
+    pub explain: Option<PathBuf>,
+    /// Whether the output of every report is bundled with the files that go
+    /// with it and its summary into a zip named after the months its rows are
+    /// dated in, such as `2024-01.zip`, instead of written as they are.
+    pub bundle: bool,
+    /// Whether a one page PDF of the period, totals, fees, and top SKUs of
+    /// every report is written next to its output, to be printed.
+    pub pdf_summary: bool,
+    /// When the `dedupy` binary writes its log to [`Config::log_dir`].
+    pub log: Log,
+    /// Directory of log files, a new one every day, keeping the last week.
+    pub log_dir: PathBuf,
+    /// Marketplace that reports are exported from, recognized by their
+    /// header when unset.
+    pub source: Option<Marketplace>,
+    /// Mappings of the columns of other exports, recognized by their header
+    /// before any built-in marketplace when [`Config::source`] is unset.
+    pub profiles: Vec<Profile>,
+    /// Columns, in any order, that the header of a report must have and no
+    /// others, so that a report whose format changed fails rather than being
+    /// read differently. Reports read with one of [`Config::profiles`] are
+    /// held to its own [`Profile::header`] instead. Unchecked when empty.
+    pub header: Vec<String>,
+    /// How a row is identified in memory.
+    ///
+    /// Changing this makes rows seen by earlier runs look new.
+    pub dedup: Dedup,
+    /// Columns whose values identify a row in memory, taking precedence over
+    /// [`Config::dedup`] when not empty.
+    ///
+    /// Changing this makes rows seen by earlier runs look new.
+    pub dedup_key: Vec<String>,
+    /// Whether to list rows that look like a row seen before, apart from
+    /// formatting or time, for review.
+    pub near_duplicates: bool,
+    /// Whether the summary of every report has its top SKUs, fee and refund
+    /// ratios, and changes from the month before, see [`crate::analytics`].
+    pub analytics: bool,
+    /// SKUs listed by revenue and by units, when `analytics` is set.
+    pub top_skus: usize,
+    /// What raises an alert about a report.
+    pub alerts: Alerts,
+    /// Whether memory keeps what was hashed alongside each hash, so that two
+    /// transactions with the same hash are not mistaken for one another.
+    ///
+    /// Hashes remembered without this adopt the first key that matches them.
+    pub verify_memory: bool,
+    /// Every this many rows, a run writes what it has aggregated and
+    /// memorized so far to a journal, so that an interrupted run over a very
+    /// large report resumes from there. No checkpoints are written when unset,
+    /// or for a batch of several reports.
+    pub checkpoint_rows: Option<u64>,
+    /// Hash function that memory is kept with.
+    ///
+    /// Existing memory is rehashed if every entry has its key, see
+    /// [`Config::verify_memory`], and otherwise keeps its hash function.
+    pub memory_hash: HashAlgorithm,
+    /// Months after which a hash that no run has seen again is forgotten, so
+    /// memory does not grow forever. Hashes are kept forever when unset.
+    pub memory_retention_months: Option<u32>,
+    /// Whether a Bloom filter is kept in front of memory, so that a row that
+    /// is certainly new is recognized without looking it up. It takes about
+    /// 1.2 bytes per hash at the default rate.
+    pub memory_bloom_filter: bool,
+    /// Share of rows that were never seen which the Bloom filter mistakes for
+    /// seen, and which are looked up anyway. Lower rates use more memory.
+    pub memory_bloom_false_positive_rate: f64,
+    /// Whether a run appends what it remembered to a journal next to each
+    /// memory file instead of rewriting the file, which is compacted once
+    /// the journal grows past a quarter of it. Encrypted memory is always
+    /// rewritten.
+    pub memory_journal: bool,
+    /// URL of a Redis server that memory is kept in instead of files, so that
+    /// several machines share it. Requires the `redis` feature.
+    pub redis: Option<String>,
+    /// Connection string of a PostgreSQL database that memory, runs, and
+    /// aggregates are kept in, so that several workers share them. Requires
+    /// the `postgres` feature.
+    pub postgres: Option<String>,
+    /// DuckDB database file that every run and the aggregates it wrote are
+    /// also written to, for querying. Requires the `duckdb` feature.
+    pub duckdb: Option<PathBuf>,
+    /// Environment variable holding a password that is required to edit the
+    /// output workbook. The workbook can still be opened and read without it.
+    pub output_password_env: Option<String>,
+    /// Name of the output's sheet of aggregates, `Sheet1` when unset. Made
+    /// from a template of `{period}`, the months the aggregated rows are
+    /// dated in, `{input_stem}`, the report's file name without its
+    /// extension, and `{date}`, the date of the run.
+    ///
+    /// Characters Excel does not allow are replaced with `_`, and the name is
+    /// cut to 31 characters.
+    pub sheet_name: Option<String>,
+    /// Columns masked in output meant for review: possible duplicates,
+    /// `--explain` traces, and previews. The aggregated output is unchanged.
+    pub redact: Vec<String>,
+    /// Rhai script that rewrites or drops rows before they are aggregated.
+    pub script: Option<PathBuf>,
+    /// WebAssembly plugins that rewrite or drop rows, run in order after
+    /// `script`.
+    pub plugins: Vec<PathBuf>,
+    /// Columns naming the jurisdiction a row is taxed in, for the tax summary
+    /// of the output. Only read from reports with tax columns.
+    pub tax_jurisdiction: Vec<String>,
+    /// How amounts with more than two decimals, such as some fees, are
+    /// rounded to cents.
+    pub amount_rounding: Rounding,
+    /// What happens to a row with fewer columns than the header, whose
+    /// missing columns would otherwise be read as empty.
+    pub short_rows: ShortRows,
+    /// What happens to a report that looks partly downloaded, because its
+    /// last line is unterminated or it has fewer rows than its preamble
+    /// declares.
+    pub truncated_reports: TruncatedReports,
+    /// Transaction types expected in reports, as written after `script` and
+    /// `plugins`. Every type is expected when empty.
+    pub known_types: Vec<String>,
+    /// What happens to a row whose type is not one of `known_types`.
+    pub unknown_types: UnknownTypes,
+    /// Rows left out of the output and listed for review instead, for what
+    /// looks wrong about them, like rows of unknown types with
+    /// `unknown_types = "quarantine"`.
+    pub quarantine: Vec<Suspect>,
+    /// Whether rows dated in a closed period are aggregated anyway, with a
+    /// warning, see [`crate::closing`].
+    pub reopen_closed_periods: bool,
+    /// SKU given to adjustments, which have none, in the output unless one of
+    /// [`Config::adjustment_sku_rules`] matches.
+    pub adjustment_sku: String,
+    /// Aggregates of a type whose total is below this amount, either way,
+    /// are rolled into a single aggregate described as "Miscellaneous", when
+    /// there are two or more of them. Nothing is rolled up when unset.
+    pub miscellaneous_below: Option<f64>,
+    /// Columns that the output is sorted by, each ascending unless followed
+    /// by `:desc`, later ones breaking ties of earlier ones. Remaining ties
+    /// are broken by every column in order, so that the same aggregates are
+    /// always written in the same order.
+    pub sort: Vec<SortKey>,
+    /// Placeholder SKUs for adjustments by type or description, such as the
+    /// account codes a bookkeeper files them under. The first match wins.
+    pub adjustment_sku_rules: Vec<AdjustmentSku>,
+    /// Rewrites of descriptions, such as removing a date, applied in order
+    /// before rows are grouped, so that rows differing only in that are
+    /// aggregated together.
+    pub description_rules: Vec<DescriptionRule>,
+    /// Accounts of a chart of accounts that the output is totalled under, in
+    /// an "Accounts" sheet or a CSV file next to it. The first match wins.
+    pub accounts: Vec<Account>,
+    /// Writes a balanced journal entry per settlement, totalled per account
+    /// of [`Config::accounts`], when set.
+    pub journal_entries: Option<JournalEntries>,
+    /// Columns of the bank statement that payouts are reconciled against,
+    /// see [`crate::reconcile`].
+    pub deposits: Deposits,
+    /// Encrypts memory files and the audit log when set.
+    pub encryption: Option<Encryption>,
+    /// Where the outputs of every successful run are emailed, if anywhere.
+    /// Requires the `email` feature.
+    pub email: Option<Email>,
+    /// URLs that a summary of every successful run is posted to. Requires
+    /// the `webhook` feature.
+    pub webhooks: Vec<Webhook>,
+    /// Cloud storage the outputs of every successful run are uploaded to, if
+    /// any. Requires the `upload` feature.
+    pub upload: Option<Upload>,
+    /// SFTP and FTPS servers that reports are fetched from and outputs
+    /// pushed to with `--remote`. Requires the `remote` feature.
+    pub remotes: Vec<Remote>,
+    /// Column names of reports exported in other languages, with the English
+    /// name each stands for, in addition to the built-in German, French,
+    /// Spanish, Italian, and Japanese names.
+    pub header_aliases: BTreeMap<String, String>,
+    /// Cleaning applied to every field before it is hashed or grouped.
+    pub normalize: Normalize,
+    /// How quantities are read and written.
+    pub quantity: Quantity,
+}
+
+/// When the `dedupy` binary writes its log to files, see [`Config::log_dir`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Log {
+    /// Only when a report is picked with the file picker, as a GUI build has
+    /// no console to show the log in.
+    #[default]
+    Gui,
+    Always,
+    Never,
+}
+
+/// Strategies for identifying a row in memory.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Dedup {
+    /// The whole row, exactly as it was read.
+    #[default]
+    Row,
+    /// The `order id`, `type`, and `total`, which survive a report being
+    /// downloaded again with different formatting.
+    ///
+    /// Rows without an order id fall back to [`Dedup::Row`].
+    OrderId,
+}
+
+/// A placeholder SKU for adjustments matching `type` and `description`, see
+/// [`Config::adjustment_sku_rules`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdjustmentSku {
+    /// Transaction type to match exactly, any when unset.
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    /// Text the description must contain, any when unset.
+    pub description: Option<String>,
+    pub sku: String,
+}
+
+/// A column of the output that it is sorted by, written as `total:desc`, see
+/// [`Config::sort`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct SortKey {
+    pub column: SortColumn,
+    pub descending: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortColumn {
+    Type,
+    Sku,
+    Description,
+    Quantity,
+    Total,
+}
+
+impl FromStr for SortKey {
+    type Err = eyre::Report;
+
+    fn from_str(key: &str) -> Result<Self, Self::Err> {
+        let (name, descending) = match key.trim().rsplit_once(':') {
+            Some((name, "asc")) => (name, false),
+            Some((name, "desc")) => (name, true),
+            Some((_, direction)) => {
+                eyre::bail!("sort direction {direction:?} is neither `asc` nor `desc`")
+            }
+            None => (key, false),
+        };
+        let column = match name.trim() {
+            "type" => SortColumn::Type,
+            "sku" => SortColumn::Sku,
+            "description" => SortColumn::Description,
+            "quantity" => SortColumn::Quantity,
+            "total" => SortColumn::Total,
+            name => eyre::bail!(
+                "the output cannot be sorted by {name:?}, only by type, sku, description, \
+                 quantity, or total"
+            ),
+        };
+        Ok(Self { column, descending })
+    }
+}
+
+impl TryFrom<String> for SortKey {
+    type Error = eyre::Report;
+
+    fn try_from(key: String) -> Result<Self, Self::Error> {
+        key.parse()
+    }
+}
+
+/// A rewrite of the descriptions of rows of `type`, see
+/// [`Config::description_rules`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DescriptionRule {
+    /// Transaction type to match exactly, any when unset.
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    /// Regular expression that every match of is replaced.
+    #[serde(deserialize_with = "regex")]
+    pub pattern: Regex,
+    /// What a match is replaced with, where `$1` is its first group. Matches
+    /// are removed when unset.
+    #[serde(default)]
+    pub replacement: String,
+}
+
+fn globs<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Pattern>, D::Error> {
+    let globs = Vec::<String>::deserialize(deserializer)?;
+    (globs.iter())
+        .map(|glob| Pattern::new(glob).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+fn regex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Regex, D::Error> {
+    let pattern = String::deserialize(deserializer)?;
+    Regex::new(&pattern).map_err(serde::de::Error::custom)
+}
+
+/// An account that aggregates matching `type` and `description` are totalled
+/// under, see [`Config::accounts`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Account {
+    /// Transaction type to match exactly, any when unset.
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    /// Text the description must contain, any when unset.
+    pub description: Option<String>,
+    /// Code of the account in the chart of accounts, such as `4000`.
+    pub code: String,
+    pub name: String,
+}
+
+/// How the columns of an export map to those of an Amazon report, see
+/// [`Config::profiles`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// Shown when a report is read with the profile.
+    pub name: String,
+    pub columns: ProfileColumns,
+    /// Rows at the top of a report that cannot be its header, for exports
+    /// whose preamble could be mistaken for one.
+    #[serde(default)]
+    pub skip_rows: u64,
+    /// Format of dates, when not one that Amazon writes.
+    pub date_format: Option<String>,
+    /// Whether amounts are written as `1.234,56`.
+    #[serde(default)]
+    pub decimal_comma: bool,
+    /// What the export writes in a field that is blank, such as `--`.
+    pub blank: Option<String>,
+    /// Column of fees charged on top of the total of a row, which are
+    /// written as rows of their own, of type `Fee`.
+    pub fee: Option<String>,
+    /// Columns that the header of an export must have and no others, see
+    /// [`Config::header`].
+    #[serde(default)]
+    pub header: Vec<String>,
+}
+
+/// Columns of an export that each column of an Amazon report is read from,
+/// the first that is not blank. A header must have every column named.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileColumns {
+    #[serde(default)]
+    pub date: Vec<String>,
+    #[serde(default)]
+    pub settlement_id: Vec<String>,
+    #[serde(rename = "type")]
+    pub kind: Vec<String>,
+    #[serde(default)]
+    pub order_id: Vec<String>,
+    #[serde(default)]
+    pub sku: Vec<String>,
+    #[serde(default)]
+    pub description: Vec<String>,
+    #[serde(default)]
+    pub quantity: Vec<String>,
+    pub total: Vec<String>,
+}
+
+/// How journal entries are written, see [`Config::journal_entries`], to
+/// match what an accounting package imports.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JournalEntries {
+    /// Account that balances each entry, such as the bank account or a
+    /// clearing account that the payout is deposited in.
+    pub balancing_code: String,
+    pub balancing_name: String,
+    /// Columns of every line, in order.
+    #[serde(default = "EntryColumn::all")]
+    pub columns: Vec<EntryColumn>,
+    /// Header of every column, their names when empty.
+    #[serde(default)]
+    pub headers: Vec<String>,
+    /// Format of the date of an entry, the day of its last row.
+    #[serde(default = "JournalEntries::date_format")]
+    pub date_format: String,
+    #[serde(default = "JournalEntries::delimiter")]
+    pub delimiter: char,
+}
+
+impl JournalEntries {
+    fn date_format() -> String {
+        "%Y-%m-%d".to_string()
+    }
+
+    fn delimiter() -> char {
+        ','
+    }
+}
+
+/// How deposits are read from a bank statement, see [`Config::deposits`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Deposits {
+    /// Column of the day of a deposit.
+    pub date: String,
+    /// Column of the amount deposited.
+    pub amount: String,
+    /// Column describing a deposit, which [`Deposits::contains`] is looked
+    /// for in.
+    pub description: Option<String>,
+    /// Text, in any case, that the description of a deposit from the
+    /// marketplace contains, such as `AMAZON`. Every row is read when unset.
+    pub contains: Option<String>,
+    /// Format of [`Deposits::date`].
+    pub date_format: String,
+    /// Days that a deposit may be dated before or after the last row of the
+    /// settlement it pays.
+    pub window_days: u32,
+}
+
+impl Default for Deposits {
+    fn default() -> Self {
+        Self {
+            date: "Date".to_string(),
+            amount: "Amount".to_string(),
+            description: None,
+            contains: None,
+            date_format: "%Y-%m-%d".to_string(),
+            window_days: 7,
+        }
+    }
+}
+
+/// A column of a journal entry line, see [`JournalEntries::columns`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EntryColumn {
+    Date,
+    /// The `settlement id` the entry is for.
+    Settlement,
+    /// Code of the account.
+    Code,
+    /// Name of the account.
+    Name,
+    /// Amount debited, empty for a credit.
+    Debit,
+    /// Amount credited, empty for a debit.
+    Credit,
+    /// Amount debited, negative for a credit.
+    Amount,
+    /// `Settlement` followed by the settlement id.
+    Memo,
+}
+
+impl EntryColumn {
+    fn all() -> Vec<Self> {
+        use EntryColumn::*;
+        vec![Date, Settlement, Code, Name, Debit, Credit, Memo]
+    }
+
+    /// Header of the column, unless [`JournalEntries::headers`] are set.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Date => "date",
+            Self::Settlement => "settlement",
+            Self::Code => "code",
+            Self::Name => "name",
+            Self::Debit => "debit",
+            Self::Credit => "credit",
+            Self::Amount => "amount",
+            Self::Memo => "memo",
+        }
+    }
+}
+
+/// How quantities are read from a report and written to the output.
+///
+/// The total of a row with a SKU is divided by its quantity to group it with
+/// rows of the same unit price, then multiplied back, so a row with a blank
+/// quantity contributes nothing to the output unless `blank` is set to 1.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Quantity {
+    /// Quantity of a row whose quantity is blank, such as a fee.
+    pub blank: i64,
+    /// Make the quantity of refunds negative, so that they offset the orders
+    /// they refund.
+    pub negative_refunds: bool,
+    /// Quantity adjustments are written with.
+    pub adjustments: AdjustmentQuantity,
+    /// What to do with a quantity that is not a whole number.
+    pub decimals: QuantityDecimals,
+}
+
+impl Quantity {
+    /// Quantity of a row of type `kind`, with `quantity` as written in the
+    /// report. Thousands separators, and quotes Excel keeps around numbers,
+    /// are ignored, but commas anywhere else are an error, so that `1,5` is
+    /// not read as 15.
+    pub(crate) fn read(&self, kind: &str, quantity: &str) -> eyre::Result<i64> {
+        let written = quantity;
+        let quantity = crate::unquote_number(quantity);
+        let (whole, _) = quantity.split_once('.').unwrap_or((quantity, ""));
+        let digits = whole.strip_prefix(['-', '+']).unwrap_or(whole);
+        if digits.contains(',') && !crate::is_grouped(digits) {
+            eyre::bail!("quantity {written:?} is not a number");
+        }
+        let quantity = quantity.replace(',', "");
+        let quantity = if quantity.is_empty() {
+            self.blank
+        } else if let Ok(quantity) = quantity.parse::<i64>() {
+            quantity
+        } else {
+            let quantity = quantity
+                .parse::<f64>()
+                .ok()
+                .filter(|q| q.is_finite())
+                .ok_or_else(|| eyre::eyre!("quantity {written:?} is not a number"))?;
+            let rounded = match self.decimals {
+                QuantityDecimals::Reject => {
+                    eyre::bail!("quantity {written:?} is not a whole number")
+                }
+                QuantityDecimals::Round => quantity.round(),
+                QuantityDecimals::Floor => quantity.floor(),
+                QuantityDecimals::Ceil => quantity.ceil(),
+            };
+            rounded as i64
+        };
+        Ok(if self.negative_refunds && kind == "Refund" {
+            -quantity.abs()
+        } else {
+            quantity
+        })
+    }
+}
+
+/// How an amount with more than two decimals, such as `0.125`, is read.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rounding {
+    /// Fail the row.
+    Reject,
+    /// Round to the nearest cent, halves to the even cent, so that rounding
+    /// many amounts does not drift in one direction.
+    #[default]
+    HalfEven,
+    /// Round to the nearest cent, halves away from zero.
+    HalfUp,
+    /// Drop the extra decimals.
+    Truncate,
+}
+
+/// How a quantity that is not a whole number, such as `1.5`, is read.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum QuantityDecimals {
+    /// Fail the row.
+    #[default]
+    Reject,
+    /// Round to the nearest whole number, halves away from zero.
+    Round,
+    /// Round down.
+    Floor,
+    /// Round up.
+    Ceil,
+}
+
+/// What happens to a row with fewer columns than the header.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ShortRows {
+    /// Fail the row.
+    #[default]
+    Reject,
+    /// Leave the row out, with a warning.
+    Skip,
+}
+
+/// What happens to a row whose type is not one of
+/// [`Config::known_types`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnknownTypes {
+    /// Aggregate the row, with a warning naming its type.
+    #[default]
+    Warn,
+    /// Leave the row out of the aggregates, and list it for review instead,
+    /// see [`Config::quarantine`].
+    Quarantine,
+    /// Fail the report, before anything is memorized.
+    Fail,
+}
+
+/// What looks wrong about a row that is quarantined, see
+/// [`Config::quarantine`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Suspect {
+    /// An amount with more than two decimals, which was rounded.
+    OddAmounts,
+    /// A row with a SKU but no quantity, which [`Quantity::blank`] stands
+    /// for.
+    MissingQuantities,
+    /// A row that looks like a row seen before, see
+    /// [`Config::near_duplicates`], which must be set.
+    NearDuplicates,
+}
+
+impl Suspect {
+    /// Why a row is listed for review.
+    pub(crate) fn reason(self) -> &'static str {
+        match self {
+            Self::OddAmounts => "amount has more than two decimals",
+            Self::MissingQuantities => "quantity is blank",
+            Self::NearDuplicates => "looks like a row seen before",
+        }
+    }
+}
+
+/// What happens to a report that looks partly downloaded.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TruncatedReports {
+    /// Fail the report, before anything is memorized.
+    #[default]
+    Reject,
+    /// Process the report, with a warning.
+    Warn,
+}
+
+/// Quantity written for an adjustment, which sums every row like it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AdjustmentQuantity {
+    /// 1, or -1 if its total is negative.
+    #[default]
+    Sign,
+    /// The sum of the quantities read.
+    Keep,
+}
+
+/// Where the key that memory and the audit log are encrypted with comes
+/// from. Exactly one must be set.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Encryption {
+    /// File containing exactly 32 random bytes.
+    pub key_file: Option<PathBuf>,
+    /// Environment variable holding a passphrase that the key is derived
+    /// from.
+    pub passphrase_env: Option<String>,
+}
+
+/// SMTP server and recipients that outputs are emailed to, see
+/// [`Config::email`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Email {
+    /// Host name of the SMTP server.
+    pub server: String,
+    /// Port of the SMTP server, the usual one for `security` when unset.
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    /// User to log in to the server as, if it needs logging in to.
+    pub username: Option<String>,
+    /// Environment variable holding the password of `username`.
+    pub password_env: Option<String>,
+    /// Sender, such as `dedupy <books@example.com>`.
+    pub from: String,
+    pub to: Vec<String>,
+    /// Whether only the summary of every report is sent, without the outputs
+    /// attached.
+    #[serde(default)]
+    pub summary_only: bool,
+}
+
+/// A URL that a summary of every successful run is posted to as JSON, see
+/// [`Config::webhooks`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Webhook {
+    pub url: String,
+    /// URL of the directory outputs are shared from, which the name of each
+    /// output is added to for a link to it. No links are posted when unset.
+    pub link: Option<String>,
+    /// What is posted.
+    #[serde(default)]
+    pub format: WebhookFormat,
+}
+
+/// What is posted to a [`Webhook`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WebhookFormat {
+    /// The summary of every report, as recorded in the audit log.
+    #[default]
+    Json,
+    /// A message for a Slack channel, for an incoming webhook.
+    Slack,
+    /// A message for a Discord channel, leaving out reports past the length
+    /// Discord takes.
+    Discord,
+}
+
+/// Folder on cloud storage that outputs are uploaded to, see
+/// [`Config::upload`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Upload {
+    pub service: UploadService,
+    /// ID of the folder on Google Drive, as in its URL, or path of the folder
+    /// from the root of the drive on OneDrive.
+    pub folder: String,
+    /// Environment variable holding an OAuth access token that can write to
+    /// `folder`.
+    pub token_env: String,
+}
+
+/// What about a report raises an alert, see [`Config::alerts`] and
+/// [`crate::alerts`]. Nothing does by default.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Alerts {
+    /// Refunds, as a percentage of orders, above which to alert.
+    pub refund_rate: Option<f64>,
+    /// Whether to alert about a transaction type that no earlier run had.
+    pub new_types: bool,
+    /// Increase of fees over the month before, as a percentage, above which
+    /// to alert.
+    pub fee_increase: Option<f64>,
+}
+
+/// An SFTP or FTPS server, see [`Config::remotes`] and [`crate::remote`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Remote {
+    /// What `--remote` names the server by.
+    pub name: String,
+    /// `sftp://user@host` or `ftps://user@host`, with `:port` if not the
+    /// usual one. FTPS is explicit, upgrading a plain connection.
+    pub url: String,
+    /// Environment variable holding the password of the user, or the
+    /// passphrase of `identity`.
+    pub password_env: Option<String>,
+    /// Private key to log in to an SFTP server with. The SSH agent is asked
+    /// when neither it nor a password is set.
+    pub identity: Option<PathBuf>,
+    /// Known hosts file that the key of an SFTP server is checked against,
+    /// `~/.ssh/known_hosts` when unset.
+    pub known_hosts: Option<PathBuf>,
+    /// Directory on the server that reports are fetched from.
+    pub inbox: Option<String>,
+    /// Directory on the server that outputs are pushed to.
+    pub outbox: Option<String>,
+}
+
+/// Cloud storage that outputs are uploaded to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UploadService {
+    GoogleDrive,
+    Onedrive,
+}
+
+/// How the connection to the SMTP server is encrypted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SmtpSecurity {
+    /// Upgraded with STARTTLS, on port 587 by default.
+    #[default]
+    Starttls,
+    /// Encrypted from the start, on port 465 by default.
+    Tls,
+    /// Not at all, on port 25 by default, such as for a relay on the same
+    /// machine.
+    Plain,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            audit_log: PathBuf::from("audit.jsonl"),
+            archive: None,
+            bundle: false,
+            analytics: false,
+            top_skus: 5,
+            alerts: Alerts::default(),
+            pdf_summary: false,
+            ignore: ["~$*", ".*", "*.tmp", "*.part", "*.crdownload"]
+                .into_iter()
+                .map(|glob| Pattern::new(glob).expect("a valid pattern"))
+                .collect(),
+            stable_seconds: 2,
+            explain: None,
+            log: Log::default(),
+            log_dir: PathBuf::from("logs"),
+            source: None,
+            profiles: Vec::new(),
+            header: Vec::new(),
+            dedup: Dedup::default(),
+            dedup_key: Vec::new(),
+            near_duplicates: false,
+            verify_memory: false,
+            checkpoint_rows: None,
+            memory_hash: HashAlgorithm::default(),
+            memory_retention_months: None,
+            memory_bloom_filter: true,
+            memory_bloom_false_positive_rate: 0.01,
+            memory_journal: true,
+            redis: None,
+            postgres: None,
+            duckdb: None,
+            output_password_env: None,
+            sheet_name: None,
+            redact: Vec::new(),
+            script: None,
+            plugins: Vec::new(),
+            tax_jurisdiction: vec!["marketplace".to_string()],
+            amount_rounding: Rounding::default(),
+            short_rows: ShortRows::default(),
+            truncated_reports: TruncatedReports::default(),
+            known_types: Vec::new(),
+            unknown_types: UnknownTypes::default(),
+            quarantine: Vec::new(),
+            reopen_closed_periods: false,
+            adjustment_sku: "FBATF".to_string(),
+            miscellaneous_below: None,
+            sort: vec![
+                SortKey {
+                    column: SortColumn::Type,
+                    descending: false,
+                },
+                SortKey {
+                    column: SortColumn::Description,
+                    descending: false,
+                },
+            ],
+            adjustment_sku_rules: Vec::new(),
+            description_rules: Vec::new(),
+            accounts: Vec::new(),
+            journal_entries: None,
+            deposits: Deposits::default(),
+            encryption: None,
+            email: None,
+            webhooks: Vec::new(),
+            upload: None,
+            remotes: Vec::new(),
+            header_aliases: BTreeMap::new(),
+            normalize: Normalize::default(),
+            quantity: Quantity::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Where [`Config::load`] looks for settings.
+    pub const PATH: &'static str = "dedupy.toml";
+
+    /// Reads [`Config::PATH`], or returns the defaults if it does not exist.
+    pub fn load() -> eyre::Result<Self> {
+        Self::from_path(Self::PATH)
+    }
+
+    /// Reads the settings at `path`, or returns the defaults if it does not
+    /// exist.
+    pub fn from_path<P>(path: P) -> eyre::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        match std::fs::read_to_string(path) {
+            Ok(s) => Ok(toml::from_str(&s)?),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// SKU of an adjustment in the output.
+    pub(crate) fn adjustment_sku(&self, kind: &str, description: &str) -> &str {
+        self.adjustment_sku_rules
+            .iter()
+            .find(|rule| matches(&rule.kind, &rule.description, kind, description))
+            .map_or(&self.adjustment_sku, |rule| &rule.sku)
+    }
+
+    /// Whether `kind` is one of `known_types`, or they are not set.
+    pub(crate) fn is_known_type(&self, kind: &str) -> bool {
+        self.known_types.is_empty() || self.known_types.iter().any(|known| known == kind)
+    }
+
+    /// `description` of a row of type `kind` as rewritten by every one of
+    /// [`Config::description_rules`] that applies, trimmed.
+    pub(crate) fn description<'d>(&self, kind: &str, description: &'d str) -> Cow<'d, str> {
+        let mut rewritten = Cow::Borrowed(description);
+        let rules = (self.description_rules.iter())
+            .filter(|rule| rule.kind.as_deref().is_none_or(|k| k == kind));
+        for rule in rules {
+            if rule.pattern.is_match(&rewritten) {
+                let replaced = rule
+                    .pattern
+                    .replace_all(&rewritten, rule.replacement.as_str());
+                rewritten = Cow::Owned(replaced.trim().to_string());
+            }
+        }
+        rewritten
+    }
+
+    /// Account an aggregate is totalled under, if any.
+    pub(crate) fn account(&self, kind: &str, description: &str) -> Option<&Account> {
+        self.accounts
+            .iter()
+            .find(|rule| matches(&rule.kind, &rule.description, kind, description))
+    }
+}
+
+/// Whether a rule for `rule_kind` and `rule_description` matches a
+/// transaction of `kind` whose description is `description`.
+fn matches(
+    rule_kind: &Option<String>,
+    rule_description: &Option<String>,
+    kind: &str,
+    description: &str,
+) -> bool {
+    rule_kind.as_deref().is_none_or(|k| k == kind)
+        && rule_description
+            .as_deref()
+            .is_none_or(|d| description.contains(d))
+}
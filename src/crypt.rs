@@ -0,0 +1,152 @@
+//! Encryption of memory and the audit log at rest, see `encryption`.
+//!
+//! An encrypted file starts with [`MAGIC`], followed by the salt that the key
+//! was derived from the passphrase with, the nonce, and the ciphertext. The
+//! salt is unused with a key file. Files that do not start with [`MAGIC`] are
+//! read as they are, and encrypted the next time they are written.
+
+use std::{
+    io::{ErrorKind, Read as _},
+    path::Path,
+};
+
+use chacha20poly1305::{
+    aead::{rand_core::RngCore as _, Aead as _, KeyInit as _, OsRng},
+    AeadCore as _, ChaCha20Poly1305, Key, Nonce,
+};
+
+use crate::Encryption;
+
+const MAGIC: &[u8] = b"dedupy-encrypted\n";
+const SALT: usize = 16;
+const NONCE: usize = 12;
+
+/// Derives the key for a file encrypted with `salt`.
+fn key(encryption: &Encryption, salt: &[u8]) -> eyre::Result<Key> {
+    let mut key = Key::default();
+    match (&encryption.key_file, &encryption.passphrase_env) {
+        (Some(path), None) => {
+            let bytes = std::fs::read(path)?;
+            if bytes.len() != key.len() {
+                eyre::bail!(
+                    "{} must contain exactly {} bytes",
+                    path.display(),
+                    key.len()
+                );
+            }
+            key.copy_from_slice(&bytes);
+        }
+        (None, Some(var)) => {
+            let passphrase = std::env::var(var)
+                .map_err(|_| eyre::eyre!("the passphrase variable {var} is not set"))?;
+            argon2::Argon2::default()
+                .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+                .map_err(|e| eyre::eyre!("cannot derive a key from {var}: {e}"))?;
+        }
+        _ => eyre::bail!("`encryption` needs exactly one of `key_file` and `passphrase_env`"),
+    }
+    Ok(key)
+}
+
+/// Reads the file at `path`, decrypting it if it is encrypted, or returns
+/// `None` if it does not exist.
+pub(crate) fn read(path: &Path, encryption: Option<&Encryption>) -> eyre::Result<Option<Vec<u8>>> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let Some(sealed) = bytes.strip_prefix(MAGIC) else {
+        return Ok(Some(bytes));
+    };
+    let Some(encryption) = encryption else {
+        eyre::bail!(
+            "{} is encrypted, but `encryption` is not set",
+            path.display()
+        );
+    };
+    if sealed.len() < SALT + NONCE {
+        eyre::bail!("{} is truncated", path.display());
+    }
+    let (salt, sealed) = sealed.split_at(SALT);
+    let (nonce, ciphertext) = sealed.split_at(NONCE);
+    let cipher = ChaCha20Poly1305::new(&key(encryption, salt)?);
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| {
+            eyre::eyre!(
+                "cannot decrypt {}, the key or passphrase is wrong or the file was modified",
+                path.display()
+            )
+        })?;
+    Ok(Some(plaintext))
+}
+
+/// Fails if the file at `path` is encrypted, such that plaintext is not
+/// appended to it.
+pub(crate) fn ensure_plain(path: &Path) -> eyre::Result<()> {
+    let mut start = Vec::with_capacity(MAGIC.len());
+    match std::fs::File::open(path) {
+        Ok(file) => file.take(MAGIC.len() as u64).read_to_end(&mut start)?,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    if start == MAGIC {
+        eyre::bail!(
+            "{} is encrypted, but `encryption` is not set",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Writes `bytes` to the file at `path`, encrypting them if `encryption` is
+/// set.
+pub(crate) fn write(
+    path: &Path,
+    encryption: Option<&Encryption>,
+    bytes: &[u8],
+) -> eyre::Result<()> {
+    let Some(encryption) = encryption else {
+        std::fs::write(path, bytes)?;
+        return Ok(());
+    };
+    let mut salt = [0; SALT];
+    OsRng.fill_bytes(&mut salt);
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let cipher = ChaCha20Poly1305::new(&key(encryption, &salt)?);
+    let ciphertext = cipher
+        .encrypt(&nonce, bytes)
+        .map_err(|_| eyre::eyre!("cannot encrypt {}", path.display()))?;
+
+    let mut sealed = Vec::with_capacity(MAGIC.len() + SALT + NONCE + ciphertext.len());
+    sealed.extend_from_slice(MAGIC);
+    sealed.extend_from_slice(&salt);
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    std::fs::write(path, sealed)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let key_file = dir.join("key");
+        std::fs::write(&key_file, [7; 32]).unwrap();
+        let encryption = Encryption {
+            key_file: Some(key_file),
+            passphrase_env: None,
+        };
+        let path = dir.join("memory");
+
+        write(&path, Some(&encryption), b"secret").unwrap();
+        assert!(!std::fs::read(&path).unwrap().ends_with(b"secret"));
+        assert_eq!(read(&path, Some(&encryption)).unwrap().unwrap(), b"secret");
+        assert!(read(&path, None).is_err(), "needs the key");
+    }
+}
@@ -0,0 +1,175 @@
+//! Compares two outputs of previous runs.
+
+use std::{collections::BTreeMap, path::Path};
+
+#[cfg(feature = "xlsx")]
+use calamine::{open_workbook_auto, DataType, RangeDeserializerBuilder, Reader as _};
+use serde::Deserialize;
+
+use crate::Cents;
+
+/// Identifies an aggregate row across outputs.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Key {
+    pub kind: String,
+    pub sku: String,
+    pub description: String,
+}
+
+/// The numeric columns of an aggregate row.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Amount {
+    pub quantity: i64,
+    pub cents: Cents,
+}
+
+/// A row as written to the output, see `Sale`'s `Serialize` impl.
+#[derive(Debug, Deserialize)]
+struct Row {
+    #[serde(rename = "Type")]
+    kind: String,
+    #[serde(rename = "SKU")]
+    sku: String,
+    #[serde(rename = "Description")]
+    description: String,
+    #[serde(rename = "Quantity")]
+    quantity: f64,
+    #[serde(rename = "Total")]
+    total: f64,
+}
+
+/// Reads an output, merging rows that share a [`Key`].
+///
+/// Files ending in `.csv` are read as CSV, anything else as a spreadsheet.
+pub fn read<P>(path: P) -> eyre::Result<BTreeMap<Key, Amount>>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let rows = if path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("csv"))
+    {
+        csv::Reader::from_path(path)?
+            .deserialize::<Row>()
+            .collect::<Result<Vec<_>, _>>()?
+    } else {
+        read_workbook(path)?
+    };
+
+    let mut merged = BTreeMap::<Key, Amount>::new();
+    for row in rows {
+        let amount = merged
+            .entry(Key {
+                kind: row.kind,
+                sku: row.sku,
+                description: row.description,
+            })
+            .or_default();
+        amount.quantity += row.quantity.round() as i64;
+        amount.cents += (row.total * 100.0).round() as Cents;
+    }
+    Ok(merged)
+}
+
+#[cfg(feature = "xlsx")]
+fn read_workbook(path: &Path) -> eyre::Result<Vec<Row>> {
+    let mut workbook = open_workbook_auto(path)?;
+    let range = workbook
+        .worksheet_range_at(0)
+        .ok_or_else(|| eyre::eyre!("{} has no worksheets", path.display()))??;
+    Ok(RangeDeserializerBuilder::new()
+        .from_range::<DataType, Row>(&range)?
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+#[cfg(not(feature = "xlsx"))]
+fn read_workbook(path: &Path) -> eyre::Result<Vec<Row>> {
+    eyre::bail!(
+        "reading {} needs the `xlsx` feature, only CSV outputs can be compared",
+        path.display()
+    )
+}
+
+/// Rows that differ between two outputs.
+#[derive(Debug, Default)]
+pub struct Diff {
+    /// Only in the second output.
+    pub added: Vec<(Key, Amount)>,
+    /// Only in the first output.
+    pub removed: Vec<(Key, Amount)>,
+    /// In both outputs, with the first and second amounts.
+    pub changed: Vec<(Key, Amount, Amount)>,
+    /// Net amount of the first output.
+    pub before: Cents,
+    /// Net amount of the second output.
+    pub after: Cents,
+}
+
+impl Diff {
+    /// Reads and compares two outputs.
+    pub fn new<A, B>(a: A, b: B) -> eyre::Result<Self>
+    where
+        A: AsRef<Path>,
+        B: AsRef<Path>,
+    {
+        Ok(Self::compare(read(a)?, read(b)?))
+    }
+
+    fn compare(a: BTreeMap<Key, Amount>, mut b: BTreeMap<Key, Amount>) -> Self {
+        let mut diff = Self {
+            before: a.values().map(|v| v.cents).sum(),
+            after: b.values().map(|v| v.cents).sum(),
+            ..Self::default()
+        };
+        for (key, old) in a {
+            match b.remove(&key) {
+                Some(new) if new == old => {}
+                Some(new) => diff.changed.push((key, old, new)),
+                None => diff.removed.push((key, old)),
+            }
+        }
+        diff.added.extend(b);
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn key(kind: &str) -> Key {
+        Key {
+            kind: kind.to_string(),
+            sku: String::new(),
+            description: String::new(),
+        }
+    }
+
+    #[test]
+    fn compare_outputs() {
+        let amount = |quantity, cents| Amount { quantity, cents };
+        let a = BTreeMap::from([
+            (key("Order"), amount(2, 1000)),
+            (key("Refund"), amount(-1, -500)),
+            (key("Transfer"), amount(1, -200)),
+        ]);
+        let b = BTreeMap::from([
+            (key("Order"), amount(3, 1500)),
+            (key("Transfer"), amount(1, -200)),
+            (key("Service Fee"), amount(1, -40)),
+        ]);
+        let diff = Diff::compare(a, b);
+        assert_eq!(diff.added, vec![(key("Service Fee"), amount(1, -40))]);
+        assert_eq!(diff.removed, vec![(key("Refund"), amount(-1, -500))]);
+        assert_eq!(
+            diff.changed,
+            vec![(key("Order"), amount(2, 1000), amount(3, 1500))]
+        );
+        assert_eq!((diff.before, diff.after), (300, 1260));
+    }
+}
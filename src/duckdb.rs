@@ -0,0 +1,183 @@
+//! Runs and their aggregates written to a DuckDB database file, see
+//! `duckdb`, so that they can be queried without loading every output.
+//!
+//! Every report processed is a row of `runs`, with the figures of its
+//! summary as columns and the whole record as JSON, and every row of its
+//! output a row of `aggregates`. Amounts are in cents, like in PostgreSQL.
+
+use std::path::Path;
+
+use duckdb::{params, Connection};
+
+use crate::{audit, Sale};
+
+/// Changes to the schema, applied in order by [`open`].
+///
+/// A migration that was released must never change, add another instead.
+const MIGRATIONS: &[&str] = &[r#"
+CREATE SEQUENCE runs_id;
+CREATE TABLE runs (
+    id BIGINT PRIMARY KEY DEFAULT nextval('runs_id'),
+    timestamp TIMESTAMP NOT NULL,
+    version VARCHAR NOT NULL,
+    inputs VARCHAR NOT NULL,
+    output VARCHAR NOT NULL,
+    rows BIGINT NOT NULL,
+    duplicates BIGINT NOT NULL,
+    near_duplicates BIGINT NOT NULL,
+    aggregates BIGINT NOT NULL,
+    first_day DATE,
+    last_day DATE,
+    cents BIGINT NOT NULL,
+    record VARCHAR NOT NULL
+);
+CREATE TABLE aggregates (
+    run BIGINT NOT NULL REFERENCES runs (id),
+    type VARCHAR NOT NULL,
+    sku VARCHAR NOT NULL,
+    description VARCHAR NOT NULL,
+    quantity BIGINT NOT NULL,
+    cents BIGINT NOT NULL
+);
+"#];
+
+/// Opens the database at `path`, creating it if missing and applying any
+/// migrations it is missing.
+fn open(path: &Path) -> eyre::Result<Connection> {
+    let mut conn = Connection::open(path)?;
+    let tx = conn.transaction()?;
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS migrations (
+            version INTEGER PRIMARY KEY,
+            applied TIMESTAMP NOT NULL DEFAULT current_timestamp
+        )",
+    )?;
+    let applied: i32 = tx.query_row(
+        "SELECT COALESCE(MAX(version), 0) FROM migrations",
+        [],
+        |row| row.get(0),
+    )?;
+    if applied as usize > MIGRATIONS.len() {
+        eyre::bail!("the database was migrated by a newer version of dedupy (version {applied})");
+    }
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(applied as usize) {
+        tx.execute_batch(migration)?;
+        tx.execute(
+            "INSERT INTO migrations (version) VALUES (?)",
+            params![version as i32 + 1],
+        )?;
+    }
+    tx.commit()?;
+    Ok(conn)
+}
+
+/// Writes a run and the aggregates it wrote.
+pub(crate) fn record(path: &Path, record: &audit::Record, sales: &[Sale]) -> eyre::Result<()> {
+    let mut conn = open(path)?;
+    let tx = conn.transaction()?;
+    let summary = &record.summary;
+    let inputs = (summary.inputs.iter())
+        .map(|input| input.path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let day = |day: Option<chrono::NaiveDate>| day.map(|day| day.to_string());
+    let run: i64 = tx.query_row(
+        "INSERT INTO runs (timestamp, version, inputs, output, rows, duplicates,
+             near_duplicates, aggregates, first_day, last_day, cents, record)
+         VALUES (CAST(? AS TIMESTAMP), ?, ?, ?, ?, ?, ?, ?, CAST(? AS DATE),
+             CAST(? AS DATE), ?, ?)
+         RETURNING id",
+        params![
+            record.timestamp.naive_local().to_string(),
+            record.version,
+            inputs,
+            summary.output.display().to_string(),
+            summary.rows,
+            summary.duplicates,
+            summary.near_duplicates,
+            summary.aggregates,
+            day(summary.period.map(|(first, _)| first)),
+            day(summary.period.map(|(_, last)| last)),
+            summary.total(),
+            serde_json::to_string(record)?,
+        ],
+        |row| row.get(0),
+    )?;
+    {
+        let mut appender = tx.appender("aggregates")?;
+        for sale in sales {
+            appender.append_row(params![
+                run,
+                &*sale.kind,
+                &*sale.sku,
+                &*sale.description,
+                sale.quantity,
+                sale.cents,
+            ])?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Input, Summary};
+
+    #[test]
+    fn records_runs() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("dedupy.duckdb");
+        let day = |d| chrono::NaiveDate::from_ymd_opt(2024, 1, d).unwrap();
+        let summary = Summary {
+            inputs: vec![Input {
+                path: "report.csv".into(),
+                checksum: String::new(),
+            }],
+            output: "AGGREGATED.xlsx".into(),
+            rows: 3,
+            aggregates: 2,
+            period: Some((day(1), day(31))),
+            totals: [("Order".to_string(), 1_000), ("Refund".to_string(), -250)].into(),
+            ..Summary::default()
+        };
+        let record = audit::Record::new(chrono::Local::now(), summary);
+        let sales = [
+            Sale {
+                kind: "Order".into(),
+                sku: "A".into(),
+                description: "Widget".into(),
+                quantity: 2,
+                cents: 1_000,
+            },
+            Sale {
+                kind: "Refund".into(),
+                sku: "A".into(),
+                description: "Widget".into(),
+                quantity: -1,
+                cents: -250,
+            },
+        ];
+        super::record(&path, &record, &sales).unwrap();
+        super::record(&path, &record, &sales[..1]).unwrap();
+
+        let conn = open(&path).unwrap();
+        let runs: (i64, i64, String) = conn
+            .query_row(
+                "SELECT count(*), sum(cents)::BIGINT, max(last_day)::VARCHAR FROM runs",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        let aggregates: Vec<(i64, i64)> = conn
+            .prepare("SELECT run, sum(cents)::BIGINT FROM aggregates GROUP BY run ORDER BY run")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(runs, (2, 1_500, "2024-01-31".to_string()));
+        assert_eq!(aggregates, [(1, 750), (2, 1_000)]);
+    }
+}
@@ -0,0 +1,158 @@
+//! Emailing the outputs of every successful run, see `email`.
+//!
+//! A single message is sent per run, with a summary of every report and its
+//! output attached, unless it was written to a stream. The run has succeeded
+//! by the time it is sent, so an email that cannot be sent is only warned
+//! about.
+
+use lettre::{
+    message::{header::ContentType, Attachment, MultiPart, SinglePart},
+    transport::smtp::authentication::Credentials,
+    Message, SmtpTransport, Transport as _,
+};
+
+use crate::{Cents, Email, SmtpSecurity, Summary};
+
+/// Sends the outputs of the reports of a run, see the [module](self).
+pub(crate) fn send(email: &Email, summaries: &[Summary]) -> eyre::Result<()> {
+    let message = message(email, summaries)?;
+    let mut transport = match email.security {
+        SmtpSecurity::Starttls => SmtpTransport::starttls_relay(&email.server)?,
+        SmtpSecurity::Tls => SmtpTransport::relay(&email.server)?,
+        SmtpSecurity::Plain => SmtpTransport::builder_dangerous(&email.server),
+    };
+    if let Some(port) = email.port {
+        transport = transport.port(port);
+    }
+    if let Some(username) = &email.username {
+        let password = match &email.password_env {
+            Some(var) => std::env::var(var)
+                .map_err(|_| eyre::eyre!("the SMTP password variable {var} is not set"))?,
+            None => String::new(),
+        };
+        transport = transport.credentials(Credentials::new(username.clone(), password));
+    }
+    transport.build().send(&message)?;
+    Ok(())
+}
+
+fn message(email: &Email, summaries: &[Summary]) -> eyre::Result<Message> {
+    let alerts = summaries
+        .iter()
+        .map(|summary| summary.alerts.len())
+        .sum::<usize>();
+    let subject = match summaries {
+        [summary] => format!(
+            "dedupy: {} processed, {} in total",
+            file(summary),
+            money(summary.total())
+        ),
+        _ => format!("dedupy: {} reports processed", summaries.len()),
+    };
+    let subject = match alerts {
+        0 => subject,
+        1 => format!("{subject}, 1 alert"),
+        n => format!("{subject}, {n} alerts"),
+    };
+    let mut builder = Message::builder()
+        .from(email.from.parse()?)
+        .subject(subject);
+    for to in &email.to {
+        builder = builder.to(to.parse()?);
+    }
+
+    let mut body = String::new();
+    for summary in summaries {
+        body += &format!(
+            "{} -> {}\nRows: {}\nDuplicates: {}\nAggregates: {}\n",
+            file(summary),
+            summary.output.display(),
+            summary.rows,
+            summary.duplicates,
+            summary.aggregates
+        );
+        for (kind, cents) in &summary.totals {
+            body += &format!("  {kind:<40} {:>12}\n", money(*cents));
+        }
+        body += &format!("  {:<40} {:>12}\n", "", money(summary.total()));
+        for alert in &summary.alerts {
+            body += &format!("Alert: {alert}\n");
+        }
+        body += "\n";
+    }
+    let mut parts = MultiPart::mixed().singlepart(SinglePart::plain(body));
+    let outputs = summaries.iter().map(|summary| &summary.output);
+    for output in outputs.filter(|_| !email.summary_only) {
+        if output.as_os_str() == "-" {
+            continue;
+        }
+        let content_type = match output.extension().and_then(|ext| ext.to_str()) {
+            Some("xlsx") => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            Some("csv") => "text/csv",
+            Some("html") => "text/html",
+            Some("ods") => "application/vnd.oasis.opendocument.spreadsheet",
+            Some("json") => "application/json",
+            Some("zip") => "application/zip",
+            _ => "application/octet-stream",
+        };
+        let name = output.file_name().unwrap_or_default().to_string_lossy();
+        parts = parts.singlepart(
+            Attachment::new(name.into_owned())
+                .body(std::fs::read(output)?, ContentType::parse(content_type)?),
+        );
+    }
+    Ok(builder.multipart(parts)?)
+}
+
+fn file(summary: &Summary) -> String {
+    let paths = summary.inputs.iter().map(|input| input.path.display());
+    paths
+        .map(|path| path.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn money(cents: Cents) -> String {
+    format!("{:.2}", cents as f64 / 100.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Input;
+
+    #[test]
+    fn attaches_outputs() {
+        let temp = tempfile::tempdir().unwrap();
+        let output = temp.path().join("AGGREGATED.csv");
+        std::fs::write(&output, "Type,Total\nOrder,15.00\n").unwrap();
+        let email: Email = toml::from_str(
+            r#"
+            server = "smtp.example.com"
+            from = "dedupy <dedupy@example.com>"
+            to = ["books@example.com"]
+            "#,
+        )
+        .unwrap();
+        let summary = Summary {
+            inputs: vec![Input {
+                path: "report.csv".into(),
+                checksum: String::new(),
+            }],
+            output: output.clone(),
+            rows: 2,
+            totals: [("Order".to_string(), 1_500)].into(),
+            alerts: vec!["`Order` was never seen before".to_string()],
+            ..Summary::default()
+        };
+
+        let message = message(&email, &[summary]).unwrap();
+        let message = String::from_utf8(message.formatted()).unwrap();
+        assert!(message.contains("Subject: dedupy: report.csv processed, 15.00 in total, 1 alert"));
+        assert!(message.contains("Alert: `Order` was never seen before"));
+        assert!(message.contains("To: books@example.com"));
+        assert!(message.contains("Order                                           15.00"));
+        let name = output.file_name().unwrap().to_str().unwrap();
+        assert!(message.contains(&format!("filename=\"{name}\"")));
+    }
+}
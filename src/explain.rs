@@ -0,0 +1,122 @@
+//! A per-row trace of how a report was aggregated, for troubleshooting totals
+//! that do not match.
+
+use std::{fs::File, path::Path};
+
+use serde::Serialize;
+
+use crate::{redact::Redact, Trx};
+
+#[derive(Serialize)]
+struct Row<'a> {
+    file: &'a str,
+    line: u64,
+    /// Hash that was looked up in memory, hex encoded.
+    hash: String,
+    /// Whether a previous run already aggregated the row.
+    duplicate: bool,
+    /// The rest are empty for duplicates, which are never classified, and
+    /// for rows a hook, the script, or a plugin dropped, whose class is
+    /// `Dropped`. Only the type of a `Quarantined` row is written.
+    class: Option<&'static str>,
+    #[serde(rename = "type")]
+    kind: Option<&'a str>,
+    sku: Option<&'a str>,
+    description: Option<&'a str>,
+    /// Price per unit, which is part of the bucket for sales with a SKU.
+    unit: Option<f64>,
+}
+
+/// Appends a row to a CSV file for every row of the report.
+pub(crate) struct Trace {
+    wtr: csv::Writer<File>,
+    file: String,
+    redact: Redact,
+}
+
+impl Trace {
+    /// Opens the trace at `path` for rows of the report at `file`, writing the
+    /// header if the trace is new.
+    pub(crate) fn open(path: &Path, file: &Path, redact: Redact) -> eyre::Result<Self> {
+        let trace = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        let is_new = trace.metadata()?.len() == 0;
+        Ok(Self {
+            wtr: csv::WriterBuilder::new()
+                .has_headers(is_new)
+                .from_writer(trace),
+            file: file.display().to_string(),
+            redact,
+        })
+    }
+
+    pub(crate) fn duplicate(&mut self, line: u64, hash: u64) -> eyre::Result<()> {
+        self.wtr.serialize(Row {
+            duplicate: true,
+            ..row(&self.file, line, hash)
+        })?;
+        Ok(())
+    }
+
+    /// Records a row left out for review, see `quarantine`.
+    pub(crate) fn quarantined(&mut self, line: u64, hash: u64, trx: &Trx) -> eyre::Result<()> {
+        self.wtr.serialize(Row {
+            class: Some("Quarantined"),
+            kind: Some(self.redact.field("type", trx.kind())),
+            ..row(&self.file, line, hash)
+        })?;
+        Ok(())
+    }
+
+    /// Records a row that a hook, the script, or a plugin dropped, see
+    /// [`crate::Hooks::row`], `script`, and `plugins`.
+    pub(crate) fn dropped(&mut self, line: u64, hash: u64) -> eyre::Result<()> {
+        self.wtr.serialize(Row {
+            class: Some("Dropped"),
+            ..row(&self.file, line, hash)
+        })?;
+        Ok(())
+    }
+
+    pub(crate) fn aggregated(&mut self, line: u64, hash: u64, trx: &Trx) -> eyre::Result<()> {
+        let row = match trx {
+            Trx::Adjustment(a) => Row {
+                class: Some("Adjustment"),
+                kind: Some(self.redact.field("type", &a.kind)),
+                description: Some(self.redact.field("description", &a.description)),
+                ..row(&self.file, line, hash)
+            },
+            Trx::WithSku(s) => Row {
+                class: Some("WithSku"),
+                kind: Some(self.redact.field("type", &s.kind)),
+                sku: Some(self.redact.field("sku", &s.sku)),
+                description: Some(self.redact.field("description", &s.description)),
+                unit: Some(s.cents as f64 / 100.0),
+                ..row(&self.file, line, hash)
+            },
+        };
+        self.wtr.serialize(row)?;
+        Ok(())
+    }
+
+    pub(crate) fn flush(&mut self) -> eyre::Result<()> {
+        self.wtr.flush()?;
+        Ok(())
+    }
+}
+
+fn row(file: &str, line: u64, hash: u64) -> Row<'_> {
+    Row {
+        file,
+        line,
+        hash: format!("{hash:016x}"),
+        duplicate: false,
+        class: None,
+        kind: None,
+        sku: None,
+        description: None,
+        unit: None,
+    }
+}
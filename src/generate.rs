@@ -0,0 +1,238 @@
+//! Synthetic transaction reports, shaped like the ones Amazon exports, for
+//! benchmarking and for trying dedupy out without real data.
+
+use std::io::Write;
+
+use chrono::{Duration, NaiveDate};
+
+use crate::Cents;
+
+/// Lines Amazon writes above the header.
+const PREAMBLE: [&str; 7] = [
+    "Includes Amazon Marketplace, Fulfillment by Amazon (FBA), and Amazon Webstore transactions",
+    "All amounts in USD, unless specified",
+    "Definitions:",
+    "Sales tax collected: Includes sales tax collected from buyers for product sales, shipping, \
+     and gift wrap.",
+    "Selling fees: Includes variable closing fees and referral fees.",
+    "Other transaction fees: Includes sales tax collection fees.",
+    "Other: Includes non-order transaction amounts. For more details, see the \"Type\" and \
+     \"Description\" columns for each order ID.",
+];
+
+const HEADER: [&str; 30] = [
+    "date/time",
+    "settlement id",
+    "type",
+    "order id",
+    "sku",
+    "description",
+    "quantity",
+    "marketplace",
+    "account type",
+    "fulfillment",
+    "order city",
+    "order state",
+    "order postal",
+    "tax collection model",
+    "product sales",
+    "product sales tax",
+    "shipping credits",
+    "shipping credits tax",
+    "gift wrap credits",
+    "giftwrap credits tax",
+    "Regulatory Fee",
+    "Tax On Regulatory Fee",
+    "promotional rebates",
+    "promotional rebates tax",
+    "marketplace withheld tax",
+    "selling fees",
+    "fba fees",
+    "other transaction fees",
+    "other",
+    "total",
+];
+
+const CITIES: [(&str, &str, &str); 4] = [
+    ("SPRINGFIELD", "IL", "62701"),
+    ("DENVER", "CO", "80202"),
+    ("AUSTIN", "TX", "73301"),
+    ("PORTLAND", "OR", "97201"),
+];
+
+/// What to generate, see [`write()`].
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Transactions in the report, not counting the preamble or header.
+    pub rows: usize,
+    /// Distinct products sold.
+    pub skus: usize,
+    /// The same seed always generates the same report.
+    pub seed: u64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            rows: 10_000,
+            skus: 200,
+            seed: 0,
+        }
+    }
+}
+
+/// SplitMix64, which is plenty for made up transactions.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`.
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n.max(1)
+    }
+}
+
+fn money(cents: Cents) -> String {
+    let sign = if cents < 0 { "-" } else { "" };
+    format!("{sign}{}.{:02}", cents.abs() / 100, cents.abs() % 100)
+}
+
+/// Writes a report of `options.rows` transactions to `w`: mostly orders, with
+/// refunds, reimbursements, fees, and transfers mixed in.
+pub fn write(options: &Options, w: impl Write) -> eyre::Result<()> {
+    let mut rng = Rng(options.seed);
+    let skus = options.skus.max(1);
+    let prices = (0..skus)
+        .map(|_| 500 + rng.below(9_500) as Cents)
+        .collect::<Vec<_>>();
+
+    let mut wtr = csv::WriterBuilder::new()
+        .flexible(true)
+        .quote_style(csv::QuoteStyle::Always)
+        .from_writer(w);
+    for line in PREAMBLE {
+        wtr.write_record([line])?;
+    }
+    wtr.write_record(HEADER)?;
+
+    let start = NaiveDate::from_ymd_opt(2024, 3, 1)
+        .expect("valid date")
+        .and_hms_opt(0, 0, 0)
+        .expect("valid time");
+    let mut time = start;
+    for i in 0..options.rows {
+        time += Duration::seconds(1 + rng.below(120) as i64);
+        let product = rng.below(skus as u64) as usize;
+        let sku = format!("SKU-{product:05}");
+        let description = format!("Product {product}, assorted");
+        let quantity = 1 + rng.below(3) as i64;
+        let (city, state, postal) = CITIES[rng.below(CITIES.len() as u64) as usize];
+
+        // Amounts in the order they appear in the header, from product
+        // sales to other.
+        let mut amounts: [Cents; 15] = [0; 15];
+        let (kind, sku, description, quantity) = match rng.below(100) {
+            0..=74 => {
+                let sales = prices[product] * quantity;
+                amounts[0] = sales;
+                amounts[11] = -sales * 15 / 100;
+                amounts[12] = -322 * quantity;
+                ("Order", sku, description, quantity)
+            }
+            75..=86 => {
+                let sales = prices[product] * quantity;
+                amounts[0] = -sales;
+                amounts[11] = sales * 12 / 100;
+                ("Refund", sku, description, quantity)
+            }
+            87..=90 => {
+                amounts[14] = prices[product];
+                let description = "FBA Inventory Reimbursement - Customer Return".to_string();
+                ("Adjustment", sku, description, 1)
+            }
+            91..=95 => {
+                amounts[14] = -(100 + rng.below(5_000) as Cents);
+                let description = "Cost of Advertising".to_string();
+                ("Service Fee", String::new(), description, 0)
+            }
+            96..=98 => {
+                amounts[14] = -(50 + rng.below(2_000) as Cents);
+                let description = "FBA Long-Term Storage Fee".to_string();
+                ("FBA Inventory Fee", String::new(), description, 0)
+            }
+            _ => {
+                amounts[14] = -(10_000 + rng.below(500_000) as Cents);
+                let description = "To account ending in: 123".to_string();
+                ("Transfer", String::new(), description, 0)
+            }
+        };
+        let total = amounts.iter().sum::<Cents>();
+        let order = if sku.is_empty() {
+            String::new()
+        } else {
+            format!("111-{:07}-{:07}", i / 10_000_000, i % 10_000_000)
+        };
+
+        let mut record = vec![
+            time.format("%b %-d, %Y %-I:%M:%S %p PST").to_string(),
+            "18000000001".to_string(),
+            kind.to_string(),
+            order,
+            sku,
+            description,
+            if quantity == 0 {
+                String::new()
+            } else {
+                quantity.to_string()
+            },
+            "amazon.com".to_string(),
+            "Standard Orders".to_string(),
+            "Amazon".to_string(),
+            city.to_string(),
+            state.to_string(),
+            postal.to_string(),
+            "MarketplaceFacilitator".to_string(),
+        ];
+        record.extend(amounts.iter().map(|&cents| money(cents)));
+        record.push(money(total));
+        wtr.write_record(&record)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Config, Report};
+
+    #[test]
+    fn generated_reports_are_valid() {
+        let options = Options {
+            rows: 1_000,
+            ..Options::default()
+        };
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("report.csv");
+        write(&options, std::fs::File::create(&path).unwrap()).unwrap();
+
+        let validation = Report::validate(&path, &Config::default()).unwrap();
+        assert_eq!(validation.rows, 1_000);
+        assert!(validation.problems.is_empty(), "{:?}", validation.problems);
+
+        let mut again = Vec::new();
+        write(&options, &mut again).unwrap();
+        assert_eq!(
+            std::fs::read(&path).unwrap(),
+            again,
+            "same seed, same report"
+        );
+    }
+}
@@ -0,0 +1,118 @@
+//! Callbacks into a run, for side effects such as logging every transaction
+//! to another system, or leaving some out, see [`Report::parse_with_hooks`].
+//!
+//! [`Report::parse_with_hooks`]: crate::Report::parse_with_hooks
+
+use crate::{Cents, Trx};
+
+/// Called by a run as it goes. Every method does nothing by default.
+///
+/// Rows are passed in the order they appear in the report. Rows that cannot
+/// be parsed are not passed: they fail the run, unless memory already has
+/// them.
+pub trait Hooks {
+    /// Called for every row before it is looked up in memory. Returning
+    /// `false` drops the row, so it is neither aggregated nor remembered.
+    fn row(&mut self, row: &Row<'_>) -> bool {
+        let _ = row;
+        true
+    }
+
+    /// Called for every row skipped because a previous run aggregated it.
+    fn duplicate(&mut self, row: &Row<'_>) {
+        let _ = row;
+    }
+
+    /// Called for every row of the output, in the order they are written.
+    fn aggregate(&mut self, aggregate: &Aggregate<'_>) {
+        let _ = aggregate;
+    }
+}
+
+/// No hooks, as used by [`Report::parse`](crate::Report::parse).
+impl Hooks for () {}
+
+/// A row of a report, as it was parsed.
+#[derive(Debug, Clone, Copy)]
+pub struct Row<'a> {
+    /// Line of the report the row was read from, starting at 1.
+    pub line: u64,
+    pub kind: &'a str,
+    /// Missing for adjustments.
+    pub sku: Option<&'a str>,
+    pub description: &'a str,
+    pub quantity: i64,
+    pub cents: Cents,
+}
+
+impl<'a> Row<'a> {
+    pub(crate) fn new(line: u64, trx: &'a Trx, quantity: i64, cents: Cents) -> Self {
+        let (kind, sku, description) = match trx {
+            Trx::Adjustment(a) => (&*a.kind, None, &*a.description),
+            Trx::WithSku(s) => (&*s.kind, Some(&*s.sku), &*s.description),
+        };
+        Self {
+            line,
+            kind,
+            sku,
+            description,
+            quantity,
+            cents,
+        }
+    }
+}
+
+/// A row of the output.
+#[derive(Debug, Clone, Copy)]
+pub struct Aggregate<'a> {
+    pub kind: &'a str,
+    pub sku: &'a str,
+    pub description: &'a str,
+    pub quantity: i64,
+    pub cents: Cents,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{aggregate, Config, Memories};
+
+    #[derive(Default)]
+    struct DropTransfers {
+        rows: Vec<u64>,
+        aggregates: Vec<String>,
+    }
+
+    impl Hooks for DropTransfers {
+        fn row(&mut self, row: &Row<'_>) -> bool {
+            self.rows.push(row.line);
+            row.kind != "Transfer"
+        }
+
+        fn aggregate(&mut self, aggregate: &Aggregate<'_>) {
+            self.aggregates.push(aggregate.kind.to_string());
+        }
+    }
+
+    #[test]
+    fn drop_rows() {
+        let report = b"type,sku,description,quantity,total\n\
+            Order,A,Widget,1,5.00\n\
+            Transfer,,To account,,-5.00\n";
+        let mut hooks = DropTransfers::default();
+        let aggregation = aggregate(
+            &report[..],
+            &Config::default(),
+            &mut Memories::default(),
+            None,
+            None,
+            &mut hooks,
+            None,
+        )
+        .unwrap();
+        assert_eq!(hooks.rows, [2, 3]);
+        assert_eq!(hooks.aggregates, ["Order"]);
+        assert_eq!(aggregation.totals()["Order"], 500);
+        assert_eq!(aggregation.sales.len(), 1);
+    }
+}
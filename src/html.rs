@@ -0,0 +1,142 @@
+//! Writing the output as a standalone HTML page, for whoever never opens a
+//! spreadsheet, such as when it is attached to an email.
+//!
+//! The page has a chart of the total of every transaction type, the totals
+//! themselves, and the sales in a table that sorts by a column when its
+//! header is clicked. Styles, script, and chart are all in the page, so it
+//! shows the same wherever it is opened, offline too.
+
+use crate::{ods::escape, Aggregation, Cents};
+
+const STYLE: &str = "
+body { font-family: system-ui, sans-serif; margin: 2rem; color: #222; }
+table { border-collapse: collapse; margin-bottom: 2rem; }
+th, td { padding: 0.25rem 0.75rem; border-bottom: 1px solid #ddd; text-align: left; }
+th { cursor: pointer; user-select: none; background: #f4f4f4; }
+td.number, th.number { text-align: right; font-variant-numeric: tabular-nums; }
+tfoot td { font-weight: bold; }
+.negative { color: #b00020; }
+";
+
+/// Sorts the sales by the column whose header is clicked, descending when
+/// clicked again.
+const SCRIPT: &str = "
+document.querySelectorAll('#sales th').forEach((th, column) => {
+  th.addEventListener('click', () => {
+    const tbody = document.querySelector('#sales tbody');
+    const descending = th.dataset.order === 'asc';
+    th.parentNode.querySelectorAll('th').forEach(other => delete other.dataset.order);
+    th.dataset.order = descending ? 'desc' : 'asc';
+    const key = row => {
+      const cell = row.children[column];
+      return cell.dataset.value === undefined ? cell.textContent : Number(cell.dataset.value);
+    };
+    const rows = Array.from(tbody.rows).sort((a, b) => {
+      const [x, y] = [key(a), key(b)];
+      const order = typeof x === 'number' ? x - y : x.localeCompare(y);
+      return descending ? -order : order;
+    });
+    tbody.append(...rows);
+  });
+});
+";
+
+/// Width of the bar of the largest total, in pixels.
+const BAR: i64 = 400;
+
+/// Writes the sales of `aggregation` as a page titled `title`, see the
+/// [module](self).
+pub(crate) fn render(aggregation: &Aggregation, title: &str) -> eyre::Result<Vec<u8>> {
+    let title = escape(title);
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>{title}</title>\n<style>{STYLE}</style>\n</head>\n<body>\n<h1>{title}</h1>\n"
+    );
+    let period = aggregation.period.to_string();
+    html += &format!(
+        "<p>{} rows read, {} skipped as duplicates, {} aggregates{}.</p>\n",
+        aggregation.rows,
+        aggregation.duplicates,
+        aggregation.sales.len(),
+        match period.is_empty() {
+            true => String::new(),
+            false => format!(", dated {period}"),
+        }
+    );
+
+    let totals = aggregation.totals();
+    html += "<h2>Totals</h2>\n";
+    html += &chart(&totals);
+    html +=
+        "<table>\n<thead><tr><th>Type</th><th class=\"number\">Total</th></tr></thead>\n<tbody>\n";
+    for (kind, cents) in &totals {
+        html += &format!("<tr><td>{}</td>{}</tr>\n", escape(kind), money(*cents));
+    }
+    html += &format!(
+        "</tbody>\n<tfoot><tr><td>Total</td>{}</tr></tfoot>\n</table>\n",
+        money(totals.values().sum())
+    );
+
+    html += "<h2>Sales</h2>\n<table id=\"sales\">\n<thead><tr><th>Type</th><th>SKU</th>\
+             <th>Description</th><th class=\"number\">Quantity</th>\
+             <th class=\"number\">Total</th></tr></thead>\n<tbody>\n";
+    for sale in &aggregation.sales {
+        html += &format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td>\
+             <td class=\"number\" data-value=\"{quantity}\">{quantity}</td>{}</tr>\n",
+            escape(&sale.kind),
+            escape(&sale.sku),
+            escape(&sale.description),
+            money(sale.cents),
+            quantity = sale.quantity,
+        );
+    }
+    html += &format!("</tbody>\n</table>\n<script>{SCRIPT}</script>\n</body>\n</html>\n");
+    Ok(html.into_bytes())
+}
+
+/// A cell of an amount, sorted by its value.
+fn money(cents: Cents) -> String {
+    let class = match cents < 0 {
+        true => "number negative",
+        false => "number",
+    };
+    format!(
+        "<td class=\"{class}\" data-value=\"{cents}\">{:.2}</td>",
+        cents as f64 / 100.0
+    )
+}
+
+/// A bar per type, as long as its total is against the largest, either way.
+fn chart(totals: &std::collections::BTreeMap<String, Cents>) -> String {
+    let largest = totals.values().map(|cents| cents.abs()).max().unwrap_or(0);
+    if largest == 0 {
+        return String::new();
+    }
+    const ROW: usize = 24;
+    let label = 180;
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+         role=\"img\" aria-label=\"Total per type\">\n",
+        label + BAR + 100,
+        totals.len() * ROW
+    );
+    for (row, (kind, cents)) in totals.iter().enumerate() {
+        let y = row * ROW;
+        let width = (cents.abs() * BAR / largest).max(1);
+        let fill = if *cents < 0 { "#b00020" } else { "#2e7d32" };
+        svg += &format!(
+            "<text x=\"0\" y=\"{}\" font-size=\"13\">{}</text>\
+             <rect x=\"{label}\" y=\"{}\" width=\"{width}\" height=\"{}\" fill=\"{fill}\"/>\
+             <text x=\"{}\" y=\"{}\" font-size=\"13\">{:.2}</text>\n",
+            y + 16,
+            escape(kind),
+            y + 4,
+            ROW - 8,
+            label + width + 6,
+            y + 16,
+            *cents as f64 / 100.0,
+        );
+    }
+    svg + "</svg>\n"
+}
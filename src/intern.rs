@@ -0,0 +1,35 @@
+//! Sharing the strings that repeat across the rows of a report, such as the
+//! transaction type, so that each is allocated once instead of per row.
+
+use std::{collections::HashSet, sync::Arc};
+
+#[derive(Debug, Default)]
+pub(crate) struct Interner {
+    strings: HashSet<Arc<str>>,
+}
+
+impl Interner {
+    /// Returns the shared copy of `s`, allocating it the first time.
+    pub(crate) fn intern(&mut self, s: &str) -> Arc<str> {
+        if let Some(shared) = self.strings.get(s) {
+            return Arc::clone(shared);
+        }
+        let shared = Arc::<str>::from(s);
+        self.strings.insert(Arc::clone(&shared));
+        shared
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn shares_equal_strings() {
+        let mut interner = Interner::default();
+        let a = interner.intern("Order");
+        let b = interner.intern(&String::from("Order"));
+        assert!(Arc::ptr_eq(&a, &b));
+        assert!(!Arc::ptr_eq(&a, &interner.intern("Refund")));
+    }
+}
@@ -0,0 +1,167 @@
+//! Balanced journal entries per settlement, see `journal_entries`.
+//!
+//! Rows are totalled per settlement and account, see [`crate::accounts`],
+//! from their own totals rather than the aggregates, which are rounded to a
+//! unit price. Each entry credits accounts with a positive total, such as
+//! revenue, debits those with a negative one, such as fees and refunds, and
+//! is balanced by a line for the account the payout is deposited in.
+
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::{accounts, Cents, Config, EntryColumn, JournalEntries};
+
+/// Totals of every settlement of a report.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub(crate) struct Ledger(BTreeMap<String, Settlement>);
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct Settlement {
+    /// Day of the last row, which the entry is dated on.
+    date: Option<NaiveDate>,
+    /// Name and total of every account, by code.
+    accounts: BTreeMap<String, (String, Cents)>,
+}
+
+impl Ledger {
+    /// Adds a row of `settlement` to the account it is totalled under.
+    pub(crate) fn add(
+        &mut self,
+        config: &Config,
+        settlement: &str,
+        date: Option<NaiveDate>,
+        kind: &str,
+        description: &str,
+        cents: Cents,
+    ) {
+        let (code, name) = accounts::account(config, kind, description);
+        if !self.0.contains_key(settlement) {
+            self.0.insert(settlement.to_string(), Settlement::default());
+        }
+        let settlement = self.0.get_mut(settlement).unwrap();
+        settlement.date = settlement.date.max(date);
+        let account = settlement.accounts.entry(code.to_string());
+        account.or_insert_with(|| (name.to_string(), 0)).1 += cents;
+    }
+
+    /// Net total of every settlement with an id.
+    pub(crate) fn payouts(&self) -> Vec<crate::reconcile::Payout> {
+        (self.0.iter())
+            .filter(|(id, _)| !id.is_empty())
+            .map(|(id, settlement)| crate::reconcile::Payout {
+                settlement: id.clone(),
+                date: settlement.date,
+                cents: settlement.accounts.values().map(|(_, cents)| cents).sum(),
+            })
+            .collect()
+    }
+
+    /// Writes an entry per settlement to `path`, laid out as `layout` says.
+    pub(crate) fn write(&self, layout: &JournalEntries, path: &str) -> eyre::Result<()> {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+        let delimiter = u8::try_from(layout.delimiter)
+            .ok()
+            .filter(u8::is_ascii)
+            .ok_or_else(|| eyre::eyre!("`journal_entries.delimiter` must be ASCII"))?;
+        let mut wtr = csv::WriterBuilder::new()
+            .delimiter(delimiter)
+            .from_path(path)?;
+        let headers = match layout.headers.is_empty() {
+            true => layout
+                .columns
+                .iter()
+                .map(|c| c.name().to_string())
+                .collect(),
+            false => layout.headers.clone(),
+        };
+        wtr.write_record(headers)?;
+        for (id, settlement) in &self.0 {
+            let date = settlement
+                .date
+                .map(|date| date.format(&layout.date_format).to_string())
+                .unwrap_or_default();
+            let memo = match id.is_empty() {
+                true => String::new(),
+                false => format!("Settlement {id}"),
+            };
+            let net = settlement.accounts.values().map(|(_, cents)| cents).sum();
+            let mut accounts = settlement.accounts.iter().collect::<Vec<_>>();
+            // Unmapped rows, whose code is empty, last like in `accounts`.
+            accounts.sort_by_key(|(code, _)| code.is_empty());
+            let lines = accounts
+                .into_iter()
+                .map(|(code, (name, cents))| (code.as_str(), name.as_str(), -cents))
+                .chain([(&*layout.balancing_code, &*layout.balancing_name, net)])
+                .filter(|(_, _, debit)| *debit != 0);
+            for (code, name, debit) in lines {
+                let money = |cents: Cents| format!("{:.2}", cents as f64 / 100.0);
+                let record = layout.columns.iter().map(|column| match column {
+                    EntryColumn::Date => date.clone(),
+                    EntryColumn::Settlement => id.clone(),
+                    EntryColumn::Code => code.to_string(),
+                    EntryColumn::Name => name.to_string(),
+                    EntryColumn::Debit if debit > 0 => money(debit),
+                    EntryColumn::Credit if debit < 0 => money(-debit),
+                    EntryColumn::Debit | EntryColumn::Credit => String::new(),
+                    EntryColumn::Amount => money(debit),
+                    EntryColumn::Memo => memo.clone(),
+                });
+                wtr.write_record(record)?;
+            }
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn balances() {
+        let config: Config = toml::from_str(
+            r#"
+            [[accounts]]
+            type = "Order"
+            code = "4000"
+            name = "Sales"
+            [[accounts]]
+            type = "Service Fee"
+            code = "6100"
+            name = "Selling fees"
+            [journal_entries]
+            balancing_code = "1200"
+            balancing_name = "Amazon clearing"
+            columns = ["settlement", "code", "debit", "credit", "amount"]
+            "#,
+        )
+        .unwrap();
+        let day = NaiveDate::from_ymd_opt(2024, 1, 5);
+        let mut ledger = Ledger::default();
+        ledger.add(&config, "1", day, "Order", "Widget", 1_000);
+        ledger.add(&config, "1", day, "Order", "Widget", 500);
+        ledger.add(&config, "1", None, "Service Fee", "Advertising", -150);
+        ledger.add(&config, "2", day, "Transfer", "To account", -1_350);
+
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("journal.csv");
+        let path = path.to_str().unwrap();
+        ledger
+            .write(config.journal_entries.as_ref().unwrap(), path)
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            "settlement,code,debit,credit,amount\n\
+             1,4000,,15.00,-15.00\n\
+             1,6100,1.50,,1.50\n\
+             1,1200,13.50,,13.50\n\
+             2,,13.50,,13.50\n\
+             2,1200,,13.50,-13.50\n"
+        );
+    }
+}
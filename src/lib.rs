@@ -3,121 +3,110 @@
 #![feature(fs_try_exists)]
 
 use std::{
-    collections::{HashMap, HashSet},
-    io::Write,
-    path::Path,
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs::File,
+    hash::Hasher as _,
+    path::{Path, PathBuf},
+    sync::Arc,
 };
 
-use eyre::bail;
-use rust_xlsxwriter::Workbook;
-use seahash::hash;
+use csv::StringRecord;
+use eyre::{bail, WrapErr as _};
+use seahash::SeaHasher;
 use serde::{ser::SerializeStruct as _, Deserialize, Serialize};
 
-/// A set of hashes of transactions that have already been written to disk.
-#[derive(Debug)]
-struct Memory {
-    set: HashSet<u64>,
-    side_set: HashSet<u64>,
-    diff: HashSet<String>,
-    path: &'static str,
-}
-
-impl Memory {
-    fn write_difference(&self, path: &str) -> eyre::Result<()> {
-        if !self.diff.is_empty() {
-            let mut file = std::fs::OpenOptions::new()
-                .create(true)
-                .write(true)
-                .open(path)?;
-            let mut temp = self.diff.iter().collect::<Vec<_>>();
-            temp.sort();
-            for item in temp {
-                writeln!(file, "{}", item)?;
-            }
-        }
-        Ok(())
-    }
-
-    fn memorize<S>(&mut self, s: S) -> bool
-    where
-        S: AsRef<str>,
-    {
-        let hash = hash(s.as_ref().as_bytes());
-        if self.set.contains(&hash) {
-            false
-        } else {
-            self.side_set.insert(hash);
-            self.diff.insert(s.as_ref().to_string());
-            true
-        }
-    }
-
-    /// Returns a new [`Memory`] instance.
-    fn new(path: &'static str) -> eyre::Result<Self> {
-        let side_set = HashSet::<u64>::default();
-        let set = if matches!(std::fs::try_exists(path), Ok(false)) {
-            Ok(HashSet::default())
-        } else {
-            csv::Reader::from_path(path).and_then(|mut val| {
-                val.deserialize::<u64>()
-                    .collect::<Result<HashSet<u64>, _>>()
-            })
-        }?;
-        Ok(Self {
-            path,
-            side_set,
-            set,
-            diff: HashSet::default(),
-        })
-    }
+mod accounts;
+mod alerts;
+mod aliases;
+pub mod analytics;
+pub mod audit;
+pub mod batch;
+mod bundle;
+mod cancel;
+pub mod checkpoint;
+pub mod closing;
+mod config;
+mod crypt;
+pub mod diff;
+#[cfg(feature = "duckdb")]
+mod duckdb;
+#[cfg(feature = "email")]
+mod email;
+mod explain;
+pub mod generate;
+pub mod hooks;
+mod html;
+mod intern;
+mod ledger;
+mod lossy;
+mod marketplace;
+pub mod memory;
+mod normalize;
+mod ods;
+mod pdf;
+mod plugin;
+#[cfg(feature = "postgres")]
+mod postgres;
+pub mod reconcile;
+mod redact;
+#[cfg(feature = "remote")]
+pub mod remote;
+mod script;
+mod sheet;
+mod tax;
+mod untrusted;
+#[cfg(feature = "upload")]
+mod upload;
+#[cfg(feature = "wasm")]
+mod wasm;
+#[cfg(feature = "webhook")]
+mod webhook;
 
-    fn write(self) -> eyre::Result<()> {
-        let mut wtr = csv::Writer::from_path(self.path)?;
-        for v in &self.set {
-            wtr.serialize(v)?;
-        }
-        for v in &self.side_set {
-            wtr.serialize(v)?;
-        }
-        wtr.flush()?;
-        Ok(())
-    }
-}
+use aliases::Aliases;
+pub use cancel::{Cancel, Cancelled};
+pub use config::{
+    Account, AdjustmentQuantity, AdjustmentSku, Alerts, Config, Dedup, Deposits, DescriptionRule,
+    Email, Encryption, EntryColumn, JournalEntries, Log, Profile, ProfileColumns, Quantity,
+    QuantityDecimals, Remote, Rounding, ShortRows, SmtpSecurity, SortColumn, SortKey, Suspect,
+    TruncatedReports, UnknownTypes, Upload, UploadService, Webhook, WebhookFormat,
+};
+pub use hooks::Hooks;
+use intern::Interner;
+use lossy::Lossy;
+pub use marketplace::Marketplace;
+pub use memory::HashAlgorithm;
+use memory::Memory;
+pub use normalize::Normalize;
+use plugin::Plugins;
+use redact::Redact;
+use script::Script;
+pub use tax::TaxTotals;
+use tax::{TaxColumns, Taxes};
+pub use untrusted::{Limits, LossyParse};
 
 /// A reference to a transaction from the input CSV.
 #[derive(Deserialize, Serialize, Debug)]
 struct RefSale<'a> {
     #[serde(alias = "type", default)]
-    kind: String,
-    sku: Option<String>,
+    kind: &'a str,
+    sku: Option<&'a str>,
     total: &'a str,
-    #[serde(default, deserialize_with = "deserialize_quantity")]
-    quantity: i64,
-    description: String,
-}
-
-fn deserialize_quantity<'de, D>(deserializer: D) -> Result<i64, D::Error>
-where
-    D: serde::Deserializer<'de>,
-{
-    let s = String::deserialize(deserializer)?;
-    if s.is_empty() {
-        Ok(0)
-    } else {
-        s.parse::<i64>().map_err(serde::de::Error::custom)
-    }
+    /// As written, see [`config::Quantity::read`].
+    #[serde(default)]
+    quantity: &'a str,
+    description: &'a str,
 }
 
 /// An owned transaction.
 // These fields are in the order that they were specified in the original
 // email. I do not know if they are read by index or by header. I guess
 // this is the safest way to do it.
-#[derive(Debug, Deserialize, Hash, Eq, PartialEq, PartialOrd, Ord, Default)]
+#[derive(Debug, Clone, Hash, Eq, PartialEq, PartialOrd, Ord, Default)]
 struct Sale {
-    kind: String,
-    sku: String,
-    description: String,
-    #[serde(default)]
+    kind: Arc<str>,
+    sku: Arc<str>,
+    description: Arc<str>,
     quantity: i64,
     cents: i64,
 }
@@ -129,9 +118,9 @@ impl Serialize for Sale {
     {
         let mut s = serializer.serialize_struct("Sale", 5)?;
         // This will not panic since we derived it from an f64
-        s.serialize_field("Type", &self.kind)?;
-        s.serialize_field("SKU", &self.sku)?;
-        s.serialize_field("Description", &self.description)?;
+        s.serialize_field("Type", &*self.kind)?;
+        s.serialize_field("SKU", &*self.sku)?;
+        s.serialize_field("Description", &*self.description)?;
         s.serialize_field("Quantity", &self.quantity)?;
         s.serialize_field("Total", &(self.cents as f64 / 100.0))?;
         s.end()
@@ -139,29 +128,429 @@ impl Serialize for Sale {
 }
 
 impl Sale {
-    fn new(t: Trx, i: i64) -> Self {
-        match t {
-            Trx::Adjustment(a) => Self {
-                kind: a.kind,
-                sku: "FBATF".to_string(),
-                description: a.description,
-                quantity: if i < 0 { -1 } else { 1 },
-                cents: i,
-            },
-            Trx::WithSku(WithSku {
-                kind,
-                sku,
-                cents,
-                description,
-            }) => Self {
-                kind,
-                sku,
-                description,
-                quantity: i,
-                cents: cents * i,
+    fn adjustment(a: Adjustment, cents: Cents, quantity: i64, config: &Config) -> Self {
+        Self {
+            sku: Arc::from(config.adjustment_sku(&a.kind, &a.description)),
+            kind: a.kind,
+            description: a.description,
+            quantity: match config.quantity.adjustments {
+                AdjustmentQuantity::Sign if cents < 0 => -1,
+                AdjustmentQuantity::Sign => 1,
+                AdjustmentQuantity::Keep => quantity,
             },
+            cents,
+        }
+    }
+
+    /// Orders `self` and `other` by the first of `keys` they differ in, then
+    /// by every field in order, so that sales are always written in the same
+    /// order however they were aggregated.
+    fn compare(&self, other: &Self, keys: &[SortKey]) -> std::cmp::Ordering {
+        keys.iter()
+            .map(|key| {
+                let ordering = match key.column {
+                    SortColumn::Type => self.kind.cmp(&other.kind),
+                    SortColumn::Sku => self.sku.cmp(&other.sku),
+                    SortColumn::Description => self.description.cmp(&other.description),
+                    SortColumn::Quantity => self.quantity.cmp(&other.quantity),
+                    SortColumn::Total => self.cents.cmp(&other.cents),
+                };
+                match key.descending {
+                    true => ordering.reverse(),
+                    false => ordering,
+                }
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| self.cmp(other))
+    }
+
+    fn with_sku(s: WithSku, quantity: i64) -> Self {
+        Self {
+            kind: s.kind,
+            sku: s.sku,
+            description: s.description,
+            quantity,
+            cents: s.cents * quantity,
+        }
+    }
+}
+
+/// A report that was read during a run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Input {
+    pub path: PathBuf,
+    /// Hex encoded seahash of the file contents.
+    pub checksum: String,
+}
+
+impl Input {
+    fn new(path: &Path) -> eyre::Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            checksum: checksum(File::open(path)?)?,
+        })
+    }
+
+    /// An input that was read into `contents` rather than from a file, such
+    /// as stdin, recorded as `path`.
+    fn from_contents(path: &Path, contents: &[u8]) -> eyre::Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            checksum: checksum(contents)?,
+        })
+    }
+
+    /// Copies the input into the archive directory, if one is configured,
+    /// from `contents` if it was not read from a file.
+    fn archive(&self, config: &Config, contents: Option<&[u8]>) -> eyre::Result<()> {
+        if let Some(dir) = &config.archive {
+            let path = dir.join(self.archive_name());
+            if !path.try_exists()? {
+                std::fs::create_dir_all(dir)?;
+                match contents {
+                    Some(contents) => std::fs::write(path, contents)?,
+                    None => {
+                        std::fs::copy(&self.path, path)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn archive_name(&self) -> String {
+        format!("{}.csv", self.checksum)
+    }
+
+    /// Whether `archive` already has a copy of the input.
+    fn is_archived(&self, config: &Config) -> eyre::Result<bool> {
+        match &config.archive {
+            Some(dir) => Ok(dir.join(self.archive_name()).try_exists()?),
+            None => Ok(false),
+        }
+    }
+
+    /// Finds a file with the same contents as this input, preferring the
+    /// archived copy over the original path.
+    pub fn locate(&self, config: &Config) -> eyre::Result<Option<PathBuf>> {
+        let archived = config
+            .archive
+            .as_ref()
+            .map(|dir| dir.join(self.archive_name()));
+        for candidate in archived.into_iter().chain([self.path.clone()]) {
+            match File::open(&candidate).and_then(checksum) {
+                Ok(checksum) if checksum == self.checksum => return Ok(Some(candidate)),
+                Ok(_) => continue,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Hashes everything read from `input` a chunk at a time.
+fn checksum(mut input: impl std::io::Read) -> std::io::Result<String> {
+    let mut hasher = SeaHasher::new();
+    let mut chunk = vec![0; 64 * 1024];
+    loop {
+        match input.read(&mut chunk)? {
+            0 => break,
+            n => hasher.write(&chunk[..n]),
+        }
+    }
+    Ok(format!("{:016x}", hasher.finish()))
+}
+
+/// What a run read and wrote.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Summary {
+    pub inputs: Vec<Input>,
+    pub output: PathBuf,
+    /// Data rows read, not counting the preamble or header.
+    pub rows: u64,
+    /// Rows skipped because a previous run already aggregated them.
+    pub duplicates: u64,
+    /// Net amount the `duplicates` would have added to the output, per
+    /// transaction type, so that a replay can leave them out too.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub duplicate_totals: BTreeMap<String, Cents>,
+    /// Rows that were aggregated, but look like a row seen before apart from
+    /// formatting or time.
+    #[serde(default)]
+    pub near_duplicates: u64,
+    /// Rows left out of the output for review, see `quarantine`.
+    #[serde(default)]
+    pub quarantined: u64,
+    /// Rows written to the output.
+    pub aggregates: u64,
+    /// First and last day of the rows written to the output, if any had a
+    /// date.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub period: Option<(chrono::NaiveDate, chrono::NaiveDate)>,
+    /// Hex encoded seahash of the output, like the checksum of an [`Input`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    /// Net amount of the output in cents, per transaction type.
+    #[serde(default)]
+    pub totals: BTreeMap<String, Cents>,
+    /// Currency codes written next to the totals of aggregated rows, such as
+    /// `EUR` in `12.34 EUR`. Empty if the report wrote none.
+    #[serde(default)]
+    pub currencies: Vec<String>,
+    /// Top SKUs, ratios, and changes from the month before, if `analytics`
+    /// is set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub analytics: Option<analytics::Analytics>,
+    /// What about the report raised an alert, see `[alerts]`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alerts: Vec<String>,
+}
+
+impl Summary {
+    /// Net amount of the output in cents, across all transaction types.
+    pub fn total(&self) -> Cents {
+        self.totals.values().sum()
+    }
+}
+
+/// How a report of a batch fared, see [`Report::parse_batch`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Outcome {
+    pub file: PathBuf,
+    pub status: Status,
+    /// Data rows read, none if the report failed.
+    pub rows: u64,
+    pub duplicates: u64,
+    /// Where the output was written, if it was.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<PathBuf>,
+    /// Why the report failed, with every cause.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl From<&Summary> for Outcome {
+    fn from(summary: &Summary) -> Self {
+        Self {
+            file: (summary.inputs.first())
+                .map(|input| input.path.clone())
+                .unwrap_or_default(),
+            status: Status::Processed,
+            rows: summary.rows,
+            duplicates: summary.duplicates,
+            output: Some(summary.output.clone()),
+            error: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// Its output and memory were written.
+    Processed,
+    /// It could not be processed, see [`Outcome::error`].
+    Failed,
+    /// It was processed, but nothing was written as another report of the
+    /// batch failed.
+    RolledBack,
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Processed => "processed",
+            Self::Failed => "failed",
+            Self::RolledBack => "rolled back",
+        })
+    }
+}
+
+/// Context of the error of a batch in which a report failed, found with
+/// `downcast_ref` on the error of [`Report::parse_batch`]. The error it is
+/// the context of is that of the first report that failed.
+#[derive(Debug, Clone)]
+pub struct BatchFailed {
+    /// Every report of the batch, in order.
+    pub outcomes: Vec<Outcome>,
+}
+
+impl BatchFailed {
+    /// Records the batch in the audit log as a run that read and wrote
+    /// nothing, with the outcome of every report.
+    fn record(&self, config: &Config, now: chrono::DateTime<chrono::Local>) -> eyre::Result<()> {
+        let mut record = audit::Record::new(now, Summary::default());
+        record.outcomes = self.outcomes.clone();
+        record.append(config)?;
+        #[cfg(feature = "postgres")]
+        if let Some(url) = &config.postgres {
+            postgres::record(url, &record, &[])?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for BatchFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let failed = (self.outcomes.iter())
+            .filter(|outcome| outcome.status == Status::Failed)
+            .count();
+        write!(
+            f,
+            "{failed} of {} reports failed, so none was written",
+            self.outcomes.len()
+        )
+    }
+}
+
+/// A single row of a report, as it is read before aggregation.
+#[derive(Debug)]
+pub struct Transaction {
+    /// Line of the report the row was read from, starting at 1.
+    pub line: u64,
+    pub kind: String,
+    /// Missing for adjustments.
+    pub sku: Option<String>,
+    pub description: String,
+    pub quantity: i64,
+    pub cents: Cents,
+}
+
+/// Result of [`Report::validate`].
+#[derive(Debug, Default)]
+pub struct Validation {
+    /// Data rows read, not counting the preamble or header.
+    pub rows: u64,
+    pub problems: Vec<Problem>,
+}
+
+impl Validation {
+    fn problem(&mut self, line: u64, message: impl ToString) {
+        self.problems.push(Problem {
+            line,
+            message: message.to_string(),
+        });
+    }
+}
+
+/// How the columns of a report are read, see [`Report::schema`].
+#[derive(Debug)]
+pub struct Schema {
+    /// Marketplace or profile the report was recognized as.
+    pub source: String,
+    /// Line of the header, starting at 1.
+    pub line: u64,
+    /// Every column of the header, in order.
+    pub columns: Vec<SchemaColumn>,
+    /// Columns of an Amazon report that no column is read as, which are left
+    /// blank.
+    pub missing: Vec<&'static str>,
+    /// Whether whole rows are hashed to recognize them in memory, rather than
+    /// some of their columns.
+    pub whole_rows: bool,
+}
+
+/// A column of the header of a report.
+#[derive(Debug)]
+pub struct SchemaColumn {
+    /// Name of the column as written.
+    pub name: String,
+    /// What the column is read as, such as `total` or `dedup key`, or nothing
+    /// if it is unused.
+    pub uses: Vec<&'static str>,
+}
+
+/// Something that would stop a report from being processed.
+#[derive(Debug)]
+pub struct Problem {
+    /// Line of the report the problem was found on, starting at 1.
+    pub line: u64,
+    pub message: String,
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Context of an error caused by what a report contains, rather than by
+/// reading it or by the settings, found with `downcast_ref` on the error of
+/// [`Report::parse`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Malformed {
+    /// No row has `type` and `total` columns, nor is the header of another
+    /// marketplace's report, see [`Config::source`].
+    Header,
+    /// The row at this line cannot be read.
+    Line(u64),
+    /// The report looks partly downloaded, see [`TruncatedReports`].
+    Truncated,
+    /// The header is not the one the settings pin, see [`Config::header`].
+    /// The error this is the context of says how it changed.
+    HeaderChanged,
+}
+
+impl std::fmt::Display for Malformed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Header => write!(
+                f,
+                "no header row with `type` and `total` columns, or of another marketplace's \
+                 report, was found"
+            ),
+            Self::Line(line) => write!(f, "line {line}"),
+            Self::Truncated => write!(f, "the report looks partly downloaded"),
+            Self::HeaderChanged => write!(f, "the header is not the one in the settings"),
+        }
+    }
+}
+
+impl std::error::Error for Malformed {}
+
+/// Warns about every earlier run whose output covers part of `period`, as
+/// posting both into accounting may count some transactions twice.
+fn warn_exported(config: &Config, period: sheet::Period, checksum: &str) -> eyre::Result<()> {
+    let Some((first, last)) = period.days() else {
+        return Ok(());
+    };
+    for (id, record) in audit::Record::load(config)?.iter().enumerate() {
+        let summary = &record.summary;
+        let Some((from, to)) = summary.period else {
+            continue;
+        };
+        if summary.aggregates == 0 || to < first || from > last {
+            continue;
+        }
+        let output = summary.output.display();
+        if summary.checksum.as_deref() == Some(checksum) {
+            tracing::warn!(
+                "the output is identical to {output} of run {}, make sure it is not posted twice",
+                id + 1
+            );
+        } else {
+            tracing::warn!(
+                "the output covers {period}, and {output} of run {} already covered {}, make \
+                 sure no transaction is posted twice",
+                id + 1,
+                sheet::Period::from(summary.period)
+            );
         }
     }
+    Ok(())
+}
+
+/// A record read from a report, with errors other than failing to read the
+/// file marked [`Malformed`].
+fn read_record(record: csv::Result<StringRecord>) -> eyre::Result<StringRecord> {
+    record.map_err(|e| {
+        let line = e.position().map_or(0, |p| p.line());
+        match e.kind() {
+            csv::ErrorKind::Io(_) => e.into(),
+            _ => eyre::Error::from(e).wrap_err(Malformed::Line(line)),
+        }
+    })
 }
 
 /// Entry point for the library.
@@ -169,180 +558,2988 @@ pub struct Report;
 
 impl Report {
     /// Parse the report at the given path and write output to disk.
-    pub fn parse<P>(path: P) -> eyre::Result<()>
+    pub fn parse<P>(path: P, config: &Config) -> eyre::Result<Summary>
     where
         P: AsRef<Path> + std::fmt::Debug,
     {
-        // Cannot guarantee the file is utf8, if anything we know it's not.
-        let file = std::fs::read(&path)?;
-        let read = String::from_utf8_lossy(&file);
-
-        let mut rdr = csv::ReaderBuilder::new()
-            .has_headers(false)
-            .flexible(true)
-            .from_reader(read.as_bytes());
-
-        // The first 7 records of the report are trash.
-        let mut iter = rdr.records().skip(7);
-
-        let mut adjustmut_map = HashMap::<Adjustment, Cents>::new();
-        let mut with_sku_map = HashMap::<WithSku, Cents>::new();
-
-        let mut recmem = Memory::new("memory")?;
-        let mut skumem = Memory::new("sku_memory")?;
-
-        let hdr = iter.next().transpose()?;
-        for record in iter {
-            let r = &record?;
-            if recmem.memorize(r.as_slice()) {
-                let sale = r.deserialize::<RefSale>(hdr.as_ref())?;
-                let qt = sale.quantity;
-                let cents = handle_punct(sale.total)?;
-                match Trx::try_from(sale)? {
-                    Trx::Adjustment(a) => adjustmut_map
-                        .entry(a)
-                        .and_modify(|v| *v += cents)
-                        .or_insert(cents),
-                    Trx::WithSku(s) => {
-                        skumem.memorize(&s.sku);
-                        with_sku_map.entry(s).and_modify(|v| *v += qt).or_insert(qt)
-                    }
+        Self::parse_with_hooks(path, config, &mut ())
+    }
+
+    /// Like [`Report::parse`], calling `hooks` for every row and aggregate.
+    pub fn parse_with_hooks<P>(
+        path: P,
+        config: &Config,
+        hooks: &mut dyn Hooks,
+    ) -> eyre::Result<Summary>
+    where
+        P: AsRef<Path> + std::fmt::Debug,
+    {
+        Self::parse_with(
+            Source::Path(path.as_ref()),
+            Output::File(Format::default()),
+            config,
+            hooks,
+            &Cancel::default(),
+        )
+    }
+
+    /// Like [`Report::parse_with_hooks`], reading the report from `source`
+    /// and writing the output to `output`, for example to use dedupy in a
+    /// pipeline.
+    ///
+    /// The run stops with [`Cancelled`] if `cancel` is cancelled before its
+    /// output is written, leaving memory as it was, see [`Cancel`].
+    pub fn parse_with(
+        source: Source<'_>,
+        output: Output<'_>,
+        config: &Config,
+        hooks: &mut dyn Hooks,
+        cancel: &Cancel,
+    ) -> eyre::Result<Summary> {
+        let mut summaries = Self::parse_batch(vec![source], output, config, hooks, cancel)?;
+        Ok(summaries.remove(0))
+    }
+
+    /// Like [`Report::parse_with`] for every report of `sources`, all or
+    /// nothing: every report is aggregated before any output or memory is
+    /// written, so a report that fails leaves memory as it was and writes no
+    /// output for any of them. Each report is deduplicated against the ones
+    /// before it, as if they were processed one after the other.
+    ///
+    /// Outputs are named after the same time, followed by `-1`, `-2` and so
+    /// on when there are several reports. A [`Output::Writer`] takes a single
+    /// report. Checkpoints, see [`checkpoint`], are only written for a single
+    /// report, as a resumed run starts from the memory its checkpoint was
+    /// written against.
+    ///
+    /// When a report of several fails, the rest are still read to find every
+    /// report that fails, and the batch fails with [`BatchFailed`] once they
+    /// are, recorded in the audit log. A cancelled batch stops right away.
+    pub fn parse_batch(
+        sources: Vec<Source<'_>>,
+        output: Output<'_>,
+        config: &Config,
+        hooks: &mut dyn Hooks,
+        cancel: &Cancel,
+    ) -> eyre::Result<Vec<Summary>> {
+        if matches!(output, Output::Writer(..)) && sources.len() != 1 {
+            bail!("a writer takes the output of a single report");
+        }
+        // Checked first, so memory is not written without output.
+        let format = output.format();
+        let password = output_password(config, format)?;
+        #[cfg(not(feature = "email"))]
+        if config.email.is_some() {
+            bail!("`email` is set, but dedupy was built without the email feature");
+        }
+        #[cfg(not(feature = "webhook"))]
+        if !config.webhooks.is_empty() {
+            bail!("`webhooks` are set, but dedupy was built without the webhook feature");
+        }
+        #[cfg(not(feature = "duckdb"))]
+        if config.duckdb.is_some() {
+            bail!("`duckdb` is set, but dedupy was built without the duckdb feature");
+        }
+        #[cfg(not(feature = "upload"))]
+        if config.upload.is_some() {
+            bail!("`upload` is set, but dedupy was built without the upload feature");
+        }
+        let now = chrono::Local::now();
+        let mut memories = tracing::info_span!("read").in_scope(|| Memories::load(config))?;
+        let batch = sources.len() > 1;
+        let mut files = Vec::with_capacity(sources.len());
+        let mut results = Vec::with_capacity(sources.len());
+        for source in sources {
+            files.push(match &source {
+                Source::Path(path) => path.to_path_buf(),
+                Source::Reader(_) => PathBuf::from("-"),
+            });
+            match stage(
+                source,
+                format,
+                password.as_deref(),
+                config,
+                &mut memories,
+                !batch,
+                hooks,
+                cancel,
+                now,
+            ) {
+                Err(e) if !batch || e.downcast_ref::<Cancelled>().is_some() => return Err(e),
+                result => results.push(result),
+            }
+            // Later reports see what this one remembered as seen before.
+            memories.iter_mut().for_each(Memory::settle);
+        }
+        if let Some(first) = results.iter().position(Result::is_err) {
+            let file = files[first].display().to_string();
+            let outcomes = (files.into_iter().zip(&results))
+                .map(|(file, result)| match result {
+                    Ok(report) => Outcome {
+                        file,
+                        status: Status::RolledBack,
+                        rows: report.aggregation.rows,
+                        duplicates: report.aggregation.duplicates,
+                        output: None,
+                        error: None,
+                    },
+                    Err(e) => Outcome {
+                        file,
+                        status: Status::Failed,
+                        rows: 0,
+                        duplicates: 0,
+                        output: None,
+                        error: Some(format!("{e:#}")),
+                    },
+                })
+                .collect();
+            let failed = BatchFailed { outcomes };
+            failed.record(config, now)?;
+            let Some(Err(e)) = results.into_iter().nth(first) else {
+                unreachable!("the report at {first} failed");
+            };
+            return Err(e.wrap_err(format!("processing {file}")).wrap_err(failed));
+        }
+        let mut staged = results.into_iter().collect::<eyre::Result<Vec<_>>>()?;
+        // The last check, as memory must match the output once it is written.
+        cancel.check()?;
+
+        let date = now.naive_local().format("%Y-%m-%d_%H-%M-%S").to_string();
+        let _write = tracing::info_span!("write").entered();
+        let mut output = Some(output);
+        let mut written = Vec::new();
+        let mut outputs = Vec::with_capacity(staged.len());
+        let committed = (|| {
+            for (n, report) in staged.iter().enumerate() {
+                let date = match batch {
+                    true => format!("{date}-{}", n + 1),
+                    false => date.clone(),
                 };
+                let output = output.take().unwrap_or(Output::File(format));
+                let from = written.len();
+                let mut path = report.write(output, config, &date, &mut written)?;
+                if config.bundle && path.as_os_str() != "-" {
+                    path = bundle::write(&report.summary(path), &written[from..], &date)?;
+                    written.truncate(from);
+                    written.push(path.clone());
+                }
+                outputs.push(path);
+            }
+            memories
+                .sku
+                .write_difference(&format!("NEW_SKU_FOUND_{}.txt", date))?;
+            // Removed before memory is written, so that a run interrupted in
+            // between starts over rather than resuming from rows memory has
+            // seen.
+            for report in &mut staged {
+                if let Some(journal) = report.journal.take() {
+                    journal.finish()?;
+                }
+            }
+            memories.write()
+        })();
+        if let Err(e) = committed {
+            // Memory is written last, so whatever failed, it was not.
+            for path in written {
+                if let Err(e) = std::fs::remove_file(&path) {
+                    tracing::warn!("cannot remove {}: {e}", path.display());
+                }
+            }
+            return Err(e);
+        }
+
+        let earlier = match config.analytics || config.alerts.any() {
+            true => audit::Record::load(config)?,
+            false => Vec::new(),
+        };
+        let mut summaries = Vec::with_capacity(staged.len());
+        for (report, output) in staged.into_iter().zip(outputs) {
+            let mut summary = report.summary(output);
+            let earlier = (earlier.iter().map(|record| &record.summary)).chain(&summaries);
+            summary.alerts = config.alerts.check(&summary, earlier.clone());
+            if config.analytics {
+                let sales = &report.aggregation.sales;
+                let analytics =
+                    analytics::Analytics::new(&summary, sales, config.top_skus, earlier);
+                summary.analytics = Some(analytics);
+            }
+            let record = audit::Record::new(now, summary);
+            record.append(config)?;
+            #[cfg(feature = "postgres")]
+            if let Some(url) = &config.postgres {
+                postgres::record(url, &record, &report.aggregation.sales)?;
+            }
+            #[cfg(feature = "duckdb")]
+            if let Some(path) = &config.duckdb {
+                duckdb::record(path, &record, &report.aggregation.sales)?;
+            }
+            summaries.push(record.summary);
+        }
+        #[cfg(feature = "upload")]
+        if let Some(upload) = &config.upload {
+            if let Err(e) = upload::upload(upload, &summaries) {
+                tracing::warn!("cannot upload the output: {e:#}");
             }
         }
+        #[cfg(feature = "email")]
+        if let Some(email) = &config.email {
+            if let Err(e) = email::send(email, &summaries) {
+                tracing::warn!("cannot email the output: {e:#}");
+            }
+        }
+        #[cfg(feature = "webhook")]
+        webhook::post(&config.webhooks, now, &summaries);
+        Ok(summaries)
+    }
 
-        let date = chrono::Local::now()
-            .naive_local()
-            .format("%Y-%m-%d_%H-%M-%S");
-        skumem.write_difference(&format!("NEW_SKU_FOUND_{}.txt", date))?;
-        recmem.write()?;
-        skumem.write()?;
+    /// Like [`Report::parse`], without blocking the async runtime it is
+    /// called from.
+    ///
+    /// Aggregating is CPU bound, so the report is processed on tokio's
+    /// blocking thread pool.
+    #[cfg(feature = "tokio")]
+    pub async fn parse_async<P>(path: P, config: &Config) -> eyre::Result<Summary>
+    where
+        P: AsRef<Path> + std::fmt::Debug + Send + 'static,
+    {
+        let config = config.clone();
+        tokio::task::spawn_blocking(move || Report::parse(path, &config)).await?
+    }
 
-        let mut buffer = adjustmut_map
-            .into_iter()
-            .map(|(k, v)| Sale::new(Trx::Adjustment(k), v))
-            .collect::<Vec<_>>();
-        buffer.extend(
-            with_sku_map
-                .into_iter()
-                .map(|(k, v)| Sale::new(Trx::WithSku(k), v)),
-        );
+    /// Writes a report read from `reader`, such as an upload, to `path` and
+    /// processes it with [`Report::parse_async`].
+    ///
+    /// The report is archived and recorded in the audit log as `path`.
+    #[cfg(feature = "tokio")]
+    pub async fn parse_reader_async<R>(
+        mut reader: R,
+        path: PathBuf,
+        config: &Config,
+    ) -> eyre::Result<Summary>
+    where
+        R: tokio::io::AsyncRead + Unpin,
+    {
+        use tokio::io::AsyncWriteExt as _;
 
-        buffer.sort_unstable_by_key(|s| (s.kind.clone(), s.description.clone()));
+        let mut file = tokio::fs::File::create(&path).await?;
+        tokio::io::copy(&mut reader, &mut file).await?;
+        file.flush().await?;
+        drop(file);
+        Self::parse_async(path, config).await
+    }
 
-        let mut wb = Workbook::new();
-        let worksheet = wb.add_worksheet();
-        worksheet.serialize_headers(0, 0, &Sale::default())?;
+    /// Checks that the report at `path` can be read, without reading or
+    /// writing memory or output.
+    pub fn validate<P>(path: P, config: &Config) -> eyre::Result<Validation>
+    where
+        P: AsRef<Path>,
+    {
+        let mut rdr = reader(File::open(path.as_ref())?);
+        let (hdr, mut iter) = find_header(&mut rdr, config)?;
 
-        for sale in buffer {
-            worksheet.serialize(&sale)?;
+        let mut validation = Validation::default();
+        let line = hdr.position().map_or(0, |p| p.line());
+        for column in COLUMNS {
+            if !hdr.iter().any(|field| field == column) {
+                validation.problem(line, format!("missing column `{column}`"));
+            }
+        }
+        if let Err(e) = DedupKey::new(&hdr, config) {
+            validation.problem(line, e);
         }
 
-        wb.save(format!("AGGREGATED_{}.xlsx", date))?;
-        Ok(())
+        for record in iter.by_ref() {
+            let r = match record {
+                Ok(r) => r,
+                Err(e) => {
+                    let line = e.position().map_or(0, |p| p.line());
+                    validation.problem(line, e);
+                    continue;
+                }
+            };
+            validation.rows += 1;
+            let line = r.position().map_or(0, |p| p.line());
+            for problem in row_problems(&r, &hdr, config) {
+                validation.problem(line, problem);
+            }
+        }
+        if let Err(e) = iter.finish(config) {
+            let line = iter.records.reader().position().line();
+            validation.problem(line, format!("{e:#}"));
+        }
+        Ok(validation)
+    }
+
+    /// Aggregates a report that may not be a report at all, such as an
+    /// upload, leaving out the rows that cannot be read instead of failing,
+    /// and saying why each was left out.
+    ///
+    /// Nothing is read from or written to memory, like with
+    /// [`Report::aggregate_bytes`]. This never panics, unless built with
+    /// `panic = "abort"`, and reads no more than `limits` allow.
+    pub fn try_parse_lossy(
+        report: &[u8],
+        format: Format,
+        limits: &Limits,
+        config: &Config,
+    ) -> LossyParse {
+        untrusted::parse(report, format, limits, config)
+    }
+
+    /// Reads the header of the report at `path` and what each of its columns
+    /// is read as, without reading its rows, memory, or output.
+    pub fn schema<P>(path: P, config: &Config) -> eyre::Result<Schema>
+    where
+        P: AsRef<Path>,
+    {
+        schema(File::open(path.as_ref())?, config)
+    }
+
+    /// Reads the first `n` transactions of the report at `path`, without
+    /// reading or writing memory or output.
+    pub fn preview<P>(path: P, n: usize, config: &Config) -> eyre::Result<Vec<Transaction>>
+    where
+        P: AsRef<Path>,
+    {
+        let mut rdr = reader(File::open(path.as_ref())?);
+        let (hdr, iter) = find_header(&mut rdr, config)?;
+        let redact = Redact::new(config);
+
+        iter.take(n)
+            .map(|record| {
+                let raw = read_record(record)?;
+                let r = config.normalize.record(&raw);
+                let line = r.position().map_or(0, |p| p.line());
+                check_columns(&r, &hdr).wrap_err(Malformed::Line(line))?;
+                let sale = r
+                    .deserialize::<RefSale>(Some(&hdr))
+                    .wrap_err(Malformed::Line(line))?;
+                let cents = handle_punct(sale.total, config.amount_rounding)
+                    .wrap_err(Malformed::Line(line))?;
+                let quantity = config
+                    .quantity
+                    .read(sale.kind, sale.quantity)
+                    .wrap_err(Malformed::Line(line))?;
+                Ok(Transaction {
+                    line,
+                    kind: redact.field("type", sale.kind).to_string(),
+                    sku: sale.sku.map(|sku| redact.field("sku", sku).to_string()),
+                    description: redact.field("description", sale.description).to_string(),
+                    quantity,
+                    cents,
+                })
+            })
+            .collect()
+    }
+
+    /// Aggregates the report at `path` as if no previous run had seen it,
+    /// returning the net amount per transaction type.
+    ///
+    /// Nothing is read from or written to memory, and no output is written.
+    pub fn totals<P>(path: P, config: &Config) -> eyre::Result<BTreeMap<String, Cents>>
+    where
+        P: AsRef<Path>,
+    {
+        let aggregation = aggregate(
+            File::open(path.as_ref())?,
+            config,
+            &mut Memories::default(),
+            None,
+            None,
+            &mut (),
+            None,
+        )?;
+        Ok(aggregation.totals())
+    }
+
+    /// Aggregates the report at `path` as if no previous run had seen it,
+    /// returning the payout of every settlement, see [`reconcile`].
+    ///
+    /// Nothing is read from or written to memory, and no output is written.
+    pub fn payouts<P>(path: P, config: &Config) -> eyre::Result<Vec<reconcile::Payout>>
+    where
+        P: AsRef<Path>,
+    {
+        // Settlements are only totalled for journal entries, which are not
+        // written here, so their layout does not matter.
+        let mut config = config.clone();
+        config.journal_entries.get_or_insert_with(Default::default);
+        let aggregation = aggregate(
+            File::open(path.as_ref())?,
+            &config,
+            &mut Memories::default(),
+            None,
+            None,
+            &mut (),
+            None,
+        )?;
+        Ok(aggregation.ledger.payouts())
+    }
+
+    /// Aggregates the bytes of a report into the bytes of an output, without
+    /// touching the file system, for example in a browser.
+    ///
+    /// Nothing is read from or written to memory, so every transaction is
+    /// aggregated.
+    pub fn aggregate_bytes(report: &[u8], config: &Config) -> eyre::Result<Aggregated> {
+        Self::aggregate_bytes_as(report, Format::default(), config)
+    }
+
+    /// Like [`Report::aggregate_bytes`], writing the output in `format`.
+    pub fn aggregate_bytes_as(
+        report: &[u8],
+        format: Format,
+        config: &Config,
+    ) -> eyre::Result<Aggregated> {
+        let password = output_password(config, format)?;
+        let aggregation = aggregate(
+            report,
+            config,
+            &mut Memories::default(),
+            None,
+            None,
+            &mut (),
+            None,
+        )?;
+        let sheet = sheet::name(
+            config.sheet_name.as_deref(),
+            Path::new("report"),
+            chrono::Local::now().date_naive(),
+            aggregation.period,
+        );
+        Ok(Aggregated {
+            output: render_output(&aggregation, format, &sheet, password.as_deref())?,
+            rows: aggregation.rows,
+            aggregates: aggregation.sales.len() as u64,
+            quarantined: aggregation.quarantined.len() as u64,
+            totals: aggregation.totals(),
+            taxes: aggregation
+                .taxes
+                .iter()
+                .map(|(jurisdiction, totals)| (jurisdiction.to_string(), totals))
+                .collect(),
+            currencies: aggregation.currencies(),
+        })
     }
 }
 
+/// Result of [`Report::aggregate_bytes`].
 #[derive(Debug)]
-enum Trx {
-    Adjustment(Adjustment),
-    WithSku(WithSku),
+pub struct Aggregated {
+    /// The output, as it would be written to `AGGREGATED_*.xlsx`, or
+    /// `AGGREGATED_*.csv` without the `xlsx` feature, unless another format
+    /// is given.
+    pub output: Vec<u8>,
+    /// Data rows read, not counting the preamble or header.
+    pub rows: u64,
+    /// Rows written to the output.
+    pub aggregates: u64,
+    /// Rows left out of the output for review, see `quarantine`.
+    pub quarantined: u64,
+    /// Net amount per transaction type, in cents.
+    pub totals: BTreeMap<String, Cents>,
+    /// Tax per jurisdiction, see `tax_jurisdiction`. Empty if the report has
+    /// no tax columns.
+    pub taxes: BTreeMap<String, TaxTotals>,
+    /// Currency codes written next to the totals, see [`Summary::currencies`].
+    pub currencies: Vec<String>,
 }
 
-impl TryFrom<RefSale<'_>> for Trx {
-    type Error = eyre::Error;
-    fn try_from(value: RefSale<'_>) -> Result<Self, Self::Error> {
-        match value.sku {
-            Some(_) => WithSku::try_from(value).map(Trx::WithSku),
-            None => Adjustment::try_from(value).map(Trx::Adjustment),
-        }
+/// Reads the password to protect an output in `format` with, see
+/// `output_password_env`.
+fn output_password(config: &Config, format: Format) -> eyre::Result<Option<String>> {
+    if format != Format::Xlsx && config.output_password_env.is_some() {
+        bail!("`output_password_env` needs an xlsx output, no other output can be protected");
     }
+    config
+        .output_password_env
+        .as_ref()
+        .map(|var| {
+            std::env::var(var)
+                .map_err(|_| eyre::eyre!("the output password variable {var} is not set"))
+        })
+        .transpose()
 }
 
-type Cents = i64;
+/// A report for [`Report::parse_with`] to process.
+pub enum Source<'a> {
+    /// A file, which is read twice: once for its checksum, then to process it.
+    Path(&'a Path),
+    /// A stream, such as stdin, which is read into memory first. It is
+    /// recorded in the audit log as `-`, so it can only be replayed if
+    /// `archive` is set.
+    Reader(&'a mut dyn std::io::Read),
+}
 
-#[derive(Debug, Hash, Eq, PartialEq)]
-struct Adjustment {
-    kind: String,
-    description: String,
+/// Where [`Report::parse_with`] writes the output.
+pub enum Output<'a> {
+    /// A new `AGGREGATED_[TIMESTAMP]` file in the working directory.
+    File(Format),
+    /// A stream, such as stdout. It is recorded in the audit log as `-`.
+    Writer(&'a mut dyn std::io::Write, Format),
 }
 
-impl TryFrom<RefSale<'_>> for Adjustment {
-    type Error = eyre::Error;
-    fn try_from(value: RefSale<'_>) -> Result<Self, Self::Error> {
-        Ok(Self {
-            kind: value.kind.to_string(),
-            description: value.description.to_string(),
-        })
+impl Output<'_> {
+    fn format(&self) -> Format {
+        match self {
+            Self::File(format) | Self::Writer(_, format) => *format,
+        }
     }
 }
 
-#[derive(Debug, Hash, Eq, PartialEq)]
-struct WithSku {
-    kind: String,
-    sku: String,
-    cents: Cents,
-    description: String,
+/// Formats the output can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// A workbook, followed by a "Tax Summary" sheet if the report had any
+    /// tax, an "Accounts" sheet with `accounts`, and a "Needs Review" sheet
+    /// of quarantined rows. Requires the `xlsx` feature.
+    Xlsx,
+    /// The sales only, with the same columns as the workbook.
+    Csv,
+    /// An array of the sales, with the same fields as the workbook.
+    Json,
+    /// An OpenDocument spreadsheet, with the same sheets as the workbook,
+    /// for LibreOffice.
+    Ods,
+    /// A standalone HTML page with a chart and the totals of every type,
+    /// followed by the sales in a table that sorts by any column.
+    Html,
+    /// An Arrow IPC file of the sales, also known as Feather, with the same
+    /// columns as the workbook. Requires the `arrow` feature.
+    Arrow,
 }
 
-impl TryFrom<RefSale<'_>> for WithSku {
-    type Error = eyre::Error;
-    fn try_from(value: RefSale<'_>) -> Result<Self, Self::Error> {
-        let RefSale {
-            kind,
-            sku,
-            total,
-            quantity,
-            description,
-        } = value;
-
-        let total = handle_punct(total)?;
-
-        // Div by 0 is None => cents
-        let cents = total.checked_div(quantity).unwrap_or(total);
-
-        Ok(Self {
-            kind,
-            sku: sku.expect("sku is some"),
-            cents,
-            description,
-        })
+impl Default for Format {
+    /// A workbook with the `xlsx` feature, CSV otherwise.
+    fn default() -> Self {
+        if cfg!(feature = "xlsx") {
+            Self::Xlsx
+        } else {
+            Self::Csv
+        }
     }
 }
 
-fn handle_punct(total: &str) -> eyre::Result<i64> {
-    let punct = ['.', ','];
-    match total.split('.').nth(1) {
-        Some(dec) => {
-            let mul = match dec.chars().count() {
-                1 => 10,
-                2 => 1,
-                _ => bail!("invalid decimal"),
-            };
-            total
-                .replace(punct, "")
-                .parse::<i64>()
-                .map(|v| v * mul)
-                .map_err(Into::into)
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Xlsx => "xlsx",
+            Self::Csv => "csv",
+            Self::Json => "json",
+            Self::Ods => "ods",
+            Self::Html => "html",
+            Self::Arrow => "arrow",
         }
-        None => total
-            .replace(punct, "")
-            .parse::<i64>()
-            .map(|v| v * 100)
-            .map_err(Into::into),
     }
 }
 
+/// Writes the sales of `aggregation` in `format`, on a sheet named `sheet` in
+/// a workbook, protected with `password` if given. A password is rejected by
+/// [`output_password`] for formats other than a workbook.
+fn render_output(
+    aggregation: &Aggregation,
+    format: Format,
+    sheet: &str,
+    password: Option<&str>,
+) -> eyre::Result<Vec<u8>> {
+    match format {
+        Format::Xlsx => render_xlsx(aggregation, sheet, password),
+        Format::Csv => {
+            let mut wtr = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(Vec::new());
+            wtr.write_record(["Type", "SKU", "Description", "Quantity", "Total"])?;
+            for sale in &aggregation.sales {
+                wtr.serialize(sale)?;
+            }
+            Ok(wtr.into_inner()?)
+        }
+        Format::Json => {
+            let mut json = serde_json::to_vec_pretty(&aggregation.sales)?;
+            json.push(b'\n');
+            Ok(json)
+        }
+        Format::Ods => ods::render(aggregation, sheet),
+        Format::Html => html::render(aggregation, sheet),
+        Format::Arrow => render_arrow(aggregation),
+    }
+}
+
+/// Writes the sales of `aggregation` to an Arrow IPC file, as a single
+/// record batch.
+#[cfg(feature = "arrow")]
+fn render_arrow(aggregation: &Aggregation) -> eyre::Result<Vec<u8>> {
+    use arrow_array::{ArrayRef, Float64Array, Int64Array, RecordBatch, StringArray};
+    use arrow_schema::{DataType, Field, Schema};
+
+    let sales = &aggregation.sales;
+    let strings = |f: fn(&Sale) -> &str| -> ArrayRef {
+        Arc::new(StringArray::from_iter_values(sales.iter().map(f)))
+    };
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("Type", DataType::Utf8, false),
+        Field::new("SKU", DataType::Utf8, false),
+        Field::new("Description", DataType::Utf8, false),
+        Field::new("Quantity", DataType::Int64, false),
+        Field::new("Total", DataType::Float64, false),
+    ]));
+    let batch = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            strings(|sale| &sale.kind),
+            strings(|sale| &sale.sku),
+            strings(|sale| &sale.description),
+            Arc::new(Int64Array::from_iter_values(
+                sales.iter().map(|sale| sale.quantity),
+            )),
+            Arc::new(Float64Array::from_iter_values(
+                sales.iter().map(|sale| sale.cents as f64 / 100.0),
+            )),
+        ],
+    )?;
+    let mut wtr = arrow_ipc::writer::FileWriter::try_new(Vec::new(), &schema)?;
+    wtr.write(&batch)?;
+    wtr.finish()?;
+    Ok(wtr.into_inner()?)
+}
+
+#[cfg(not(feature = "arrow"))]
+fn render_arrow(_aggregation: &Aggregation) -> eyre::Result<Vec<u8>> {
+    bail!("an Arrow output needs the `arrow` feature")
+}
+
+/// Writes the sales of `aggregation` to a workbook, protected with `password`
+/// if given, followed by a "Tax Summary" sheet if the report had any tax, an
+/// "Accounts" sheet if `accounts` are configured, and a "Needs Review" sheet
+/// if rows were quarantined.
+#[cfg(feature = "xlsx")]
+fn render_xlsx(
+    aggregation: &Aggregation,
+    sheet: &str,
+    password: Option<&str>,
+) -> eyre::Result<Vec<u8>> {
+    let mut wb = rust_xlsxwriter::Workbook::new();
+    let worksheet = wb.add_worksheet().set_name(sheet)?;
+    if let Some(password) = password {
+        worksheet.protect_with_password(password);
+    }
+    worksheet.serialize_headers(0, 0, &Sale::default())?;
+    for sale in &aggregation.sales {
+        worksheet.serialize(sale)?;
+    }
+
+    let taxes = sheet::unique("Tax Summary", &[sheet]);
+    if aggregation.taxes.iter().next().is_some() {
+        let worksheet = wb.add_worksheet().set_name(&taxes)?;
+        if let Some(password) = password {
+            worksheet.protect_with_password(password);
+        }
+        for (col, name) in ["Jurisdiction", "Collected", "Withheld", "Net"]
+            .into_iter()
+            .enumerate()
+        {
+            worksheet.write_string(0, col as u16, name)?;
+        }
+        for (row, (jurisdiction, totals)) in (1..).zip(aggregation.taxes.iter()) {
+            let net = totals.collected + totals.withheld;
+            worksheet.write_string(row, 0, jurisdiction)?;
+            for (col, cents) in [(1, totals.collected), (2, totals.withheld), (3, net)] {
+                worksheet.write_number(row, col, cents as f64 / 100.0)?;
+            }
+        }
+    }
+
+    let accounts = sheet::unique("Accounts", &[sheet, &taxes]);
+    if let Some(first) = aggregation.accounts.first() {
+        let worksheet = wb.add_worksheet().set_name(&accounts)?;
+        if let Some(password) = password {
+            worksheet.protect_with_password(password);
+        }
+        worksheet.serialize_headers(0, 0, first)?;
+        for total in &aggregation.accounts {
+            worksheet.serialize(total)?;
+        }
+    }
+
+    if !aggregation.quarantined.is_empty() {
+        let worksheet = wb
+            .add_worksheet()
+            .set_name(sheet::unique("Needs Review", &[sheet, &taxes, &accounts]))?;
+        if let Some(password) = password {
+            worksheet.protect_with_password(password);
+        }
+        for (row, fields) in (0..).zip(aggregation.quarantined()) {
+            for (col, field) in (0..).zip(&fields) {
+                worksheet.write_string(row, col, field)?;
+            }
+        }
+    }
+    Ok(wb.save_to_buffer()?)
+}
+
+#[cfg(not(feature = "xlsx"))]
+fn render_xlsx(
+    _aggregation: &Aggregation,
+    _sheet: &str,
+    _password: Option<&str>,
+) -> eyre::Result<Vec<u8>> {
+    bail!("an xlsx output needs the `xlsx` feature")
+}
+
+/// Alternate names [`write_output`] tries before giving up.
+const OUTPUT_ATTEMPTS: u32 = 10;
+
+/// Writes `output` to `path`, returning where it was written.
+///
+/// If another program has `path` open, as Excel does with a workbook, the
+/// output is written next to it with `-1`, `-2`, and so on added to its name
+/// instead.
+fn write_output(path: &Path, output: &[u8]) -> eyre::Result<PathBuf> {
+    let mut attempt = path.to_path_buf();
+    for n in 1..=OUTPUT_ATTEMPTS {
+        match std::fs::write(&attempt, output) {
+            Ok(()) => return Ok(attempt),
+            Err(e) if is_locked(&e) => {
+                let next = alternate_name(path, n);
+                tracing::warn!(
+                    "{} is in use, writing {} instead",
+                    attempt.display(),
+                    next.display()
+                );
+                attempt = next;
+            }
+            Err(e) => return Err(e).wrap_err_with(|| format!("writing {}", attempt.display())),
+        }
+    }
+    bail!(
+        "{} and {OUTPUT_ATTEMPTS} alternatives are in use, close them and run again",
+        path.display()
+    )
+}
+
+/// Whether writing failed because another program has the file open.
+fn is_locked(e: &std::io::Error) -> bool {
+    // ERROR_SHARING_VIOLATION, ERROR_LOCK_VIOLATION, and access denied, which
+    // is how Windows refuses to replace a workbook open in Excel. Elsewhere a
+    // denied write is only ever a permission problem.
+    cfg!(windows)
+        && (matches!(e.raw_os_error(), Some(32 | 33))
+            || e.kind() == std::io::ErrorKind::PermissionDenied)
+}
+
+/// `path` with `-n` added to its name, before the extension.
+fn alternate_name(path: &Path, n: u32) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{stem}-{n}.{}", ext.to_string_lossy()),
+        None => format!("{stem}-{n}"),
+    };
+    path.with_file_name(name)
+}
+
+/// Reads a report from `input`, decoding it as it is read.
+///
+/// Cannot guarantee the file is utf8, if anything we know it's not.
+fn reader<R: std::io::Read>(input: R) -> csv::Reader<Lossy<R>> {
+    csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(Lossy::new(input))
+}
+
+/// Columns that [`RefSale`] is read from.
+const COLUMNS: [&str; 5] = ["type", "sku", "description", "quantity", "total"];
+
+/// Skips the preamble, returning the header row with its names in English,
+/// see [`aliases`], and the rows after it.
+///
+/// The preamble is not the same length in every marketplace, so the header is
+/// taken to be the first row with both a `type` and a `total` column, or the
+/// header of another marketplace's report, whose rows are then converted, see
+/// [`marketplace`].
+fn find_header<'r, R>(
+    rdr: &'r mut csv::Reader<R>,
+    config: &Config,
+) -> eyre::Result<(StringRecord, Rows<'r, R>)>
+where
+    R: std::io::Read,
+{
+    let aliases = Aliases::new(config);
+    let mut records = rdr.records();
+    let mut declared = None;
+    for (row, record) in records.by_ref().enumerate() {
+        let mut raw = read_record(record)?;
+        raw.truncate(width(&raw));
+        let hdr = aliases.translate(&raw);
+        let has = |name| hdr.iter().any(|field| field == name);
+        let convert = match config.source {
+            Some(Marketplace::Amazon) => None,
+            _ => marketplace::Convert::detect(&raw, row as u64, config),
+        };
+        let amazon = matches!(config.source, None | Some(Marketplace::Amazon));
+        if amazon && has("type") && has("total") || convert.is_some() {
+            let pinned = convert.as_ref().and_then(|c| c.pinned.as_deref());
+            check_pinned(&raw, pinned.unwrap_or(&config.header))?;
+            let hdr = match &convert {
+                Some(convert) => {
+                    tracing::info!("reading a report from {}", convert.name);
+                    convert.header(&raw)
+                }
+                None => hdr,
+            };
+            let rows = Rows {
+                records,
+                hdr: raw,
+                short_rows: config.short_rows,
+                declared,
+                count: 0,
+                convert,
+                converted: Vec::new(),
+            };
+            return Ok((hdr, rows));
+        }
+        declared = declared.or_else(|| raw.iter().find_map(declared_rows));
+    }
+    Err(Malformed::Header.into())
+}
+
+fn schema(input: impl std::io::Read, config: &Config) -> eyre::Result<Schema> {
+    let mut rdr = reader(input);
+    let (hdr, rows) = find_header(&mut rdr, config)?;
+    let dedup = DedupKey::new(&hdr, config)?;
+    let tax_columns = TaxColumns::new(&hdr, config)?;
+    // What the `i`th column of `hdr`, as translated or converted, is read as.
+    let read_as = |i: usize| {
+        let name = hdr.get(i).unwrap_or_default();
+        let mut uses = Vec::new();
+        uses.extend(marketplace::COLUMNS.into_iter().find(|c| *c == name));
+        let key = match &dedup {
+            DedupKey::Row => false,
+            DedupKey::Columns(columns) => columns.contains(&i),
+            DedupKey::OrderId(columns) => columns.contains(&i),
+        };
+        if key {
+            uses.push("dedup key");
+        }
+        uses.extend(tax_columns.as_ref().and_then(|t| t.column(i)));
+        uses
+    };
+    let columns = (rows.hdr.iter().enumerate())
+        .map(|(i, name)| {
+            let uses = match &rows.convert {
+                Some(convert) => {
+                    let read = convert.read_into(i).into_iter();
+                    let mut uses = read.flat_map(read_as).collect::<Vec<_>>();
+                    if convert.is_fee(i) {
+                        uses.push("fee");
+                    }
+                    uses
+                }
+                None => read_as(i),
+            };
+            SchemaColumn {
+                name: name.to_string(),
+                uses,
+            }
+        })
+        .collect();
+    let missing = match &rows.convert {
+        Some(convert) => convert.missing().collect(),
+        None => (marketplace::COLUMNS.into_iter())
+            .filter(|column| !hdr.iter().any(|name| name == *column))
+            .collect(),
+    };
+    Ok(Schema {
+        source: match &rows.convert {
+            Some(convert) => convert.name.clone(),
+            None => Marketplace::Amazon.to_string(),
+        },
+        line: hdr.position().map_or(0, |p| p.line()),
+        columns,
+        missing,
+        whole_rows: matches!(dedup, DedupKey::Row),
+    })
+}
+
+/// Fails with how `hdr` changed if it does not have exactly the columns of
+/// `pinned`, in any order, unless nothing is pinned. Names are compared
+/// trimmed and in any case.
+fn check_pinned(hdr: &StringRecord, pinned: &[String]) -> eyre::Result<()> {
+    if pinned.is_empty() {
+        return Ok(());
+    }
+    let key = |name: &str| name.trim().to_lowercase();
+    let written = hdr.iter().map(key).collect::<Vec<_>>();
+    let expected = pinned.iter().map(|name| key(name)).collect::<Vec<_>>();
+    let mut removed = (0..expected.len())
+        .filter(|&i| !written.contains(&expected[i]))
+        .collect::<Vec<_>>();
+    let mut added = (0..written.len())
+        .filter(|&i| !expected.contains(&written[i]))
+        .collect::<Vec<_>>();
+    // A pinned column gone from where an unknown one now is was most likely
+    // renamed.
+    let renamed = (removed.iter().copied())
+        .filter(|i| added.contains(i))
+        .collect::<Vec<_>>();
+    removed.retain(|i| !renamed.contains(i));
+    added.retain(|i| !renamed.contains(i));
+
+    let changes = (added.iter().map(|&i| format!("added `{}`", hdr[i].trim())))
+        .chain(removed.iter().map(|&i| format!("removed `{}`", pinned[i])))
+        .chain((renamed.iter()).map(|&i| format!("renamed `{}` to `{}`", pinned[i], hdr[i].trim())))
+        .collect::<Vec<_>>();
+    match changes.is_empty() {
+        true => Ok(()),
+        false => Err(eyre::eyre!("{}", changes.join(", ")).wrap_err(Malformed::HeaderChanged)),
+    }
+}
+
+/// Number of rows that a preamble field such as `Total rows: 1,234` declares
+/// the report to have.
+fn declared_rows(field: &str) -> Option<u64> {
+    const LABELS: [&str; 5] = [
+        "rows",
+        "row count",
+        "total rows",
+        "transactions",
+        "total transactions",
+    ];
+    let (label, count) = field.split_once(':')?;
+    if !LABELS.contains(&label.trim().to_lowercase().as_str()) {
+        return None;
+    }
+    count.trim().replace(',', "").parse().ok()
+}
+
+/// Number of fields of `r` before any trailing empty ones, such as the one
+/// Excel adds for a stray delimiter at the end of a row.
+fn width(r: &StringRecord) -> usize {
+    r.len() - r.iter().rev().take_while(|f| f.trim().is_empty()).count()
+}
+
+/// Fails a row with fewer columns than `hdr`, which would otherwise be read
+/// with the missing columns empty, see [`ShortRows`].
+fn check_columns(r: &StringRecord, hdr: &StringRecord) -> eyre::Result<()> {
+    if r.len() < hdr.len() {
+        bail!("row has {} of the header's {} columns", r.len(), hdr.len());
+    }
+    Ok(())
+}
+
+/// What stops `r` from being read, if anything, see [`Report::validate`].
+fn row_problems(r: &StringRecord, hdr: &StringRecord, config: &Config) -> Vec<String> {
+    if let Err(e) = check_columns(r, hdr) {
+        return vec![e.to_string()];
+    }
+    match r.deserialize::<RefSale>(Some(hdr)) {
+        Ok(sale) => {
+            let mut problems = Vec::new();
+            if let Err(e) = handle_punct(sale.total, config.amount_rounding) {
+                problems.push(format!("total: {e}"));
+            }
+            if let Err(e) = config.quantity.read(sale.kind, sale.quantity) {
+                problems.push(e.to_string());
+            }
+            problems
+        }
+        Err(e) => vec![e.to_string()],
+    }
+}
+
+/// The rows of a report after its header.
+///
+/// Reports pasted together by hand repeat the header where each one starts,
+/// so rows identical to the header are skipped with a warning instead of
+/// being read as a transaction. So are rows with fewer columns than the
+/// header if `short_rows` is [`ShortRows::Skip`].
+struct Rows<'r, R> {
+    records: csv::StringRecordsIter<'r, R>,
+    /// The header as written, before its names are translated.
+    hdr: StringRecord,
+    short_rows: ShortRows,
+    /// Number of rows declared by the preamble, if any.
+    declared: Option<u64>,
+    /// Number of rows read so far, skipped or not, besides repeated headers.
+    count: u64,
+    /// Converts rows of another marketplace's report, see [`marketplace`].
+    convert: Option<marketplace::Convert>,
+    /// Rows converted from the last row read and not yet returned, last
+    /// first.
+    converted: Vec<StringRecord>,
+}
+
+impl<R: std::io::Read> Rows<'_, Lossy<R>> {
+    /// Fails if, having read every row, the report looks partly downloaded,
+    /// unless `truncated_reports` is [`TruncatedReports::Warn`].
+    fn finish(&self, config: &Config) -> eyre::Result<()> {
+        let problem = if !self.records.reader().get_ref().terminated() {
+            "its last line ends partway through a row".to_string()
+        } else {
+            match self.declared {
+                Some(declared) if declared > self.count => format!(
+                    "its preamble declares {declared} rows, but only {} were read",
+                    self.count
+                ),
+                _ => return Ok(()),
+            }
+        };
+        match config.truncated_reports {
+            TruncatedReports::Reject => Err(eyre::eyre!(
+                "{problem}; download it again, or set `truncated_reports = \"warn\"` to \
+                 process it anyway"
+            )
+            .wrap_err(Malformed::Truncated)),
+            TruncatedReports::Warn => {
+                tracing::warn!("the report looks partly downloaded: {problem}");
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<R: std::io::Read> Iterator for Rows<'_, R> {
+    type Item = csv::Result<StringRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(row) = self.converted.pop() {
+                return Some(Ok(row));
+            }
+            let record = self.records.next()?;
+            let Ok(r) = &record else {
+                self.count += 1;
+                return Some(record);
+            };
+            let line = r.position().map_or(0, |p| p.line());
+            let repeated = r
+                .iter()
+                .take(width(r))
+                .map(str::trim)
+                .eq(self.hdr.iter().map(str::trim));
+            if repeated {
+                tracing::warn!("skipping repeated header on line {line}");
+                continue;
+            }
+            self.count += 1;
+            if self.short_rows == ShortRows::Skip && r.len() < self.hdr.len() {
+                tracing::warn!(
+                    "skipping line {line}, which has {} of the header's {} columns",
+                    r.len(),
+                    self.hdr.len()
+                );
+                continue;
+            }
+            if let Some(convert) = &self.convert {
+                self.converted = convert.rows(r);
+                self.converted.reverse();
+                continue;
+            }
+            return Some(record);
+        }
+    }
+}
+
+/// Picks the text of a row that is hashed to recognize it in memory.
+enum DedupKey {
+    /// The whole row.
+    Row,
+    /// Indexes of the configured columns.
+    Columns(Vec<usize>),
+    /// Indexes of the `order id`, `type`, and `total` columns.
+    OrderId([usize; 3]),
+}
+
+impl DedupKey {
+    fn new(hdr: &StringRecord, config: &Config) -> eyre::Result<Self> {
+        let index = |name: &str| {
+            hdr.iter()
+                .position(|field| field == name)
+                .ok_or_else(|| eyre::eyre!("dedup key column `{name}` is not in the header"))
+        };
+        if !config.dedup_key.is_empty() {
+            let columns = config
+                .dedup_key
+                .iter()
+                .map(|name| index(name))
+                .collect::<eyre::Result<_>>()?;
+            return Ok(Self::Columns(columns));
+        }
+        match config.dedup {
+            Dedup::Row => Ok(Self::Row),
+            Dedup::OrderId => Ok(Self::OrderId([
+                index("order id")?,
+                index("type")?,
+                index("total")?,
+            ])),
+        }
+    }
+
+    fn key<'r>(&self, r: &'r StringRecord) -> Cow<'r, str> {
+        let field = |i: &usize| r.get(*i).unwrap_or_default();
+        match self {
+            Self::Row => Cow::Borrowed(r.as_slice()),
+            Self::Columns(columns) => Cow::Owned(join(columns.iter().map(field))),
+            // Without an order id, such as for fees and transfers, only the
+            // whole row tells one month's row from the next.
+            Self::OrderId([order, ..]) if field(order).trim().is_empty() => {
+                Cow::Borrowed(r.as_slice())
+            }
+            Self::OrderId([order, kind, total]) => {
+                // Compare amounts by value, `1,000.00` and `1000.00` are the
+                // same transaction downloaded twice.
+                let total = match handle_punct(field(total).trim(), Rounding::Reject) {
+                    Ok(cents) => cents.to_string(),
+                    Err(_) => field(total).to_string(),
+                };
+                let fields = [field(order).trim(), field(kind).trim(), &total];
+                Cow::Owned(join(fields.into_iter()))
+            }
+        }
+    }
+}
+
+fn join<'a>(fields: impl Iterator<Item = &'a str>) -> String {
+    // The unit separator keeps `ab`,`c` and `a`,`bc` apart.
+    fields.collect::<Vec<_>>().join("\u{1f}")
+}
+
+/// Loosens a row so that copies with different formatting, or a different
+/// time, hash the same.
+fn near_key(r: &StringRecord, hdr: &StringRecord) -> String {
+    let fields = r
+        .iter()
+        .zip(hdr.iter())
+        .filter(|(_, name)| *name != "date/time")
+        .map(|(field, _)| {
+            let field = field.split_whitespace().collect::<Vec<_>>().join(" ");
+            match handle_punct(&field, Rounding::Reject) {
+                Ok(cents) => cents.to_string(),
+                Err(_) => field,
+            }
+        })
+        .collect::<Vec<_>>();
+    join(fields.iter().map(String::as_str))
+}
+
+/// Everything that is remembered between runs.
+#[derive(Debug, Default)]
+struct Memories {
+    /// Rows that were aggregated, see [`DedupKey`].
+    rec: Memory,
+    /// SKUs of sales that were aggregated.
+    sku: Memory,
+    /// Rows that were aggregated, see [`near_key`].
+    near: Option<Memory>,
+}
+
+impl Memories {
+    fn load(config: &Config) -> eyre::Result<Self> {
+        Ok(Self {
+            rec: Memory::new(memory::RECORDS, config)?,
+            sku: Memory::new(memory::SKUS, config)?,
+            near: config
+                .near_duplicates
+                .then(|| Memory::new(memory::NEAR, config))
+                .transpose()?,
+        })
+    }
+
+    /// Every memory that was loaded, records first.
+    fn iter_mut(&mut self) -> impl Iterator<Item = &mut Memory> {
+        [&mut self.rec, &mut self.sku]
+            .into_iter()
+            .chain(self.near.as_mut())
+    }
+
+    fn write(self) -> eyre::Result<()> {
+        self.rec.write()?;
+        self.sku.write()?;
+        if let Some(near) = self.near {
+            near.write()?;
+        }
+        Ok(())
+    }
+}
+
+/// Sales aggregated from a single report, sorted for output.
+struct Aggregation {
+    sales: Vec<Sale>,
+    rows: u64,
+    duplicates: u64,
+    /// Net amount of the `duplicates` per transaction type, see
+    /// [`Summary::duplicate_totals`].
+    duplicate_totals: BTreeMap<String, Cents>,
+    header: StringRecord,
+    /// Rows that were aggregated, but look like a row seen before.
+    near_duplicates: Vec<StringRecord>,
+    /// Rows left out of `sales` for review, see `quarantine`, already
+    /// redacted, with why.
+    quarantined: Vec<(String, StringRecord)>,
+    taxes: Taxes,
+    /// Currency codes written next to the totals of aggregated rows.
+    currencies: BTreeSet<Arc<str>>,
+    /// Months the aggregated rows are dated in.
+    period: sheet::Period,
+    /// Days the aggregated rows are dated on, see [`closing`].
+    days: BTreeSet<chrono::NaiveDate>,
+    /// Totals of `sales` per account, see [`accounts`].
+    accounts: Vec<accounts::Total>,
+    /// Totals of every settlement, when writing journal entries.
+    ledger: ledger::Ledger,
+}
+
+impl Aggregation {
+    /// Writes the totals per account to `path`, for outputs that have no
+    /// sheet for them.
+    fn write_accounts(&self, path: &str) -> eyre::Result<()> {
+        if self.accounts.is_empty() {
+            return Ok(());
+        }
+        let mut wtr = csv::Writer::from_path(path)?;
+        for total in &self.accounts {
+            wtr.serialize(total)?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    fn write_near_duplicates(&self, path: &str, redact: &Redact) -> eyre::Result<()> {
+        if self.near_duplicates.is_empty() {
+            return Ok(());
+        }
+        let mut wtr = csv::WriterBuilder::new().flexible(true).from_path(path)?;
+        wtr.write_record(std::iter::once("line").chain(self.header.iter()))?;
+        for r in &self.near_duplicates {
+            let line = r.position().map_or(0, |p| p.line()).to_string();
+            let r = redact.record(&self.header, r);
+            wtr.write_record(std::iter::once(line.as_str()).chain(r.iter()))?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    /// `line` and `reason` followed by the header, then the line, reason,
+    /// and fields of every quarantined row.
+    fn quarantined(&self) -> Vec<Vec<String>> {
+        let header = ["line", "reason"].into_iter().chain(self.header.iter());
+        let header = header.map(str::to_string).collect();
+        let rows = self.quarantined.iter().map(|(reason, r)| {
+            let line = r.position().map_or(0, |p| p.line()).to_string();
+            [line, reason.clone()]
+                .into_iter()
+                .chain(r.iter().map(str::to_string))
+                .collect()
+        });
+        std::iter::once(header).chain(rows).collect()
+    }
+
+    /// Writes the quarantined rows to `path`, for outputs that have no sheet
+    /// for them.
+    fn write_quarantined(&self, path: &str) -> eyre::Result<()> {
+        if self.quarantined.is_empty() {
+            return Ok(());
+        }
+        let mut wtr = csv::WriterBuilder::new().flexible(true).from_path(path)?;
+        for row in self.quarantined() {
+            wtr.write_record(row)?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    fn currencies(&self) -> Vec<String> {
+        self.currencies.iter().map(|c| c.to_string()).collect()
+    }
+
+    fn totals(&self) -> BTreeMap<String, Cents> {
+        let mut totals = BTreeMap::new();
+        for sale in &self.sales {
+            *totals.entry(sale.kind.to_string()).or_default() += sale.cents;
+        }
+        totals
+    }
+}
+
+/// Rows parsed by each thread at a time, see [`parse_rows`].
+const CHUNK: usize = 8 * 1024;
+
+/// A row of a report, parsed ahead of aggregating it.
+struct Parsed {
+    line: u64,
+    key: String,
+    near_key: Option<String>,
+    /// Only needed, and only an error, if no earlier run saw the row. `None`
+    /// if the script or a plugin dropped the row.
+    sale: eyre::Result<Option<(Trx, i64, Cents)>>,
+    /// `None` if the report has no tax columns. Like `sale`, only needed if
+    /// the row is aggregated.
+    tax: eyre::Result<Option<tax::Tax>>,
+    /// Currency code written next to the total, if any.
+    currency: Option<Arc<str>>,
+    /// Date of the `date/time` column, if it can be read.
+    date: Option<chrono::NaiveDate>,
+    /// What about the row is to be quarantined, see `quarantine`.
+    suspect: Option<Suspect>,
+}
+
+/// The fields of a row that the script and plugins can change.
+#[derive(Debug, Serialize, Deserialize)]
+struct Fields {
+    #[serde(rename = "type")]
+    kind: String,
+    sku: Option<String>,
+    description: String,
+    quantity: i64,
+    cents: Cents,
+}
+
+/// What a thread parsing rows keeps between batches, see [`parse_rows`].
+struct Worker<'a> {
+    interner: Interner,
+    script: Option<&'a Script>,
+    plugins: Option<plugin::Instances>,
+}
+
+fn parse_row(
+    raw: &StringRecord,
+    hdr: &StringRecord,
+    dedup: &DedupKey,
+    config: &Config,
+    tax: Option<&TaxColumns>,
+    dates: Option<usize>,
+    worker: &mut Worker<'_>,
+) -> Parsed {
+    let r = &*config.normalize.record(raw);
+    let line = r.position().map_or(0, |p| p.line());
+    let mut currency = None;
+    let mut suspect = None;
+    let sale = check_columns(r, hdr)
+        .and_then(|()| Ok(r.deserialize::<RefSale>(Some(hdr))?))
+        .and_then(|sale| {
+            currency = split_currency(sale.total)
+                .1
+                .map(|code| worker.interner.intern(code));
+            suspect = self::suspect(&sale, config);
+            let cents = handle_punct(sale.total, config.amount_rounding)?;
+            let quantity = config.quantity.read(sale.kind, sale.quantity)?;
+            if worker.script.is_none() && worker.plugins.is_none() {
+                let trx = Trx::new(
+                    sale.kind,
+                    sale.sku,
+                    sale.description,
+                    quantity,
+                    cents,
+                    config,
+                    &mut worker.interner,
+                );
+                return Ok(Some((trx, quantity, cents)));
+            }
+            let mut fields = Fields {
+                kind: sale.kind.to_string(),
+                sku: sale.sku.map(str::to_string),
+                description: sale.description.to_string(),
+                quantity,
+                cents,
+            };
+            if let Some(script) = worker.script {
+                let Some(f) = script.row(fields)? else {
+                    return Ok(None);
+                };
+                fields = f;
+            }
+            if let Some(plugins) = &mut worker.plugins {
+                let Some(f) = plugins.row(hdr, r, fields)? else {
+                    return Ok(None);
+                };
+                fields = f;
+            }
+            let trx = Trx::new(
+                &fields.kind,
+                fields.sku.as_deref(),
+                &fields.description,
+                fields.quantity,
+                fields.cents,
+                config,
+                &mut worker.interner,
+            );
+            Ok(Some((trx, fields.quantity, fields.cents)))
+        })
+        .wrap_err(Malformed::Line(line));
+    Parsed {
+        line,
+        key: dedup.key(r).into_owned(),
+        near_key: config.near_duplicates.then(|| near_key(r, hdr)),
+        sale,
+        tax: tax
+            .map(|tax| tax.row(r, &mut worker.interner))
+            .transpose()
+            .wrap_err(Malformed::Line(line)),
+        currency,
+        date: dates.and_then(|i| sheet::parse_date(r.get(i)?)),
+        suspect,
+    }
+}
+
+/// What about `sale`, as written, is to be quarantined, see `quarantine`.
+fn suspect(sale: &RefSale<'_>, config: &Config) -> Option<Suspect> {
+    let (total, _) = split_currency(sale.total);
+    let (_, decimals) = total.split_once('.').unwrap_or_default();
+    let odd = decimals.bytes().skip(2).any(|b| b != b'0');
+    let blank = sale.sku.is_some() && sale.quantity.trim().is_empty();
+    [
+        (odd, Suspect::OddAmounts),
+        (blank, Suspect::MissingQuantities),
+    ]
+    .into_iter()
+    .find(|(found, suspect)| *found && config.quarantine.contains(suspect))
+    .map(|(_, suspect)| suspect)
+}
+
+/// Parses `rows` in order, splitting them between `workers`, each on its own
+/// thread.
+fn parse_rows(
+    rows: &[StringRecord],
+    hdr: &StringRecord,
+    dedup: &DedupKey,
+    config: &Config,
+    tax: Option<&TaxColumns>,
+    dates: Option<usize>,
+    workers: &mut [Worker<'_>],
+) -> Vec<Parsed> {
+    if workers.len() == 1 || rows.len() <= CHUNK {
+        let worker = &mut workers[0];
+        return rows
+            .iter()
+            .map(|raw| parse_row(raw, hdr, dedup, config, tax, dates, worker))
+            .collect();
+    }
+    let size = rows.len().div_ceil(workers.len());
+    std::thread::scope(|s| {
+        let handles = rows
+            .chunks(size)
+            .zip(workers.iter_mut())
+            .map(|(chunk, worker)| {
+                s.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|raw| parse_row(raw, hdr, dedup, config, tax, dates, worker))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+            .collect()
+    })
+}
+
+/// Aggregates the report read from `input`.
+///
+/// Rows are read a batch at a time and parsed in parallel. They are then
+/// looked up in memory and aggregated in order, since whether a row is
+/// flagged as a possible duplicate depends on the rows before it.
+///
+/// Each phase runs in a span named after it, see `--timings`: `parse`, then
+/// `dedup` for looking rows up and adding them up in order, then `aggregate`
+/// for collecting and sorting the output. Reading and decoding the report
+/// happen in `read` and `decode` spans within `parse`.
+fn aggregate(
+    input: impl std::io::Read,
+    config: &Config,
+    memories: &mut Memories,
+    mut trace: Option<&mut explain::Trace>,
+    mut journal: Option<&mut checkpoint::Journal<'_>>,
+    hooks: &mut dyn Hooks,
+    cancel: Option<&Cancel>,
+) -> eyre::Result<Aggregation> {
+    if config.quarantine.contains(&Suspect::NearDuplicates) && !config.near_duplicates {
+        bail!("`quarantine` has \"near-duplicates\", but `near_duplicates` is not set");
+    }
+    let mut rdr = reader(input);
+    let (hdr, mut iter) = find_header(&mut rdr, config)?;
+    let dedup = DedupKey::new(&hdr, config)?;
+    let tax_columns = TaxColumns::new(&hdr, config)?;
+    let dates = hdr.iter().position(|name| name == "date/time");
+    let script = config.script.as_deref().map(Script::load).transpose()?;
+    let plugins = (!config.plugins.is_empty())
+        .then(|| Plugins::load(&config.plugins))
+        .transpose()?;
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut workers = (0..threads)
+        .map(|_| {
+            Ok(Worker {
+                interner: Interner::default(),
+                script: script.as_ref(),
+                plugins: plugins.as_ref().map(Plugins::instantiate).transpose()?,
+            })
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    // Total and quantity of each adjustment.
+    let mut adjustmut_map = HashMap::<Adjustment, (Cents, i64)>::new();
+    let mut with_sku_map = HashMap::<WithSku, Cents>::new();
+
+    let (mut rows, mut duplicates) = (0, 0);
+    let mut duplicate_totals = BTreeMap::<String, Cents>::new();
+    let mut near_duplicates = Vec::new();
+    let mut near_seen = HashSet::new();
+    let mut quarantined = Vec::new();
+    // Rows of every type aggregated though it is not one of `known_types`.
+    let mut unknown = BTreeMap::<Arc<str>, u64>::new();
+    let redact = Redact::new(config);
+    let mut taxes = Taxes::default();
+    let mut currencies = BTreeSet::new();
+    let mut period = sheet::Period::default();
+    let mut days = BTreeSet::new();
+    let mut ledger = ledger::Ledger::default();
+    let settlements = config
+        .journal_entries
+        .is_some()
+        .then(|| hdr.iter().position(|name| name == "settlement id"));
+
+    if let Some(resumed) = journal.as_mut().map(|j| j.resume()).transpose()?.flatten() {
+        tracing::info!(
+            rows = resumed.rows,
+            "resuming an interrupted run from its last checkpoint"
+        );
+        iter.by_ref().take(resumed.rows as usize).for_each(drop);
+        rows = resumed.rows;
+        duplicates = resumed.duplicates;
+        duplicate_totals = resumed.duplicate_totals;
+        adjustmut_map.extend(resumed.adjustments);
+        with_sku_map.extend(resumed.with_sku);
+        taxes = resumed.taxes;
+        currencies = resumed.currencies;
+        period = resumed.period;
+        days = resumed.days;
+        ledger = resumed.ledger;
+        near_duplicates.extend(resumed.near_duplicates.into_iter().map(StringRecord::from));
+        quarantined.extend(
+            (resumed.quarantined.into_iter())
+                .map(|(reason, fields)| (reason, StringRecord::from(fields))),
+        );
+        near_seen = resumed.near_seen;
+        memories.rec.resume(resumed.records);
+        memories.sku.resume(resumed.skus);
+        if let (Some(near), Some(progress)) = (&mut memories.near, resumed.near) {
+            near.resume(progress);
+        }
+    }
+
+    let batch_size = CHUNK * threads;
+    let mut batch = Vec::with_capacity(batch_size);
+    loop {
+        if let Some(cancel) = cancel {
+            cancel.check()?;
+        }
+        let parse = tracing::info_span!("parse").entered();
+        batch.clear();
+        for record in iter.by_ref().take(batch_size) {
+            batch.push(read_record(record)?);
+        }
+        if batch.is_empty() {
+            iter.finish(config)?;
+            break;
+        }
+        let parsed = parse_rows(
+            &batch,
+            &hdr,
+            &dedup,
+            config,
+            tax_columns.as_ref(),
+            dates,
+            &mut workers,
+        );
+        drop(parse);
+        let _dedup = tracing::info_span!("dedup").entered();
+        for (raw, parsed) in batch.iter().zip(parsed) {
+            let Parsed {
+                line,
+                key,
+                near_key,
+                sale,
+                tax,
+                currency,
+                date,
+                mut suspect,
+            } = parsed;
+            rows += 1;
+            let sale = match sale {
+                Ok(None) => {
+                    if let Some(trace) = trace.as_mut() {
+                        trace.dropped(line, memories.rec.hash(&key))?;
+                    }
+                    continue;
+                }
+                Ok(Some(sale)) => Ok(sale),
+                Err(e) => Err(e),
+            };
+            let row = sale
+                .as_ref()
+                .ok()
+                .map(|(trx, qt, cents)| hooks::Row::new(line, trx, *qt, *cents));
+            if row.as_ref().is_some_and(|row| !hooks.row(row)) {
+                if let Some(trace) = trace.as_mut() {
+                    trace.dropped(line, memories.rec.hash(&key))?;
+                }
+                continue;
+            }
+            // Only rows that are aggregated are memorized, so that quarantined
+            // ones are read again once reviewed.
+            if memories.rec.remembers(&key) {
+                memories.rec.memorize(&key);
+                duplicates += 1;
+                if let Ok((trx, qt, cents)) = &sale {
+                    // What aggregating it would have added, see `Sale::with_sku`.
+                    let cents = match trx {
+                        Trx::Adjustment(_) => *cents,
+                        Trx::WithSku(s) => s.cents * qt,
+                    };
+                    *duplicate_totals.entry(trx.kind().to_string()).or_default() += cents;
+                }
+                if let Some(row) = &row {
+                    hooks.duplicate(row);
+                }
+                if let Some(trace) = trace.as_mut() {
+                    trace.duplicate(line, memories.rec.hash(&key))?;
+                }
+                continue;
+            }
+            if let (Some(near), Some(key)) = (&memories.near, near_key.as_deref()) {
+                // Memory only knows about earlier runs, so rows earlier in
+                // this report are checked separately.
+                let seen = !near_seen.insert(seahash::hash(key.as_bytes()));
+                if near.remembers(key) || seen {
+                    match config.quarantine.contains(&Suspect::NearDuplicates) {
+                        true => suspect = suspect.or(Some(Suspect::NearDuplicates)),
+                        false => near_duplicates.push(raw.clone()),
+                    }
+                }
+            }
+            let (trx, qt, cents) = sale?;
+            let kind = trx.kind();
+            let mut review = suspect.map(|suspect| suspect.reason().to_string());
+            if !config.is_known_type(kind) {
+                match config.unknown_types {
+                    UnknownTypes::Warn => *unknown.entry(kind.clone()).or_default() += 1,
+                    UnknownTypes::Quarantine => {
+                        review
+                            .get_or_insert_with(|| format!("`{kind}` is not one of `known_types`"));
+                    }
+                    UnknownTypes::Fail => {
+                        return Err(eyre::eyre!("`{kind}` is not one of `known_types`")
+                            .wrap_err(Malformed::Line(line)));
+                    }
+                }
+            }
+            if let Some(reason) = review {
+                let mut r = redact.record(&hdr, raw);
+                r.set_position(raw.position().cloned());
+                quarantined.push((reason, r));
+                if let Some(trace) = trace.as_mut() {
+                    trace.quarantined(line, memories.rec.hash(&key), &trx)?;
+                }
+                continue;
+            }
+            memories.rec.memorize(&key);
+            if let (Some(near), Some(key)) = (&mut memories.near, near_key) {
+                near.memorize(key);
+            }
+            if let Some(tax) = tax? {
+                taxes.add(tax);
+            }
+            currencies.extend(currency);
+            if let Some(date) = date {
+                period.add(date);
+                days.insert(date);
+            }
+            if let Some(trace) = trace.as_mut() {
+                trace.aggregated(line, memories.rec.hash(&key), &trx)?;
+            }
+            if let Some(column) = settlements {
+                let settlement = column.and_then(|i| raw.get(i)).unwrap_or_default();
+                let (kind, description) = match &trx {
+                    Trx::Adjustment(a) => (&a.kind, &a.description),
+                    Trx::WithSku(s) => (&s.kind, &s.description),
+                };
+                ledger.add(config, settlement, date, kind, description, cents);
+            }
+            match trx {
+                Trx::Adjustment(a) => {
+                    let v = adjustmut_map.entry(a).or_default();
+                    v.0 += cents;
+                    v.1 += qt;
+                }
+                Trx::WithSku(s) => {
+                    memories.sku.memorize(&s.sku);
+                    *with_sku_map.entry(s).or_default() += qt;
+                }
+            };
+        }
+
+        if let Some(journal) = journal.as_deref_mut().filter(|j| j.due(rows)) {
+            let _checkpoint = tracing::info_span!("checkpoint").entered();
+            journal.write(&checkpoint::Checkpoint {
+                rows,
+                duplicates,
+                duplicate_totals: duplicate_totals.clone(),
+                adjustments: adjustmut_map.iter().map(|(k, v)| (k.clone(), *v)).collect(),
+                with_sku: with_sku_map.iter().map(|(k, v)| (k.clone(), *v)).collect(),
+                taxes: taxes.clone(),
+                currencies: currencies.clone(),
+                period,
+                days: days.clone(),
+                ledger: ledger.clone(),
+                near_duplicates: near_duplicates
+                    .iter()
+                    .map(|r| r.iter().map(str::to_string).collect())
+                    .collect(),
+                near_seen: near_seen.clone(),
+                quarantined: quarantined
+                    .iter()
+                    .map(|(reason, r)| (reason.clone(), r.iter().map(str::to_string).collect()))
+                    .collect(),
+                records: memories.rec.progress(false),
+                skus: memories.sku.progress(true),
+                near: memories.near.as_ref().map(|near| near.progress(false)),
+            })?;
+        }
+    }
+
+    for (kind, rows) in unknown {
+        tracing::warn!("`{kind}` is not one of `known_types`, its {rows} rows were aggregated");
+    }
+    if !quarantined.is_empty() {
+        tracing::warn!(
+            "{} rows were left out of the output to be reviewed",
+            quarantined.len()
+        );
+    }
+
+    let _aggregate = tracing::info_span!("aggregate").entered();
+    let mut sales = adjustmut_map
+        .into_iter()
+        .map(|(k, (cents, qt))| Sale::adjustment(k, cents, qt, config))
+        .collect::<Vec<_>>();
+    sales.extend(
+        with_sku_map
+            .into_iter()
+            .map(|(k, qt)| Sale::with_sku(k, qt)),
+    );
+
+    let mut sales = roll_up(sales, config);
+    sales.sort_unstable_by(|a, b| a.compare(b, &config.sort));
+    for sale in &sales {
+        hooks.aggregate(&hooks::Aggregate {
+            kind: &sale.kind,
+            sku: &sale.sku,
+            description: &sale.description,
+            quantity: sale.quantity,
+            cents: sale.cents,
+        });
+    }
+
+    let accounts = accounts::summarize(&sales, config);
+    Ok(Aggregation {
+        sales,
+        rows,
+        duplicates,
+        duplicate_totals,
+        header: hdr,
+        near_duplicates,
+        quarantined,
+        taxes,
+        currencies,
+        period,
+        days,
+        accounts,
+        ledger,
+    })
+}
+
+/// A report that a run has aggregated and rendered the output of, but
+/// written nothing for yet, see [`Report::parse_batch`].
+struct Staged<'c> {
+    input: Input,
+    aggregation: Aggregation,
+    rendered: Vec<u8>,
+    /// Checksum of `rendered`.
+    checksum: String,
+    journal: Option<checkpoint::Journal<'c>>,
+}
+
+/// Reads the report from `source` and aggregates it against `memories`,
+/// rendering the output in `format`.
+#[allow(clippy::too_many_arguments)]
+fn stage<'c>(
+    source: Source<'_>,
+    format: Format,
+    password: Option<&str>,
+    config: &'c Config,
+    memories: &mut Memories,
+    checkpoints: bool,
+    hooks: &mut dyn Hooks,
+    cancel: &Cancel,
+    now: chrono::DateTime<chrono::Local>,
+) -> eyre::Result<Staged<'c>> {
+    let read = tracing::info_span!("read").entered();
+    let (input, contents) = match source {
+        Source::Path(path) => (Input::new(path)?, None),
+        Source::Reader(reader) => {
+            let mut contents = Vec::new();
+            reader.read_to_end(&mut contents)?;
+            (
+                Input::from_contents(Path::new("-"), &contents)?,
+                Some(contents),
+            )
+        }
+    };
+    input.archive(config, contents.as_deref())?;
+    drop(read);
+    let mut trace = config
+        .explain
+        .as_deref()
+        .map(|explain| explain::Trace::open(explain, &input.path, Redact::new(config)))
+        .transpose()?;
+    let report: Box<dyn std::io::Read> = match &contents {
+        Some(contents) => Box::new(contents.as_slice()),
+        None => Box::new(File::open(&input.path)?),
+    };
+    let mut journal = checkpoints
+        .then(|| checkpoint::Journal::new(config, &input.checksum))
+        .flatten();
+    let aggregation = aggregate(
+        report,
+        config,
+        memories,
+        trace.as_mut(),
+        journal.as_mut(),
+        hooks,
+        Some(cancel),
+    )?;
+    closing::check(config, &aggregation.days)?;
+    if let Some(trace) = &mut trace {
+        trace.flush()?;
+    }
+
+    let _render = tracing::info_span!("write").entered();
+    let sheet = sheet::name(
+        config.sheet_name.as_deref(),
+        &input.path,
+        now.date_naive(),
+        aggregation.period,
+    );
+    let rendered = render_output(&aggregation, format, &sheet, password)?;
+    let checksum = checksum(rendered.as_slice())?;
+    if !aggregation.sales.is_empty() {
+        warn_exported(config, aggregation.period, &checksum)?;
+    }
+    Ok(Staged {
+        input,
+        aggregation,
+        rendered,
+        checksum,
+        journal,
+    })
+}
+
+impl Staged<'_> {
+    /// What processing the report read and wrote, its output at `output`.
+    fn summary(&self, output: PathBuf) -> Summary {
+        let aggregation = &self.aggregation;
+        Summary {
+            inputs: vec![self.input.clone()],
+            output,
+            rows: aggregation.rows,
+            duplicates: aggregation.duplicates,
+            duplicate_totals: aggregation.duplicate_totals.clone(),
+            near_duplicates: aggregation.near_duplicates.len() as u64,
+            quarantined: aggregation.quarantined.len() as u64,
+            aggregates: aggregation.sales.len() as u64,
+            period: aggregation.period.days(),
+            checksum: Some(self.checksum.clone()),
+            totals: aggregation.totals(),
+            currencies: aggregation.currencies(),
+            analytics: None,
+            alerts: Vec::new(),
+        }
+    }
+
+    /// Writes the output to `output`, and the files that go with it named
+    /// after `date`, adding every file written to `written`. Returns where
+    /// the output was written.
+    fn write(
+        &self,
+        output: Output<'_>,
+        config: &Config,
+        date: &str,
+        written: &mut Vec<PathBuf>,
+    ) -> eyre::Result<PathBuf> {
+        let format = output.format();
+        let output = match output {
+            Output::File(format) => {
+                let path = write_output(
+                    Path::new(&format!("AGGREGATED_{date}.{}", format.extension())),
+                    &self.rendered,
+                )?;
+                written.push(path.clone());
+                path
+            }
+            Output::Writer(writer, _) => {
+                writer.write_all(&self.rendered)?;
+                writer.flush()?;
+                PathBuf::from("-")
+            }
+        };
+        let mut write = |path: String, f: &dyn Fn(&str) -> eyre::Result<()>| {
+            f(&path)?;
+            if Path::new(&path).try_exists()? {
+                written.push(path.into());
+            }
+            Ok::<_, eyre::Report>(())
+        };
+        let aggregation = &self.aggregation;
+        if !matches!(format, Format::Xlsx | Format::Ods) {
+            write(format!("ACCOUNTS_{date}.csv"), &|path| {
+                aggregation.write_accounts(path)
+            })?;
+            write(format!("NEEDS_REVIEW_{date}.csv"), &|path| {
+                aggregation.write_quarantined(path)
+            })?;
+        }
+        if let Some(layout) = &config.journal_entries {
+            write(format!("JOURNAL_ENTRIES_{date}.csv"), &|path| {
+                aggregation.ledger.write(layout, path)
+            })?;
+        }
+        write(format!("POSSIBLE_DUPLICATES_{date}.csv"), &|path| {
+            aggregation.write_near_duplicates(path, &Redact::new(config))
+        })?;
+        if config.pdf_summary {
+            let input = self.input.path.display().to_string();
+            write(format!("SUMMARY_{date}.pdf"), &|path| {
+                Ok(std::fs::write(path, pdf::render(aggregation, &input))?)
+            })?;
+        }
+        Ok(output)
+    }
+}
+
+/// Description of the aggregate that small ones are rolled into, see
+/// [`roll_up`].
+const MISCELLANEOUS: &str = "Miscellaneous";
+
+/// Rolls the aggregates of each type whose total is below
+/// `miscellaneous_below`, either way, into one described as
+/// [`MISCELLANEOUS`], summing their quantities and totals. A type with a
+/// single such aggregate keeps it as it is.
+fn roll_up(sales: Vec<Sale>, config: &Config) -> Vec<Sale> {
+    let Some(below) = config.miscellaneous_below else {
+        return sales;
+    };
+    let below = (below * 100.0).round() as Cents;
+    let (small, mut kept) = (sales.into_iter()).partition::<Vec<_>, _>(|s| s.cents.abs() < below);
+    let mut by_kind = BTreeMap::<Arc<str>, Vec<Sale>>::new();
+    for sale in small {
+        by_kind.entry(sale.kind.clone()).or_default().push(sale);
+    }
+    for (kind, mut small) in by_kind {
+        if small.len() < 2 {
+            kept.append(&mut small);
+            continue;
+        }
+        kept.push(Sale {
+            sku: Arc::from(config.adjustment_sku(&kind, MISCELLANEOUS)),
+            kind,
+            description: Arc::from(MISCELLANEOUS),
+            quantity: small.iter().map(|s| s.quantity).sum(),
+            cents: small.iter().map(|s| s.cents).sum(),
+        });
+    }
+    kept
+}
+
+#[derive(Debug)]
+enum Trx {
+    Adjustment(Adjustment),
+    WithSku(WithSku),
+}
+
+impl Trx {
+    fn kind(&self) -> &Arc<str> {
+        match self {
+            Trx::Adjustment(a) => &a.kind,
+            Trx::WithSku(s) => &s.kind,
+        }
+    }
+
+    /// Classifies a row by whether it has a SKU, with its description
+    /// rewritten by `description_rules`, sharing its strings through
+    /// `interner`.
+    fn new(
+        kind: &str,
+        sku: Option<&str>,
+        description: &str,
+        quantity: i64,
+        total: Cents,
+        config: &Config,
+        interner: &mut Interner,
+    ) -> Self {
+        let description = interner.intern(&config.description(kind, description));
+        let kind = interner.intern(kind);
+        match sku {
+            Some(sku) => Trx::WithSku(WithSku {
+                kind,
+                sku: interner.intern(sku),
+                // Div by 0 is None => cents
+                cents: total.checked_div(quantity).unwrap_or(total),
+                description,
+            }),
+            None => Trx::Adjustment(Adjustment { kind, description }),
+        }
+    }
+}
+
+/// An amount of money, in hundredths of the report's currency.
+pub type Cents = i64;
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+struct Adjustment {
+    kind: Arc<str>,
+    description: Arc<str>,
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+struct WithSku {
+    kind: Arc<str>,
+    sku: Arc<str>,
+    cents: Cents,
+    description: Arc<str>,
+}
+
+/// `field` without the quotes Excel keeps around a number re-exported as
+/// text, as in `="12.34"`, or `"12.34"` with the quotes inside the field.
+pub(crate) fn unquote_number(field: &str) -> &str {
+    let field = field.trim();
+    let quoted = field.strip_prefix('=').unwrap_or(field);
+    match quoted.strip_prefix('"').and_then(|f| f.strip_suffix('"')) {
+        Some(number) => number.trim(),
+        None => field,
+    }
+}
+
+/// Splits an amount such as `USD 12.34` or `12.34\u{a0}EUR` into the
+/// number, without any whitespace, and its ISO 4217 currency code.
+fn split_currency(total: &str) -> (Cow<'_, str>, Option<&str>) {
+    let total = unquote_number(total);
+    let is_code = |code: &&str| code.len() == 3 && code.bytes().all(|b| b.is_ascii_uppercase());
+    let (number, currency) = match (
+        total.get(..3).filter(is_code),
+        total.get(total.len().saturating_sub(3)..).filter(is_code),
+    ) {
+        (Some(code), _) => (&total[3..], Some(code)),
+        (None, Some(code)) => (&total[..total.len() - 3], Some(code)),
+        (None, None) => (total, None),
+    };
+    let number = number.trim();
+    if number.contains(char::is_whitespace) {
+        let number = number.chars().filter(|c| !c.is_whitespace()).collect();
+        (Cow::Owned(number), currency)
+    } else {
+        (Cow::Borrowed(number), currency)
+    }
+}
+
+/// Whether `digits` are digits, with commas only between groups of three
+/// after the first, as in `1,345`.
+pub(crate) fn is_grouped(digits: &str) -> bool {
+    let is_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    match digits.split_once(',') {
+        None => is_digits(digits),
+        Some((first, rest)) => {
+            (1..=3).contains(&first.len())
+                && is_digits(first)
+                && rest
+                    .split(',')
+                    .all(|group| group.len() == 3 && is_digits(group))
+        }
+    }
+}
+
+/// Reads an amount such as `-1,345.30` or `USD 12.34` into cents, rounding
+/// any decimals past the second with `rounding`.
+///
+/// Commas are only accepted between groups of three digits, so that
+/// something like `1.2.3` or `1,2` is an error rather than a made up number.
+fn handle_punct(written: &str, rounding: Rounding) -> eyre::Result<i64> {
+    let (total, _) = split_currency(written);
+    let total = &*total;
+    if total.is_empty() {
+        bail!("empty amount");
+    }
+    let not_amount = || eyre::eyre!("{written:?} is not an amount");
+    let is_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    let (whole, dec) = total.split_once('.').unwrap_or((total, ""));
+    let digits = whole.strip_prefix('-').unwrap_or(whole);
+    if !is_grouped(digits)
+        || !is_digits(dec)
+        || (total.contains('.') && dec.is_empty())
+        || (digits.is_empty() && dec.is_empty())
+    {
+        return Err(not_amount());
+    }
+    let (kept, rest) = dec.split_at(dec.len().min(2));
+    let cents = format!("{whole}{kept:0<2}")
+        .replace(',', "")
+        .parse::<i64>()
+        .map_err(|_| not_amount())?;
+    if rest.is_empty() {
+        return Ok(cents);
+    }
+    let away = if whole.trim_start().starts_with('-') {
+        cents - 1
+    } else {
+        cents + 1
+    };
+    let exact = rest.bytes().all(|b| b == b'0');
+    let (first, tail_zero) = (rest.as_bytes()[0], rest[1..].bytes().all(|b| b == b'0'));
+    Ok(match rounding {
+        Rounding::Reject => bail!("{written:?} has more than two decimals"),
+        _ if exact => cents,
+        Rounding::Truncate => cents,
+        Rounding::HalfUp if first >= b'5' => away,
+        Rounding::HalfUp => cents,
+        Rounding::HalfEven if first > b'5' || first == b'5' && !tail_zero => away,
+        Rounding::HalfEven if first == b'5' && cents % 2 != 0 => away,
+        Rounding::HalfEven => cents,
+    })
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     #[test]
     fn assert_punct() {
-        assert_eq!(handle_punct("1.00").unwrap_or_default(), 100);
-        assert_eq!(handle_punct("1.0").unwrap_or_default(), 100);
-        assert_eq!(handle_punct("1").unwrap_or_default(), 100);
-        assert_eq!(handle_punct("1,345.3").unwrap_or_default(), 134_530);
-        assert_eq!(handle_punct("0.30").unwrap_or_default(), 30);
-        assert!(handle_punct("0.300").is_err());
+        let punct = |total| handle_punct(total, Rounding::Reject).unwrap_or_default();
+        assert_eq!(punct("1.00"), 100);
+        assert_eq!(punct("1.0"), 100);
+        assert_eq!(punct("1"), 100);
+        assert_eq!(punct("1,345.3"), 134_530);
+        assert_eq!(punct("0.30"), 30);
+        assert_eq!(punct("-0.30"), -30);
+        assert!(handle_punct("0.300", Rounding::Reject).is_err());
+        assert!(handle_punct("1.", Rounding::Reject).is_err());
+        assert!(handle_punct("", Rounding::Reject).is_err());
+        assert_eq!(punct(".5"), 50);
+        assert_eq!(punct("-1,000,000"), -100_000_000);
+        for garbage in [
+            "1.2.3",
+            "1,2",
+            "1,0000.00",
+            ",100",
+            "-",
+            "1-2",
+            "99999999999999999999",
+        ] {
+            let e = handle_punct(garbage, Rounding::Reject).unwrap_err();
+            assert!(e.to_string().contains(garbage), "{e}");
+        }
+    }
+
+    #[test]
+    fn currencies() {
+        let punct = |total| handle_punct(total, Rounding::Reject).unwrap();
+        assert_eq!(punct("12.34 "), 1_234);
+        assert_eq!(punct("USD 12.34"), 1_234);
+        assert_eq!(punct("-12.34\u{a0}EUR"), -1_234);
+        assert_eq!(punct("1\u{202f}234.56 EUR"), 123_456);
+        assert_eq!(split_currency("GBP-1.00"), ("-1.00".into(), Some("GBP")));
+        assert_eq!(split_currency("1.00"), ("1.00".into(), None));
+        assert!(handle_punct("USD", Rounding::Reject).is_err());
+        assert_eq!(punct("=\"-1,234.56\""), -123_456);
+        assert_eq!(punct(" \"12.34\" "), 1_234);
+        assert!(handle_punct("=12.34", Rounding::Reject).is_err());
+    }
+
+    #[test]
+    fn alternate_names() {
+        let path = Path::new("out/AGGREGATED_2024-01-01.xlsx");
+        assert_eq!(
+            alternate_name(path, 2),
+            Path::new("out/AGGREGATED_2024-01-01-2.xlsx")
+        );
+        assert_eq!(alternate_name(Path::new("out"), 1), Path::new("out-1"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_only_output() {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o555)).unwrap();
+        // Root writes anyway, which leaves nothing to test.
+        let denied = std::fs::write(dir.join("probe"), b"").is_err();
+        if denied {
+            let error = write_output(&dir.join("out.csv"), b"").unwrap_err();
+            let io = error.root_cause().downcast_ref::<std::io::Error>().unwrap();
+            assert_eq!(io.kind(), std::io::ErrorKind::PermissionDenied);
+            assert!(!error.to_string().contains("in use"), "{error}");
+            assert!(!dir.join("out-1.csv").exists());
+        }
+        // Writable again, so that it can be removed.
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn rounding() {
+        let round = |total, rounding| handle_punct(total, rounding).unwrap();
+        assert_eq!(round("0.300", Rounding::HalfEven), 30);
+        assert_eq!(round("0.125", Rounding::HalfEven), 12);
+        assert_eq!(round("0.135", Rounding::HalfEven), 14);
+        assert_eq!(round("0.1251", Rounding::HalfEven), 13);
+        assert_eq!(round("-0.135", Rounding::HalfEven), -14);
+        assert_eq!(round("-0.125", Rounding::HalfUp), -13);
+        assert_eq!(round("0.124", Rounding::HalfUp), 12);
+        assert_eq!(round("1,000.999", Rounding::Truncate), 100_099);
+    }
+
+    #[test]
+    fn dedup_key_columns() {
+        let hdr = StringRecord::from(vec!["date/time", "type", "order id", "total"]);
+        let a = StringRecord::from(vec!["Mar 1", "Order", "111-1", "1,000.00"]);
+        let b = StringRecord::from(vec!["Mar 2", "Order", "111-1", "1000.00"]);
+        let config = |dedup, names: &[&str]| Config {
+            dedup,
+            dedup_key: names.iter().map(|s| s.to_string()).collect(),
+            ..Config::default()
+        };
+
+        let whole = DedupKey::new(&hdr, &config(Dedup::Row, &[])).unwrap();
+        assert_ne!(whole.key(&a), whole.key(&b));
+
+        let order = DedupKey::new(&hdr, &config(Dedup::OrderId, &[])).unwrap();
+        assert_eq!(order.key(&a), order.key(&b));
+
+        let some = DedupKey::new(&hdr, &config(Dedup::Row, &["order id", "type"])).unwrap();
+        assert_eq!(some.key(&a), some.key(&b));
+
+        assert!(DedupKey::new(&hdr, &config(Dedup::Row, &["sku"])).is_err());
+    }
+
+    #[test]
+    fn aggregate_bytes() {
+        let report = b"\"preamble\"\n\
+            type,sku,description,quantity,total\n\
+            Order,A,Widget,2,10.00\n\
+            Order,A,Widget,1,5.00\n\
+            Service Fee,,Advertising,,-1.50\n";
+        let aggregated = Report::aggregate_bytes(report, &Config::default()).unwrap();
+        assert_eq!(aggregated.rows, 3);
+        assert_eq!(aggregated.aggregates, 2);
+        assert_eq!(aggregated.totals["Order"], 1_500);
+        assert_eq!(aggregated.totals["Service Fee"], -150);
+        assert!(aggregated.taxes.is_empty(), "no tax columns");
+        #[cfg(feature = "xlsx")]
+        assert!(aggregated.output.starts_with(b"PK"), "an xlsx is a zip");
+    }
+
+    #[test]
+    fn cancels() {
+        let report = b"type,sku,description,quantity,total\n\
+            Order,A,Widget,1,5.00\n";
+        let cancel = Cancel::default();
+        let run = || {
+            let memories = &mut Memories::default();
+            aggregate(
+                &report[..],
+                &Config::default(),
+                memories,
+                None,
+                None,
+                &mut (),
+                Some(&cancel),
+            )
+        };
+        assert!(run().is_ok());
+        cancel.clone().cancel();
+        let Err(e) = run() else {
+            panic!("a cancelled run aggregates nothing");
+        };
+        assert_eq!(e.downcast_ref::<Cancelled>(), Some(&Cancelled));
+    }
+
+    #[test]
+    fn repeated_header() {
+        let report = b"type,sku,description,quantity,total\n\
+            Order,A,Widget,2,10.00\n\
+            type,sku,description,quantity,total\n\
+            Order,A,Widget,1,5.00\n";
+        let aggregated = Report::aggregate_bytes(report, &Config::default()).unwrap();
+        assert_eq!(aggregated.rows, 2);
+        assert_eq!(aggregated.totals["Order"], 1_500);
+    }
+
+    #[test]
+    fn excel_quirks() {
+        let report = b"\xef\xbb\xbftype,sku,description,quantity,total,\n\
+            Order,A,Widget,\"=\"\"2\"\"\",\"=\"\"1,000.00\"\"\",\n\
+            Order,A,Widget,1,5.00\n\
+            type,sku,description,quantity,total,,\n";
+        let aggregated = Report::aggregate_bytes(report, &Config::default()).unwrap();
+        assert_eq!(aggregated.rows, 2);
+        assert_eq!(aggregated.totals["Order"], 100_500);
+    }
+
+    #[test]
+    fn output_formats() {
+        let report = b"type,sku,description,quantity,total\n\
+            Order,A,Widget,2,10.00\n\
+            Service Fee,,Advertising,,-1.50\n";
+        let aggregation = aggregate(
+            &report[..],
+            &Config::default(),
+            &mut Memories::default(),
+            None,
+            None,
+            &mut (),
+            None,
+        )
+        .unwrap();
+        let render = |format| {
+            String::from_utf8(render_output(&aggregation, format, "Sheet1", None).unwrap())
+        };
+        assert_eq!(
+            render(Format::Csv).unwrap(),
+            "Type,SKU,Description,Quantity,Total\n\
+             Order,A,Widget,2,10.0\n\
+             Service Fee,FBATF,Advertising,-1,-1.5\n"
+        );
+        let json: serde_json::Value = serde_json::from_str(&render(Format::Json).unwrap()).unwrap();
+        assert_eq!(json[1]["SKU"], "FBATF");
+        assert_eq!(json[1]["Total"], -1.5);
+
+        let ods = render_output(&aggregation, Format::Ods, "Sheet1", None).unwrap();
+        let mut ods = zip::ZipArchive::new(std::io::Cursor::new(ods)).unwrap();
+        assert_eq!(ods.by_index(0).unwrap().name(), "mimetype");
+        let content = std::io::read_to_string(ods.by_name("content.xml").unwrap()).unwrap();
+        assert!(content.contains(r#"<table:table table:name="Sheet1">"#));
+        assert!(content.contains(r#"office:value-type="float" office:value="-1.5""#));
+
+        let html = render(Format::Html).unwrap();
+        assert!(html.contains("<title>Sheet1</title>"));
+        assert!(html.contains(r#"<td class="number negative" data-value="-150">-1.50</td>"#));
+
+        #[cfg(feature = "arrow")]
+        {
+            let arrow = render_output(&aggregation, Format::Arrow, "Sheet1", None).unwrap();
+            let rdr = arrow_ipc::reader::FileReader::try_new(std::io::Cursor::new(arrow), None);
+            let batches = rdr.unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+            let totals = batches[0].column_by_name("Total").unwrap();
+            let totals = totals.as_any().downcast_ref::<arrow_array::Float64Array>();
+            assert_eq!(totals.unwrap().values(), &[10.0, -1.5]);
+        }
+
+        let config: Config = toml::from_str(r#"output_password_env = "PASSWORD""#).unwrap();
+        assert!(output_password(&config, Format::Csv).is_err());
+    }
+
+    #[test]
+    fn malformed() {
+        let malformed = |report: &[u8]| {
+            Report::aggregate_bytes(report, &Config::default())
+                .unwrap_err()
+                .downcast_ref::<Malformed>()
+                .copied()
+        };
+        assert_eq!(malformed(b"a,b\n1,2\n"), Some(Malformed::Header));
+        assert_eq!(
+            malformed(b"type,sku,description,quantity,total\nOrder,A,Widget,1,1.2.3\n"),
+            Some(Malformed::Line(2))
+        );
+        assert_eq!(
+            malformed(b"type,sku,description,quantity,total\nOrder,A\n"),
+            Some(Malformed::Line(2))
+        );
+        assert_eq!(
+            malformed(b"total,type,sku,description,quantity\n1.00,Order,A,Widget\n"),
+            Some(Malformed::Line(2)),
+            "a missing quantity is not read as blank"
+        );
+        assert_eq!(
+            malformed(b"type,sku,description,quantity,total\nOrder,A,Widget,\"1,5\",1.00\n"),
+            Some(Malformed::Line(2)),
+            "a decimal comma is not a thousands separator"
+        );
+
+        let config: Config = toml::from_str(r#"dedup_key = ["order id"]"#).unwrap();
+        let report = b"type,sku,description,quantity,total\n";
+        let e = Report::aggregate_bytes(report, &config).unwrap_err();
+        assert!(
+            e.downcast_ref::<Malformed>().is_none(),
+            "a setting is wrong"
+        );
+    }
+
+    #[test]
+    fn truncated() {
+        let truncated = |report: &[u8], config: &Config| {
+            Report::aggregate_bytes(report, config)
+                .err()
+                .and_then(|e| e.downcast_ref::<Malformed>().copied())
+                == Some(Malformed::Truncated)
+        };
+        let config = Config::default();
+        let report = b"type,sku,description,quantity,total\nOrder,A,Widget,1,1.0";
+        assert!(truncated(report, &config));
+        let report = b"\"Total rows: 2\"\n\
+            type,sku,description,quantity,total\n\
+            Order,A,Widget,1,1.00\n";
+        assert!(truncated(report, &config));
+        let report = b"\"Total rows: 1\"\n\
+            type,sku,description,quantity,total\n\
+            Order,A,Widget,1,1.00\r\n";
+        assert!(!truncated(report, &config));
+
+        let config: Config = toml::from_str(r#"truncated_reports = "warn""#).unwrap();
+        let report = b"type,sku,description,quantity,total\nOrder,A,Widget,1,1.0";
+        assert!(!truncated(report, &config));
+    }
+
+    #[test]
+    fn short_rows() {
+        let report = b"total,type,sku,description,quantity\n\
+            1.00,Order,A,Widget\n\
+            2.00,Order,A,Widget,1\n";
+        let config: Config = toml::from_str(r#"short_rows = "skip""#).unwrap();
+        let aggregated = Report::aggregate_bytes(report, &config).unwrap();
+        assert_eq!(aggregated.rows, 1);
+        assert_eq!(aggregated.totals["Order"], 200);
+    }
+
+    #[test]
+    fn unknown_types() {
+        let report = b"type,sku,description,quantity,total\n\
+            Order,A,Widget,1,5.00\n\
+            Storage Surcharge,,Storage,,-2.00\n";
+        let config = |policy: &str| -> Config {
+            toml::from_str(&format!(
+                "known_types = [\"Order\"]\nunknown_types = \"{policy}\""
+            ))
+            .unwrap()
+        };
+        let warned = Report::aggregate_bytes(report, &config("warn")).unwrap();
+        assert_eq!(warned.totals["Storage Surcharge"], -200);
+
+        let quarantined = Report::aggregate_bytes(report, &config("quarantine")).unwrap();
+        assert_eq!(quarantined.quarantined, 1);
+        assert_eq!(quarantined.rows, 2);
+        assert_eq!(quarantined.totals.keys().collect::<Vec<_>>(), ["Order"]);
+
+        let e = Report::aggregate_bytes(report, &config("fail")).unwrap_err();
+        assert_eq!(
+            format!("{e:#}"),
+            "line 3: `Storage Surcharge` is not one of `known_types`"
+        );
+        // Which the CLI exits with 2 for.
+        assert_eq!(e.downcast_ref::<Malformed>(), Some(&Malformed::Line(3)));
+    }
+
+    #[test]
+    fn quarantines_suspect_rows() {
+        let report = b"type,sku,description,quantity,total,order city\n\
+            Order,A,Widget,1,5.00,Berlin\n\
+            Order,A,Widget,1,5.125,Berlin\n\
+            Order,B,Gadget,,3.00,Berlin\n\
+            Order, A,Widget,1,5.00,Berlin\n\
+            Storage Surcharge,,Storage,,-2.00,Berlin\n";
+        let config: Config = toml::from_str(
+            r#"
+            near_duplicates = true
+            quarantine = ["odd-amounts", "missing-quantities", "near-duplicates"]
+            known_types = ["Order"]
+            unknown_types = "quarantine"
+            redact = ["order city"]
+            "#,
+        )
+        .unwrap();
+        let aggregation = aggregate(
+            &report[..],
+            &config,
+            &mut Memories {
+                near: Some(Memory::default()),
+                ..Memories::default()
+            },
+            None,
+            None,
+            &mut (),
+            None,
+        )
+        .unwrap();
+        assert_eq!(aggregation.totals(), [("Order".to_string(), 500)].into());
+        assert!(aggregation.near_duplicates.is_empty());
+        let rows = aggregation.quarantined();
+        assert_eq!(
+            rows[0],
+            [
+                "line",
+                "reason",
+                "type",
+                "sku",
+                "description",
+                "quantity",
+                "total",
+                "order city"
+            ]
+        );
+        let reasons = rows[1..]
+            .iter()
+            .map(|row| (row[0].as_str(), row[1].as_str()));
+        assert_eq!(
+            reasons.collect::<Vec<_>>(),
+            [
+                ("3", "amount has more than two decimals"),
+                ("4", "quantity is blank"),
+                ("5", "looks like a row seen before"),
+                ("6", "`Storage Surcharge` is not one of `known_types`"),
+            ]
+        );
+        assert_eq!(rows[1][7], "[redacted]");
+
+        let config: Config = toml::from_str(r#"quarantine = ["near-duplicates"]"#).unwrap();
+        assert!(Report::aggregate_bytes(report, &config).is_err());
+    }
+
+    #[test]
+    fn totals_duplicates() {
+        let first = b"type,sku,description,quantity,total\n\
+            Order,A,Widget,3,10.00\n\
+            Service Fee,,Advertising,,-1.50\n";
+        let second = b"type,sku,description,quantity,total\n\
+            Order,A,Widget,3,10.00\n\
+            Service Fee,,Advertising,,-1.50\n\
+            Order,B,Gadget,1,4.00\n";
+        let config = Config::default();
+        let mut memories = Memories::default();
+        let run = |report: &[u8], memories: &mut Memories| {
+            aggregate(report, &config, memories, None, None, &mut (), None).unwrap()
+        };
+        run(&first[..], &mut memories);
+        memories.rec.settle();
+        let again = run(&second[..], &mut memories);
+        assert_eq!(again.duplicates, 2);
+        assert_eq!(
+            again.duplicate_totals,
+            [
+                ("Order".to_string(), 999),
+                ("Service Fee".to_string(), -150)
+            ]
+            .into()
+        );
+
+        // What a replay reads, without memory.
+        let mut replayed = run(&second[..], &mut Memories::default()).totals();
+        for (kind, cents) in &again.duplicate_totals {
+            *replayed.get_mut(kind).unwrap() -= cents;
+        }
+        replayed.retain(|_, cents| *cents != 0);
+        assert_eq!(replayed, again.totals());
+    }
+
+    #[test]
+    fn rereads_quarantined_rows() {
+        let report = b"type,sku,description,quantity,total\n\
+            Order,A,Widget,1,5.00\n\
+            Storage Surcharge,,Storage,,-2.00\n";
+        let config = |known: &str| -> Config {
+            toml::from_str(&format!(
+                r#"
+                near_duplicates = true
+                quarantine = ["near-duplicates"]
+                known_types = [{known}]
+                unknown_types = "quarantine"
+                "#
+            ))
+            .unwrap()
+        };
+        let mut memories = Memories {
+            near: Some(Memory::default()),
+            ..Memories::default()
+        };
+        let mut run = |config: &Config| {
+            let aggregation = aggregate(
+                &report[..],
+                config,
+                &mut memories,
+                None,
+                None,
+                &mut (),
+                None,
+            )
+            .unwrap();
+            memories.rec.settle();
+            memories.near.as_mut().unwrap().settle();
+            aggregation
+        };
+
+        let first = run(&config(r#""Order""#));
+        assert_eq!(first.totals(), [("Order".to_string(), 500)].into());
+        assert_eq!(first.quarantined.len(), 1);
+
+        let second = run(&config(r#""Order""#));
+        assert_eq!(second.duplicates, 1);
+        assert!(second.totals().is_empty());
+        assert_eq!(second.quarantined.len(), 1, "listed again");
+
+        let third = run(&config(r#""Order", "Storage Surcharge""#));
+        assert_eq!(third.duplicates, 1);
+        assert_eq!(
+            third.totals(),
+            [("Storage Surcharge".to_string(), -200)].into()
+        );
+        assert!(
+            third.quarantined.is_empty(),
+            "not a near duplicate of itself"
+        );
+        assert!(third.near_duplicates.is_empty());
+    }
+
+    #[test]
+    fn adjustment_skus() {
+        let report = b"type,sku,description,quantity,total\n\
+            Service Fee,,Cost of Advertising,,-1.50\n\
+            FBA Inventory Fee,,FBA Long-Term Storage Fee,,-2.00\n\
+            Transfer,,To account,,-5.00\n";
+        let config: Config = toml::from_str(
+            r#"
+            [[adjustment_sku_rules]]
+            type = "Service Fee"
+            sku = "6100"
+            [[adjustment_sku_rules]]
+            description = "Storage"
+            sku = "6200"
+            "#,
+        )
+        .unwrap();
+        let aggregation = aggregate(
+            &report[..],
+            &config,
+            &mut Memories::default(),
+            None,
+            None,
+            &mut (),
+            None,
+        )
+        .unwrap();
+        let skus = aggregation
+            .sales
+            .iter()
+            .map(|sale| (&*sale.kind, &*sale.sku))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            skus,
+            [
+                ("FBA Inventory Fee", "6200"),
+                ("Service Fee", "6100"),
+                ("Transfer", "FBATF"),
+            ]
+        );
+    }
+
+    #[test]
+    fn rewrites_descriptions() {
+        let report = b"type,sku,description,quantity,total\n\
+            FBA Inventory Fee,,FBA Inventory Fee (2024-03-12),,-0.01\n\
+            FBA Inventory Fee,,FBA Inventory Fee (2024-03-13),,-0.02\n\
+            Service Fee,,Refund for order 111-2233445-5566778,,1.00\n\
+            Order,A,Widget (2024-03-12),1,5.00\n";
+        let config: Config = toml::from_str(
+            r#"
+            [[description_rules]]
+            type = "FBA Inventory Fee"
+            pattern = '\(\d{4}-\d{2}-\d{2}\)'
+            [[description_rules]]
+            pattern = '\d{3}-\d{7}-\d{7}'
+            replacement = "(order)"
+            "#,
+        )
+        .unwrap();
+        let aggregation = aggregate(
+            &report[..],
+            &config,
+            &mut Memories::default(),
+            None,
+            None,
+            &mut (),
+            None,
+        )
+        .unwrap();
+        let sales = (aggregation.sales.iter())
+            .map(|sale| (&*sale.description, sale.cents))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sales,
+            [
+                ("FBA Inventory Fee", -3),
+                ("Widget (2024-03-12)", 500),
+                ("Refund for order (order)", 100),
+            ]
+        );
+        assert!(toml::from_str::<Config>("[[description_rules]]\npattern = '('").is_err());
+    }
+
+    #[test]
+    fn rolls_up_small_aggregates() {
+        let report = b"type,sku,description,quantity,total\n\
+            FBA Inventory Fee,,Storage,,-0.01\n\
+            FBA Inventory Fee,,Labeling,,-0.02\n\
+            FBA Inventory Fee,,Removal,,-12.00\n\
+            Order,A,Widget,1,0.40\n\
+            Order,B,Gadget,1,9.00\n";
+        let config: Config = toml::from_str("miscellaneous_below = 0.50").unwrap();
+        let aggregation = aggregate(
+            &report[..],
+            &config,
+            &mut Memories::default(),
+            None,
+            None,
+            &mut (),
+            None,
+        )
+        .unwrap();
+        let sales = (aggregation.sales.iter())
+            .map(|sale| (&*sale.kind, &*sale.description, sale.quantity, sale.cents))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sales,
+            [
+                ("FBA Inventory Fee", "Miscellaneous", -2, -3),
+                ("FBA Inventory Fee", "Removal", -1, -1_200),
+                ("Order", "Gadget", 1, 900),
+                ("Order", "Widget", 1, 40),
+            ]
+        );
+    }
+
+    #[test]
+    fn sorts_by_keys() {
+        let sale = |kind: &str, sku: &str, cents| Sale {
+            kind: kind.into(),
+            sku: sku.into(),
+            cents,
+            ..Sale::default()
+        };
+        let mut sales = [
+            sale("Order", "B", 500),
+            sale("Refund", "A", -200),
+            sale("Order", "A", 500),
+            sale("Order", "C", 900),
+        ];
+        let keys = ["total:desc", "sku"].map(|key| key.parse::<SortKey>().unwrap());
+        sales.sort_unstable_by(|a, b| a.compare(b, &keys));
+        let sorted = sales.iter().map(|s| &*s.sku).collect::<Vec<_>>();
+        assert_eq!(sorted, ["C", "A", "B", "A"]);
+
+        // Ties of the keys are broken by every field, whatever order the
+        // sales were aggregated in.
+        let keys = Config::default().sort;
+        let mut ties = [
+            sale("Order", "B", 500),
+            sale("Order", "A", 700),
+            sale("Order", "A", 500),
+        ];
+        let mut reversed = ties.clone();
+        reversed.reverse();
+        ties.sort_unstable_by(|a, b| a.compare(b, &keys));
+        reversed.sort_unstable_by(|a, b| a.compare(b, &keys));
+        assert_eq!(ties, reversed);
+        assert_eq!(
+            ties[..2],
+            [sale("Order", "A", 500), sale("Order", "A", 700)]
+        );
+
+        assert!("total:up".parse::<SortKey>().is_err());
+        assert!("date".parse::<SortKey>().is_err());
+    }
+
+    /// `cents` written as an amount, with thousands separators if `grouped`.
+    fn amount(cents: Cents, grouped: bool) -> String {
+        let sign = if cents < 0 { "-" } else { "" };
+        let units = (cents.abs() / 100).to_string();
+        let units = match grouped {
+            true => {
+                let digits = units.chars().rev().collect::<Vec<_>>();
+                let groups = digits.chunks(3).map(|g| g.iter().rev().collect::<String>());
+                groups.rev().collect::<Vec<_>>().join(",")
+            }
+            false => units,
+        };
+        format!("{sign}{units}.{:02}", cents.abs() % 100)
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn parses_written_amounts(cents in -1_000_000_000_000i64..1_000_000_000_000, grouped: bool) {
+            let written = amount(cents, grouped);
+            proptest::prop_assert_eq!(handle_punct(&written, Rounding::Reject).unwrap(), cents);
+        }
+
+        #[test]
+        fn rejects_malformed_amounts(
+            extra_decimals in "-?[0-9]{1,6}\\.[0-9]{3,6}",
+            two_points in "[0-9]{1,3}\\.[0-9]{1,2}\\.[0-9]{1,2}",
+            anything in "\\PC*",
+        ) {
+            proptest::prop_assert!(handle_punct(&extra_decimals, Rounding::Reject).is_err());
+            proptest::prop_assert!(handle_punct(&two_points, Rounding::HalfEven).is_err());
+            // Whatever is written, it is read or rejected without panicking.
+            let _ = handle_punct(&anything, Rounding::HalfEven);
+        }
+
+        #[test]
+        fn aggregates_add_up_and_dedup_is_idempotent(
+            rows in proptest::collection::vec(
+                (0..4usize, proptest::option::of(0..3usize), 1..5i64, -100_000..100_000i64),
+                1..50,
+            ),
+        ) {
+            const KINDS: [&str; 4] = ["Order", "Refund", "Service Fee", "Transfer"];
+            let mut report = String::from("type,sku,description,quantity,total\n");
+            let mut totals = BTreeMap::<String, Cents>::new();
+            for (kind, sku, quantity, unit) in rows {
+                let (kind, total) = (KINDS[kind], unit * quantity);
+                let sku = sku.map(|sku| format!("SKU-{sku}")).unwrap_or_default();
+                let total_written = amount(total, true);
+                report += &format!("{kind},{sku},Item {sku},{quantity},\"{total_written}\"\n");
+                *totals.entry(kind.to_string()).or_default() += total;
+            }
+            let config = Config::default();
+            let mut memories = Memories::default();
+            let run = |memories: &mut Memories| {
+                aggregate(report.as_bytes(), &config, memories, None, None, &mut (), None).unwrap()
+            };
+
+            let first = run(&mut memories);
+            proptest::prop_assert_eq!(first.totals(), totals);
+            memories.iter_mut().for_each(Memory::settle);
+            let second = run(&mut memories);
+            proptest::prop_assert!(second.sales.is_empty());
+            proptest::prop_assert_eq!(second.duplicates, second.rows);
+        }
+    }
+
+    #[test]
+    fn quantities() {
+        let report = b"type,sku,description,quantity,total\n\
+            Order,A,Widget,,5.00\n\
+            Refund,A,Widget,2,-10.00\n\
+            Adjustment,B,Reimbursement,3,7.50\n\
+            Adjustment,,Reimbursement,3,7.50\n";
+        let sales = |config: &str| {
+            let config = toml::from_str::<Config>(config).unwrap();
+            let mut sales = aggregate(
+                &report[..],
+                &config,
+                &mut Memories::default(),
+                None,
+                None,
+                &mut (),
+                None,
+            )
+            .unwrap()
+            .sales
+            .iter()
+            .map(|sale| (sale.kind.to_string(), sale.quantity, sale.cents))
+            .collect::<Vec<_>>();
+            // The output is also sorted by the SKU and description left out here.
+            sales.sort();
+            sales
+        };
+        let sale = |kind: &str, quantity, cents| (kind.to_string(), quantity, cents);
+
+        assert_eq!(
+            sales(""),
+            [
+                sale("Adjustment", 1, 750),
+                sale("Adjustment", 3, 750),
+                sale("Order", 0, 0),
+                sale("Refund", 2, -1_000),
+            ]
+        );
+        assert_eq!(
+            sales("[quantity]\nblank = 1\nnegative_refunds = true\nadjustments = \"keep\""),
+            [
+                sale("Adjustment", 3, 750),
+                sale("Adjustment", 3, 750),
+                sale("Order", 1, 500),
+                sale("Refund", -2, -1_000),
+            ]
+        );
+
+        let round = Quantity {
+            decimals: QuantityDecimals::Round,
+            ..Quantity::default()
+        };
+        assert_eq!(round.read("Order", "1,024").unwrap(), 1_024);
+        assert_eq!(round.read("Order", "-12,345.6").unwrap(), -12_346);
+        for garbage in ["1,5", "1,,2", ",100", "1,0000"] {
+            let e = round.read("Order", garbage).unwrap_err();
+            assert!(e.to_string().contains(garbage), "{e}");
+        }
+        assert_eq!(round.read("Order", " 2.5 ").unwrap(), 3);
+        assert!(Quantity::default().read("Order", "2.5").is_err());
+        assert!(round.read("Order", "two").is_err());
+    }
+
+    #[test]
+    fn checks_pinned_header() {
+        let pinned = ["date/time", "type", "sku", "total", "fulfillment"].map(String::from);
+        let hdr = StringRecord::from(vec!["Type", "date/time ", "sku", "total", "fulfillment"]);
+        assert!(check_pinned(&hdr, &pinned).is_ok());
+        assert!(check_pinned(&hdr, &[]).is_ok());
+
+        let hdr = StringRecord::from(vec!["date/time", "type", "merchant sku", "region", "total"]);
+        let e = check_pinned(&hdr, &pinned).unwrap_err();
+        assert_eq!(e.downcast_ref(), Some(&Malformed::HeaderChanged));
+        assert_eq!(
+            e.root_cause().to_string(),
+            "added `region`, removed `fulfillment`, renamed `sku` to `merchant sku`"
+        );
+    }
+
+    #[test]
+    fn reads_schema() {
+        let report = b"\"preamble\"\n\
+            date/time,type,order id,sku,description,quantity,total,marketplace,fulfillment,product sales tax\n\
+            2024-01-05,Order,1,A,Widget,1,5.00,amazon.com,Amazon,0.40\n";
+        let config = Config {
+            dedup: Dedup::OrderId,
+            ..Config::default()
+        };
+        let schema = schema(&report[..], &config).unwrap();
+        assert_eq!((schema.source.as_str(), schema.line), ("Amazon", 2));
+        let columns = (schema.columns.iter())
+            .map(|c| (c.name.as_str(), c.uses.join(", ")))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            columns,
+            [
+                ("date/time", "date/time".to_string()),
+                ("type", "type, dedup key".to_string()),
+                ("order id", "order id, dedup key".to_string()),
+                ("sku", "sku".to_string()),
+                ("description", "description".to_string()),
+                ("quantity", "quantity".to_string()),
+                ("total", "total, dedup key".to_string()),
+                ("marketplace", "tax jurisdiction".to_string()),
+                ("fulfillment", String::new()),
+                ("product sales tax", "tax".to_string()),
+            ]
+        );
+        assert_eq!(schema.missing, ["settlement id"]);
+        assert!(!schema.whole_rows);
     }
 }
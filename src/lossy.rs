@@ -0,0 +1,163 @@
+//! Decoding reports as UTF-8 while they are read, instead of loading them
+//! whole.
+
+use std::io::{self, Read};
+
+const CHUNK: usize = 64 * 1024;
+
+/// Byte order mark that Excel writes at the start of a CSV saved as UTF-8.
+const BOM: &[u8] = "\u{feff}".as_bytes();
+
+/// Replaces invalid UTF-8 with U+FFFD, exactly like
+/// [`String::from_utf8_lossy`], as bytes are read from `R`. A byte order mark
+/// at the start is dropped.
+pub(crate) struct Lossy<R> {
+    inner: R,
+    /// Bytes of a character that continues in the next chunk.
+    pending: Vec<u8>,
+    /// Decoded bytes not yet read.
+    decoded: Vec<u8>,
+    pos: usize,
+    eof: bool,
+    /// Whether anything has been decoded, after which a byte order mark is
+    /// kept.
+    started: bool,
+    /// Last byte read.
+    last: Option<u8>,
+}
+
+impl<R: Read> Lossy<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        Self {
+            inner,
+            pending: Vec::new(),
+            decoded: Vec::new(),
+            pos: 0,
+            eof: false,
+            started: false,
+            last: None,
+        }
+    }
+
+    /// Whether what was read so far is empty or ends with a line break,
+    /// unlike a download that stopped partway through a line.
+    pub(crate) fn terminated(&self) -> bool {
+        matches!(self.last, None | Some(b'\n' | b'\r'))
+    }
+
+    /// Decodes the next chunk of `inner` into `decoded`.
+    fn fill(&mut self) -> io::Result<()> {
+        self.decoded.clear();
+        self.pos = 0;
+        let start = self.pending.len();
+        self.pending.resize(start + CHUNK, 0);
+        let read = tracing::info_span!("read").entered();
+        let n = match self.inner.read(&mut self.pending[start..]) {
+            Ok(n) => n,
+            Err(e) => {
+                self.pending.truncate(start);
+                return Err(e);
+            }
+        };
+        self.pending.truncate(start + n);
+        drop(read);
+        let _decode = tracing::info_span!("decode").entered();
+        if n == 0 {
+            self.eof = true;
+            if !self.pending.is_empty() {
+                self.decoded.extend_from_slice("\u{FFFD}".as_bytes());
+                self.pending.clear();
+            }
+            return Ok(());
+        }
+
+        let mut rest = 0;
+        loop {
+            match std::str::from_utf8(&self.pending[rest..]) {
+                Ok(s) => {
+                    self.decoded.extend_from_slice(s.as_bytes());
+                    rest = self.pending.len();
+                    break;
+                }
+                Err(e) => {
+                    let valid = rest + e.valid_up_to();
+                    self.decoded.extend_from_slice(&self.pending[rest..valid]);
+                    match e.error_len() {
+                        Some(len) => {
+                            self.decoded.extend_from_slice("\u{FFFD}".as_bytes());
+                            rest = valid + len;
+                        }
+                        // The character may be completed by the next chunk.
+                        None => {
+                            rest = valid;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        self.pending.drain(..rest);
+        if !self.started && !self.decoded.is_empty() {
+            self.started = true;
+            if self.decoded.starts_with(BOM) {
+                self.pos = BOM.len();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Lossy<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.decoded.len() {
+            if self.eof {
+                return Ok(0);
+            }
+            self.fill()?;
+        }
+        let n = buf.len().min(self.decoded.len() - self.pos);
+        buf[..n].copy_from_slice(&self.decoded[self.pos..self.pos + n]);
+        self.pos += n;
+        if n > 0 {
+            self.last = Some(buf[n - 1]);
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Reads one byte at a time, so that characters are split across chunks.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.0.len().min(buf.len()).min(1);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn matches_from_utf8_lossy() {
+        let bytes = b"caf\xc3\xa9 \xff fee \xe2\x82 \xf0\x9f\x92\xb8 end \xe2";
+        let mut decoded = String::new();
+        Lossy::new(Trickle(bytes))
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, String::from_utf8_lossy(bytes));
+    }
+
+    #[test]
+    fn drops_leading_bom() {
+        let bytes = b"\xef\xbb\xbf\"preamble\"\xef\xbb\xbf";
+        let mut decoded = String::new();
+        Lossy::new(Trickle(bytes))
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "\"preamble\"\u{feff}");
+    }
+}
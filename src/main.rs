@@ -1,30 +1,1163 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use tracing::info;
-use tracing_subscriber::EnvFilter;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 
-fn main() -> eyre::Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env())
-        .init();
+use clap::{CommandFactory as _, Parser, Subcommand};
+use dedupy::{audit, Cents, Config, Log};
+use timings::Timings;
+use tracing::{error, info, warn};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::{
+    layer::SubscriberExt as _, util::SubscriberInitExt as _, EnvFilter, Layer,
+};
 
-    match std::env::args().nth(1) {
-        Some(path) => dedupy::Report::parse(path)?,
+mod timings;
+mod update;
+
+/// Aggregates Amazon transaction reports, skipping transactions that were
+/// already aggregated by a previous run.
+#[derive(Parser)]
+#[command(version, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Reports to process, `-` for stdin, or directories of reports, leaving
+    /// out those that match `ignore`. A file picker is opened when none are
+    /// given.
+    files: Vec<PathBuf>,
+    /// Format of the output, `xlsx` unless built without it.
+    #[arg(long, value_enum)]
+    format: Option<OutputFormat>,
+    /// Write the output to stdout instead of a file. Takes a single report.
+    #[arg(long)]
+    stdout: bool,
+    /// Append a row to this CSV file for every row read, explaining how it
+    /// was classified, deduplicated, and aggregated.
+    #[arg(long, value_name = "TRACE")]
+    explain: Option<PathBuf>,
+    /// Also process the reports in the inbox of this server from `remotes`,
+    /// and push the outputs to its outbox. Requires the `remote` feature.
+    #[arg(long, value_name = "NAME")]
+    remote: Option<String>,
+    /// Aggregate rows dated in a closed period anyway, see `close-period`.
+    #[arg(long)]
+    reopen: bool,
+    /// Columns to sort the output by, such as `total:desc,sku`, instead of
+    /// `sort` in the settings.
+    #[arg(long, value_delimiter = ',', value_parser = sort_key)]
+    sort: Vec<dedupy::SortKey>,
+    /// Marketplace that reports are exported from, recognized by their
+    /// header unless given.
+    #[arg(long, value_enum, global = true)]
+    source: Option<Source>,
+    /// Format of the log, on the console and in log files. `json` writes an
+    /// object per event, for log aggregators.
+    #[arg(long, value_enum, default_value_t, global = true)]
+    log_format: LogFormat,
+    /// Print how long each phase of processing took: reading, decoding,
+    /// parsing, deduplicating, aggregating, and writing.
+    #[arg(long, global = true)]
+    timings: bool,
+    /// Only print errors, not the summary of each report.
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Log more of what is being done, more with each repetition: `-v` for
+    /// progress, `-vv` for details, `-vvv` for everything.
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum OutputFormat {
+    Xlsx,
+    Csv,
+    Json,
+    /// An OpenDocument spreadsheet, for LibreOffice.
+    Ods,
+    /// A page with a chart, the totals, and a table that sorts.
+    Html,
+    /// An Arrow IPC file, also known as Feather. Requires the `arrow`
+    /// feature.
+    Arrow,
+}
+
+impl From<OutputFormat> for dedupy::Format {
+    fn from(format: OutputFormat) -> Self {
+        match format {
+            OutputFormat::Xlsx => Self::Xlsx,
+            OutputFormat::Csv => Self::Csv,
+            OutputFormat::Json => Self::Json,
+            OutputFormat::Ods => Self::Ods,
+            OutputFormat::Html => Self::Html,
+            OutputFormat::Arrow => Self::Arrow,
+        }
+    }
+}
+
+fn sort_key(key: &str) -> Result<dedupy::SortKey, String> {
+    key.parse().map_err(|e: eyre::Report| e.to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Source {
+    Amazon,
+    Ebay,
+    Shopify,
+    Etsy,
+    Walmart,
+}
+
+impl From<Source> for dedupy::Marketplace {
+    fn from(source: Source) -> Self {
+        match source {
+            Source::Amazon => Self::Amazon,
+            Source::Ebay => Self::Ebay,
+            Source::Shopify => Self::Shopify,
+            Source::Etsy => Self::Etsy,
+            Source::Walmart => Self::Walmart,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List previous runs from the audit log.
+    History {
+        #[command(subcommand)]
+        command: Option<HistoryCommand>,
+    },
+    /// Print the revenue, fees, refunds, and net total of the last months
+    /// that runs in the audit log have rows in.
+    Trends {
+        /// Number of months.
+        #[arg(short, default_value_t = 12)]
+        n: usize,
+    },
+    /// Aggregate a previous run's inputs again with the current settings and
+    /// compare the totals against what the run recorded.
+    ///
+    /// Memory, outputs, and the audit log are left untouched.
+    Replay {
+        /// Run number, as listed by `dedupy history`.
+        id: usize,
+    },
+    /// Compare two outputs, listing aggregate rows that were added, removed,
+    /// or changed.
+    Diff {
+        /// The earlier output, `.xlsx` or `.csv`.
+        a: PathBuf,
+        /// The later output, `.xlsx` or `.csv`.
+        b: PathBuf,
+    },
+    /// Check that a report can be processed, without reading or writing
+    /// memory or output.
+    ///
+    /// Exits with an error if any problem is found.
+    Validate {
+        /// Report to check.
+        file: PathBuf,
+    },
+    /// Print the header of a report and what each of its columns is read
+    /// as, to check how a new kind of report maps before processing it.
+    ///
+    /// Rows, memory, and outputs are left untouched.
+    Schema {
+        /// Report to read the header of.
+        file: PathBuf,
+    },
+    /// Print the first rows of a report as they are read, without reading or
+    /// writing memory or output.
+    Preview {
+        /// Report to read.
+        file: PathBuf,
+        /// Number of rows to print.
+        #[arg(short, default_value_t = 20)]
+        n: usize,
+    },
+    /// Match the payout of every settlement in reports against the deposits
+    /// of a bank statement, listing payouts that were never deposited.
+    ///
+    /// Memory, outputs, and the audit log are left untouched.
+    Reconcile {
+        /// Bank statement, read as `deposits` in the settings say.
+        deposits: PathBuf,
+        /// Reports whose settlements were paid out.
+        #[arg(required = true)]
+        reports: Vec<PathBuf>,
+    },
+    /// Close the days from `from` to `to` in accounting, so that runs refuse
+    /// to aggregate new rows dated in them unless `--reopen` is passed.
+    ClosePeriod {
+        /// First day of the period, such as 2024-01-01.
+        from: chrono::NaiveDate,
+        /// Last day of the period, included.
+        to: chrono::NaiveDate,
+    },
+    /// Manage the memory of transactions seen by earlier runs.
+    Memory {
+        #[command(subcommand)]
+        command: MemoryCommand,
+    },
+    /// Write a synthetic report, for benchmarking or trying dedupy out
+    /// without real data.
+    Generate {
+        /// Where to write the report.
+        file: PathBuf,
+        /// Number of transactions.
+        #[arg(long, default_value_t = 10_000)]
+        rows: usize,
+        /// Number of distinct products.
+        #[arg(long, default_value_t = 200)]
+        skus: usize,
+        /// The same seed always generates the same report.
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+    },
+    /// Print a script that completes commands and options as they are typed,
+    /// to be loaded by `shell`.
+    Completions { shell: clap_complete::Shell },
+    /// Print a man page, in roff.
+    Manpage,
+    /// Replace this program with the latest release, after checking that the
+    /// download is intact. Requires the `self-update` feature.
+    SelfUpdate {
+        /// Only say whether there is a newer release.
+        #[arg(long)]
+        check: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum HistoryCommand {
+    /// Show everything recorded about a single run.
+    Show {
+        /// Run number, as listed by `dedupy history`.
+        id: usize,
+    },
+}
+
+#[derive(Subcommand)]
+enum MemoryCommand {
+    /// Forget hashes that no run has seen within the retention window.
+    Prune {
+        /// Retention window in months, instead of `memory_retention_months`.
+        #[arg(long)]
+        months: Option<u32>,
+    },
+    /// Write all memory to a file, to back it up or move it to another
+    /// machine.
+    Export {
+        /// File to write, `.json` or `.csv`.
+        file: PathBuf,
+    },
+    /// Add everything in an exported file to memory.
+    ///
+    /// Hashes already remembered are kept, so exports of several machines
+    /// can be imported one after another.
+    Import {
+        /// File to read, `.json` or `.csv`.
+        file: PathBuf,
+    },
+    /// Combine several memory files into one, for example from machines that
+    /// processed reports separately.
+    Merge {
+        /// Memory files to combine.
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+        /// File to write the combined memory to.
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Write new memory files by reading the reports of every recorded run
+    /// again, for when memory was lost or cannot be read.
+    ///
+    /// Reports are found in `archive` or where they were read from. Existing
+    /// memory files are kept with `.old` appended to their names.
+    Rebuild {
+        /// Audit log to read instead of `audit_log`, such as a backup.
+        #[arg(long, value_name = "AUDIT_LOG")]
+        from: Option<PathBuf>,
+    },
+}
+
+/// How a run ended, as its exit code, so that scripts can tell outcomes
+/// apart. Usage errors exit with 2 as well.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Exit {
+    Ok = 0,
+    /// Any error not listed below.
+    Failed = 1,
+    /// A report is malformed, see [`dedupy::Malformed`], or `validate` found
+    /// problems.
+    Malformed = 2,
+    /// Every report had been seen before, so the outputs are empty.
+    NothingNew = 3,
+    /// `replay` totals differ from those recorded.
+    Mismatch = 4,
+    /// Memory cannot be read, see [`dedupy::memory::Corrupt`].
+    CorruptMemory = 5,
+    /// `reconcile` found payouts that no deposit paid.
+    Unreconciled = 6,
+    /// Reports were processed, but one raised an alert, see `[alerts]`.
+    Alerted = 7,
+}
+
+impl From<Exit> for ExitCode {
+    fn from(exit: Exit) -> Self {
+        ExitCode::from(exit as u8)
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let timings = cli.timings.then(Timings::default);
+    let exit = start(cli, timings.clone()).unwrap_or_else(|e| {
+        error!(target: FAILED, "{e:?}");
+        eprintln!("Error: {e:?}");
+        if e.downcast_ref::<dedupy::Malformed>().is_some() {
+            Exit::Malformed
+        } else if e.downcast_ref::<dedupy::memory::Corrupt>().is_some() {
+            Exit::CorruptMemory
+        } else {
+            Exit::Failed
+        }
+    });
+    if let Some(timings) = timings {
+        timings.print();
+    }
+    exit.into()
+}
+
+fn start(mut cli: Cli, timings: Option<Timings>) -> eyre::Result<Exit> {
+    let mut config = Config::load()?;
+    if cli.explain.is_some() {
+        config.explain = cli.explain.take();
+    }
+    config.reopen_closed_periods |= cli.reopen;
+    if !cli.sort.is_empty() {
+        config.sort = std::mem::take(&mut cli.sort);
+    }
+    if let Some(source) = cli.source {
+        config.source = Some(source.into());
+    }
+    init_tracing(&config, &cli, timings)?;
+    run(cli, &config)
+}
+
+/// Log files kept in `log_dir`, one per day.
+const LOG_FILES: usize = 7;
+
+/// Target of the error a run failed with. It is printed to the console
+/// anyway, so as text it only goes to the log file.
+const FAILED: &str = "dedupy::failed";
+
+type BoxedLayer = Box<dyn Layer<tracing_subscriber::Registry> + Send + Sync>;
+
+/// Formats events as `format`, written to `writer`, colored if `ansi`.
+fn fmt_layer<W>(format: LogFormat, writer: W, ansi: bool) -> BoxedLayer
+where
+    W: for<'w> tracing_subscriber::fmt::MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(ansi);
+    match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer.json().boxed(),
+    }
+}
+
+/// Logs to the console as `-q` or `-v` say, and to a file in `log_dir` as
+/// `log` says, at `info`, unless `RUST_LOG` says otherwise. Phases are timed
+/// into `timings` if given.
+fn init_tracing(config: &Config, cli: &Cli, timings: Option<Timings>) -> eyre::Result<()> {
+    let format = cli.log_format;
+    let level = match (cli.quiet, cli.verbose) {
+        (true, _) => "error",
+        (false, 0) => "warn",
+        (false, 1) => "info",
+        (false, 2) => "debug",
+        (false, _) => "trace",
+    };
+    let mut filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(level));
+    if format == LogFormat::Text {
+        filter = filter.add_directive(format!("{FAILED}=off").parse()?);
+    }
+    // The output is written to stdout with `--stdout`, so the log is not.
+    let console = if cli.stdout {
+        fmt_layer(format, std::io::stderr, true)
+    } else {
+        fmt_layer(format, std::io::stdout, true)
+    };
+    let mut layers = vec![console.with_filter(filter).boxed()];
+    let to_file = match config.log {
+        Log::Gui => cli.command.is_none() && cli.files.is_empty(),
+        Log::Always => true,
+        Log::Never => false,
+    };
+    if to_file {
+        let appender = RollingFileAppender::builder()
+            .rotation(Rotation::DAILY)
+            .filename_prefix("dedupy")
+            .filename_suffix("log")
+            .max_log_files(LOG_FILES)
+            .build(&config.log_dir)?;
+        let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+        layers.push(
+            fmt_layer(format, appender, false)
+                .with_filter(filter)
+                .boxed(),
+        );
+    }
+    if let Some(timings) = timings {
+        layers.push(timings.boxed());
+    }
+    tracing_subscriber::registry().with(layers).init();
+    Ok(())
+}
+
+fn run(cli: Cli, config: &Config) -> eyre::Result<Exit> {
+    // A JSON log is read by programs, which find the summary in the
+    // `processed report` event. With `--stdout` the output is.
+    let summarize = !cli.quiet && cli.log_format == LogFormat::Text && !cli.stdout;
+    let format = cli.format.map_or_else(dedupy::Format::default, Into::into);
+    match cli.command {
+        Some(Command::History { command: None }) => history(config),
+        Some(Command::History {
+            command: Some(HistoryCommand::Show { id }),
+        }) => history_show(config, id),
+        Some(Command::Trends { n }) => trends(config, n),
+        Some(Command::Replay { id }) => return replay(config, id),
+        Some(Command::Diff { a, b }) => diff(a, b),
+        Some(Command::Validate { file }) => return validate(config, file),
+        Some(Command::Schema { file }) => schema(config, file),
+        Some(Command::Preview { file, n }) => preview(config, file, n),
+        Some(Command::Reconcile { deposits, reports }) => {
+            return reconcile(config, deposits, reports)
+        }
+        Some(Command::ClosePeriod { from, to }) => close_period(from, to),
+        Some(Command::Memory {
+            command: MemoryCommand::Prune { months },
+        }) => memory_prune(config, months),
+        Some(Command::Memory {
+            command: MemoryCommand::Export { file },
+        }) => memory_export(config, file),
+        Some(Command::Memory {
+            command: MemoryCommand::Import { file },
+        }) => memory_import(config, file),
+        Some(Command::Memory {
+            command: MemoryCommand::Merge { inputs, output },
+        }) => memory_merge(config, inputs, output),
+        Some(Command::Memory {
+            command: MemoryCommand::Rebuild { from },
+        }) => memory_rebuild(config, from),
+        Some(Command::Generate {
+            file,
+            rows,
+            skus,
+            seed,
+        }) => generate(file, rows, skus, seed),
+        Some(Command::Completions { shell }) => completions(shell),
+        Some(Command::Manpage) => manpage(),
+        Some(Command::SelfUpdate { check }) => update::run(check),
         None => {
-            let file_picker = rfd::FileDialog::new()
-                .add_filter("csv", &["csv"])
-                .set_directory(std::env::current_dir()?)
-                .set_title("Select a transaction report")
-                .pick_files();
+            let remote = (cli.remote.as_deref())
+                .map(|name| {
+                    let remote = config.remotes.iter().find(|remote| remote.name == name);
+                    remote.ok_or_else(|| eyre::eyre!("no remote named {name} in `remotes`"))
+                })
+                .transpose()?;
+            return process(config, cli.files, remote, format, cli.stdout, summarize);
+        }
+    }?;
+    Ok(Exit::Ok)
+}
 
-            match file_picker {
-                Some(files) => files.into_iter().try_for_each(dedupy::Report::parse),
-                _ => {
-                    info!("No files selected, exiting.");
-                    return Ok(());
-                }
-            }?
+/// Processes each of `files` and the reports fetched from `remote`, writing
+/// the output in `format`, to stdout if `stdout`, and printing a summary of
+/// each if `summarize`.
+fn process(
+    config: &Config,
+    files: Vec<PathBuf>,
+    remote: Option<&dedupy::Remote>,
+    format: dedupy::Format,
+    stdout: bool,
+    summarize: bool,
+) -> eyre::Result<Exit> {
+    #[cfg(not(feature = "remote"))]
+    if remote.is_some() {
+        eyre::bail!("`--remote` is given, but dedupy was built without the remote feature");
+    }
+    // Fetched reports are only kept for the run, in a directory of their own
+    // that is removed with it.
+    #[cfg(feature = "remote")]
+    let dir = (remote.map(|_| tempfile::Builder::new().prefix("dedupy-remote-").tempdir()))
+        .transpose()?;
+    #[cfg(feature = "remote")]
+    let fetched = match (remote, &dir) {
+        (Some(remote), Some(dir)) => dedupy::remote::fetch(remote, dir.path(), config),
+        _ => Ok(Vec::new()),
+    };
+    #[cfg(not(feature = "remote"))]
+    let fetched = Ok::<_, eyre::Report>(Vec::new());
+    fetched.and_then(|fetched| {
+        process_files(config, files, fetched, remote, format, stdout, summarize)
+    })
+}
+
+fn process_files(
+    config: &Config,
+    files: Vec<PathBuf>,
+    fetched: Vec<PathBuf>,
+    remote: Option<&dedupy::Remote>,
+    format: dedupy::Format,
+    stdout: bool,
+    summarize: bool,
+) -> eyre::Result<Exit> {
+    let files = if files.is_empty() && remote.is_none() {
+        let file_picker = rfd::FileDialog::new()
+            .add_filter("csv", &["csv"])
+            .set_directory(std::env::current_dir()?)
+            .set_title("Select a transaction report")
+            .pick_files();
+
+        match file_picker {
+            Some(files) => files,
+            _ => {
+                info!("No files selected, exiting.");
+                return Ok(Exit::Ok);
+            }
+        }
+    } else {
+        let given = files.len();
+        let mut files = dedupy::batch::expand(files, config)?;
+        files.extend(fetched);
+        if files.is_empty() {
+            match remote {
+                Some(remote) => info!("No reports found on {}, exiting.", remote.name),
+                None => info!("No reports found in the {given} directories given, exiting."),
+            }
+            return Ok(Exit::NothingNew);
         }
+        files
+    };
+    if stdout && files.len() != 1 {
+        eyre::bail!("`--stdout` takes a single report");
+    }
+
+    let start = std::time::Instant::now();
+    let (mut stdin, mut out) = (std::io::stdin(), std::io::stdout());
+    let mut stdin = Some(&mut stdin);
+    let mut sources = Vec::with_capacity(files.len());
+    for file in &files {
+        sources.push(if file.as_os_str() == "-" {
+            let stdin = stdin
+                .take()
+                .ok_or_else(|| eyre::eyre!("stdin, `-`, can be read once"))?;
+            dedupy::Source::Reader(stdin)
+        } else {
+            dedupy::Source::Path(file)
+        });
+    }
+    let output = if stdout {
+        dedupy::Output::Writer(&mut out, format)
+    } else {
+        dedupy::Output::File(format)
+    };
+    // All or nothing, so that a report that fails does not leave the ones
+    // before it remembered and the ones after it not.
+    let summaries =
+        dedupy::Report::parse_batch(sources, output, config, &mut (), &dedupy::Cancel::default());
+    let summaries = match summaries {
+        Ok(summaries) => summaries,
+        Err(e) => {
+            if let Some(failed) = e.downcast_ref::<dedupy::BatchFailed>() {
+                print_outcomes(&failed.outcomes);
+            }
+            return Err(e);
+        }
+    };
+    let mut new = false;
+    let mut alerted = false;
+    for (file, summary) in files.iter().zip(&summaries) {
+        info!(
+            file = %file.display(),
+            output = %summary.output.display(),
+            rows = summary.rows,
+            duplicates = summary.duplicates,
+            near_duplicates = summary.near_duplicates,
+            aggregates = summary.aggregates,
+            total = summary.total(),
+            duration_ms = start.elapsed().as_millis() as u64,
+            "processed report"
+        );
+        if summary.currencies.len() > 1 {
+            warn!(
+                "{} mixes {}, which were aggregated together",
+                file.display(),
+                summary.currencies.join(", ")
+            );
+        }
+        for alert in &summary.alerts {
+            warn!("{}: {alert}", file.display());
+        }
+        if summarize {
+            println!("{} -> {}", file.display(), summary.output.display());
+            print_counts(summary);
+        }
+        new |= summary.aggregates > 0;
+        alerted |= !summary.alerts.is_empty();
+    }
+    if summarize && summaries.len() > 1 {
+        print_outcomes(&summaries.iter().map(Into::into).collect::<Vec<_>>());
+    }
+    #[cfg(feature = "remote")]
+    if let Some(remote) = remote.filter(|remote| remote.outbox.is_some()) {
+        let outputs = (summaries.iter())
+            .map(|summary| summary.output.clone())
+            .filter(|output| output.as_os_str() != "-")
+            .collect::<Vec<_>>();
+        dedupy::remote::push(remote, &outputs).map_err(|e| {
+            e.wrap_err("the reports were processed, but their outputs were not pushed")
+        })?;
+    }
+    Ok(match (new, alerted) {
+        (_, true) => Exit::Alerted,
+        (true, false) => Exit::Ok,
+        (false, false) => Exit::NothingNew,
+    })
+}
+
+fn history(config: &Config) -> eyre::Result<()> {
+    let records = audit::Record::load(config)?;
+    if records.is_empty() {
+        match &config.postgres {
+            Some(_) => println!("No runs recorded in the database"),
+            None => println!("No runs recorded in {}", config.audit_log.display()),
+        }
+        return Ok(());
+    }
+
+    println!(
+        "{:>4}  {:<19}  {:>8}  {:>10}  {:>12}  INPUTS -> OUTPUT",
+        "ID", "DATE", "ROWS", "DUPLICATES", "TOTAL"
+    );
+    for (id, record) in records.iter().enumerate() {
+        let summary = &record.summary;
+        let inputs = match record.outcomes.len() {
+            0 => summary
+                .inputs
+                .iter()
+                .map(|i| i.path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            n => format!("batch of {n} reports that failed"),
+        };
+        println!(
+            "{:>4}  {:<19}  {:>8}  {:>10}  {:>12}  {} -> {}",
+            id + 1,
+            record.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            summary.rows,
+            summary.duplicates,
+            money(summary.total()),
+            inputs,
+            summary.output.display(),
+        );
     }
     Ok(())
 }
+
+fn trends(config: &Config, n: usize) -> eyre::Result<()> {
+    let records = audit::Record::load(config)?;
+    let trends = dedupy::analytics::trends(records.iter().map(|r| &r.summary), n);
+    if trends.is_empty() {
+        println!("No runs with dated rows recorded");
+        return Ok(());
+    }
+    println!(
+        "{:<7}  {:>4}  {:>12}  {:>12}  {:>12}  {:>12}",
+        "MONTH", "RUNS", "REVENUE", "FEES", "REFUNDS", "NET"
+    );
+    for trend in trends {
+        println!(
+            "{:<7}  {:>4}  {:>12}  {:>12}  {:>12}  {:>12}",
+            trend.month,
+            trend.runs,
+            money(trend.revenue),
+            money(trend.fees),
+            money(trend.refunds),
+            money(trend.net),
+        );
+    }
+    Ok(())
+}
+
+fn record(config: &Config, id: usize) -> eyre::Result<audit::Record> {
+    let mut records = audit::Record::load(config)?;
+    let count = records.len();
+    match id.checked_sub(1).filter(|i| *i < count) {
+        Some(i) => Ok(records.swap_remove(i)),
+        None => eyre::bail!("no run {id}, there are {count} recorded runs"),
+    }
+}
+
+fn history_show(config: &Config, id: usize) -> eyre::Result<()> {
+    let record = record(config, id)?;
+    let summary = &record.summary;
+
+    println!("Run:        {id}");
+    println!("Date:       {}", record.timestamp.to_rfc3339());
+    println!("Version:    {}", record.version);
+    for input in &summary.inputs {
+        println!("Input:      {} ({})", input.path.display(), input.checksum);
+    }
+    match &summary.checksum {
+        Some(checksum) => println!("Output:     {} ({checksum})", summary.output.display()),
+        None => println!("Output:     {}", summary.output.display()),
+    }
+    if let Some((first, last)) = summary.period {
+        println!("Period:     {first} to {last}");
+    }
+    if !record.outcomes.is_empty() {
+        println!("Failed, nothing was written:");
+        print_outcomes(&record.outcomes);
+        return Ok(());
+    }
+    print_counts(summary);
+    Ok(())
+}
+
+/// Prints how every report of a batch fared, one per line.
+fn print_outcomes(outcomes: &[dedupy::Outcome]) {
+    println!(
+        "{:<32}  {:<11}  {:>8}  {:>10}  {:<40}  ERROR",
+        "FILE", "STATUS", "ROWS", "DUPLICATES", "OUTPUT"
+    );
+    for outcome in outcomes {
+        let output = outcome.output.as_deref().unwrap_or(Path::new(""));
+        println!(
+            "{:<32}  {:<11}  {:>8}  {:>10}  {:<40}  {}",
+            outcome.file.display(),
+            outcome.status.to_string(),
+            outcome.rows,
+            outcome.duplicates,
+            output.display(),
+            outcome.error.as_deref().unwrap_or_default(),
+        );
+    }
+}
+
+/// Prints the row counts and totals of a run.
+fn print_counts(summary: &dedupy::Summary) {
+    println!("Rows:       {}", summary.rows);
+    println!("Duplicates: {}", summary.duplicates);
+    println!("Possible duplicates: {}", summary.near_duplicates);
+    if summary.quarantined > 0 {
+        println!("Quarantined: {}", summary.quarantined);
+    }
+    println!("Aggregates: {}", summary.aggregates);
+    println!("Totals:");
+    for (kind, cents) in &summary.totals {
+        println!("  {:<40} {:>12}", kind, money(*cents));
+    }
+    println!("  {:<40} {:>12}", "", money(summary.total()));
+    if !summary.currencies.is_empty() {
+        println!("Currencies: {}", summary.currencies.join(", "));
+    }
+    if let Some(analytics) = &summary.analytics {
+        print_analytics(analytics);
+    }
+    if !summary.alerts.is_empty() {
+        println!("Alerts:");
+        for alert in &summary.alerts {
+            println!("  {alert}");
+        }
+    }
+}
+
+fn print_analytics(analytics: &dedupy::analytics::Analytics) {
+    let top = |title: &str, skus: &[dedupy::analytics::SkuTotal]| {
+        println!("{title}");
+        for sku in skus {
+            println!(
+                "  {:<40} {:>8} {:>12}",
+                sku.sku,
+                sku.quantity,
+                money(sku.cents)
+            );
+        }
+    };
+    top("Top SKUs by revenue:", &analytics.top_by_revenue);
+    top("Top SKUs by units:", &analytics.top_by_units);
+    let percent = |ratio: Option<f64>| match ratio {
+        Some(ratio) => format!("{:.1}%", ratio * 100.0),
+        None => "no orders".to_string(),
+    };
+    println!("Fees to revenue: {}", percent(analytics.fee_ratio));
+    println!("Refund rate:     {}", percent(analytics.refund_rate));
+    if let Some(change) = &analytics.month_over_month {
+        println!("Change from {}:", change.previous);
+        for (kind, cents) in &change.deltas {
+            println!("  {:<40} {:>12}", kind, money(*cents));
+        }
+    }
+}
+
+fn replay(config: &Config, id: usize) -> eyre::Result<Exit> {
+    let record = record(config, id)?;
+    let summary = &record.summary;
+
+    let mut replayed = BTreeMap::<String, Cents>::new();
+    for input in &summary.inputs {
+        let Some(path) = input.locate(config)? else {
+            eyre::bail!(
+                "{} ({}) is not archived and no longer matches its checksum",
+                input.path.display(),
+                input.checksum
+            );
+        };
+        for (kind, cents) in dedupy::Report::totals(path, config)? {
+            *replayed.entry(kind).or_default() += cents;
+        }
+    }
+    // The replay reads every row, including those the run skipped as
+    // duplicates.
+    for (kind, cents) in &summary.duplicate_totals {
+        *replayed.entry(kind.clone()).or_default() -= cents;
+    }
+    replayed.retain(|kind, cents| *cents != 0 || summary.totals.contains_key(kind));
+
+    let kinds = summary
+        .totals
+        .keys()
+        .chain(replayed.keys())
+        .collect::<BTreeSet<_>>();
+    let mut changed = 0;
+    println!(
+        "  {:<40} {:>12} {:>12} {:>12}",
+        "TYPE", "RECORDED", "REPLAYED", "DIFFERENCE"
+    );
+    for kind in kinds {
+        let recorded = summary.totals.get(kind).copied().unwrap_or_default();
+        let now = replayed.get(kind).copied().unwrap_or_default();
+        let marker = if recorded == now { ' ' } else { '*' };
+        changed += usize::from(recorded != now);
+        println!(
+            "{marker} {:<40} {:>12} {:>12} {:>12}",
+            kind,
+            money(recorded),
+            money(now),
+            money(now - recorded)
+        );
+    }
+
+    if summary.duplicates > 0 && summary.duplicate_totals.is_empty() {
+        println!(
+            "Run {id} skipped {} rows already aggregated by earlier runs, and was recorded \
+             before their totals were. The replay includes them, so some difference is \
+             expected.",
+            summary.duplicates
+        );
+    } else if summary.duplicates > 0 {
+        println!(
+            "Run {id} skipped {} rows already aggregated by earlier runs, which the replay \
+             leaves out too.",
+            summary.duplicates
+        );
+    }
+    match changed {
+        0 => {
+            println!("Totals match run {id}.");
+            Ok(Exit::Ok)
+        }
+        n => {
+            println!("{n} transaction types differ from run {id}.");
+            Ok(Exit::Mismatch)
+        }
+    }
+}
+
+fn diff(a: PathBuf, b: PathBuf) -> eyre::Result<()> {
+    let diff = dedupy::diff::Diff::new(&a, &b)?;
+    let row = |sign: char, key: &dedupy::diff::Key, amount: dedupy::diff::Amount| {
+        println!(
+            "{sign} {:<24} {:<16} {:<40} {:>8} {:>12}",
+            key.kind,
+            key.sku,
+            key.description,
+            amount.quantity,
+            money(amount.cents)
+        );
+    };
+
+    for (key, amount) in &diff.removed {
+        row('-', key, *amount);
+    }
+    for (key, amount) in &diff.added {
+        row('+', key, *amount);
+    }
+    for (key, old, new) in &diff.changed {
+        row('-', key, *old);
+        row('+', key, *new);
+    }
+
+    if diff.is_empty() {
+        println!("No differences.");
+    } else {
+        println!(
+            "{} added, {} removed, {} changed.",
+            diff.added.len(),
+            diff.removed.len(),
+            diff.changed.len()
+        );
+    }
+    println!(
+        "Total: {} -> {} ({})",
+        money(diff.before),
+        money(diff.after),
+        money(diff.after - diff.before)
+    );
+    Ok(())
+}
+
+fn validate(config: &Config, file: PathBuf) -> eyre::Result<Exit> {
+    let validation = dedupy::Report::validate(&file, config)?;
+    for problem in &validation.problems {
+        println!("{problem}");
+    }
+    match validation.problems.len() {
+        0 => {
+            println!("{}: {} rows, no problems.", file.display(), validation.rows);
+            Ok(Exit::Ok)
+        }
+        n => {
+            println!(
+                "{}: {n} problems in {} rows.",
+                file.display(),
+                validation.rows
+            );
+            Ok(Exit::Malformed)
+        }
+    }
+}
+
+fn schema(config: &Config, file: PathBuf) -> eyre::Result<()> {
+    let schema = dedupy::Report::schema(&file, config)?;
+    println!(
+        "{}: header on line {}, read as a report from {}.",
+        file.display(),
+        schema.line,
+        schema.source
+    );
+    println!("  {:<32}  READ AS", "COLUMN");
+    for column in &schema.columns {
+        let uses = match column.uses.is_empty() {
+            true => "-".to_string(),
+            false => column.uses.join(", "),
+        };
+        println!("  {:<32}  {uses}", truncate(&column.name, 32));
+    }
+    let unused = (schema.columns.iter())
+        .filter(|column| column.uses.is_empty())
+        .map(|column| column.name.as_str())
+        .collect::<Vec<_>>();
+    if !unused.is_empty() {
+        println!("Unused: {}", unused.join(", "));
+    }
+    if !schema.missing.is_empty() {
+        println!("Missing, left blank: {}", schema.missing.join(", "));
+    }
+    if schema.whole_rows {
+        println!("Whole rows are hashed to recognize them in memory.");
+    }
+    Ok(())
+}
+
+fn preview(config: &Config, file: PathBuf, n: usize) -> eyre::Result<()> {
+    let transactions = dedupy::Report::preview(file, n, config)?;
+    println!(
+        "{:>6}  {:<24}  {:<16}  {:<40}  {:>8}  {:>12}",
+        "LINE", "TYPE", "SKU", "DESCRIPTION", "QUANTITY", "TOTAL"
+    );
+    for t in transactions {
+        println!(
+            "{:>6}  {:<24}  {:<16}  {:<40}  {:>8}  {:>12}",
+            t.line,
+            truncate(&t.kind, 24),
+            truncate(t.sku.as_deref().unwrap_or("-"), 16),
+            truncate(&t.description, 40),
+            t.quantity,
+            money(t.cents)
+        );
+    }
+    Ok(())
+}
+
+fn generate(file: PathBuf, rows: usize, skus: usize, seed: u64) -> eyre::Result<()> {
+    let options = dedupy::generate::Options { rows, skus, seed };
+    dedupy::generate::write(&options, std::fs::File::create(&file)?)?;
+    println!("Wrote {rows} transactions to {}.", file.display());
+    Ok(())
+}
+
+fn completions(shell: clap_complete::Shell) -> eyre::Result<()> {
+    clap_complete::generate(shell, &mut Cli::command(), "dedupy", &mut std::io::stdout());
+    Ok(())
+}
+
+fn manpage() -> eyre::Result<()> {
+    clap_mangen::Man::new(Cli::command()).render(&mut std::io::stdout())?;
+    Ok(())
+}
+
+fn reconcile(config: &Config, deposits: PathBuf, reports: Vec<PathBuf>) -> eyre::Result<Exit> {
+    let mut payouts = Vec::new();
+    for report in &reports {
+        payouts.extend(dedupy::Report::payouts(report, config)?);
+    }
+    if payouts.is_empty() {
+        eyre::bail!("no settlements were found, the reports need a `settlement id` column");
+    }
+    let deposits = dedupy::reconcile::read_deposits(&deposits, config)?;
+    let reconciliation =
+        dedupy::reconcile::reconcile(payouts, deposits, config.deposits.window_days);
+    let date = |date: Option<chrono::NaiveDate>| date.map_or("-".to_string(), |d| d.to_string());
+
+    println!(
+        "  {:<16} {:<10} {:>12} {:<10} {:>6}",
+        "SETTLEMENT", "LAST ROW", "PAYOUT", "DEPOSITED", "LINE"
+    );
+    for (payout, deposit) in &reconciliation.matched {
+        println!(
+            "  {:<16} {:<10} {:>12} {:<10} {:>6}",
+            payout.settlement,
+            date(payout.date),
+            money(payout.cents),
+            deposit.date,
+            deposit.line
+        );
+    }
+    for payout in &reconciliation.unmatched {
+        println!(
+            "* {:<16} {:<10} {:>12} never",
+            payout.settlement,
+            date(payout.date),
+            money(payout.cents)
+        );
+    }
+    for deposit in &reconciliation.unclaimed {
+        println!(
+            "Deposit of {} on {} at line {} pays no settlement: {}",
+            money(deposit.cents),
+            deposit.date,
+            deposit.line,
+            deposit.description
+        );
+    }
+    match reconciliation.unmatched.len() {
+        0 => {
+            println!("Every payout was deposited.");
+            Ok(Exit::Ok)
+        }
+        n => {
+            println!(
+                "{n} of {} payouts were not deposited within {} days.",
+                n + reconciliation.matched.len(),
+                config.deposits.window_days
+            );
+            Ok(Exit::Unreconciled)
+        }
+    }
+}
+
+fn close_period(from: chrono::NaiveDate, to: chrono::NaiveDate) -> eyre::Result<()> {
+    let path = std::path::Path::new(dedupy::closing::CLOSED);
+    for period in dedupy::closing::close(path, from, to)? {
+        println!(
+            "Closed {} to {} on {}.",
+            period.from,
+            period.to,
+            period.closed.format("%Y-%m-%d %H:%M:%S")
+        );
+    }
+    Ok(())
+}
+
+fn memory_prune(config: &Config, months: Option<u32>) -> eyre::Result<()> {
+    let Some(months) = months.or(config.memory_retention_months) else {
+        eyre::bail!("no retention window, pass --months or set `memory_retention_months`");
+    };
+    let pruned = dedupy::memory::prune(config, months)?;
+    if pruned.is_empty() {
+        println!("No memory to prune.");
+    }
+    for (path, forgotten, remaining) in pruned {
+        println!("{path}: forgot {forgotten} hashes, {remaining} remain.");
+    }
+    Ok(())
+}
+
+fn memory_export(config: &Config, file: PathBuf) -> eyre::Result<()> {
+    let count = dedupy::memory::export(config, &file)?;
+    println!("Exported {count} hashes to {}.", file.display());
+    Ok(())
+}
+
+fn memory_import(config: &Config, file: PathBuf) -> eyre::Result<()> {
+    for (path, new) in dedupy::memory::import(config, &file)? {
+        println!("{path}: {new} new hashes.");
+    }
+    Ok(())
+}
+
+fn memory_merge(config: &Config, inputs: Vec<PathBuf>, output: PathBuf) -> eyre::Result<()> {
+    let count = dedupy::memory::merge(config, &inputs, &output)?;
+    println!(
+        "Merged {} files into {} with {count} hashes.",
+        inputs.len(),
+        output.display()
+    );
+    Ok(())
+}
+
+fn memory_rebuild(config: &Config, from: Option<PathBuf>) -> eyre::Result<()> {
+    let mut config = config.clone();
+    if let Some(from) = from {
+        config.audit_log = from;
+    }
+    let rebuilt = dedupy::memory::rebuild(&config)?;
+    for input in &rebuilt.missing {
+        println!("Missing: {} ({})", input.path.display(), input.checksum);
+    }
+    println!("Read {} reports again.", rebuilt.inputs);
+    for (path, count) in rebuilt.memories {
+        println!("{path}: {count} hashes.");
+    }
+    Ok(())
+}
+
+/// Shortens `s` to at most `width` characters for a table column.
+fn truncate(s: &str, width: usize) -> String {
+    if s.chars().count() <= width {
+        s.to_string()
+    } else {
+        let mut short = s.chars().take(width - 1).collect::<String>();
+        short.push('…');
+        short
+    }
+}
+
+fn money(cents: Cents) -> String {
+    format!("{:.2}", cents as f64 / 100.0)
+}
@@ -0,0 +1,741 @@
+//! Reports of marketplaces other than Amazon, read as if they were Amazon's.
+//!
+//! The marketplace a report comes from is recognized by its header, as are
+//! exports mapped by `profiles`. Each of its rows is converted into a row with the columns of an Amazon report, see
+//! [`COLUMNS`], followed by the marketplace's other columns as written, so
+//! that the rest of dedupy, memory, and the settings naming columns work the
+//! same whichever marketplace a report comes from.
+
+use std::{borrow::Cow, fmt};
+
+use chrono::NaiveDate;
+use csv::StringRecord;
+use serde::Deserialize;
+
+use crate::{Config, Profile, Rounding};
+
+/// Columns of an Amazon report that a converted row has, in order.
+pub(crate) const COLUMNS: [&str; 8] = [
+    "date/time",
+    "settlement id",
+    "type",
+    "order id",
+    "sku",
+    "description",
+    "quantity",
+    "total",
+];
+
+/// Type of the row a fee is written as, see [`Layout::fee`].
+const FEE: &str = "Fee";
+
+/// Marketplace that a report was exported from, see `source`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Marketplace {
+    Amazon,
+    /// An eBay transaction report.
+    Ebay,
+    /// A Shopify Payments payout transactions export.
+    Shopify,
+    /// An Etsy payment account statement.
+    Etsy,
+    /// A Walmart settlement (reconciliation) report.
+    Walmart,
+}
+
+impl fmt::Display for Marketplace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Amazon => "Amazon",
+            Self::Ebay => "eBay",
+            Self::Shopify => "Shopify",
+            Self::Etsy => "Etsy",
+            Self::Walmart => "Walmart",
+        })
+    }
+}
+
+/// How one of [`COLUMNS`] is read from a marketplace's columns, lowercase.
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    /// The first of these that is not blank, or blank when empty.
+    First(&'static [&'static str]),
+    /// Those of these that are not blank, joined with ` - `.
+    Join(&'static [&'static str]),
+}
+
+const NONE: Field = Field::First(&[]);
+
+/// How the columns of a marketplace's report map to [`COLUMNS`].
+struct Layout {
+    marketplace: Marketplace,
+    /// Columns, lowercase, that a header must have to be recognized.
+    required: &'static [&'static str],
+    /// How each of [`COLUMNS`] is read, in the same order.
+    columns: [Field; COLUMNS.len()],
+    /// A column of fees, charged on top of the total of a row, and the
+    /// description they are written with as a row of their own.
+    fee: Option<(&'static str, &'static str)>,
+    /// What the marketplace writes in a field that is blank.
+    blank: Option<&'static str>,
+    /// Format of dates that [`crate::sheet`] cannot read as written, which
+    /// are rewritten as `%Y-%m-%d`, followed by any time.
+    date_format: Option<&'static str>,
+}
+
+const LAYOUTS: &[Layout] = &[
+    Layout {
+        marketplace: Marketplace::Ebay,
+        required: &["transaction creation date", "type", "net amount"],
+        columns: [
+            Field::First(&["transaction creation date"]),
+            Field::First(&["payout id"]),
+            Field::First(&["type"]),
+            Field::First(&["order number"]),
+            Field::First(&["custom label"]),
+            // Fees and other rows without an item say what they are instead.
+            Field::First(&["item title", "description"]),
+            Field::First(&["quantity"]),
+            // Net of the fees eBay takes from each sale.
+            Field::First(&["net amount"]),
+        ],
+        fee: None,
+        blank: Some("--"),
+        date_format: None,
+    },
+    Layout {
+        marketplace: Marketplace::Shopify,
+        required: &["transaction date", "type", "amount", "fee", "net"],
+        columns: [
+            Field::First(&["transaction date"]),
+            Field::First(&["payout id"]),
+            Field::First(&["type"]),
+            Field::First(&["order"]),
+            NONE,
+            NONE,
+            NONE,
+            Field::First(&["amount"]),
+        ],
+        fee: Some(("fee", "Shopify Payments fee")),
+        blank: None,
+        date_format: None,
+    },
+    Layout {
+        marketplace: Marketplace::Etsy,
+        required: &["date", "type", "title", "info", "net"],
+        columns: [
+            Field::First(&["date"]),
+            NONE,
+            Field::First(&["type"]),
+            NONE,
+            NONE,
+            Field::First(&["title"]),
+            NONE,
+            // Net of the fees and taxes Etsy takes from each sale.
+            Field::First(&["net"]),
+        ],
+        fee: None,
+        blank: Some("--"),
+        date_format: None,
+    },
+    Layout {
+        marketplace: Marketplace::Walmart,
+        required: &[
+            "transaction posted timestamp",
+            "transaction type",
+            "amount type",
+            "amount",
+        ],
+        columns: [
+            Field::First(&["transaction posted timestamp"]),
+            NONE,
+            // Each amount of a sale, such as its price and the commission on
+            // it, is a row of its own.
+            Field::Join(&["transaction type", "amount type"]),
+            Field::First(&["customer order #"]),
+            Field::First(&["partner item id"]),
+            Field::First(&["partner item name", "transaction description"]),
+            Field::First(&["ship qty"]),
+            Field::First(&["amount"]),
+        ],
+        fee: None,
+        blank: None,
+        date_format: Some("%m/%d/%Y"),
+    },
+];
+
+/// Currency symbols that some marketplaces write amounts with, unlike ISO
+/// codes, which are read as they are, see `split_currency`.
+const SYMBOLS: [char; 4] = ['$', '€', '£', '¥'];
+
+/// How the columns of a report map to [`COLUMNS`], from a built-in
+/// [`Layout`] or one of `profiles`. Column names are lowercase.
+struct Mapping {
+    name: String,
+    required: Vec<String>,
+    /// The columns each of [`COLUMNS`] is read from, and whether they are
+    /// joined, see [`Field`].
+    columns: [(Vec<String>, bool); COLUMNS.len()],
+    fee: Option<(String, String)>,
+    blank: Option<String>,
+    date_format: Option<String>,
+    decimal_comma: bool,
+    skip_rows: u64,
+    /// The profile's [`Profile::header`], or `None` for a built-in layout.
+    pinned: Option<Vec<String>>,
+}
+
+impl Layout {
+    fn mapping(&self) -> Mapping {
+        let owned = |names: &[&str]| names.iter().map(|n| n.to_string()).collect();
+        Mapping {
+            name: self.marketplace.to_string(),
+            required: owned(self.required),
+            columns: self.columns.map(|field| match field {
+                Field::First(names) => (owned(names), false),
+                Field::Join(names) => (owned(names), true),
+            }),
+            fee: (self.fee).map(|(name, description)| (name.into(), description.into())),
+            blank: self.blank.map(str::to_string),
+            date_format: self.date_format.map(str::to_string),
+            decimal_comma: false,
+            skip_rows: 0,
+            pinned: None,
+        }
+    }
+}
+
+impl From<&Profile> for Mapping {
+    fn from(profile: &Profile) -> Self {
+        let lower = |names: &[String]| {
+            (names.iter())
+                .map(|name| name.trim().to_lowercase())
+                .collect::<Vec<_>>()
+        };
+        let c = &profile.columns;
+        let columns = [
+            &c.date,
+            &c.settlement_id,
+            &c.kind,
+            &c.order_id,
+            &c.sku,
+            &c.description,
+            &c.quantity,
+            &c.total,
+        ]
+        .map(|names| (lower(names), false));
+        let fee = profile.fee.as_deref().map(|fee| fee.trim().to_lowercase());
+        Self {
+            name: profile.name.clone(),
+            required: (columns.iter())
+                .flat_map(|(names, _)| names.iter().cloned())
+                .chain(fee.clone())
+                .collect(),
+            columns,
+            fee: fee.map(|fee| (fee, format!("{} fee", profile.name))),
+            blank: profile.blank.clone(),
+            date_format: profile.date_format.clone(),
+            decimal_comma: profile.decimal_comma,
+            skip_rows: profile.skip_rows,
+            pinned: Some(profile.header.clone()),
+        }
+    }
+}
+
+/// Converts the rows of a report from another marketplace, see the
+/// [module](self).
+#[derive(Debug, Clone)]
+pub(crate) struct Convert {
+    /// Name of the marketplace or profile.
+    pub(crate) name: String,
+    /// Indices of the columns each of [`COLUMNS`] is read from, and whether
+    /// they are joined.
+    columns: [(Vec<usize>, bool); COLUMNS.len()],
+    /// Other columns, kept after [`COLUMNS`] as written.
+    rest: Vec<usize>,
+    fee: Option<(usize, String)>,
+    blank: Option<String>,
+    date_format: Option<String>,
+    decimal_comma: bool,
+    /// Columns the header must have when read with a profile, see
+    /// [`Profile::header`], or `None` for a built-in marketplace.
+    pub(crate) pinned: Option<Vec<String>>,
+}
+
+impl Convert {
+    /// The converter for `hdr`, the `row`th row of a report counting from 0,
+    /// if it is the header of an export of one of `profiles` or of another
+    /// marketplace than Amazon. With `source` set, only that marketplace's.
+    pub(crate) fn detect(hdr: &StringRecord, row: u64, config: &Config) -> Option<Self> {
+        let names = hdr
+            .iter()
+            .map(|name| name.trim().to_lowercase())
+            .collect::<Vec<_>>();
+        let position = |name: &str| names.iter().position(|n| n == name);
+        let profiles = config
+            .profiles
+            .iter()
+            .filter(|_| config.source.is_none())
+            .map(Mapping::from);
+        let layouts = LAYOUTS
+            .iter()
+            .filter(|layout| {
+                config
+                    .source
+                    .is_none_or(|source| source == layout.marketplace)
+            })
+            .map(Layout::mapping);
+        let mapping = profiles.chain(layouts).find(|mapping| {
+            row >= mapping.skip_rows && mapping.required.iter().all(|name| position(name).is_some())
+        })?;
+        let columns = (mapping.columns).map(|(names, join)| {
+            (
+                names.iter().filter_map(|n| position(n)).collect::<Vec<_>>(),
+                join,
+            )
+        });
+        let fee =
+            (mapping.fee).and_then(|(name, description)| Some((position(&name)?, description)));
+        let rest = (0..names.len())
+            .filter(|i| !columns.iter().any(|(c, _)| c.contains(i)))
+            .filter(|i| fee.as_ref().map(|(f, _)| f) != Some(i))
+            // Which would be mistaken for the converted column.
+            .filter(|i| !COLUMNS.contains(&names[*i].as_str()))
+            .collect();
+        Some(Self {
+            name: mapping.name,
+            columns,
+            rest,
+            fee,
+            blank: mapping.blank,
+            date_format: mapping.date_format,
+            decimal_comma: mapping.decimal_comma,
+            pinned: mapping.pinned,
+        })
+    }
+
+    /// The header of converted rows, for a report whose header is `hdr`.
+    pub(crate) fn header(&self, hdr: &StringRecord) -> StringRecord {
+        let rest = self.rest.iter().map(|&i| hdr.get(i).unwrap_or_default());
+        let mut converted = COLUMNS.into_iter().chain(rest).collect::<StringRecord>();
+        converted.set_position(hdr.position().cloned());
+        converted
+    }
+
+    /// Indices of the columns of the converted header that the `i`th column
+    /// of the report is read into.
+    pub(crate) fn read_into(&self, i: usize) -> Vec<usize> {
+        let columns = (self.columns.iter().enumerate())
+            .filter(|(_, (columns, _))| columns.contains(&i))
+            .map(|(c, _)| c);
+        let rest = self.rest.iter().position(|&r| r == i);
+        columns.chain(rest.map(|r| COLUMNS.len() + r)).collect()
+    }
+
+    /// Whether the `i`th column of the report is read as fees.
+    pub(crate) fn is_fee(&self, i: usize) -> bool {
+        self.fee.as_ref().is_some_and(|(fee, _)| *fee == i)
+    }
+
+    /// Those of [`COLUMNS`] that no column of the report is read into.
+    pub(crate) fn missing(&self) -> impl Iterator<Item = &'static str> + '_ {
+        (COLUMNS.into_iter().zip(&self.columns))
+            .filter(|(_, (columns, _))| columns.is_empty())
+            .map(|(column, _)| column)
+    }
+
+    /// The rows that `r` is read as: itself, followed by its fee if it has
+    /// one.
+    pub(crate) fn rows(&self, r: &StringRecord) -> Vec<StringRecord> {
+        let mut rows = vec![self.convert(r, |_| None)];
+        if let Some((i, description)) = &self.fee {
+            let fee = self.amount(r.get(i.to_owned()).unwrap_or_default().trim());
+            let charged = !matches!(crate::handle_punct(&fee, Rounding::HalfEven), Ok(0));
+            if !fee.is_empty() && charged {
+                let negated = match fee.strip_prefix('-') {
+                    Some(refunded) => refunded.to_string(),
+                    None => format!("-{fee}"),
+                };
+                rows.push(self.convert(r, |column| match column {
+                    "type" => Some(FEE),
+                    "sku" | "quantity" => Some(""),
+                    "description" => Some(description),
+                    "total" => Some(&negated),
+                    _ => None,
+                }));
+            }
+        }
+        for row in &mut rows {
+            row.set_position(r.position().cloned());
+        }
+        rows
+    }
+
+    /// `r` rearranged into [`COLUMNS`] and the rest, with the fields that
+    /// `overrides` gives for some of [`COLUMNS`] instead.
+    fn convert<'a>(
+        &self,
+        r: &'a StringRecord,
+        overrides: impl Fn(&str) -> Option<&'a str>,
+    ) -> StringRecord {
+        let field = |i: usize| match r.get(i).unwrap_or_default() {
+            field if Some(field.trim()) == self.blank.as_deref() => "",
+            field => field,
+        };
+        let mut row = StringRecord::new();
+        for (column, (columns, join)) in COLUMNS.iter().zip(&self.columns) {
+            let mut fields = columns
+                .iter()
+                .map(|&i| field(i))
+                .filter(|f| !f.trim().is_empty());
+            let value = match overrides(column) {
+                Some(value) => Cow::Borrowed(value),
+                None if *join => Cow::Owned(fields.collect::<Vec<_>>().join(" - ")),
+                None => Cow::Borrowed(fields.next().unwrap_or_default()),
+            };
+            let value = match *column {
+                "date/time" => self.date(value),
+                "total" => match self.amount(&value) {
+                    Cow::Owned(amount) => Cow::Owned(amount),
+                    Cow::Borrowed(_) => value,
+                },
+                _ => value,
+            };
+            row.push_field(&value);
+        }
+        for &i in &self.rest {
+            row.push_field(field(i));
+        }
+        row
+    }
+
+    /// `written` rewritten as `%Y-%m-%d` and any time after it, if it is in
+    /// the export's date format.
+    fn date<'a>(&self, written: Cow<'a, str>) -> Cow<'a, str> {
+        let Some(format) = &self.date_format else {
+            return written;
+        };
+        match NaiveDate::parse_and_remainder(written.trim(), format) {
+            Ok((date, time)) => Cow::Owned(
+                format!("{} {}", date.format("%Y-%m-%d"), time.trim())
+                    .trim_end()
+                    .to_string(),
+            ),
+            Err(_) => written,
+        }
+    }
+
+    /// `amount` without any of [`SYMBOLS`], as in `-$1.20`, and with a
+    /// decimal point if the export writes a decimal comma.
+    fn amount<'a>(&self, amount: &'a str) -> Cow<'a, str> {
+        let amount = match amount.contains(SYMBOLS) {
+            true => Cow::Owned(amount.replace(SYMBOLS, "").trim().to_string()),
+            false => Cow::Borrowed(amount),
+        };
+        match self.decimal_comma {
+            true => Cow::Owned(amount.replace('.', "").replace(',', ".")),
+            false => amount,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn source(marketplace: Marketplace) -> Config {
+        Config {
+            source: Some(marketplace),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn converts_shopify() {
+        let hdr = StringRecord::from(vec![
+            "Transaction Date",
+            "Type",
+            "Order",
+            "Card Brand",
+            "Payout ID",
+            "Amount",
+            "Fee",
+            "Net",
+        ]);
+        let convert = Convert::detect(&hdr, 0, &Config::default()).unwrap();
+        assert_eq!(convert.name, Marketplace::Shopify.to_string());
+        assert_eq!(
+            convert.header(&hdr),
+            vec![
+                "date/time",
+                "settlement id",
+                "type",
+                "order id",
+                "sku",
+                "description",
+                "quantity",
+                "total",
+                "Card Brand",
+                "Net"
+            ]
+        );
+        let row = |fields: Vec<&str>| convert.rows(&StringRecord::from(fields));
+        assert_eq!(
+            row(vec![
+                "2024-01-05",
+                "charge",
+                "#1001",
+                "visa",
+                "7",
+                "20.00",
+                "0.88",
+                "19.12"
+            ]),
+            [
+                vec![
+                    "2024-01-05",
+                    "7",
+                    "charge",
+                    "#1001",
+                    "",
+                    "",
+                    "",
+                    "20.00",
+                    "visa",
+                    "19.12"
+                ],
+                vec![
+                    "2024-01-05",
+                    "7",
+                    FEE,
+                    "#1001",
+                    "",
+                    "Shopify Payments fee",
+                    "",
+                    "-0.88",
+                    "visa",
+                    "19.12"
+                ],
+            ]
+        );
+        assert_eq!(
+            row(vec![
+                "2024-01-06",
+                "adjustment",
+                "",
+                "",
+                "7",
+                "-5.00",
+                "0.00",
+                "-5.00"
+            ])
+            .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn converts_ebay() {
+        let hdr = StringRecord::from(vec![
+            "Transaction creation date",
+            "Type",
+            "Order number",
+            "Custom label",
+            "Quantity",
+            "Item title",
+            "Net amount",
+            "Description",
+        ]);
+        let convert = Convert::detect(&hdr, 0, &Config::default()).unwrap();
+        assert_eq!(convert.name, Marketplace::Ebay.to_string());
+        let rows = convert.rows(&StringRecord::from(vec![
+            "Jan 5, 2024",
+            "Order",
+            "01-1",
+            "A",
+            "1",
+            "Widget",
+            "8.50",
+            "--",
+        ]));
+        assert_eq!(
+            rows,
+            [vec![
+                "Jan 5, 2024",
+                "",
+                "Order",
+                "01-1",
+                "A",
+                "Widget",
+                "1",
+                "8.50"
+            ]]
+        );
+        let fee = convert.rows(&StringRecord::from(vec![
+            "Jan 7, 2024",
+            "Other fee",
+            "--",
+            "--",
+            "--",
+            "--",
+            "-1.00",
+            "Promoted listing fee",
+        ]));
+        assert_eq!(
+            fee,
+            [vec![
+                "Jan 7, 2024",
+                "",
+                "Other fee",
+                "",
+                "",
+                "Promoted listing fee",
+                "",
+                "-1.00"
+            ]]
+        );
+        let amazon = StringRecord::from(vec!["date/time", "type", "sku", "total"]);
+        assert!(Convert::detect(&amazon, 0, &Config::default()).is_none());
+        assert!(Convert::detect(&hdr, 0, &source(Marketplace::Shopify)).is_none());
+    }
+
+    #[test]
+    fn converts_etsy() {
+        let hdr = StringRecord::from(vec![
+            "Date",
+            "Type",
+            "Title",
+            "Info",
+            "Currency",
+            "Amount",
+            "Fees & Taxes",
+            "Net",
+        ]);
+        let convert = Convert::detect(&hdr, 0, &Config::default()).unwrap();
+        assert_eq!(convert.name, Marketplace::Etsy.to_string());
+        let row = |fields: Vec<&str>| convert.rows(&StringRecord::from(fields));
+        assert_eq!(
+            row(vec![
+                "January 5, 2024",
+                "Fee",
+                "Transaction fee: Widget",
+                "Order #12",
+                "USD",
+                "--",
+                "-$0.65",
+                "-$0.65"
+            ]),
+            [vec![
+                "January 5, 2024",
+                "",
+                "Fee",
+                "",
+                "",
+                "Transaction fee: Widget",
+                "",
+                "-0.65",
+                "Order #12",
+                "USD",
+                "",
+                "-$0.65"
+            ]]
+        );
+    }
+
+    #[test]
+    fn converts_walmart() {
+        let hdr = StringRecord::from(vec![
+            "Transaction Posted Timestamp",
+            "Transaction Type",
+            "Customer Order #",
+            "Amount",
+            "Amount Type",
+            "Ship Qty",
+            "Partner Item Id",
+            "Partner Item Name",
+        ]);
+        let convert = Convert::detect(&hdr, 0, &source(Marketplace::Walmart)).unwrap();
+        let rows = convert.rows(&StringRecord::from(vec![
+            "01/05/2024 10:11:12",
+            "SALE",
+            "2001",
+            "-1.50",
+            "Commission on Product",
+            "1",
+            "W-1",
+            "Widget",
+        ]));
+        assert_eq!(
+            rows,
+            [vec![
+                "2024-01-05 10:11:12",
+                "",
+                "SALE - Commission on Product",
+                "2001",
+                "W-1",
+                "Widget",
+                "1",
+                "-1.50"
+            ]]
+        );
+    }
+
+    #[test]
+    fn converts_profile() {
+        let config: Config = toml::from_str(
+            r#"
+            [[profiles]]
+            name = "Bank"
+            skip_rows = 1
+            date_format = "%d.%m.%Y"
+            decimal_comma = true
+            [profiles.columns]
+            date = ["Buchungstag"]
+            type = ["Art"]
+            description = ["Verwendungszweck", "Empfänger"]
+            total = ["Betrag"]
+            "#,
+        )
+        .unwrap();
+        let hdr = StringRecord::from(vec![
+            "Buchungstag",
+            "Art",
+            "Empfänger",
+            "Verwendungszweck",
+            "Betrag",
+        ]);
+        assert!(Convert::detect(&hdr, 0, &config).is_none());
+        let convert = Convert::detect(&hdr, 1, &config).unwrap();
+        assert_eq!(convert.name, "Bank");
+        let rows = convert.rows(&StringRecord::from(vec![
+            "05.01.2024",
+            "Gutschrift",
+            "Amazon",
+            "",
+            "1.234,50 €",
+        ]));
+        assert_eq!(
+            rows,
+            [vec![
+                "2024-01-05",
+                "",
+                "Gutschrift",
+                "",
+                "",
+                "Amazon",
+                "",
+                "1234.50"
+            ]]
+        );
+        assert!(Convert::detect(
+            &hdr,
+            1,
+            &Config {
+                source: Some(Marketplace::Etsy),
+                ..config
+            }
+        )
+        .is_none());
+    }
+}
@@ -0,0 +1,1151 @@
+//! Hashes of what earlier runs have seen, persisted between runs.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use chrono::{Months, NaiveDate};
+use eyre::WrapErr as _;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{audit, crypt, Config, Encryption, Input, Memories};
+
+mod binary;
+mod bloom;
+#[cfg(feature = "postgres")]
+mod postgres;
+#[cfg(feature = "redis")]
+mod redis;
+
+/// Memory of rows that were aggregated.
+pub const RECORDS: &str = "memory";
+/// Memory of SKUs that were aggregated.
+pub const SKUS: &str = "sku_memory";
+/// Memory of loosened rows, see `near_duplicates`.
+pub const NEAR: &str = "near_memory";
+
+/// Marks the first record of a memory file that has a version.
+const MAGIC: &str = "dedupy-memory";
+
+/// Version of the memory file format that is written.
+///
+/// 1. One hash per line, always seahash.
+/// 2. A header of [`MAGIC`], the version, and the [`HashAlgorithm`].
+/// 3. The day a hash was last seen follows it on every line.
+/// 4. Binary, see [`binary`]. Files of earlier versions are CSV, and are
+///    rewritten in binary when memory is next written.
+const VERSION: u32 = 4;
+
+/// The journal of a memory file is compacted into it once it is larger than
+/// this fraction of the file.
+const COMPACT: u64 = 4;
+
+/// Format of the day a hash was last seen.
+const DATE: &str = "%Y-%m-%d";
+
+/// Hash functions that memory can be kept with.
+///
+/// All of them are truncated to 64 bits, see `verify_memory` for protection
+/// against collisions.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Seahash,
+    Xxh3,
+    Blake3,
+}
+
+impl HashAlgorithm {
+    pub(crate) fn hash(self, bytes: &[u8]) -> u64 {
+        match self {
+            Self::Seahash => seahash::hash(bytes),
+            Self::Xxh3 => xxhash_rust::xxh3::xxh3_64(bytes),
+            Self::Blake3 => {
+                let digest = blake3::hash(bytes);
+                let mut first = [0; 8];
+                first.copy_from_slice(&digest.as_bytes()[..8]);
+                u64::from_le_bytes(first)
+            }
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Seahash => "seahash",
+            Self::Xxh3 => "xxh3",
+            Self::Blake3 => "blake3",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [Self::Seahash, Self::Xxh3, Self::Blake3]
+            .into_iter()
+            .find(|a| a.name() == name)
+    }
+}
+
+/// Context of an error reading a memory file that exists, found with
+/// `downcast_ref` on the error of [`crate::Report::parse`].
+///
+/// Memory that cannot be read is never replaced with empty memory, as every
+/// transaction would then be aggregated again, see [`rebuild`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Corrupt(pub PathBuf);
+
+impl std::fmt::Display for Corrupt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} cannot be read, restore it from a backup or run `dedupy memory rebuild`",
+            self.0.display()
+        )
+    }
+}
+
+impl std::error::Error for Corrupt {}
+
+/// What is remembered about a hash.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Entry {
+    /// The last day a run saw the hash.
+    seen: NaiveDate,
+    /// Keys that produced the hash, empty unless verifying.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    keys: Vec<String>,
+}
+
+impl Entry {
+    fn new(seen: NaiveDate) -> Self {
+        Self {
+            seen,
+            keys: Vec::new(),
+        }
+    }
+
+    /// Adds what `other` remembers about the same hash.
+    fn absorb(&mut self, other: Self) {
+        self.seen = self.seen.max(other.seen);
+        for key in other.keys {
+            if !self.keys.contains(&key) {
+                self.keys.push(key);
+            }
+        }
+    }
+}
+
+/// Where what runs remember is appended to the memory file at `path`, see
+/// `memory_journal`.
+fn journal(path: &Path) -> PathBuf {
+    path.with_extension("journal")
+}
+
+/// A set of hashes of transactions that have already been written to disk.
+///
+/// Every hash maps to the keys that produced it. Keys are only kept when
+/// verifying, in which case a hash that matches a different key is a collision
+/// rather than a duplicate. Hashes without keys were written without verifying,
+/// and adopt the first key that matches them.
+#[derive(Debug, Default)]
+pub(crate) struct Memory {
+    set: HashMap<u64, Entry>,
+    side_set: HashMap<u64, Entry>,
+    diff: HashSet<String>,
+    path: PathBuf,
+    verify: bool,
+    algorithm: HashAlgorithm,
+    today: NaiveDate,
+    /// Hashes of `set` that were seen again, or added by merging or
+    /// settling.
+    changed: HashSet<u64>,
+    /// Hashes of `set` that were pruned.
+    forgotten: Vec<u64>,
+    /// Whether `set` was rehashed since it was loaded.
+    rehashed: bool,
+    /// Where memory is kept when it is not the file at `path`.
+    store: Option<Box<dyn Store>>,
+    encryption: Option<Encryption>,
+    /// Filter of the hashes of `set`, see `memory_bloom_filter`.
+    bloom: Option<bloom::Bloom>,
+    /// Whether what changed may be appended to the journal of the file at
+    /// `path` instead of rewriting it.
+    append: bool,
+}
+
+/// What a run has memorized so far, see [`crate::checkpoint`].
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct Progress {
+    /// Hashes remembered from earlier runs that were seen again.
+    seen: Vec<u64>,
+    /// Hashes that are new, with their keys when verifying.
+    added: Vec<(u64, Vec<String>)>,
+    /// Keys that are new, see [`Memory::write_difference`].
+    diff: Vec<String>,
+}
+
+/// Somewhere memory is kept instead of the file at its path.
+trait Store: std::fmt::Debug {
+    /// Reads everything remembered, or nothing if the memory was never saved.
+    fn load(&mut self, path: &Path) -> eyre::Result<Option<(HashAlgorithm, HashMap<u64, Entry>)>>;
+
+    /// Writes `entries`, replacing everything if `replace` is set. Otherwise
+    /// removes `forgotten`, and keeps anything else, such as what another
+    /// machine saved in the meantime.
+    fn save(
+        &mut self,
+        algorithm: HashAlgorithm,
+        replace: bool,
+        forgotten: &[u64],
+        entries: &mut dyn Iterator<Item = (&u64, &Entry)>,
+    ) -> eyre::Result<()>;
+}
+
+#[cfg(feature = "redis")]
+fn redis(url: &str, path: &Path) -> eyre::Result<Box<dyn Store>> {
+    Ok(Box::new(redis::Redis::connect(url, path)?))
+}
+
+#[cfg(not(feature = "redis"))]
+fn redis(_: &str, _: &Path) -> eyre::Result<Box<dyn Store>> {
+    eyre::bail!("`redis` is set, but dedupy was built without the redis feature")
+}
+
+#[cfg(feature = "postgres")]
+fn postgres(url: &str, path: &Path) -> eyre::Result<Box<dyn Store>> {
+    Ok(Box::new(postgres::Postgres::connect(url, path)?))
+}
+
+#[cfg(not(feature = "postgres"))]
+fn postgres(_: &str, _: &Path) -> eyre::Result<Box<dyn Store>> {
+    eyre::bail!("`postgres` is set, but dedupy was built without the postgres feature")
+}
+
+impl Memory {
+    pub(crate) fn write_difference(&self, path: &str) -> eyre::Result<()> {
+        if !self.diff.is_empty() {
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .truncate(true)
+                .write(true)
+                .open(path)?;
+            let mut temp = self.diff.iter().collect::<Vec<_>>();
+            temp.sort();
+            for item in temp {
+                writeln!(file, "{}", item)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remembers `s`, returning `false` if an earlier run already saw it.
+    pub(crate) fn memorize<S>(&mut self, s: S) -> bool
+    where
+        S: AsRef<str>,
+    {
+        let s = s.as_ref();
+        let hash = self.hash(s);
+        let entry = match &self.bloom {
+            Some(bloom) if !bloom.contains(hash) => None,
+            _ => self.set.get_mut(&hash),
+        };
+        if let Some(entry) = entry {
+            // Only what changed is saved, see `memory_journal`.
+            let mut changed = entry.seen != self.today;
+            if self.verify && entry.keys.is_empty() {
+                entry.keys.push(s.to_string());
+                changed = true;
+            }
+            if !self.verify || entry.keys.iter().any(|key| key == s) {
+                entry.seen = self.today;
+                if changed {
+                    self.changed.insert(hash);
+                }
+                return false;
+            }
+            warn!(
+                hash,
+                "hash collision in {}, treating as new",
+                self.path.display()
+            );
+        }
+        let today = self.today;
+        let entry = self
+            .side_set
+            .entry(hash)
+            .or_insert_with(|| Entry::new(today));
+        if self.verify && !entry.keys.iter().any(|key| key == s) {
+            entry.keys.push(s.to_string());
+        }
+        self.diff.insert(s.to_string());
+        true
+    }
+
+    /// Whether [`Memory::memorize`] would find `s` seen before, without
+    /// memorizing it.
+    pub(crate) fn remembers(&self, s: &str) -> bool {
+        let hash = self.hash(s);
+        if self
+            .bloom
+            .as_ref()
+            .is_some_and(|bloom| !bloom.contains(hash))
+        {
+            return false;
+        }
+        self.set.get(&hash).is_some_and(|entry| {
+            !self.verify || entry.keys.is_empty() || entry.keys.iter().any(|key| key == s)
+        })
+    }
+
+    /// What was memorized since memory was loaded, with the new keys only if
+    /// `diff`, as they are only written for SKUs.
+    pub(crate) fn progress(&self, diff: bool) -> Progress {
+        Progress {
+            seen: self.changed.iter().copied().collect(),
+            added: self
+                .side_set
+                .iter()
+                .map(|(hash, entry)| (*hash, entry.keys.clone()))
+                .collect(),
+            diff: match diff {
+                true => self.diff.iter().cloned().collect(),
+                false => Vec::new(),
+            },
+        }
+    }
+
+    /// Moves what was memorized into what was loaded, so that a later run
+    /// with the same memory sees it as seen before without reading it again.
+    /// It is still written as changed.
+    pub(crate) fn settle(&mut self) {
+        for (hash, entry) in std::mem::take(&mut self.side_set) {
+            self.set
+                .entry(hash)
+                .or_insert_with(|| Entry::new(entry.seen))
+                .absorb(entry);
+            self.changed.insert(hash);
+            if let Some(bloom) = &mut self.bloom {
+                bloom.insert(hash);
+            }
+        }
+    }
+
+    /// Memorizes `progress` of an interrupted run again, as if the rows it
+    /// came from had been read.
+    pub(crate) fn resume(&mut self, progress: Progress) {
+        for hash in progress.seen {
+            if let Some(entry) = self.set.get_mut(&hash) {
+                entry.seen = self.today;
+                self.changed.insert(hash);
+            }
+        }
+        for (hash, keys) in progress.added {
+            self.side_set.insert(
+                hash,
+                Entry {
+                    seen: self.today,
+                    keys,
+                },
+            );
+        }
+        self.diff.extend(progress.diff);
+    }
+
+    /// Hashes `s` the same way [`Memory::memorize`] does.
+    pub(crate) fn hash(&self, s: &str) -> u64 {
+        self.algorithm.hash(s.as_bytes())
+    }
+
+    /// Returns a new [`Memory`] instance.
+    ///
+    /// When `verify_memory` is set, the key of every new entry is written
+    /// alongside its hash. Memory kept with another algorithm is rehashed with
+    /// `memory_hash` if every entry has its key, and otherwise keeps using the
+    /// algorithm it was written with. Hashes last seen before
+    /// `memory_retention_months` are forgotten.
+    pub(crate) fn new<P>(path: P, config: &Config) -> eyre::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let mut memory = Self::open(path, config)?;
+        if let Some(months) = config.memory_retention_months {
+            let pruned = memory.prune(months);
+            if pruned > 0 {
+                info!(
+                    "forgot {pruned} hashes of {} not seen in {months} months",
+                    path.display()
+                );
+            }
+        }
+        if config.memory_bloom_filter {
+            let rate = config.memory_bloom_false_positive_rate;
+            memory.bloom = Some(bloom::Bloom::new(memory.set.keys().copied(), rate));
+        }
+        Ok(memory)
+    }
+
+    /// Like [`Memory::new`], without forgetting anything.
+    fn open(path: &Path, config: &Config) -> eyre::Result<Self> {
+        let mut store = match (&config.redis, &config.postgres) {
+            (None, None) => return Self::open_file(path, config),
+            (Some(url), None) => redis(url, path)?,
+            (None, Some(url)) => postgres(url, path)?,
+            (Some(_), Some(_)) => eyre::bail!("only one of `redis` and `postgres` can be set"),
+        };
+        let loaded = store.load(path)?;
+        let mut memory = Self::from_loaded(path, config, loaded);
+        memory.store = Some(store);
+        Ok(memory)
+    }
+
+    /// Like [`Memory::open`], always reading the file at `path`, and applying
+    /// its journal.
+    fn open_file(path: &Path, config: &Config) -> eyre::Result<Self> {
+        let Some(bytes) = crypt::read(path, config.encryption.as_ref())? else {
+            return Ok(Self::from_loaded(path, config, None));
+        };
+        let binary = bytes.starts_with(binary::MAGIC);
+        let decoded = match bytes.strip_prefix(binary::MAGIC) {
+            // Nothing writes an empty memory file, but a write that was cut
+            // short may leave one.
+            _ if bytes.is_empty() => Err(eyre::eyre!("{} is empty", path.display())),
+            Some(bytes) => binary::decode(bytes, path),
+            None => read_csv(&bytes, path),
+        };
+        let (found, mut set) = decoded.wrap_err_with(|| Corrupt(path.to_path_buf()))?;
+        let journal = journal(path);
+        let mut append = binary && config.memory_journal && config.encryption.is_none();
+        if let Some(bytes) = crypt::read(&journal, config.encryption.as_ref())? {
+            let (batches, complete) = binary::decode_journal(&bytes, &journal);
+            append &= complete;
+            for (algorithm, batch) in batches {
+                if algorithm != found {
+                    eyre::bail!(
+                        "{} uses {} but {} uses {}, remove it to continue without it",
+                        journal.display(),
+                        algorithm.name(),
+                        path.display(),
+                        found.name()
+                    );
+                }
+                for (hash, theirs) in batch {
+                    set.entry(hash)
+                        .or_insert_with(|| Entry::new(theirs.seen))
+                        .absorb(theirs);
+                }
+            }
+        }
+        let mut memory = Self::from_loaded(path, config, Some((found, set)));
+        memory.append = append;
+        Ok(memory)
+    }
+
+    /// Returns memory of what was loaded, or of nothing, rehashing it to
+    /// `memory_hash` when possible.
+    fn from_loaded(
+        path: &Path,
+        config: &Config,
+        loaded: Option<(HashAlgorithm, HashMap<u64, Entry>)>,
+    ) -> Self {
+        let algorithm = config.memory_hash;
+        let (mut found, mut set) = loaded.unwrap_or((algorithm, HashMap::default()));
+        let mut rehashed = false;
+        if found != algorithm {
+            if set.values().all(|entry| !entry.keys.is_empty()) {
+                info!(
+                    "rehashing {} from {} to {}",
+                    path.display(),
+                    found.name(),
+                    algorithm.name()
+                );
+                set = rehash(set, algorithm);
+                found = algorithm;
+                rehashed = true;
+            } else {
+                warn!(
+                    "{} uses {} and cannot be rehashed to {} because some entries were \
+                     remembered without `verify_memory`, continuing with {}",
+                    path.display(),
+                    found.name(),
+                    algorithm.name(),
+                    found.name()
+                );
+            }
+        }
+
+        Self {
+            path: path.to_path_buf(),
+            set,
+            verify: config.verify_memory,
+            algorithm: found,
+            today: chrono::Local::now().date_naive(),
+            rehashed,
+            encryption: config.encryption.clone(),
+            ..Self::default()
+        }
+    }
+
+    /// Forgets hashes last seen more than `months` ago, returning how many.
+    pub(crate) fn prune(&mut self, months: u32) -> usize {
+        let Some(cutoff) = self.today.checked_sub_months(Months::new(months)) else {
+            return 0;
+        };
+        let before = self.forgotten.len();
+        self.set.retain(|hash, entry| {
+            let keep = entry.seen >= cutoff;
+            if !keep {
+                self.forgotten.push(*hash);
+            }
+            keep
+        });
+        self.forgotten.len() - before
+    }
+
+    /// Adds everything `other` remembers, returning how many hashes were new.
+    ///
+    /// When the two were kept with different algorithms, whichever has every
+    /// key is rehashed to the other's.
+    pub(crate) fn merge(&mut self, other: Self) -> eyre::Result<usize> {
+        // Not kept up to date with what is merged.
+        self.bloom = None;
+        let mut set = other.set;
+        if other.algorithm != self.algorithm {
+            if set.values().all(|entry| !entry.keys.is_empty()) {
+                set = rehash(set, self.algorithm);
+            } else if self.set.values().all(|entry| !entry.keys.is_empty()) {
+                self.set = rehash(std::mem::take(&mut self.set), other.algorithm);
+                self.algorithm = other.algorithm;
+                self.rehashed = true;
+            } else {
+                eyre::bail!(
+                    "{} uses {} and {} uses {}, and neither can be rehashed because some \
+                     entries were remembered without `verify_memory`",
+                    self.path.display(),
+                    self.algorithm.name(),
+                    other.path.display(),
+                    other.algorithm.name()
+                );
+            }
+        }
+        let before = self.set.len();
+        for (hash, theirs) in set {
+            self.changed.insert(hash);
+            self.set
+                .entry(hash)
+                .or_insert_with(|| Entry::new(theirs.seen))
+                .absorb(theirs);
+        }
+        Ok(self.set.len() - before)
+    }
+
+    /// Number of hashes remembered.
+    pub(crate) fn len(&self) -> usize {
+        self.set.len() + self.side_set.len()
+    }
+
+    /// Saves what is remembered.
+    ///
+    /// A memory file only has what changed appended to its journal, unless
+    /// hashes were forgotten or rehashed, or the journal has grown past a
+    /// fraction of the file, in which case the journal is compacted into a
+    /// new file.
+    pub(crate) fn write(self) -> eyre::Result<()> {
+        if let Some(mut store) = self.store {
+            let set = &self.set;
+            let mut entries: Box<dyn Iterator<Item = _>> = if self.rehashed {
+                Box::new(set.iter().chain(&self.side_set))
+            } else {
+                let changed = self.changed.iter();
+                Box::new(
+                    changed
+                        .filter_map(|hash| set.get_key_value(hash))
+                        .chain(&self.side_set),
+                )
+            };
+            return store.save(self.algorithm, self.rehashed, &self.forgotten, &mut entries);
+        }
+        let journal = journal(&self.path);
+        if self.append && !self.rehashed && self.forgotten.is_empty() {
+            let len = |path: &Path| match std::fs::metadata(path) {
+                Ok(metadata) => Ok(metadata.len()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+                Err(e) => Err(e),
+            };
+            if len(&journal)? < len(&self.path)? / COMPACT {
+                let set = &self.set;
+                let entries = (self.changed.iter())
+                    .filter_map(|hash| set.get_key_value(hash))
+                    .chain(&self.side_set)
+                    .collect::<Vec<_>>();
+                if entries.is_empty() {
+                    return Ok(());
+                }
+                let bytes = binary::encode(self.algorithm, entries.len(), entries.into_iter());
+                let mut file = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&journal)?;
+                file.write_all(&bytes)?;
+                return Ok(());
+            }
+        }
+
+        let bytes = binary::encode(
+            self.algorithm,
+            self.set.len() + self.side_set.len(),
+            self.set.iter().chain(&self.side_set),
+        );
+        // Written next to itself and then renamed, so that the file is never
+        // left half written. The journal is applied again if removing it
+        // fails, which changes nothing.
+        let partial = self.path.with_extension("partial");
+        crypt::write(&partial, self.encryption.as_ref(), &bytes)?;
+        std::fs::rename(&partial, &self.path)?;
+        match std::fs::remove_file(&journal) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Whether the memory named `name` may exist, without loading it.
+fn exists(name: &str, config: &Config) -> eyre::Result<bool> {
+    Ok(config.redis.is_some() || config.postgres.is_some() || Path::new(name).try_exists()?)
+}
+
+/// Forgets hashes last seen more than `months` ago from every memory file.
+///
+/// Returns each file that exists with the number of hashes forgotten and
+/// remaining.
+pub fn prune(config: &Config, months: u32) -> eyre::Result<Vec<(&'static str, usize, usize)>> {
+    let mut pruned = Vec::new();
+    for path in [RECORDS, SKUS, NEAR] {
+        if !exists(path, config)? {
+            continue;
+        }
+        let mut memory = Memory::open(Path::new(path), config)?;
+        let forgotten = memory.prune(months);
+        pruned.push((path, forgotten, memory.len()));
+        memory.write()?;
+    }
+    Ok(pruned)
+}
+
+/// What [`rebuild`] did.
+#[derive(Debug, Default)]
+pub struct Rebuilt {
+    /// Reports that were read again.
+    pub inputs: usize,
+    /// Reports of recorded runs that are neither archived nor where they were
+    /// read from, whose transactions are not remembered.
+    pub missing: Vec<Input>,
+    /// Each memory file that was written, with the number of hashes it has.
+    pub memories: Vec<(&'static str, usize)>,
+}
+
+/// Writes new memory files by reading the reports of every run in
+/// `audit_log` again, oldest first, as if each run was repeated on the day
+/// it was made.
+///
+/// Reports are found like `replay` finds them, in `archive` or where they
+/// were read from. Outputs only have aggregates, so memory cannot be rebuilt
+/// from them. Memory files that exist are kept with `.old` appended to their
+/// names.
+pub fn rebuild(config: &Config) -> eyre::Result<Rebuilt> {
+    if config.redis.is_some() || config.postgres.is_some() {
+        eyre::bail!("only memory files can be rebuilt, not memory in `redis` or `postgres`");
+    }
+    let empty = |name| Memory::from_loaded(Path::new(name), config, None);
+    let mut memories = Memories {
+        rec: empty(RECORDS),
+        sku: empty(SKUS),
+        near: config.near_duplicates.then(|| empty(NEAR)),
+    };
+    let mut rebuilt = Rebuilt::default();
+    for record in audit::Record::read_all(config)? {
+        let day = record.timestamp.date_naive();
+        for input in record.summary.inputs {
+            let Some(path) = input.locate(config)? else {
+                warn!(
+                    "{} ({}) is not archived and no longer matches its checksum, skipping it",
+                    input.path.display(),
+                    input.checksum
+                );
+                rebuilt.missing.push(input);
+                continue;
+            };
+            for memory in memories.iter_mut() {
+                memory.today = day;
+            }
+            let file = std::fs::File::open(&path)?;
+            crate::aggregate(file, config, &mut memories, None, None, &mut (), None)
+                .wrap_err_with(|| format!("cannot read {} again", path.display()))?;
+            // Later runs see what this one remembered, as if it was loaded.
+            memories.iter_mut().for_each(Memory::settle);
+            rebuilt.inputs += 1;
+        }
+    }
+
+    for (name, memory) in [RECORDS, SKUS, NEAR].into_iter().zip(memories.iter_mut()) {
+        rebuilt.memories.push((name, memory.len()));
+        let path = Path::new(name);
+        for path in [path.to_path_buf(), journal(path)] {
+            let mut old = path.clone().into_os_string();
+            old.push(".old");
+            match std::fs::rename(&path, old) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+    }
+    memories.write()?;
+    Ok(rebuilt)
+}
+
+/// Everything remembered, for moving memory between machines.
+#[derive(Serialize, Deserialize)]
+struct Export {
+    /// [`VERSION`] of the dedupy that exported it.
+    format: u32,
+    exported: chrono::DateTime<chrono::Local>,
+    memories: Vec<Exported>,
+}
+
+/// A single memory file of an [`Export`].
+#[derive(Serialize, Deserialize)]
+struct Exported {
+    /// One of [`RECORDS`], [`SKUS`], or [`NEAR`].
+    name: String,
+    algorithm: HashAlgorithm,
+    entries: Vec<ExportedEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ExportedEntry {
+    hash: u64,
+    seen: NaiveDate,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    keys: Vec<String>,
+}
+
+/// A line of an [`Export`] written as CSV, repeated for every key.
+#[derive(Serialize, Deserialize)]
+struct ExportedRow {
+    memory: String,
+    algorithm: HashAlgorithm,
+    hash: u64,
+    seen: NaiveDate,
+    key: Option<String>,
+}
+
+fn is_csv(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("csv"))
+}
+
+/// Writes every memory file that exists to `path`, returning how many hashes
+/// were written.
+///
+/// Files ending in `.csv` are written as CSV, anything else as JSON.
+pub fn export(config: &Config, path: &Path) -> eyre::Result<usize> {
+    let mut memories = Vec::new();
+    for name in [RECORDS, SKUS, NEAR] {
+        if !exists(name, config)? {
+            continue;
+        }
+        let memory = Memory::open(Path::new(name), config)?;
+        let mut entries = memory
+            .set
+            .into_iter()
+            .map(|(hash, entry)| ExportedEntry {
+                hash,
+                seen: entry.seen,
+                keys: entry.keys,
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.hash);
+        memories.push(Exported {
+            name: name.to_string(),
+            algorithm: memory.algorithm,
+            entries,
+        });
+    }
+    let count = memories.iter().map(|m| m.entries.len()).sum();
+
+    if is_csv(path) {
+        let mut wtr = csv::Writer::from_path(path)?;
+        for memory in memories {
+            for entry in memory.entries {
+                let row = |key| ExportedRow {
+                    memory: memory.name.clone(),
+                    algorithm: memory.algorithm,
+                    hash: entry.hash,
+                    seen: entry.seen,
+                    key,
+                };
+                if entry.keys.is_empty() {
+                    wtr.serialize(row(None))?;
+                }
+                for key in &entry.keys {
+                    wtr.serialize(row(Some(key.clone())))?;
+                }
+            }
+        }
+        wtr.flush()?;
+    } else {
+        let export = Export {
+            format: VERSION,
+            exported: chrono::Local::now(),
+            memories,
+        };
+        serde_json::to_writer_pretty(std::fs::File::create(path)?, &export)?;
+    }
+    Ok(count)
+}
+
+/// Adds everything in an export at `path` to the memory files, returning each
+/// file with the number of hashes that were new to it.
+///
+/// Files ending in `.csv` are read as CSV, anything else as JSON.
+pub fn import(config: &Config, path: &Path) -> eyre::Result<Vec<(&'static str, usize)>> {
+    let memories = if is_csv(path) {
+        let mut memories = Vec::<Exported>::new();
+        for row in csv::Reader::from_path(path)?.deserialize::<ExportedRow>() {
+            let row = row?;
+            let position = memories
+                .iter()
+                .position(|m| m.name == row.memory && m.algorithm == row.algorithm);
+            let memory = match position {
+                Some(i) => &mut memories[i],
+                None => {
+                    memories.push(Exported {
+                        name: row.memory,
+                        algorithm: row.algorithm,
+                        entries: Vec::new(),
+                    });
+                    memories.last_mut().unwrap()
+                }
+            };
+            memory.entries.push(ExportedEntry {
+                hash: row.hash,
+                seen: row.seen,
+                keys: row.key.into_iter().collect(),
+            });
+        }
+        memories
+    } else {
+        let export = serde_json::from_reader::<_, Export>(std::fs::File::open(path)?)?;
+        if export.format > VERSION {
+            eyre::bail!(
+                "{} was exported by a newer version of dedupy (format {})",
+                path.display(),
+                export.format
+            );
+        }
+        export.memories
+    };
+
+    let mut imported = Vec::new();
+    for exported in memories {
+        let Some(name) = [RECORDS, SKUS, NEAR]
+            .into_iter()
+            .find(|name| *name == exported.name)
+        else {
+            eyre::bail!(
+                "{} has an unknown memory `{}`",
+                path.display(),
+                exported.name
+            );
+        };
+        let mut theirs = Memory {
+            path: path.to_path_buf(),
+            algorithm: exported.algorithm,
+            ..Memory::default()
+        };
+        for entry in exported.entries {
+            let merged = theirs
+                .set
+                .entry(entry.hash)
+                .or_insert_with(|| Entry::new(entry.seen));
+            merged.seen = merged.seen.max(entry.seen);
+            merged.keys.extend(entry.keys);
+        }
+        let mut memory = Memory::open(Path::new(name), config)?;
+        let new = memory.merge(theirs)?;
+        memory.write()?;
+        match imported.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, count)) => *count += new,
+            None => imported.push((name, new)),
+        }
+    }
+    Ok(imported)
+}
+
+/// Merges the memory files at `inputs` into a new one at `output`, returning
+/// how many hashes it has.
+///
+/// Inputs may have been written by any version of dedupy, and with any hash
+/// function, as long as the ones that differ can be rehashed, see
+/// `verify_memory`.
+pub fn merge<P>(config: &Config, inputs: &[P], output: &Path) -> eyre::Result<usize>
+where
+    P: AsRef<Path>,
+{
+    let mut merged = Memory {
+        path: output.to_path_buf(),
+        algorithm: config.memory_hash,
+        encryption: config.encryption.clone(),
+        ..Memory::default()
+    };
+    for input in inputs {
+        let input = input.as_ref();
+        if !input.try_exists()? {
+            eyre::bail!("{} does not exist", input.display());
+        }
+        merged.merge(Memory::open_file(input, config)?)?;
+    }
+    let count = merged.len();
+    merged.write()?;
+    Ok(count)
+}
+
+/// Reads memory written as CSV, before format 4.
+fn read_csv(bytes: &[u8], path: &Path) -> eyre::Result<(HashAlgorithm, HashMap<u64, Entry>)> {
+    let today = chrono::Local::now().date_naive();
+    let mut set = HashMap::<u64, Entry>::default();
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(bytes);
+    let mut records = rdr.records().peekable();
+    let mut version = 1;
+    let mut found = HashAlgorithm::Seahash;
+    if let Some(Ok(header)) = records.peek() {
+        if header.get(0) == Some(MAGIC) {
+            let field = |i| header.get(i).unwrap_or_default();
+            (version, found) = parse_header(field(1), field(2), path)?;
+            records.next();
+        }
+    }
+    for record in records {
+        let record = record?;
+        let hash = record.get(0).unwrap_or_default().parse::<u64>()?;
+        // Hashes from before days were kept start their window now.
+        let (seen, key) = if version < 3 {
+            (today, record.get(1))
+        } else {
+            let seen = record.get(1).unwrap_or_default();
+            (NaiveDate::parse_from_str(seen, DATE)?, record.get(2))
+        };
+        let entry = set.entry(hash).or_insert_with(|| Entry::new(seen));
+        entry.seen = entry.seen.max(seen);
+        entry.keys.extend(key.map(str::to_string));
+    }
+    Ok((found, set))
+}
+
+/// Checks the format version and hash function that memory was written with.
+fn parse_header(version: &str, name: &str, path: &Path) -> eyre::Result<(u32, HashAlgorithm)> {
+    let path = path.display();
+    let version = version.parse::<u32>()?;
+    if version > VERSION {
+        eyre::bail!("{path} was written by a newer version of dedupy (format {version})");
+    }
+    let algorithm = HashAlgorithm::from_name(name)
+        .ok_or_else(|| eyre::eyre!("{path} uses an unknown hash algorithm `{name}`"))?;
+    Ok((version, algorithm))
+}
+
+fn rehash(set: HashMap<u64, Entry>, algorithm: HashAlgorithm) -> HashMap<u64, Entry> {
+    let mut rehashed = HashMap::<u64, Entry>::with_capacity(set.len());
+    for old in set.into_values() {
+        for key in old.keys {
+            let entry = rehashed
+                .entry(algorithm.hash(key.as_bytes()))
+                .or_insert_with(|| Entry::new(old.seen));
+            entry.seen = entry.seen.max(old.seen);
+            entry.keys.push(key);
+        }
+    }
+    rehashed
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn hash(bytes: &[u8]) -> u64 {
+        HashAlgorithm::Seahash.hash(bytes)
+    }
+
+    fn entry(keys: &[&str]) -> Entry {
+        Entry {
+            keys: keys.iter().map(|k| k.to_string()).collect(),
+            ..Entry::default()
+        }
+    }
+
+    #[test]
+    fn verify_collisions() {
+        let mut memory = Memory {
+            verify: true,
+            ..Memory::default()
+        };
+        // Pretend that an earlier run saw "b" with the hash of "a", and that
+        // an unverified run saw "c".
+        memory.set.insert(hash(b"a"), entry(&["b"]));
+        memory.set.insert(hash(b"c"), entry(&[]));
+
+        assert!(memory.memorize("a"), "collision is new");
+        assert!(!memory.memorize("c"), "unverified hash matches");
+        assert_eq!(memory.set[&hash(b"c")].keys, ["c"], "and adopts the key");
+    }
+
+    #[test]
+    fn rehash_keys() {
+        let set = HashMap::from([(hash(b"a"), entry(&["a"]))]);
+        let set = rehash(set, HashAlgorithm::Xxh3);
+        assert_eq!(set[&HashAlgorithm::Xxh3.hash(b"a")].keys, ["a"]);
+    }
+
+    #[test]
+    fn merge_algorithms() {
+        let mut ours = Memory::default();
+        ours.set.insert(hash(b"a"), entry(&[]));
+        let mut theirs = Memory {
+            algorithm: HashAlgorithm::Xxh3,
+            ..Memory::default()
+        };
+        theirs
+            .set
+            .insert(HashAlgorithm::Xxh3.hash(b"a"), entry(&["a"]));
+        theirs
+            .set
+            .insert(HashAlgorithm::Xxh3.hash(b"b"), entry(&["b"]));
+
+        assert_eq!(ours.merge(theirs).unwrap(), 1, "rehashed to ours");
+        assert_eq!(ours.set[&hash(b"a")].keys, ["a"]);
+        assert!(ours.set.contains_key(&hash(b"b")));
+    }
+
+    #[test]
+    fn binary_migration() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let path = dir.join(RECORDS);
+        std::fs::write(
+            &path,
+            format!(
+                "dedupy-memory,3,seahash\n{},2024-01-02\n{},2024-01-03,b\n",
+                hash(b"a"),
+                hash(b"b")
+            ),
+        )
+        .unwrap();
+        let config = Config::default();
+
+        let mut memory = Memory::new(&path, &config).unwrap();
+        assert!(memory.memorize("c"));
+        memory.write().unwrap();
+        assert!(std::fs::read(&path).unwrap().starts_with(binary::MAGIC));
+
+        let mut memory = Memory::new(&path, &config).unwrap();
+        let day = |d| NaiveDate::from_ymd_opt(2024, 1, d).unwrap();
+        assert_eq!(memory.set[&hash(b"a")].seen, day(2));
+        assert_eq!(memory.set[&hash(b"b")].keys, ["b"]);
+        assert!(!memory.memorize("c"), "written in binary");
+    }
+
+    #[test]
+    fn journal_compaction() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let path = dir.join(RECORDS);
+        let config = Config::default();
+        let mut memory = Memory::new(&path, &config).unwrap();
+        for i in 0..100 {
+            memory.memorize(i.to_string());
+        }
+        memory.write().unwrap();
+        let written = std::fs::read(&path).unwrap();
+
+        let mut memory = Memory::new(&path, &config).unwrap();
+        assert!(memory.memorize("new"));
+        // As between the reports of a batch.
+        memory.settle();
+        assert!(!memory.memorize("new"), "seen before once settled");
+        memory.write().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), written, "appended instead");
+        assert!(journal(&path).exists());
+
+        // A run interrupted while appending.
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(journal(&path))
+            .unwrap();
+        file.write_all(&binary::MAGIC[..4]).unwrap();
+        let mut memory = Memory::new(&path, &config).unwrap();
+        assert!(!memory.memorize("new"), "read from the journal");
+        memory.write().unwrap();
+        assert!(!journal(&path).exists(), "compacted after a torn batch");
+
+        let mut memory = Memory::new(&path, &config).unwrap();
+        for i in 100..200 {
+            memory.memorize(i.to_string());
+        }
+        memory.write().unwrap();
+        let mut memory = Memory::new(&path, &config).unwrap();
+        assert!(memory.memorize("last"));
+        memory.write().unwrap();
+        assert!(!journal(&path).exists(), "compacted once it grew");
+        assert_eq!(Memory::new(&path, &config).unwrap().len(), 202);
+    }
+
+    #[test]
+    fn corrupt() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let path = dir.join(RECORDS);
+        let config = Config::default();
+        let mut memory = Memory::new(&path, &config).unwrap();
+        memory.memorize("a");
+        memory.write().unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+
+        for cut in [&bytes[..bytes.len() - 3], &[]] {
+            std::fs::write(&path, cut).unwrap();
+            let e = Memory::new(&path, &config).unwrap_err();
+            assert_eq!(e.downcast_ref(), Some(&Corrupt(path.clone())), "{e:?}");
+        }
+    }
+
+    #[test]
+    fn replaces_difference() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("NEW_SKU_FOUND.txt");
+        std::fs::write(&path, "a longer line left from before\n").unwrap();
+        let mut memory = Memory::default();
+        memory.memorize("b");
+        memory.write_difference(path.to_str().unwrap()).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "b\n");
+    }
+
+    #[test]
+    fn prune_unseen() {
+        let day = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        let mut memory = Memory {
+            today: day(2024, 6, 15),
+            ..Memory::default()
+        };
+        memory.set.insert(1, Entry::new(day(2022, 12, 14)));
+        memory.set.insert(2, Entry::new(day(2022, 12, 15)));
+        memory.set.insert(hash(b"a"), Entry::new(day(2020, 1, 1)));
+
+        assert!(!memory.memorize("a"), "seen again today");
+        assert_eq!(memory.prune(18), 1);
+        assert!(!memory.set.contains_key(&1));
+    }
+}
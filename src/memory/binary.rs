@@ -0,0 +1,136 @@
+//! The binary memory file format, written since format 4.
+//!
+//! After [`MAGIC`], the format version and the length of the name of the hash
+//! function, then the name itself, and the number of entries follow. Every
+//! entry is its hash, the day it was last seen as days since the first of
+//! January of year 1, and the number of its keys, each written as its length
+//! followed by its UTF-8 bytes. Numbers are little endian, a `u32` each except
+//! for hashes and the number of entries, which are a `u64`.
+//!
+//! The journal of a memory file is a series of batches, each written exactly
+//! like a memory file, see [`decode_journal`].
+
+use std::{collections::HashMap, path::Path};
+
+use chrono::{Datelike as _, NaiveDate};
+use tracing::warn;
+
+use super::{parse_header, Entry, HashAlgorithm, VERSION};
+
+/// Marks a memory file written in binary, unlike any CSV memory file.
+pub(super) const MAGIC: &[u8] = b"\0dedupy-memory\0";
+
+/// Entries of a memory file or a batch of its journal, kept with the hash
+/// function.
+type Batch = (HashAlgorithm, HashMap<u64, Entry>);
+
+/// Writes `entries`, kept with `algorithm`.
+pub(super) fn encode<'e>(
+    algorithm: HashAlgorithm,
+    len: usize,
+    entries: impl Iterator<Item = (&'e u64, &'e Entry)>,
+) -> Vec<u8> {
+    // 16 bytes for the hash, day, and number of keys of every entry.
+    let mut bytes = Vec::with_capacity(MAGIC.len() + 32 + len * 16);
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    let name = algorithm.name();
+    bytes.extend_from_slice(&(name.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(name.as_bytes());
+    bytes.extend_from_slice(&(len as u64).to_le_bytes());
+    for (hash, entry) in entries {
+        bytes.extend_from_slice(&hash.to_le_bytes());
+        bytes.extend_from_slice(&entry.seen.num_days_from_ce().to_le_bytes());
+        bytes.extend_from_slice(&(entry.keys.len() as u32).to_le_bytes());
+        for key in &entry.keys {
+            bytes.extend_from_slice(&(key.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(key.as_bytes());
+        }
+    }
+    bytes
+}
+
+/// Reads the entries of the memory file at `path`, whose `bytes` follow
+/// [`MAGIC`].
+pub(super) fn decode(bytes: &[u8], path: &Path) -> eyre::Result<Batch> {
+    read(&mut Reader { bytes, path })
+}
+
+/// Reads every batch of the journal at `path`, in the order they were
+/// appended, and whether all of them could be read.
+///
+/// A batch that cannot be read was left by a run that was interrupted while
+/// appending it, and is ignored with whatever follows it. Nothing appended
+/// after it could be read either, so the journal must be compacted instead.
+pub(super) fn decode_journal<'b>(mut bytes: &'b [u8], path: &'b Path) -> (Vec<Batch>, bool) {
+    let mut batches = Vec::new();
+    while !bytes.is_empty() {
+        let mut rdr = Reader { bytes, path };
+        match rdr.magic().and_then(|()| read(&mut rdr)) {
+            Ok(batch) => batches.push(batch),
+            Err(e) => {
+                warn!("ignoring the end of {}: {e}", path.display());
+                return (batches, false);
+            }
+        }
+        bytes = rdr.bytes;
+    }
+    (batches, true)
+}
+
+fn read(rdr: &mut Reader<'_>) -> eyre::Result<Batch> {
+    let path = rdr.path;
+    let version = u32::from_le_bytes(rdr.take()?);
+    let name = rdr.string()?;
+    let (_, algorithm) = parse_header(&version.to_string(), name, path)?;
+    let len = u64::from_le_bytes(rdr.take()?);
+    let mut set = HashMap::with_capacity(len.min(rdr.bytes.len() as u64 / 16) as usize);
+    for _ in 0..len {
+        let hash = u64::from_le_bytes(rdr.take()?);
+        let days = i32::from_le_bytes(rdr.take()?);
+        let seen = NaiveDate::from_num_days_from_ce_opt(days)
+            .ok_or_else(|| eyre::eyre!("{} has an invalid day", path.display()))?;
+        let keys = (0..u32::from_le_bytes(rdr.take()?))
+            .map(|_| rdr.string().map(str::to_string))
+            .collect::<eyre::Result<Vec<_>>>()?;
+        // A hash is written twice when a new key collided with it.
+        let entry = set.entry(hash).or_insert_with(|| Entry::new(seen));
+        entry.absorb(Entry { seen, keys });
+    }
+    Ok((algorithm, set))
+}
+
+struct Reader<'b> {
+    bytes: &'b [u8],
+    path: &'b Path,
+}
+
+impl<'b> Reader<'b> {
+    fn magic(&mut self) -> eyre::Result<()> {
+        match self.bytes.strip_prefix(MAGIC) {
+            Some(rest) => {
+                self.bytes = rest;
+                Ok(())
+            }
+            None => eyre::bail!("{} has a batch without a header", self.path.display()),
+        }
+    }
+
+    fn take<const N: usize>(&mut self) -> eyre::Result<[u8; N]> {
+        let Some((taken, rest)) = self.bytes.split_first_chunk::<N>() else {
+            eyre::bail!("{} is truncated", self.path.display());
+        };
+        self.bytes = rest;
+        Ok(*taken)
+    }
+
+    fn string(&mut self) -> eyre::Result<&'b str> {
+        let len = u32::from_le_bytes(self.take()?) as usize;
+        if self.bytes.len() < len {
+            eyre::bail!("{} is truncated", self.path.display());
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(std::str::from_utf8(taken)?)
+    }
+}
@@ -0,0 +1,87 @@
+//! A Bloom filter in front of the hashes of memory, see
+//! `memory_bloom_filter`.
+//!
+//! Most rows of a report are new, and a Bloom filter tells so without looking
+//! the hash up in memory. The filter is blocked: every bit of a hash falls in
+//! the same 512 bit block, so checking one touches a single cache line.
+
+use std::f64::consts::LN_2;
+
+/// Bits of a block.
+const BLOCK: u32 = 512;
+
+#[derive(Debug)]
+pub(super) struct Bloom {
+    blocks: Vec<[u64; 8]>,
+    /// Bits set for every hash.
+    k: u32,
+}
+
+impl Bloom {
+    /// A filter of `hashes` that mistakes about `rate` of other hashes for
+    /// one of them.
+    pub(super) fn new(hashes: impl ExactSizeIterator<Item = u64>, rate: f64) -> Self {
+        let n = hashes.len().max(1) as f64;
+        let bits = -n * rate.clamp(1e-9, 0.5).ln() / (LN_2 * LN_2);
+        let blocks = (bits / f64::from(BLOCK)).ceil().max(1.0) as usize;
+        let k = (bits / n * LN_2).round().clamp(1.0, 16.0) as u32;
+        let mut bloom = Self {
+            blocks: vec![[0; 8]; blocks],
+            k,
+        };
+        for hash in hashes {
+            bloom.insert(hash);
+        }
+        bloom
+    }
+
+    /// Index of the block of `hash`, and the bits of it that are set.
+    fn bits(&self, hash: u64) -> (usize, impl Iterator<Item = u32>) {
+        // Memory holds hashes already, so the block is picked with the hash
+        // and the bits in it with a remix of the hash.
+        let block = ((u128::from(hash) * self.blocks.len() as u128) >> 64) as usize;
+        let mixed = splitmix(hash);
+        let (a, b) = (mixed as u32, (mixed >> 32) as u32 | 1);
+        let bits = (0..self.k).map(move |i| a.wrapping_add(i.wrapping_mul(b)) % BLOCK);
+        (block, bits)
+    }
+
+    pub(super) fn insert(&mut self, hash: u64) {
+        let (block, bits) = self.bits(hash);
+        let block = &mut self.blocks[block];
+        for bit in bits {
+            block[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    /// Whether `hash` may have been inserted. If not, it certainly was not.
+    pub(super) fn contains(&self, hash: u64) -> bool {
+        let (block, mut bits) = self.bits(hash);
+        let block = &self.blocks[block];
+        bits.all(|bit| block[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+}
+
+/// The finalizer of SplitMix64, which spreads every bit of `x` over the
+/// whole result.
+fn splitmix(x: u64) -> u64 {
+    let x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn false_positives() {
+        let hash = |i: u32| seahash::hash(&i.to_le_bytes());
+        let bloom = Bloom::new((0..10_000).map(hash), 0.01);
+        assert!((0..10_000).all(|i| bloom.contains(hash(i))));
+        let mistaken = (10_000..110_000)
+            .filter(|&i| bloom.contains(hash(i)))
+            .count();
+        assert!(mistaken < 2_000, "{mistaken} false positives");
+    }
+}
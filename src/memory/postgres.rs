@@ -0,0 +1,114 @@
+//! Memory kept in PostgreSQL, so that several workers share it.
+//!
+//! A worker holds a lock on every memory it loaded until it saves it, so
+//! workers processing reports at the same time take turns rather than both
+//! aggregating the same transactions.
+
+use std::{collections::HashMap, fmt, path::Path};
+
+use postgres::Client;
+
+use super::{parse_header, Entry, HashAlgorithm, Store, VERSION};
+
+pub(super) struct Postgres {
+    client: Client,
+    /// File name of the memory, which identifies its rows.
+    memory: String,
+}
+
+impl fmt::Debug for Postgres {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Postgres")
+            .field("memory", &self.memory)
+            .finish()
+    }
+}
+
+impl Postgres {
+    /// Connects to the database at `url` for the memory kept at `path`,
+    /// waiting for any other worker using it.
+    pub(super) fn connect(url: &str, path: &Path) -> eyre::Result<Self> {
+        let memory = path
+            .file_name()
+            .ok_or_else(|| eyre::eyre!("{} is not a memory file name", path.display()))?
+            .to_string_lossy()
+            .into_owned();
+        let mut client = crate::postgres::connect(url)?;
+        client.execute("SELECT pg_advisory_lock(hashtext($1))", &[&memory])?;
+        Ok(Self { client, memory })
+    }
+}
+
+impl Store for Postgres {
+    fn load(&mut self, path: &Path) -> eyre::Result<Option<(HashAlgorithm, HashMap<u64, Entry>)>> {
+        let Some(meta) = self.client.query_opt(
+            "SELECT format, algorithm FROM dedupy_memory_meta WHERE memory = $1",
+            &[&self.memory],
+        )?
+        else {
+            return Ok(None);
+        };
+        let format = meta.get::<_, i32>(0).to_string();
+        let (_, algorithm) = parse_header(&format, meta.get(1), path)?;
+
+        let rows = self.client.query(
+            "SELECT hash, seen, keys FROM dedupy_memory WHERE memory = $1",
+            &[&self.memory],
+        )?;
+        let mut set = HashMap::with_capacity(rows.len());
+        for row in rows {
+            let entry = Entry {
+                seen: row.get(1),
+                keys: row.get(2),
+            };
+            set.insert(row.get::<_, i64>(0) as u64, entry);
+        }
+        Ok(Some((algorithm, set)))
+    }
+
+    /// Writes everything in a single transaction, then lets other workers
+    /// load the memory.
+    fn save(
+        &mut self,
+        algorithm: HashAlgorithm,
+        replace: bool,
+        forgotten: &[u64],
+        entries: &mut dyn Iterator<Item = (&u64, &Entry)>,
+    ) -> eyre::Result<()> {
+        let mut tx = self.client.transaction()?;
+        if replace {
+            tx.execute(
+                "DELETE FROM dedupy_memory WHERE memory = $1",
+                &[&self.memory],
+            )?;
+        } else if !forgotten.is_empty() {
+            let forgotten = forgotten.iter().map(|h| *h as i64).collect::<Vec<_>>();
+            tx.execute(
+                "DELETE FROM dedupy_memory WHERE memory = $1 AND hash = ANY($2)",
+                &[&self.memory, &forgotten],
+            )?;
+        }
+        tx.execute(
+            "INSERT INTO dedupy_memory_meta (memory, format, algorithm) VALUES ($1, $2, $3)
+             ON CONFLICT (memory) DO UPDATE
+             SET format = EXCLUDED.format, algorithm = EXCLUDED.algorithm",
+            &[&self.memory, &(VERSION as i32), &algorithm.name()],
+        )?;
+        let upsert = tx.prepare(
+            "INSERT INTO dedupy_memory (memory, hash, seen, keys) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (memory, hash) DO UPDATE
+             SET seen = GREATEST(dedupy_memory.seen, EXCLUDED.seen),
+                 keys = ARRAY(SELECT DISTINCT unnest(dedupy_memory.keys || EXCLUDED.keys))",
+        )?;
+        for (hash, entry) in entries {
+            tx.execute(
+                &upsert,
+                &[&self.memory, &(*hash as i64), &entry.seen, &entry.keys],
+            )?;
+        }
+        tx.commit()?;
+        self.client
+            .execute("SELECT pg_advisory_unlock(hashtext($1))", &[&self.memory])?;
+        Ok(())
+    }
+}
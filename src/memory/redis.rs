@@ -0,0 +1,100 @@
+//! Memory kept in Redis, so that several machines share it.
+//!
+//! Every memory is a Redis hash named `dedupy:` followed by its file name,
+//! mapping every hash to its [`Entry`] as JSON. The format version and hash
+//! function are kept in another Redis hash with `:meta` appended.
+
+use std::{collections::HashMap, fmt, path::Path};
+
+use redis::Commands as _;
+
+use super::{parse_header, Entry, HashAlgorithm, Store, VERSION};
+
+/// Entries written to Redis in a single command.
+const BATCH: usize = 10_000;
+
+pub(super) struct Redis {
+    con: redis::Connection,
+    key: String,
+}
+
+impl fmt::Debug for Redis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Redis").field("key", &self.key).finish()
+    }
+}
+
+impl Redis {
+    /// Connects to the server at `url` for the memory kept at `path`.
+    pub(super) fn connect(url: &str, path: &Path) -> eyre::Result<Self> {
+        let name = path
+            .file_name()
+            .ok_or_else(|| eyre::eyre!("{} is not a memory file name", path.display()))?;
+        Ok(Self {
+            con: redis::Client::open(url)?.get_connection()?,
+            key: format!("dedupy:{}", name.to_string_lossy()),
+        })
+    }
+
+    fn meta(&self) -> String {
+        format!("{}:meta", self.key)
+    }
+}
+
+impl Store for Redis {
+    fn load(&mut self, path: &Path) -> eyre::Result<Option<(HashAlgorithm, HashMap<u64, Entry>)>> {
+        let meta = self
+            .con
+            .hgetall::<_, HashMap<String, String>>(self.meta())?;
+        if meta.is_empty() {
+            return Ok(None);
+        }
+        let field = |name| meta.get(name).map(String::as_str).unwrap_or_default();
+        let (_, algorithm) = parse_header(field("format"), field("algorithm"), path)?;
+
+        let mut set = HashMap::new();
+        for (hash, entry) in self.con.hgetall::<_, HashMap<u64, String>>(&self.key)? {
+            set.insert(hash, serde_json::from_str::<Entry>(&entry)?);
+        }
+        Ok(Some((algorithm, set)))
+    }
+
+    /// Writes everything in a single transaction.
+    fn save(
+        &mut self,
+        algorithm: HashAlgorithm,
+        replace: bool,
+        forgotten: &[u64],
+        entries: &mut dyn Iterator<Item = (&u64, &Entry)>,
+    ) -> eyre::Result<()> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        if replace {
+            pipe.del(&self.key).ignore();
+        } else if !forgotten.is_empty() {
+            pipe.hdel(&self.key, forgotten).ignore();
+        }
+        pipe.hset_multiple(
+            self.meta(),
+            &[
+                ("format", VERSION.to_string()),
+                ("algorithm", algorithm.name().to_string()),
+            ],
+        )
+        .ignore();
+
+        let mut batch = Vec::with_capacity(BATCH);
+        for (hash, entry) in entries {
+            batch.push((*hash, serde_json::to_string(entry)?));
+            if batch.len() == BATCH {
+                pipe.hset_multiple(&self.key, &batch).ignore();
+                batch.clear();
+            }
+        }
+        if !batch.is_empty() {
+            pipe.hset_multiple(&self.key, &batch).ignore();
+        }
+        pipe.query::<()>(&mut self.con)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+//! Cleaning up fields before they are hashed or grouped, so that cosmetic
+//! differences do not split aggregates or defeat dedup.
+
+use std::borrow::Cow;
+
+use csv::StringRecord;
+use serde::Deserialize;
+use unicode_normalization::UnicodeNormalization as _;
+
+/// Steps applied to every field of every row, in the order listed.
+///
+/// Enabling any of these makes rows seen by earlier runs look new.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Normalize {
+    /// Remove leading and trailing whitespace.
+    pub trim: bool,
+    /// Replace runs of whitespace with a single space.
+    pub collapse_whitespace: bool,
+    /// Compose characters to Unicode normalization form C.
+    pub nfc: bool,
+    /// Lowercase everything.
+    pub case_fold: bool,
+}
+
+impl Normalize {
+    fn is_noop(&self) -> bool {
+        !(self.trim || self.collapse_whitespace || self.nfc || self.case_fold)
+    }
+
+    /// Normalizes every field of `r`, keeping its position.
+    pub(crate) fn record<'r>(&self, r: &'r StringRecord) -> Cow<'r, StringRecord> {
+        if self.is_noop() {
+            return Cow::Borrowed(r);
+        }
+        let mut normalized = r.iter().map(|f| self.field(f)).collect::<StringRecord>();
+        normalized.set_position(r.position().cloned());
+        Cow::Owned(normalized)
+    }
+
+    fn field<'f>(&self, field: &'f str) -> Cow<'f, str> {
+        let mut field = Cow::Borrowed(field);
+        if self.trim {
+            field = match field {
+                Cow::Borrowed(f) => Cow::Borrowed(f.trim()),
+                Cow::Owned(f) => Cow::Owned(f.trim().to_string()),
+            };
+        }
+        if self.collapse_whitespace {
+            field = Cow::Owned(collapse(&field));
+        }
+        if self.nfc {
+            field = Cow::Owned(field.nfc().collect());
+        }
+        if self.case_fold {
+            field = Cow::Owned(field.to_lowercase());
+        }
+        field
+    }
+}
+
+/// Replaces runs of whitespace with a single space, keeping a leading or
+/// trailing one.
+fn collapse(s: &str) -> String {
+    let mut collapsed = String::with_capacity(s.len());
+    let mut in_space = false;
+    for c in s.chars() {
+        if c.is_whitespace() {
+            if !in_space {
+                collapsed.push(' ');
+            }
+            in_space = true;
+        } else {
+            collapsed.push(c);
+            in_space = false;
+        }
+    }
+    collapsed
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn normalize_fields() {
+        let all = Normalize {
+            trim: true,
+            collapse_whitespace: true,
+            nfc: true,
+            case_fold: true,
+        };
+        // "e" followed by a combining acute accent.
+        assert_eq!(all.field("  Cafe\u{301}\u{a0}\t Fee "), "café fee");
+        assert_eq!(Normalize::default().field(" A  B "), " A  B ");
+        let collapse = Normalize {
+            collapse_whitespace: true,
+            ..Normalize::default()
+        };
+        assert_eq!(collapse.field(" A \t B "), " A B ");
+    }
+}
@@ -0,0 +1,142 @@
+//! Writing the output as an OpenDocument spreadsheet, for LibreOffice.
+//!
+//! The spreadsheet has the same sheets as the workbook: the sales, then a
+//! "Tax Summary" sheet if the report had any tax, an "Accounts" sheet with
+//! `accounts`, and a "Needs Review" sheet of quarantined rows. Only its
+//! content is written, so LibreOffice shows it in its default styles.
+
+use std::io::Write as _;
+
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use crate::{sheet, Aggregation, Cents};
+
+const MANIFEST: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<manifest:manifest xmlns:manifest="urn:oasis:names:tc:opendocument:xmlns:manifest:1.0" manifest:version="1.2">
+ <manifest:file-entry manifest:full-path="/" manifest:version="1.2" manifest:media-type="application/vnd.oasis.opendocument.spreadsheet"/>
+ <manifest:file-entry manifest:full-path="content.xml" manifest:media-type="text/xml"/>
+</manifest:manifest>
+"#;
+
+enum Cell<'a> {
+    Text(&'a str),
+    Number(f64),
+}
+
+fn money(cents: Cents) -> Cell<'static> {
+    Cell::Number(cents as f64 / 100.0)
+}
+
+/// Writes the sales of `aggregation` on a sheet named `sheet`, followed by
+/// the sheets that go with them, see the [module](self).
+pub(crate) fn render(aggregation: &Aggregation, sheet: &str) -> eyre::Result<Vec<u8>> {
+    let mut content = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<office:document-content xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0" xmlns:table="urn:oasis:names:tc:opendocument:xmlns:table:1.0" xmlns:text="urn:oasis:names:tc:opendocument:xmlns:text:1.0" office:version="1.2"><office:body><office:spreadsheet>"#,
+    );
+    let header = ["Type", "SKU", "Description", "Quantity", "Total"];
+    let sales = aggregation.sales.iter().map(|sale| {
+        vec![
+            Cell::Text(&sale.kind),
+            Cell::Text(&sale.sku),
+            Cell::Text(&sale.description),
+            Cell::Number(sale.quantity as f64),
+            money(sale.cents),
+        ]
+    });
+    table(&mut content, sheet, &header, sales);
+
+    let taxes = sheet::unique("Tax Summary", &[sheet]);
+    if aggregation.taxes.iter().next().is_some() {
+        let header = ["Jurisdiction", "Collected", "Withheld", "Net"];
+        let rows = aggregation.taxes.iter().map(|(jurisdiction, totals)| {
+            vec![
+                Cell::Text(jurisdiction),
+                money(totals.collected),
+                money(totals.withheld),
+                money(totals.collected + totals.withheld),
+            ]
+        });
+        table(&mut content, &taxes, &header, rows);
+    }
+
+    let accounts = sheet::unique("Accounts", &[sheet, &taxes]);
+    if !aggregation.accounts.is_empty() {
+        let rows = aggregation.accounts.iter().map(|total| {
+            vec![
+                Cell::Text(&total.code),
+                Cell::Text(&total.name),
+                money(total.cents),
+            ]
+        });
+        table(&mut content, &accounts, &["Code", "Name", "Total"], rows);
+    }
+
+    if !aggregation.quarantined.is_empty() {
+        let quarantined = aggregation.quarantined();
+        let (header, rows) = quarantined.split_first().expect("a header");
+        let header = header.iter().map(String::as_str).collect::<Vec<_>>();
+        let rows = rows
+            .iter()
+            .map(|row| row.iter().map(|field| Cell::Text(field)).collect());
+        let name = sheet::unique("Needs Review", &[sheet, &taxes, &accounts]);
+        table(&mut content, &name, &header, rows);
+    }
+    content += "</office:spreadsheet></office:body></office:document-content>\n";
+
+    let mut zip = ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    // Read as the type of the file, so first and uncompressed.
+    let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+    zip.start_file("mimetype", stored)?;
+    zip.write_all(b"application/vnd.oasis.opendocument.spreadsheet")?;
+    let deflated = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    zip.start_file("META-INF/manifest.xml", deflated)?;
+    zip.write_all(MANIFEST.as_bytes())?;
+    zip.start_file("content.xml", deflated)?;
+    zip.write_all(content.as_bytes())?;
+    Ok(zip.finish()?.into_inner())
+}
+
+/// Appends a sheet named `name` with a row of `header` followed by `rows`.
+fn table<'a>(
+    content: &mut String,
+    name: &str,
+    header: &[&'a str],
+    rows: impl Iterator<Item = Vec<Cell<'a>>>,
+) {
+    *content += &format!(r#"<table:table table:name="{}">"#, escape(name));
+    let header = header.iter().map(|name| Cell::Text(name)).collect();
+    for row in std::iter::once(header).chain(rows) {
+        *content += "<table:table-row>";
+        for cell in row {
+            *content += &match cell {
+                Cell::Text(text) => format!(
+                    r#"<table:table-cell office:value-type="string"><text:p>{}</text:p></table:table-cell>"#,
+                    escape(text)
+                ),
+                Cell::Number(number) => format!(
+                    r#"<table:table-cell office:value-type="float" office:value="{number}"><text:p>{number}</text:p></table:table-cell>"#
+                ),
+            };
+        }
+        *content += "</table:table-row>";
+    }
+    *content += "</table:table>";
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped += "&amp;",
+            '<' => escaped += "&lt;",
+            '>' => escaped += "&gt;",
+            '"' => escaped += "&quot;",
+            '\'' => escaped += "&apos;",
+            // Not allowed in XML at all.
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
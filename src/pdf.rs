@@ -0,0 +1,177 @@
+//! A one page PDF summary of a report, see `pdf_summary`, for whoever wants
+//! a printable snapshot rather than the rows.
+//!
+//! The page has the period and counts of the report, the total of every
+//! transaction type, the fees, and the SKUs with the largest orders. It is
+//! written by hand in the standard fonts every reader has, so that nothing
+//! is embedded, and lines past the bottom of the page are left out.
+
+use std::collections::BTreeMap;
+
+use crate::{analytics::is_fee, Aggregation, Cents};
+
+/// SKUs listed under "Top SKUs".
+const TOP_SKUS: usize = 10;
+
+/// Lines that fit on a page below the title.
+const LINES: usize = 56;
+
+enum Line {
+    Heading(String),
+    /// Monospaced, so that columns line up.
+    Text(String),
+    Blank,
+}
+
+/// Renders the summary of `aggregation`, read from `input`, see the
+/// [module](self).
+pub(crate) fn render(aggregation: &Aggregation, input: &str) -> Vec<u8> {
+    let mut lines = Vec::new();
+    let period = aggregation.period.to_string();
+    if !period.is_empty() {
+        lines.push(Line::Text(format!("Period       {period}")));
+    }
+    lines.push(Line::Text(format!("Rows         {}", aggregation.rows)));
+    lines.push(Line::Text(format!(
+        "Duplicates   {}",
+        aggregation.duplicates
+    )));
+    lines.push(Line::Text(format!(
+        "Aggregates   {}",
+        aggregation.sales.len()
+    )));
+
+    let totals = aggregation.totals();
+    lines.push(Line::Blank);
+    lines.push(Line::Heading("Totals by type".to_string()));
+    for (kind, cents) in &totals {
+        lines.push(Line::Text(row(kind, *cents)));
+    }
+    lines.push(Line::Text(row("Total", totals.values().sum())));
+
+    let fees = (totals.iter())
+        .filter(|(kind, _)| is_fee(kind))
+        .map(|(_, cents)| cents)
+        .sum();
+    lines.push(Line::Blank);
+    lines.push(Line::Heading("Fees".to_string()));
+    lines.push(Line::Text(row("Fees", fees)));
+
+    let mut skus = BTreeMap::<&str, Cents>::new();
+    for sale in aggregation
+        .sales
+        .iter()
+        .filter(|sale| &*sale.kind == "Order")
+    {
+        *skus.entry(&*sale.sku).or_default() += sale.cents;
+    }
+    let mut skus = skus.into_iter().collect::<Vec<_>>();
+    skus.sort_by_key(|&(sku, cents)| (std::cmp::Reverse(cents), sku));
+    if !skus.is_empty() {
+        lines.push(Line::Blank);
+        lines.push(Line::Heading("Top SKUs by orders".to_string()));
+        for (sku, cents) in skus.into_iter().take(TOP_SKUS) {
+            lines.push(Line::Text(row(sku, cents)));
+        }
+    }
+    lines.truncate(LINES);
+
+    let mut content = String::new();
+    content += &text("F2", 18, 50, 742, &format!("dedupy summary: {input}"));
+    let mut y = 712;
+    for line in &lines {
+        match line {
+            Line::Heading(heading) => content += &text("F2", 12, 50, y, heading),
+            Line::Text(line) => content += &text("F3", 10, 50, y, line),
+            Line::Blank => {}
+        }
+        y -= 12;
+    }
+    document(&content)
+}
+
+/// A name and an amount, in columns.
+fn row(name: &str, cents: Cents) -> String {
+    let name = match name.chars().count() > 48 {
+        true => format!("{}...", name.chars().take(45).collect::<String>()),
+        false => name.to_string(),
+    };
+    format!("{name:<48} {:>14.2}", cents as f64 / 100.0)
+}
+
+/// Draws `text` at `x`, `y` points from the bottom left of the page.
+fn text(font: &str, size: u32, x: u32, y: u32, text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            // The standard fonts are only written in Latin-1 here.
+            c if (c as u32) < 0x20 || (c as u32) > 0xff => escaped.push('?'),
+            c if (c as u32) > 0x7e => escaped += &format!("\\{:03o}", c as u32),
+            c => escaped.push(c),
+        }
+    }
+    format!("BT /{font} {size} Tf {x} {y} Td ({escaped}) Tj ET\n")
+}
+
+/// A PDF of a single US Letter page drawn by `content`.
+fn document(content: &str) -> Vec<u8> {
+    let font = |name: &str| {
+        format!("<< /Type /Font /Subtype /Type1 /BaseFont /{name} /Encoding /WinAnsiEncoding >>")
+    };
+    let objects = [
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+        "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 612 792] /Contents 4 0 R \
+         /Resources << /Font << /F1 5 0 R /F2 6 0 R /F3 7 0 R >> >> >>"
+            .to_string(),
+        format!(
+            "<< /Length {} >>\nstream\n{content}endstream",
+            content.len()
+        ),
+        font("Helvetica"),
+        font("Helvetica-Bold"),
+        font("Courier"),
+    ];
+    let mut pdf = b"%PDF-1.4\n".to_vec();
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (n, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(format!("{} 0 obj\n{object}\nendobj\n", n + 1).as_bytes());
+    }
+    let xref = pdf.len();
+    let mut trailer = format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+    for offset in offsets {
+        trailer += &format!("{offset:010} 00000 n \n");
+    }
+    trailer += &format!(
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+        objects.len() + 1
+    );
+    pdf.extend_from_slice(trailer.as_bytes());
+    pdf
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cross_references_objects() {
+        let pdf = document(&text("F1", 10, 50, 700, "Fees (all) \\ Gebühren"));
+        let pdf = String::from_utf8(pdf).unwrap();
+        assert!(pdf.contains(r"(Fees \(all\) \\ Geb\374hren) Tj"));
+
+        let xref = pdf.rsplit("startxref\n").next().unwrap();
+        let xref = xref.lines().next().unwrap().parse::<usize>().unwrap();
+        assert!(pdf[xref..].starts_with("xref\n0 8\n"));
+        let offsets = pdf[xref..].lines().skip(3).take(7);
+        for (n, offset) in offsets.enumerate() {
+            let offset = offset[..10].parse::<usize>().unwrap();
+            assert!(pdf[offset..].starts_with(&format!("{} 0 obj\n", n + 1)));
+        }
+    }
+}
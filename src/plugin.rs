@@ -0,0 +1,246 @@
+//! Plugins compiled to WebAssembly that rewrite or drop rows before they are
+//! aggregated, see `plugins`.
+//!
+//! A plugin is a core WebAssembly module, without WASI, exporting:
+//!
+//! - `memory`.
+//! - `dedupy_abi() -> i32`, returning the version of this interface, 1.
+//! - `dedupy_alloc(len: i32) -> i32`, returning where `len` bytes can be
+//!   written.
+//! - `dedupy_row(ptr: i32, len: i32) -> i64`, called with a row as JSON: an
+//!   object of `type`, `sku` (`null` for adjustments), `description`,
+//!   `quantity`, `cents`, and `columns`, every column of the row by header
+//!   after `normalize`. It returns `(ptr << 32) | len` of the row to use
+//!   instead, as JSON of the same shape without `columns`, or of `null` to
+//!   drop the row.
+//!
+//! An instance of every plugin is kept per thread parsing rows, so a plugin
+//! can keep state between rows, but not across threads.
+
+use std::path::PathBuf;
+
+use csv::StringRecord;
+
+use crate::Fields;
+
+/// Version of the interface above, bumped on any change to it.
+#[cfg(feature = "plugins")]
+const ABI: i32 = 1;
+
+#[cfg(feature = "plugins")]
+pub(crate) struct Plugins {
+    engine: wasmtime::Engine,
+    modules: Vec<(PathBuf, wasmtime::Module)>,
+}
+
+/// Cannot be loaded without the `plugins` feature.
+#[cfg(not(feature = "plugins"))]
+pub(crate) enum Plugins {}
+
+#[cfg(feature = "plugins")]
+impl Plugins {
+    /// Compiles the plugins at `paths`, which run in that order.
+    pub(crate) fn load(paths: &[PathBuf]) -> eyre::Result<Self> {
+        let engine = wasmtime::Engine::default();
+        let modules = paths
+            .iter()
+            .map(|path| {
+                let module = wasmtime::Module::from_file(&engine, path)
+                    .map_err(|e| eyre::eyre!("{}: {e:#}", path.display()))?;
+                Ok((path.clone(), module))
+            })
+            .collect::<eyre::Result<_>>()?;
+        Ok(Self { engine, modules })
+    }
+
+    /// Instantiates every plugin, for a single thread.
+    pub(crate) fn instantiate(&self) -> eyre::Result<Instances> {
+        self.modules
+            .iter()
+            .map(|(path, module)| {
+                Instance::new(&self.engine, path, module)
+                    .map_err(|e| eyre::eyre!("{}: {e:#}", path.display()))
+            })
+            .collect::<eyre::Result<_>>()
+            .map(Instances)
+    }
+}
+
+#[cfg(not(feature = "plugins"))]
+impl Plugins {
+    pub(crate) fn load(_paths: &[PathBuf]) -> eyre::Result<Self> {
+        eyre::bail!("`plugins` requires building with `--features plugins`")
+    }
+
+    pub(crate) fn instantiate(&self) -> eyre::Result<Instances> {
+        match *self {}
+    }
+}
+
+/// Instances of every plugin, see [`Plugins::instantiate`].
+#[cfg(feature = "plugins")]
+pub(crate) struct Instances(Vec<Instance>);
+
+#[cfg(not(feature = "plugins"))]
+pub(crate) enum Instances {}
+
+#[cfg(feature = "plugins")]
+struct Instance {
+    path: PathBuf,
+    store: wasmtime::Store<()>,
+    memory: wasmtime::Memory,
+    alloc: wasmtime::TypedFunc<i32, i32>,
+    row: wasmtime::TypedFunc<(i32, i32), i64>,
+}
+
+#[cfg(feature = "plugins")]
+impl Instance {
+    fn new(
+        engine: &wasmtime::Engine,
+        path: &std::path::Path,
+        module: &wasmtime::Module,
+    ) -> wasmtime::Result<Self> {
+        let mut store = wasmtime::Store::new(engine, ());
+        let instance = wasmtime::Instance::new(&mut store, module, &[])?;
+        let abi = instance
+            .get_typed_func::<(), i32>(&mut store, "dedupy_abi")?
+            .call(&mut store, ())?;
+        if abi != ABI {
+            wasmtime::bail!("built for plugin interface {abi}, but this is {ABI}");
+        }
+        Ok(Self {
+            memory: instance
+                .get_memory(&mut store, "memory")
+                .ok_or_else(|| wasmtime::format_err!("does not export `memory`"))?,
+            alloc: instance.get_typed_func(&mut store, "dedupy_alloc")?,
+            row: instance.get_typed_func(&mut store, "dedupy_row")?,
+            path: path.to_path_buf(),
+            store,
+        })
+    }
+
+    fn call(&mut self, input: &[u8]) -> wasmtime::Result<Vec<u8>> {
+        let len = i32::try_from(input.len())?;
+        let ptr = self.alloc.call(&mut self.store, len)?;
+        self.memory
+            .write(&mut self.store, ptr as u32 as usize, input)?;
+        let packed = self.row.call(&mut self.store, (ptr, len))? as u64;
+        let (ptr, len) = ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize);
+        let mut output = vec![0; len];
+        self.memory.read(&self.store, ptr, &mut output)?;
+        Ok(output)
+    }
+}
+
+/// A row as it is passed to a plugin.
+#[cfg(feature = "plugins")]
+#[derive(serde::Serialize)]
+struct Input<'a> {
+    #[serde(flatten)]
+    fields: &'a Fields,
+    columns: std::collections::BTreeMap<&'a str, &'a str>,
+}
+
+#[cfg(feature = "plugins")]
+impl Instances {
+    /// Runs `fields` through every plugin in turn, returning `None` if one
+    /// dropped them.
+    pub(crate) fn row(
+        &mut self,
+        hdr: &StringRecord,
+        r: &StringRecord,
+        mut fields: Fields,
+    ) -> eyre::Result<Option<Fields>> {
+        for instance in &mut self.0 {
+            let input = serde_json::to_vec(&Input {
+                fields: &fields,
+                columns: hdr.iter().zip(r.iter()).collect(),
+            })?;
+            let output = instance
+                .call(&input)
+                .map_err(|e| eyre::eyre!("{}: {e:#}", instance.path.display()))?;
+            match serde_json::from_slice::<Option<Fields>>(&output).map_err(|e| {
+                eyre::eyre!("{} returned an invalid row: {e}", instance.path.display())
+            })? {
+                Some(changed) => fields = changed,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(fields))
+    }
+}
+
+#[cfg(not(feature = "plugins"))]
+impl Instances {
+    pub(crate) fn row(
+        &mut self,
+        _hdr: &StringRecord,
+        _r: &StringRecord,
+        _fields: Fields,
+    ) -> eyre::Result<Option<Fields>> {
+        match *self {}
+    }
+}
+
+#[cfg(all(test, feature = "plugins"))]
+mod test {
+    use super::*;
+
+    /// Returns the row it is given, `columns` and all.
+    const ECHO: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "dedupy_abi") (result i32) i32.const 1)
+          (func (export "dedupy_alloc") (param i32) (result i32) i32.const 1024)
+          (func (export "dedupy_row") (param $ptr i32) (param $len i32) (result i64)
+            (i64.or
+              (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+              (i64.extend_i32_u (local.get $len)))))
+    "#;
+
+    /// Drops every row.
+    const DROP: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (data (i32.const 0) "null")
+          (func (export "dedupy_abi") (result i32) i32.const 1)
+          (func (export "dedupy_alloc") (param i32) (result i32) i32.const 1024)
+          (func (export "dedupy_row") (param i32 i32) (result i64) i64.const 4))
+    "#;
+
+    fn instances(wats: &[&str]) -> Instances {
+        let temp = tempfile::tempdir().unwrap();
+        let paths = wats
+            .iter()
+            .enumerate()
+            .map(|(i, wat)| {
+                let path = temp.path().join(format!("{i}.wat"));
+                std::fs::write(&path, wat).unwrap();
+                path
+            })
+            .collect::<Vec<_>>();
+        let plugins = Plugins::load(&paths).unwrap();
+        plugins.instantiate().unwrap()
+    }
+
+    #[test]
+    fn rewrite_and_drop() {
+        let hdr = StringRecord::from(vec!["type", "sku"]);
+        let r = StringRecord::from(vec!["Order", "A"]);
+        let fields = || Fields {
+            kind: "Order".to_string(),
+            sku: Some("A".to_string()),
+            description: "Widget".to_string(),
+            quantity: 2,
+            cents: 1_000,
+        };
+
+        let echoed = instances(&[ECHO]).row(&hdr, &r, fields()).unwrap().unwrap();
+        assert_eq!(echoed.sku.as_deref(), Some("A"));
+        assert_eq!(echoed.description, "Widget");
+        assert_eq!(echoed.cents, 1_000);
+
+        let dropped = instances(&[ECHO, DROP]).row(&hdr, &r, fields()).unwrap();
+        assert!(dropped.is_none());
+    }
+}
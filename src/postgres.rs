@@ -0,0 +1,113 @@
+//! Runs and their aggregates kept in PostgreSQL, alongside memory, so that
+//! several workers can process reports against one database.
+
+use postgres::{types::Json, Client, NoTls};
+
+use crate::{audit, Sale};
+
+/// Changes to the schema, applied in order by [`connect`].
+///
+/// A migration that was released must never change, add another instead.
+const MIGRATIONS: &[&str] = &[r#"
+CREATE TABLE dedupy_memory_meta (
+    memory TEXT PRIMARY KEY,
+    format INTEGER NOT NULL,
+    algorithm TEXT NOT NULL
+);
+CREATE TABLE dedupy_memory (
+    memory TEXT NOT NULL,
+    hash BIGINT NOT NULL,
+    seen DATE NOT NULL,
+    keys TEXT[] NOT NULL DEFAULT '{}',
+    PRIMARY KEY (memory, hash)
+);
+CREATE TABLE dedupy_runs (
+    id BIGSERIAL PRIMARY KEY,
+    timestamp TIMESTAMPTZ NOT NULL,
+    record JSONB NOT NULL
+);
+CREATE TABLE dedupy_aggregates (
+    run BIGINT NOT NULL REFERENCES dedupy_runs (id),
+    type TEXT NOT NULL,
+    sku TEXT NOT NULL,
+    description TEXT NOT NULL,
+    quantity BIGINT NOT NULL,
+    cents BIGINT NOT NULL
+);
+"#];
+
+/// Connects to the database at `url`, applying any migrations it is missing.
+pub(crate) fn connect(url: &str) -> eyre::Result<Client> {
+    let mut client = Client::connect(url, NoTls)?;
+    let mut tx = client.transaction()?;
+    // Workers starting at the same time wait for the first to migrate.
+    tx.execute(
+        "SELECT pg_advisory_xact_lock(hashtext('dedupy_migrations'))",
+        &[],
+    )?;
+    tx.batch_execute(
+        "SET LOCAL client_min_messages = warning;
+        CREATE TABLE IF NOT EXISTS dedupy_migrations (
+            version INTEGER PRIMARY KEY,
+            applied TIMESTAMPTZ NOT NULL DEFAULT now()
+        )",
+    )?;
+    let applied = tx
+        .query_one(
+            "SELECT COALESCE(MAX(version), 0) FROM dedupy_migrations",
+            &[],
+        )?
+        .get::<_, i32>(0);
+    if applied as usize > MIGRATIONS.len() {
+        eyre::bail!("the database was migrated by a newer version of dedupy (version {applied})");
+    }
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(applied as usize) {
+        tx.batch_execute(migration)?;
+        tx.execute(
+            "INSERT INTO dedupy_migrations (version) VALUES ($1)",
+            &[&(version as i32 + 1)],
+        )?;
+    }
+    tx.commit()?;
+    Ok(client)
+}
+
+/// Stores a run and the aggregates it wrote.
+pub(crate) fn record(url: &str, record: &audit::Record, sales: &[Sale]) -> eyre::Result<()> {
+    let mut client = connect(url)?;
+    let mut tx = client.transaction()?;
+    let run = tx
+        .query_one(
+            "INSERT INTO dedupy_runs (timestamp, record) VALUES ($1, $2) RETURNING id",
+            &[&record.timestamp, &Json(record)],
+        )?
+        .get::<_, i64>(0);
+    let insert = tx.prepare(
+        "INSERT INTO dedupy_aggregates (run, type, sku, description, quantity, cents)
+         VALUES ($1, $2, $3, $4, $5, $6)",
+    )?;
+    for sale in sales {
+        tx.execute(
+            &insert,
+            &[
+                &run,
+                &&*sale.kind,
+                &&*sale.sku,
+                &&*sale.description,
+                &sale.quantity,
+                &sale.cents,
+            ],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Reads every stored run, oldest first.
+pub(crate) fn records(url: &str) -> eyre::Result<Vec<audit::Record>> {
+    let rows = connect(url)?.query("SELECT record FROM dedupy_runs ORDER BY id", &[])?;
+    Ok(rows
+        .into_iter()
+        .map(|row| row.get::<_, Json<audit::Record>>(0).0)
+        .collect())
+}
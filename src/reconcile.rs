@@ -0,0 +1,168 @@
+//! Matches the payouts of settlements against deposits on a bank statement,
+//! see `dedupy reconcile`.
+//!
+//! A payout is the net total of a settlement's rows, and matches a deposit
+//! of exactly that amount dated within [`window_days`](crate::Deposits::window_days) of the
+//! settlement's last row. The closest deposit wins, and each deposit pays at
+//! most one settlement.
+
+use std::path::Path;
+
+use chrono::NaiveDate;
+use eyre::WrapErr as _;
+
+use crate::{Cents, Config, Malformed};
+
+/// Net total of a settlement, paid out in a single deposit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Payout {
+    pub settlement: String,
+    /// Day of the settlement's last row.
+    pub date: Option<NaiveDate>,
+    pub cents: Cents,
+}
+
+/// A row of a bank statement, see [`crate::Deposits`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deposit {
+    /// Line of the statement the deposit was read from.
+    pub line: u64,
+    pub date: NaiveDate,
+    pub cents: Cents,
+    pub description: String,
+}
+
+/// Result of [`reconcile`].
+#[derive(Debug, Default)]
+pub struct Reconciliation {
+    /// Payouts with the deposit that paid each.
+    pub matched: Vec<(Payout, Deposit)>,
+    /// Payouts that no deposit paid.
+    pub unmatched: Vec<Payout>,
+    /// Deposits that paid no payout, such as those of reports not given.
+    pub unclaimed: Vec<Deposit>,
+}
+
+/// Reads the deposits of the bank statement at `path`, laid out as
+/// `deposits` in `config` says, skipping rows whose description does not
+/// match.
+pub fn read_deposits(path: &Path, config: &Config) -> eyre::Result<Vec<Deposit>> {
+    let layout = &config.deposits;
+    let mut rdr = csv::Reader::from_path(path)?;
+    let hdr = rdr.headers()?.clone();
+    let column = |name: &str| {
+        hdr.iter()
+            .position(|h| h.trim() == name)
+            .ok_or_else(|| eyre::eyre!("{} has no {name:?} column", path.display()))
+    };
+    let (date, amount) = (column(&layout.date)?, column(&layout.amount)?);
+    let description = layout.description.as_deref().map(column).transpose()?;
+    let contains = layout.contains.as_deref().map(str::to_lowercase);
+
+    let mut deposits = Vec::new();
+    for record in rdr.records() {
+        let record = record?;
+        let line = record.position().map_or(0, |p| p.line());
+        let text = description
+            .and_then(|i| record.get(i))
+            .unwrap_or_default()
+            .trim();
+        if contains
+            .as_deref()
+            .is_some_and(|contains| !text.to_lowercase().contains(contains))
+        {
+            continue;
+        }
+        let field = |i: usize| record.get(i).unwrap_or_default().trim();
+        let deposit = NaiveDate::parse_from_str(field(date), &layout.date_format)
+            .wrap_err_with(|| format!("{:?} is not a {} date", field(date), layout.date_format))
+            .and_then(|date| {
+                let cents = crate::handle_punct(field(amount), config.amount_rounding)?;
+                Ok(Deposit {
+                    line,
+                    date,
+                    cents,
+                    description: text.to_string(),
+                })
+            })
+            .wrap_err(Malformed::Line(line))?;
+        deposits.push(deposit);
+    }
+    Ok(deposits)
+}
+
+/// Matches every payout against `deposits`, earliest payout first, see the
+/// [module](self). Payouts of zero are left out, as nothing is deposited.
+pub fn reconcile(
+    mut payouts: Vec<Payout>,
+    deposits: Vec<Deposit>,
+    window_days: u32,
+) -> Reconciliation {
+    payouts.retain(|payout| payout.cents != 0);
+    payouts.sort_by_key(|payout| payout.date);
+    let mut unclaimed = deposits.into_iter().map(Some).collect::<Vec<_>>();
+    let mut reconciliation = Reconciliation::default();
+    for payout in payouts {
+        let closest = unclaimed
+            .iter()
+            .enumerate()
+            .filter_map(|(i, deposit)| Some((i, deposit.as_ref()?)))
+            .filter(|(_, deposit)| deposit.cents == payout.cents)
+            .filter_map(|(i, deposit)| {
+                let days = (deposit.date - payout.date?).num_days().unsigned_abs();
+                (days <= u64::from(window_days)).then_some((days, i))
+            })
+            .min();
+        match closest.and_then(|(_, i)| unclaimed[i].take()) {
+            Some(deposit) => reconciliation.matched.push((payout, deposit)),
+            None => reconciliation.unmatched.push(payout),
+        }
+    }
+    reconciliation.unclaimed = unclaimed.into_iter().flatten().collect();
+    reconciliation
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn matches_closest_deposit() {
+        let day = |d| NaiveDate::from_ymd_opt(2024, 1, d).unwrap();
+        let payout = |settlement: &str, d, cents| Payout {
+            settlement: settlement.to_string(),
+            date: Some(day(d)),
+            cents,
+        };
+        let deposit = |line, d, cents| Deposit {
+            line,
+            date: day(d),
+            cents,
+            description: "AMAZON".to_string(),
+        };
+        let reconciliation = reconcile(
+            vec![
+                payout("2", 15, 10_000),
+                payout("1", 1, 10_000),
+                payout("3", 15, 2_500),
+                payout("4", 20, 0),
+            ],
+            vec![
+                deposit(2, 3, 10_000),
+                deposit(3, 16, 10_000),
+                deposit(4, 30, 2_500),
+                deposit(5, 31, 999),
+            ],
+            7,
+        );
+        let matched = reconciliation
+            .matched
+            .iter()
+            .map(|(payout, deposit)| (payout.settlement.as_str(), deposit.line))
+            .collect::<Vec<_>>();
+        assert_eq!(matched, [("1", 2), ("2", 3)]);
+        assert_eq!(reconciliation.unmatched, [payout("3", 15, 2_500)]);
+        let unclaimed = reconciliation.unclaimed.iter().map(|d| d.line);
+        assert_eq!(unclaimed.collect::<Vec<_>>(), [4, 5]);
+    }
+}
@@ -0,0 +1,55 @@
+//! Masking columns that identify buyers in output meant for review, so that
+//! it can be shared with third parties.
+
+use csv::StringRecord;
+
+use crate::Config;
+
+/// What a redacted field is replaced with.
+const MASK: &str = "[redacted]";
+
+/// Columns to mask, see `redact`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Redact {
+    columns: Vec<String>,
+}
+
+impl Redact {
+    pub(crate) fn new(config: &Config) -> Self {
+        Self {
+            columns: config.redact.clone(),
+        }
+    }
+
+    /// Returns `value`, or the mask if `column` is redacted.
+    pub(crate) fn field<'a>(&self, column: &str, value: &'a str) -> &'a str {
+        if self.columns.iter().any(|c| c == column) {
+            MASK
+        } else {
+            value
+        }
+    }
+
+    /// Masks the fields of `r` whose column in `hdr` is redacted.
+    pub(crate) fn record(&self, hdr: &StringRecord, r: &StringRecord) -> StringRecord {
+        r.iter()
+            .enumerate()
+            .map(|(i, value)| self.field(hdr.get(i).unwrap_or_default(), value))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn redact_columns() {
+        let redact = Redact {
+            columns: vec!["buyer name".to_string()],
+        };
+        let hdr = StringRecord::from(vec!["type", "buyer name", "total"]);
+        let r = StringRecord::from(vec!["Order", "Jane Doe", "12.00"]);
+        assert_eq!(redact.record(&hdr, &r), vec!["Order", MASK, "12.00"]);
+    }
+}
@@ -0,0 +1,308 @@
+//! Fetching reports from and pushing outputs to SFTP and FTPS servers, see
+//! `remotes`.
+//!
+//! Every file directly in the `inbox` of a remote is fetched, unless it
+//! would be left out of a directory, see [`crate::batch`], or its name would
+//! lead out of the directory it is fetched into. Fetched reports are left on
+//! the server, as what was processed before is remembered anyway. Outputs are
+//! pushed to the `outbox` under a temporary name and renamed once whole, so
+//! that whoever picks them up never reads one half written.
+//!
+//! SFTP servers are only connected to if their key is in `known_hosts`.
+
+use std::{
+    io::{Read as _, Write as _},
+    net::TcpStream,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use eyre::WrapErr as _;
+use suppaftp::{native_tls::TlsConnector, types::FileType, NativeTlsConnector, NativeTlsFtpStream};
+
+use crate::{Config, Remote};
+
+/// How long a server has to answer.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where a remote is, parsed from its `url`.
+#[derive(Debug, PartialEq, Eq)]
+struct Location<'a> {
+    sftp: bool,
+    user: &'a str,
+    host: &'a str,
+    port: u16,
+}
+
+impl<'a> Location<'a> {
+    fn parse(url: &'a str) -> eyre::Result<Self> {
+        let (sftp, rest) = match url.split_once("://") {
+            Some(("sftp", rest)) => (true, rest),
+            Some(("ftps", rest)) => (false, rest),
+            _ => eyre::bail!("{url} is not an sftp:// or ftps:// URL"),
+        };
+        let rest = rest.trim_end_matches('/');
+        let (user, rest) = rest
+            .split_once('@')
+            .ok_or_else(|| eyre::eyre!("{url} has no user, as in sftp://user@host"))?;
+        let (host, port) = match rest.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().wrap_err("invalid port")?),
+            None => (rest, if sftp { 22 } else { 21 }),
+        };
+        Ok(Self {
+            sftp,
+            user,
+            host,
+            port,
+        })
+    }
+}
+
+enum Connection {
+    Sftp(ssh2::Sftp),
+    Ftps(NativeTlsFtpStream),
+}
+
+impl Connection {
+    fn open(remote: &Remote) -> eyre::Result<Self> {
+        let location = Location::parse(&remote.url)?;
+        let password = match &remote.password_env {
+            Some(var) => Some(
+                std::env::var(var)
+                    .map_err(|_| eyre::eyre!("the password variable {var} is not set"))?,
+            ),
+            None => None,
+        };
+        let address = (location.host, location.port);
+        if !location.sftp {
+            let ftp = NativeTlsFtpStream::connect(address)?;
+            ftp.get_ref().set_read_timeout(Some(TIMEOUT))?;
+            let tls = NativeTlsConnector::from(TlsConnector::new()?);
+            let mut ftp = ftp.into_secure(tls, location.host)?;
+            ftp.login(location.user, password.as_deref().unwrap_or_default())?;
+            ftp.transfer_type(FileType::Binary)?;
+            return Ok(Self::Ftps(ftp));
+        }
+
+        let tcp = TcpStream::connect(address)?;
+        let mut session = ssh2::Session::new()?;
+        session.set_timeout(TIMEOUT.as_millis() as u32);
+        session.set_tcp_stream(tcp);
+        session.handshake()?;
+        trusted(&session, &location, remote)?;
+        match (&remote.identity, &password) {
+            (Some(key), passphrase) => {
+                session.userauth_pubkey_file(location.user, None, key, passphrase.as_deref())?
+            }
+            (None, Some(password)) => session.userauth_password(location.user, password)?,
+            (None, None) => session.userauth_agent(location.user)?,
+        }
+        Ok(Self::Sftp(session.sftp()?))
+    }
+
+    /// Names of the files directly in `dir`.
+    fn list(&mut self, dir: &str) -> eyre::Result<Vec<String>> {
+        let names = match self {
+            Self::Sftp(sftp) => (sftp.readdir(Path::new(dir))?.into_iter())
+                .filter(|(_, stat)| stat.is_file())
+                .filter_map(|(path, _)| Some(path.file_name()?.to_string_lossy().into_owned()))
+                .collect(),
+            Self::Ftps(ftp) => ftp_files(ftp.list(Some(dir))?),
+        };
+        Ok(names)
+    }
+
+    fn get(&mut self, path: &str) -> eyre::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        match self {
+            Self::Sftp(sftp) => sftp.open(Path::new(path))?.read_to_end(&mut bytes)?,
+            Self::Ftps(ftp) => ftp.retr_as_buffer(path)?.read_to_end(&mut bytes)?,
+        };
+        Ok(bytes)
+    }
+
+    /// Writes `bytes` to `path`, by way of a temporary file next to it.
+    fn put(&mut self, path: &str, bytes: &[u8]) -> eyre::Result<()> {
+        let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+        let part = match dir {
+            "" => format!(".{name}.part"),
+            _ => format!("{dir}/.{name}.part"),
+        };
+        match self {
+            Self::Sftp(sftp) => {
+                sftp.create(Path::new(&part))?.write_all(bytes)?;
+                let overwrite = Some(ssh2::RenameFlags::OVERWRITE | ssh2::RenameFlags::ATOMIC);
+                sftp.rename(Path::new(&part), Path::new(path), overwrite)?;
+            }
+            Self::Ftps(ftp) => {
+                ftp.put_file(&part, &mut &*bytes)?;
+                // Some servers refuse to rename over a file, which is only
+                // removed then, such that the previous output stays in place
+                // when renaming fails otherwise.
+                if ftp.rename(part.as_str(), path).is_err() {
+                    (ftp.rm(path)).wrap_err_with(|| format!("cannot replace {path}"))?;
+                    ftp.rename(part.as_str(), path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks the key of the SFTP server against `known_hosts`.
+fn trusted(session: &ssh2::Session, location: &Location, remote: &Remote) -> eyre::Result<()> {
+    let file = match &remote.known_hosts {
+        Some(file) => file.clone(),
+        None => PathBuf::from(std::env::var("HOME").unwrap_or_default()).join(".ssh/known_hosts"),
+    };
+    let mut known = session.known_hosts()?;
+    known
+        .read_file(&file, ssh2::KnownHostFileKind::OpenSSH)
+        .wrap_err_with(|| format!("cannot read {}", file.display()))?;
+    let (key, _) = session
+        .host_key()
+        .ok_or_else(|| eyre::eyre!("{} sent no host key", location.host))?;
+    match known.check_port(location.host, location.port, key) {
+        ssh2::CheckResult::Match => Ok(()),
+        ssh2::CheckResult::Mismatch => eyre::bail!(
+            "the key of {} does not match the one in {}",
+            location.host,
+            file.display()
+        ),
+        _ => eyre::bail!(
+            "{} is not in {}, add it with `ssh-keyscan`",
+            location.host,
+            file.display()
+        ),
+    }
+}
+
+/// Names of the files in the lines of an FTP `LIST`, leaving out directories,
+/// links, and lines that cannot be read.
+fn ftp_files(lines: Vec<String>) -> Vec<String> {
+    (lines.iter())
+        .filter_map(|line| match line.parse::<suppaftp::list::File>() {
+            Ok(file) => file.is_file().then(|| file.name().to_string()),
+            Err(e) => {
+                tracing::warn!("cannot read `{line}` of a listing, skipping it: {e}");
+                None
+            }
+        })
+        .collect()
+}
+
+/// Whether a name the server listed is a plain file name, that stays in the
+/// directory it is fetched into.
+fn is_plain(name: &str) -> bool {
+    !matches!(name, "" | "." | "..") && !name.contains(['/', '\\'])
+}
+
+/// Joins `name` to the directory `dir` of a server.
+fn join(dir: &str, name: &str) -> String {
+    match dir.trim_end_matches('/') {
+        "" if dir.starts_with('/') => format!("/{name}"),
+        "" => name.to_string(),
+        dir => format!("{dir}/{name}"),
+    }
+}
+
+/// Fetches the reports in the `inbox` of `remote` into `dir`, see the
+/// [module](self). None are without an `inbox`.
+pub fn fetch(remote: &Remote, dir: &Path, config: &Config) -> eyre::Result<Vec<PathBuf>> {
+    let Some(inbox) = remote.inbox.as_deref() else {
+        return Ok(Vec::new());
+    };
+    let mut connection = Connection::open(remote)
+        .wrap_err_with(|| format!("cannot connect to the remote {}", remote.name))?;
+    std::fs::create_dir_all(dir)?;
+    let mut names = connection.list(inbox)?;
+    names.sort();
+    let mut files = Vec::with_capacity(names.len());
+    for name in names {
+        if !is_plain(&name) {
+            tracing::warn!(file = %name, "is not a plain file name, skipping it");
+            continue;
+        }
+        let file = dir.join(&name);
+        if config.ignore.iter().any(|pattern| pattern.matches(&name)) {
+            tracing::info!(file = %name, "matches `ignore`, skipping it");
+            continue;
+        }
+        let bytes = (connection.get(&join(inbox, &name)))
+            .wrap_err_with(|| format!("cannot fetch {name} from {}", remote.name))?;
+        std::fs::write(&file, bytes)?;
+        match crate::batch::ignored(&file, config)? {
+            Some(why) => {
+                tracing::info!(file = %name, "{why}, skipping it");
+                std::fs::remove_file(&file)?;
+            }
+            None => files.push(file),
+        }
+    }
+    Ok(files)
+}
+
+/// Pushes `files` to the `outbox` of `remote`, see the [module](self).
+pub fn push(remote: &Remote, files: &[PathBuf]) -> eyre::Result<()> {
+    let outbox = (remote.outbox.as_deref())
+        .ok_or_else(|| eyre::eyre!("the remote {} has no `outbox`", remote.name))?;
+    let mut connection = Connection::open(remote)
+        .wrap_err_with(|| format!("cannot connect to the remote {}", remote.name))?;
+    for file in files {
+        let name = (file.file_name())
+            .ok_or_else(|| eyre::eyre!("{} has no file name", file.display()))?
+            .to_string_lossy();
+        let bytes = std::fs::read(file)?;
+        (connection.put(&join(outbox, &name), &bytes))
+            .wrap_err_with(|| format!("cannot push {name} to {}", remote.name))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_locations() {
+        let location = |sftp, user, host, port| Location {
+            sftp,
+            user,
+            host,
+            port,
+        };
+        assert_eq!(
+            Location::parse("sftp://books@files.example.com").unwrap(),
+            location(true, "books", "files.example.com", 22)
+        );
+        assert_eq!(
+            Location::parse("ftps://firm@ftp.example.com:990/").unwrap(),
+            location(false, "firm", "ftp.example.com", 990)
+        );
+        assert!(Location::parse("ftp://firm@ftp.example.com").is_err());
+        assert!(Location::parse("sftp://files.example.com").is_err());
+
+        assert_eq!(join("/in/", "a.csv"), "/in/a.csv");
+        assert_eq!(join("/", "a.csv"), "/a.csv");
+        assert_eq!(join("", "a.csv"), "a.csv");
+    }
+
+    #[test]
+    fn lists_plain_files() {
+        let lines = [
+            "-rw-r--r-- 1 firm firm 1024 Jan 02 10:00 report.csv",
+            "drwxr-xr-x 2 firm firm 4096 Jan 02 10:00 archive",
+            "lrwxrwxrwx 1 firm firm 10 Jan 02 10:00 latest -> report.csv",
+            "01-02-24  10:00AM       <DIR>          old",
+            "01-02-24  10:00AM                 2048 other.csv",
+            "total 3",
+        ];
+        let files = ftp_files(lines.map(String::from).to_vec());
+        assert_eq!(files, ["report.csv", "other.csv"]);
+
+        assert!(is_plain("report.csv"));
+        for name in ["", ".", "..", "../report.csv", "a/b.csv", "..\\report.csv"] {
+            assert!(!is_plain(name), "{name}");
+        }
+    }
+}
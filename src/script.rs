@@ -0,0 +1,140 @@
+//! Rows rewritten or dropped by a Rhai script before they are aggregated, see
+//! `script`.
+//!
+//! The script defines `fn row(row)`, which is called with a map of `type`,
+//! `sku` (`()` for adjustments), `description`, `quantity`, and `cents`, and
+//! returns the map, changed or not, or `false` to drop the row.
+
+use std::path::Path;
+
+use crate::Fields;
+
+#[cfg(feature = "rhai")]
+pub(crate) struct Script {
+    engine: rhai::Engine,
+    ast: rhai::AST,
+}
+
+/// Cannot be loaded without the `rhai` feature.
+#[cfg(not(feature = "rhai"))]
+pub(crate) enum Script {}
+
+#[cfg(feature = "rhai")]
+impl Script {
+    pub(crate) fn load(path: &Path) -> eyre::Result<Self> {
+        let engine = rhai::Engine::new();
+        let ast = engine
+            .compile_file(path.into())
+            .map_err(|e| eyre::eyre!("{}: {e}", path.display()))?;
+        if !ast
+            .iter_functions()
+            .any(|f| f.name == "row" && f.params.len() == 1)
+        {
+            eyre::bail!("{} does not define `fn row(row)`", path.display());
+        }
+        Ok(Self { engine, ast })
+    }
+
+    /// Runs `fields` through the script, returning `None` if it dropped them.
+    pub(crate) fn row(&self, fields: Fields) -> eyre::Result<Option<Fields>> {
+        use rhai::{Dynamic, Map};
+
+        let mut map = Map::new();
+        map.insert("type".into(), fields.kind.into());
+        map.insert(
+            "sku".into(),
+            fields.sku.map_or(Dynamic::UNIT, Dynamic::from),
+        );
+        map.insert("description".into(), fields.description.into());
+        map.insert("quantity".into(), fields.quantity.into());
+        map.insert("cents".into(), fields.cents.into());
+
+        let returned = self
+            .engine
+            .call_fn::<Dynamic>(&mut rhai::Scope::new(), &self.ast, "row", (map,))
+            .map_err(|e| eyre::eyre!("script: {e}"))?;
+        if returned.as_bool() == Ok(false) {
+            return Ok(None);
+        }
+        let mut map = returned
+            .try_cast::<Map>()
+            .ok_or_else(|| eyre::eyre!("script: `row` must return the row, or `false`"))?;
+        let mut take = |key: &str| {
+            map.remove(key)
+                .ok_or_else(|| eyre::eyre!("script: the row returned has no `{key}`"))
+        };
+        let string = |value: Dynamic, key: &str| {
+            value
+                .into_string()
+                .map_err(|_| eyre::eyre!("script: `{key}` must be a string"))
+        };
+        let int = |value: Dynamic, key: &str| {
+            value
+                .as_int()
+                .map_err(|_| eyre::eyre!("script: `{key}` must be an integer"))
+        };
+        let kind = string(take("type")?, "type")?;
+        let sku = take("sku")?;
+        let sku = if sku.is_unit() {
+            None
+        } else {
+            Some(string(sku, "sku")?)
+        };
+        Ok(Some(Fields {
+            kind,
+            sku,
+            description: string(take("description")?, "description")?,
+            quantity: int(take("quantity")?, "quantity")?,
+            cents: int(take("cents")?, "cents")?,
+        }))
+    }
+}
+
+#[cfg(not(feature = "rhai"))]
+impl Script {
+    pub(crate) fn load(_path: &Path) -> eyre::Result<Self> {
+        eyre::bail!("`script` requires building with `--features rhai`")
+    }
+
+    pub(crate) fn row(&self, _fields: Fields) -> eyre::Result<Option<Fields>> {
+        match *self {}
+    }
+}
+
+#[cfg(all(test, feature = "rhai"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rewrite_and_drop() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("script.rhai");
+        std::fs::write(
+            &path,
+            r#"
+            fn row(row) {
+                if row.type == "Transfer" {
+                    return false;
+                }
+                row.description.replace("Widget", "Gadget");
+                row
+            }
+            "#,
+        )
+        .unwrap();
+        let script = Script::load(&path).unwrap();
+
+        let fields = |kind: &str, sku: Option<&str>| Fields {
+            kind: kind.to_string(),
+            sku: sku.map(str::to_string),
+            description: "Widget, blue".to_string(),
+            quantity: 2,
+            cents: 1_000,
+        };
+        let order = script.row(fields("Order", Some("A"))).unwrap().unwrap();
+        assert_eq!(order.description, "Gadget, blue");
+        assert_eq!(order.sku.as_deref(), Some("A"));
+        assert_eq!(order.cents, 1_000);
+        assert!(script.row(fields("Transfer", None)).unwrap().is_none());
+    }
+}
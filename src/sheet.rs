@@ -0,0 +1,159 @@
+//! Names of the sheets of the output workbook, see `sheet_name`.
+
+use std::path::Path;
+
+use chrono::{Datelike as _, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+/// Name of the sheet of aggregates when `sheet_name` is unset.
+const DEFAULT: &str = "Sheet1";
+
+/// Longest sheet name Excel accepts, in characters.
+const MAX_LEN: usize = 31;
+
+/// Months that the aggregated rows of a report are dated in.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct Period(Option<(NaiveDate, NaiveDate)>);
+
+impl Period {
+    /// The first and last day of the rows, if any had a date.
+    pub(crate) fn days(self) -> Option<(NaiveDate, NaiveDate)> {
+        self.0
+    }
+
+    pub(crate) fn add(&mut self, date: NaiveDate) {
+        self.0 = Some(match self.0 {
+            Some((first, last)) => (first.min(date), last.max(date)),
+            None => (date, date),
+        });
+    }
+}
+
+impl From<Option<(NaiveDate, NaiveDate)>> for Period {
+    fn from(days: Option<(NaiveDate, NaiveDate)>) -> Self {
+        Self(days)
+    }
+}
+
+/// `2024-01`, or `2024-01 to 2024-03` if the rows span several months.
+/// Empty if no row had a date.
+impl std::fmt::Display for Period {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let month = |date: NaiveDate| format!("{}-{:02}", date.year(), date.month());
+        match self.0 {
+            None => Ok(()),
+            Some((first, last)) if month(first) == month(last) => f.write_str(&month(first)),
+            Some((first, last)) => write!(f, "{} to {}", month(first), month(last)),
+        }
+    }
+}
+
+/// Reads the date of a `date/time` field, as written by any marketplace,
+/// such as `Jan 5, 2024 1:02:03 AM PST` or `05.01.2024 01:02:03 UTC`.
+pub(crate) fn parse_date(field: &str) -> Option<NaiveDate> {
+    const FORMATS: [&str; 5] = ["%b %d, %Y", "%d %b %Y", "%d.%m.%Y", "%Y-%m-%d", "%Y/%m/%d"];
+    let field = field.trim();
+    FORMATS.iter().find_map(|format| {
+        NaiveDate::parse_and_remainder(field, format)
+            .ok()
+            .map(|(date, _)| date)
+    })
+}
+
+/// Name of the sheet of aggregates, from `template`, for the report read
+/// from `input` on `date`.
+pub(crate) fn name(
+    template: Option<&str>,
+    input: &Path,
+    date: NaiveDate,
+    period: Period,
+) -> String {
+    let Some(template) = template else {
+        return DEFAULT.to_string();
+    };
+    let stem = match input.file_stem() {
+        _ if input == Path::new("-") => "stdin".into(),
+        Some(stem) => stem.to_string_lossy(),
+        None => "".into(),
+    };
+    safe(
+        &template
+            .replace("{period}", &period.to_string())
+            .replace("{input_stem}", &stem)
+            .replace("{date}", &date.format("%Y-%m-%d").to_string()),
+    )
+}
+
+/// `name` as Excel accepts it: without any of `[]:*?/\`, not starting or
+/// ending with an apostrophe, not empty, and at most 31 characters.
+fn safe(name: &str) -> String {
+    let name = name
+        .chars()
+        .map(|c| match c {
+            '[' | ']' | ':' | '*' | '?' | '/' | '\\' => '_',
+            c => c,
+        })
+        .take(MAX_LEN)
+        .collect::<String>();
+    match name.trim().trim_matches('\'') {
+        "" => DEFAULT.to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// `name`, or `name (2)`, `name (3)`, and so on, whichever is not `taken`.
+/// Like Excel, case is ignored.
+pub(crate) fn unique(name: &str, taken: &[&str]) -> String {
+    let is_taken = |candidate: &str| {
+        taken
+            .iter()
+            .any(|t| t.to_lowercase() == candidate.to_lowercase())
+    };
+    if !is_taken(name) {
+        return name.to_string();
+    }
+    (2..)
+        .map(|n| {
+            let suffix = format!(" ({n})");
+            let kept = MAX_LEN.saturating_sub(suffix.chars().count());
+            format!("{}{suffix}", name.chars().take(kept).collect::<String>())
+        })
+        .find(|candidate| !is_taken(candidate))
+        .expect("there are fewer sheets than numbers")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn names() {
+        let date = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let mut period = Period::default();
+        assert_eq!(name(None, Path::new("a.csv"), date, period), "Sheet1");
+
+        for field in ["Jan 5, 2024 1:02:03 AM PST", "28.02.2024 01:02:03 UTC"] {
+            period.add(parse_date(field).unwrap());
+        }
+        assert_eq!(period.to_string(), "2024-01 to 2024-02");
+        let template = Some("{input_stem} {period} [{date}]");
+        assert_eq!(
+            name(template, Path::new("dir/report.csv"), date, period),
+            "report 2024-01 to 2024-02 _2024"
+        );
+        assert_eq!(
+            name(Some("{input_stem}"), Path::new("-"), date, period),
+            "stdin"
+        );
+        assert_eq!(name(Some("''"), Path::new("-"), date, period), "Sheet1");
+    }
+
+    #[cfg(feature = "xlsx")]
+    #[test]
+    fn unique_names() {
+        assert_eq!(unique("Tax Summary", &["Sheet1"]), "Tax Summary");
+        assert_eq!(unique("Tax Summary", &["tax summary"]), "Tax Summary (2)");
+        let long = "x".repeat(MAX_LEN);
+        assert_eq!(unique(&long, &[&long]).chars().count(), MAX_LEN);
+    }
+}
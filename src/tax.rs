@@ -0,0 +1,184 @@
+//! Tax collected and withheld per jurisdiction, for the "Tax Summary" sheet of
+//! the output, see `tax_jurisdiction`.
+//!
+//! A tax column is any column whose name ends in `tax` or `VAT`, such as
+//! `product sales tax` or `shipping credits tax`. Those that mention
+//! `withheld`, such as `marketplace withheld tax`, are tax the marketplace
+//! remits itself rather than tax the seller owes.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use csv::StringRecord;
+use serde::{Deserialize, Serialize};
+
+use crate::{handle_punct, intern::Interner, Cents, Config, Rounding};
+
+/// Indexes of the tax columns of a report, and of the columns naming the
+/// jurisdiction a row is taxed in.
+pub(crate) struct TaxColumns {
+    collected: Vec<usize>,
+    withheld: Vec<usize>,
+    jurisdiction: Vec<usize>,
+    rounding: Rounding,
+}
+
+impl TaxColumns {
+    /// Finds the tax columns in `hdr`, or `None` if it has none.
+    pub(crate) fn new(hdr: &StringRecord, config: &Config) -> eyre::Result<Option<Self>> {
+        let (mut collected, mut withheld) = (Vec::new(), Vec::new());
+        for (i, name) in hdr.iter().enumerate() {
+            let name = name.trim().to_lowercase();
+            if !(name.ends_with("tax") || name.ends_with("vat")) {
+                continue;
+            }
+            if name.contains("withheld") {
+                withheld.push(i);
+            } else {
+                collected.push(i);
+            }
+        }
+        if collected.is_empty() && withheld.is_empty() {
+            return Ok(None);
+        }
+        let jurisdiction = config
+            .tax_jurisdiction
+            .iter()
+            .map(|name| {
+                hdr.iter().position(|field| field == name).ok_or_else(|| {
+                    eyre::eyre!("tax jurisdiction column `{name}` is not in the header")
+                })
+            })
+            .collect::<eyre::Result<_>>()?;
+        Ok(Some(Self {
+            collected,
+            withheld,
+            jurisdiction,
+            rounding: config.amount_rounding,
+        }))
+    }
+
+    /// What the `i`th column is read as, if it is one of these.
+    pub(crate) fn column(&self, i: usize) -> Option<&'static str> {
+        [
+            (&self.collected, "tax"),
+            (&self.withheld, "withheld tax"),
+            (&self.jurisdiction, "tax jurisdiction"),
+        ]
+        .into_iter()
+        .find(|(columns, _)| columns.contains(&i))
+        .map(|(_, name)| name)
+    }
+
+    /// Reads the tax of a single row. Empty amounts are zero.
+    pub(crate) fn row(&self, r: &StringRecord, interner: &mut Interner) -> eyre::Result<Tax> {
+        let sum = |columns: &[usize]| {
+            columns
+                .iter()
+                .map(|&i| match r.get(i).unwrap_or_default().trim() {
+                    "" => Ok(0),
+                    amount => {
+                        handle_punct(amount, self.rounding).map_err(|e| eyre::eyre!("tax: {e}"))
+                    }
+                })
+                .sum::<eyre::Result<Cents>>()
+        };
+        let jurisdiction = self
+            .jurisdiction
+            .iter()
+            .map(|&i| r.get(i).unwrap_or_default().trim())
+            .filter(|field| !field.is_empty())
+            .collect::<Vec<_>>()
+            .join(", ");
+        Ok(Tax {
+            jurisdiction: interner.intern(if jurisdiction.is_empty() {
+                "Unknown"
+            } else {
+                &jurisdiction
+            }),
+            collected: sum(&self.collected)?,
+            withheld: sum(&self.withheld)?,
+        })
+    }
+}
+
+/// Tax of a single row.
+#[derive(Debug)]
+pub(crate) struct Tax {
+    jurisdiction: Arc<str>,
+    collected: Cents,
+    withheld: Cents,
+}
+
+/// Tax of every aggregated row of a report, per jurisdiction.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub(crate) struct Taxes(BTreeMap<Arc<str>, TaxTotals>);
+
+impl Taxes {
+    pub(crate) fn add(&mut self, tax: Tax) {
+        let totals = self.0.entry(tax.jurisdiction).or_default();
+        totals.collected += tax.collected;
+        totals.withheld += tax.withheld;
+    }
+
+    /// Jurisdictions with any tax, in order.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&str, TaxTotals)> {
+        self.0
+            .iter()
+            .filter(|(_, totals)| totals.collected != 0 || totals.withheld != 0)
+            .map(|(jurisdiction, totals)| (&**jurisdiction, *totals))
+    }
+}
+
+/// Tax of a jurisdiction, in cents.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaxTotals {
+    /// Tax charged to buyers, net of refunds, that the seller owes.
+    pub collected: Cents,
+    /// Tax the marketplace withheld to remit itself, usually negative.
+    pub withheld: Cents,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn per_jurisdiction() {
+        let hdr = StringRecord::from(vec![
+            "marketplace",
+            "product sales",
+            "product sales tax",
+            "shipping credits tax",
+            "marketplace withheld tax",
+            "tax collection model",
+        ]);
+        let columns = TaxColumns::new(&hdr, &Config::default()).unwrap().unwrap();
+        let mut interner = Interner::default();
+        let mut taxes = Taxes::default();
+        for r in [
+            vec!["amazon.de", "10.00", "1.90", "0.19", "", "x"],
+            vec!["amazon.de", "-10.00", "-1.90", "", "", "x"],
+            vec!["amazon.fr", "10.00", "2.00", "", "-2.00", "x"],
+            vec!["amazon.fr", "10.00", "", "", "", "x"],
+        ] {
+            let tax = columns.row(&StringRecord::from(r), &mut interner).unwrap();
+            taxes.add(tax);
+        }
+        let taxes = taxes
+            .iter()
+            .map(|(j, t)| (j.to_string(), t.collected, t.withheld))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            taxes,
+            [
+                ("amazon.de".to_string(), 19, 0),
+                ("amazon.fr".to_string(), 200, -200),
+            ]
+        );
+
+        let no_tax = StringRecord::from(vec!["type", "total"]);
+        assert!(TaxColumns::new(&no_tax, &Config::default())
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,87 @@
+//! Time spent in each phase of a run, for `--timings`. Part of the binary,
+//! not the library.
+//!
+//! The library enters a span named after each phase, see `aggregate`. Time is
+//! counted for the innermost span only, so `read` and `decode` are not also
+//! counted in the `parse` span around them.
+
+use std::{
+    cell::RefCell,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tracing::span;
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+thread_local! {
+    /// Spans entered on this thread, innermost last, with when they were
+    /// last resumed.
+    static STACK: RefCell<Vec<(&'static str, Instant)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Time per phase, in the order the phases first ran.
+#[derive(Clone, Default)]
+pub(crate) struct Timings(Arc<Mutex<Vec<(&'static str, Duration)>>>);
+
+impl Timings {
+    fn add(&self, phase: &'static str, elapsed: Duration) {
+        let mut phases = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        match phases.iter_mut().find(|(name, _)| *name == phase) {
+            Some((_, total)) => *total += elapsed,
+            None => phases.push((phase, elapsed)),
+        }
+    }
+
+    /// Prints a table of the time spent in each phase.
+    pub(crate) fn print(&self) {
+        let phases = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let total = phases.iter().map(|(_, d)| *d).sum::<Duration>();
+        eprintln!("{:<10} {:>12} {:>6}", "PHASE", "TIME", "SHARE");
+        for (phase, elapsed) in phases.iter() {
+            let share = elapsed.as_secs_f64() / total.as_secs_f64().max(f64::EPSILON);
+            eprintln!(
+                "{:<10} {:>9.1} ms {:>5.1}%",
+                phase,
+                elapsed.as_secs_f64() * 1_000.0,
+                share * 100.0
+            );
+        }
+        eprintln!("{:<10} {:>9.1} ms", "total", total.as_secs_f64() * 1_000.0);
+    }
+}
+
+impl<S> Layer<S> for Timings
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_enter(&self, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        if !span.metadata().target().starts_with("dedupy") {
+            return;
+        }
+        let now = Instant::now();
+        STACK.with_borrow_mut(|stack| {
+            if let Some((outer, resumed)) = stack.last() {
+                self.add(outer, now - *resumed);
+            }
+            stack.push((span.name(), now));
+        });
+    }
+
+    fn on_exit(&self, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        if !span.metadata().target().starts_with("dedupy") {
+            return;
+        }
+        let now = Instant::now();
+        STACK.with_borrow_mut(|stack| {
+            if let Some((phase, resumed)) = stack.pop() {
+                self.add(phase, now - resumed);
+            }
+            if let Some((_, resumed)) = stack.last_mut() {
+                *resumed = now;
+            }
+        });
+    }
+}
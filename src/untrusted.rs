@@ -0,0 +1,220 @@
+//! Processing reports that cannot be trusted to be reports at all, such as
+//! uploads, see [`Report::try_parse_lossy`](crate::Report::try_parse_lossy).
+//!
+//! Rows are checked like [`Report::validate`](crate::Report::validate) does,
+//! and those that cannot be read are left out rather than failing the whole
+//! report. What can be read is then aggregated as a report of its own.
+
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::{find_header, reader, row_problems, Aggregated, Config, Format, Marketplace, Problem};
+use crate::{Report, TruncatedReports};
+
+/// Caps on what [`Report::try_parse_lossy`] reads.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Size of the largest report read at all.
+    pub max_bytes: usize,
+    /// Rows read before the rest of a report is left out.
+    pub max_rows: u64,
+    /// Problems kept, after which only their number is.
+    pub max_problems: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_bytes: 64 * 1024 * 1024,
+            max_rows: 1_000_000,
+            max_problems: 1_000,
+        }
+    }
+}
+
+/// Result of [`Report::try_parse_lossy`].
+#[derive(Debug, Default)]
+pub struct LossyParse {
+    /// Output of the rows that could be read, or `None` if the report was
+    /// refused altogether, such as for having no header.
+    pub aggregated: Option<Aggregated>,
+    /// Data rows read, including those left out.
+    pub rows: u64,
+    /// Why rows were left out, or the report refused, in the order they were
+    /// found.
+    pub problems: Vec<Problem>,
+}
+
+impl LossyParse {
+    fn problem(&mut self, line: u64, message: impl ToString, limits: &Limits) {
+        if self.problems.len() < limits.max_problems {
+            self.problems.push(Problem {
+                line,
+                message: message.to_string(),
+            });
+        }
+    }
+}
+
+pub(crate) fn parse(report: &[u8], format: Format, limits: &Limits, config: &Config) -> LossyParse {
+    // Reading a report is not meant to panic, this only keeps a bug from
+    // taking down whatever is serving the upload.
+    match panic::catch_unwind(AssertUnwindSafe(|| {
+        parse_unchecked(report, format, limits, config)
+    })) {
+        Ok(parsed) => parsed,
+        Err(panic) => {
+            let message = (panic.downcast_ref::<&str>().copied())
+                .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown cause");
+            let mut parsed = LossyParse::default();
+            parsed.problem(
+                0,
+                format!("the report could not be read: {message}"),
+                limits,
+            );
+            parsed
+        }
+    }
+}
+
+fn parse_unchecked(report: &[u8], format: Format, limits: &Limits, config: &Config) -> LossyParse {
+    let mut parsed = LossyParse::default();
+    if report.len() > limits.max_bytes {
+        let message = format!(
+            "the report is {} bytes, more than the {} that are read",
+            report.len(),
+            limits.max_bytes
+        );
+        parsed.problem(0, message, limits);
+        return parsed;
+    }
+    let mut rdr = reader(report);
+    let (hdr, mut iter) = match find_header(&mut rdr, config) {
+        Ok(found) => found,
+        Err(e) => {
+            parsed.problem(0, format!("{e:#}"), limits);
+            return parsed;
+        }
+    };
+
+    // The rows that can be read, under the header as it is read, so that
+    // they are aggregated as an Amazon report whatever the report was.
+    let mut readable = csv::Writer::from_writer(Vec::new());
+    let mut found = 0;
+    let result = (|| {
+        readable.write_record(&hdr)?;
+        for record in iter.by_ref() {
+            let r = match record {
+                Ok(r) => r,
+                Err(e) => {
+                    let line = e.position().map_or(0, |p| p.line());
+                    found += 1;
+                    parsed.problem(line, e, limits);
+                    continue;
+                }
+            };
+            if parsed.rows == limits.max_rows {
+                let line = r.position().map_or(0, |p| p.line());
+                let message = format!(
+                    "only {} rows are read, the rest is left out",
+                    limits.max_rows
+                );
+                found += 1;
+                parsed.problem(line, message, limits);
+                break;
+            }
+            parsed.rows += 1;
+            let line = r.position().map_or(0, |p| p.line());
+            let problems = row_problems(&r, &hdr, config);
+            if problems.is_empty() {
+                readable.write_record(&r)?;
+            }
+            for problem in problems {
+                found += 1;
+                parsed.problem(line, problem, limits);
+            }
+        }
+        Ok::<_, eyre::Report>(())
+    })();
+    if let Err(e) = result {
+        parsed.problem(0, format!("{e:#}"), limits);
+        return parsed;
+    }
+    if parsed.rows < limits.max_rows {
+        if let Err(e) = iter.finish(config) {
+            let line = iter.records.reader().position().line();
+            found += 1;
+            parsed.problem(line, format!("{e:#}"), limits);
+        }
+    }
+    if found > parsed.problems.len() {
+        let more = found - parsed.problems.len();
+        parsed.problems.push(Problem {
+            line: 0,
+            message: format!("and {more} more problems"),
+        });
+    }
+
+    let config = Config {
+        source: Some(Marketplace::Amazon),
+        header: Vec::new(),
+        truncated_reports: TruncatedReports::Warn,
+        ..config.clone()
+    };
+    let aggregated = readable
+        .into_inner()
+        .map_err(eyre::Report::from)
+        .and_then(|readable| Report::aggregate_bytes_as(&readable, format, &config));
+    match aggregated {
+        Ok(aggregated) => parsed.aggregated = Some(aggregated),
+        Err(e) => parsed.problem(0, format!("{e:#}"), limits),
+    }
+    parsed
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn leaves_out_unreadable_rows() {
+        let report = b"\"preamble\"\n\
+            type,sku,description,quantity,total\n\
+            Order,A,Widget,1,5.00\n\
+            Order,A,Widget,one,5.00\n\
+            Order,B\n\
+            Refund,A,Widget,1,1.2.3\n\
+            Service Fee,,Subscription,,-39.99\n";
+        let limits = Limits {
+            max_problems: 2,
+            ..Limits::default()
+        };
+        let parsed = parse(report, Format::Csv, &limits, &Config::default());
+        assert_eq!(parsed.rows, 5);
+        let problems = (parsed.problems.iter())
+            .map(|p| (p.line, p.message.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            problems,
+            [
+                (4, "quantity \"one\" is not a number"),
+                (5, "row has 2 of the header's 5 columns"),
+                (0, "and 1 more problems"),
+            ]
+        );
+        let aggregated = parsed.aggregated.unwrap();
+        assert_eq!(aggregated.rows, 2);
+        assert_eq!(aggregated.totals["Order"], 500);
+
+        let parsed = parse(b"\xff\x00garbage", Format::Csv, &limits, &Config::default());
+        assert!(parsed.aggregated.is_none());
+        assert_eq!(parsed.problems.len(), 1);
+
+        let limits = Limits {
+            max_bytes: 8,
+            ..Limits::default()
+        };
+        let parsed = parse(report, Format::Csv, &limits, &Config::default());
+        assert!(parsed.aggregated.is_none());
+    }
+}
@@ -0,0 +1,109 @@
+//! `dedupy self-update`, which replaces the running binary with the latest
+//! GitHub release. Part of the binary, not the library.
+//!
+//! A release has a binary per platform, named `dedupy-{arch}-{os}` as in
+//! `dedupy-x86_64-windows.exe`, next to its hex encoded SHA-256 in a file of
+//! the same name ending in `.sha256`. A binary that does not match is not
+//! installed, and neither is a release older than the running binary.
+
+#[cfg(feature = "self-update")]
+use std::io::{Read as _, Write as _};
+
+#[cfg(feature = "self-update")]
+use serde::Deserialize;
+
+#[cfg(feature = "self-update")]
+const LATEST: &str = "https://api.github.com/repos/chrisp60/dedupy/releases/latest";
+
+#[cfg(feature = "self-update")]
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+#[cfg(feature = "self-update")]
+#[derive(Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+/// Installs the latest release, or only says whether there is one if `check`.
+#[cfg(feature = "self-update")]
+pub(crate) fn run(check: bool) -> eyre::Result<()> {
+    use sha2::{Digest as _, Sha256};
+
+    let current = semver::Version::parse(env!("CARGO_PKG_VERSION"))?;
+    let release = get(LATEST)?.into_json::<Release>()?;
+    let latest = semver::Version::parse(release.tag_name.trim_start_matches('v'))
+        .map_err(|e| eyre::eyre!("release {} is not a version: {e}", release.tag_name))?;
+    if latest <= current {
+        println!("dedupy {current} is up to date, the latest release is {latest}.");
+        return Ok(());
+    }
+    if check {
+        println!("dedupy {latest} is available, this is {current}.");
+        return Ok(());
+    }
+
+    let name = format!(
+        "dedupy-{}-{}{}",
+        std::env::consts::ARCH,
+        std::env::consts::OS,
+        std::env::consts::EXE_SUFFIX
+    );
+    let url = |name: &str| {
+        release
+            .assets
+            .iter()
+            .find(|asset| asset.name == name)
+            .map(|asset| asset.browser_download_url.as_str())
+            .ok_or_else(|| eyre::eyre!("release {} has no {name}", release.tag_name))
+    };
+    let expected = get(url(&format!("{name}.sha256"))?)?.into_string()?;
+    let expected = expected.split_whitespace().next().unwrap_or_default();
+    let mut binary = Vec::new();
+    get(url(&name)?)?.into_reader().read_to_end(&mut binary)?;
+    let actual = format!("{:x}", Sha256::digest(&binary));
+    if !actual.eq_ignore_ascii_case(expected) {
+        eyre::bail!(
+            "{name} of release {} does not match its checksum",
+            release.tag_name
+        );
+    }
+
+    // Next to the binary rather than in a shared temporary directory, under
+    // a name nobody else can claim, so that it cannot be swapped before it
+    // replaces the binary.
+    let exe = std::env::current_exe()?;
+    let dir = exe
+        .parent()
+        .ok_or_else(|| eyre::eyre!("{} has no directory", exe.display()))?;
+    let mut download = tempfile::Builder::new()
+        .prefix(".dedupy-update-")
+        .tempfile_in(dir)?;
+    download.write_all(&binary)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt as _;
+        (download.as_file()).set_permissions(std::fs::Permissions::from_mode(0o755))?;
+    }
+    self_replace::self_replace(download.path())?;
+    if let Err(e) = download.close() {
+        tracing::warn!("cannot remove the downloaded update: {e}");
+    }
+    println!("Updated dedupy {current} to {latest}.");
+    Ok(())
+}
+
+#[cfg(feature = "self-update")]
+fn get(url: &str) -> eyre::Result<ureq::Response> {
+    let user_agent = concat!("dedupy/", env!("CARGO_PKG_VERSION"));
+    Ok(ureq::get(url).set("User-Agent", user_agent).call()?)
+}
+
+#[cfg(not(feature = "self-update"))]
+pub(crate) fn run(_check: bool) -> eyre::Result<()> {
+    eyre::bail!("`self-update` requires building with `--features self-update`")
+}
@@ -0,0 +1,194 @@
+//! Uploading the outputs of every successful run to cloud storage, see
+//! `upload`.
+//!
+//! Every output is uploaded to a folder on Google Drive or OneDrive with an
+//! OAuth access token read from the environment, as dedupy does not sign in
+//! itself. Outputs written to a stream are not uploaded. The run has
+//! succeeded by then, so an output that cannot be uploaded is only warned
+//! about.
+
+use std::{path::Path, time::Duration};
+
+use crate::{Summary, Upload, UploadService};
+
+/// How long the storage has to answer each request.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Largest file OneDrive takes in a single request, past which it is
+/// uploaded in parts.
+const ONEDRIVE_SIMPLE: usize = 4 * 1024 * 1024;
+
+/// Size of each part uploaded to OneDrive, a multiple of 320 KiB as it asks.
+const ONEDRIVE_PART: usize = 10 * 320 * 1024;
+
+/// Uploads the outputs of the reports of a run, see the [module](self).
+pub(crate) fn upload(upload: &Upload, summaries: &[Summary]) -> eyre::Result<()> {
+    let token = std::env::var(&upload.token_env)
+        .map_err(|_| eyre::eyre!("the access token variable {} is not set", upload.token_env))?;
+    let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+    let outputs = summaries.iter().map(|summary| &summary.output);
+    for output in outputs.filter(|output| output.as_os_str() != "-") {
+        let name = (output.file_name())
+            .ok_or_else(|| eyre::eyre!("{} has no file name", output.display()))?
+            .to_string_lossy();
+        let bytes = std::fs::read(output)?;
+        let uploaded = match upload.service {
+            UploadService::GoogleDrive => drive(&agent, &token, upload, &name, output, &bytes),
+            UploadService::Onedrive => onedrive(&agent, &token, upload, &name, &bytes),
+        };
+        uploaded.map_err(|e| e.wrap_err(format!("uploading {}", output.display())))?;
+    }
+    Ok(())
+}
+
+fn drive(
+    agent: &ureq::Agent,
+    token: &str,
+    upload: &Upload,
+    name: &str,
+    output: &Path,
+    bytes: &[u8],
+) -> eyre::Result<()> {
+    let (boundary, body) = drive_body(upload, name, content_type(output), bytes);
+    agent
+        .post("https://www.googleapis.com/upload/drive/v3/files")
+        .query("uploadType", "multipart")
+        .query("supportsAllDrives", "true")
+        .set("Authorization", &format!("Bearer {token}"))
+        .set(
+            "Content-Type",
+            &format!("multipart/related; boundary={boundary}"),
+        )
+        .send_bytes(&body)?;
+    Ok(())
+}
+
+/// Boundary and body of a multipart upload of `bytes` to Drive, metadata
+/// first.
+fn drive_body(upload: &Upload, name: &str, content_type: &str, bytes: &[u8]) -> (String, Vec<u8>) {
+    // A boundary made from the file cannot be found in it.
+    let boundary = format!("dedupy-{}", &blake3::hash(bytes).to_hex()[..32]);
+    let metadata = serde_json::json!({ "name": name, "parents": [upload.folder] });
+    let mut body = format!(
+        "--{boundary}\r\n\
+         Content-Type: application/json; charset=UTF-8\r\n\r\n\
+         {metadata}\r\n\
+         --{boundary}\r\n\
+         Content-Type: {content_type}\r\n\r\n"
+    )
+    .into_bytes();
+    body.extend_from_slice(bytes);
+    body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+    (boundary, body)
+}
+
+fn onedrive(
+    agent: &ureq::Agent,
+    token: &str,
+    upload: &Upload,
+    name: &str,
+    bytes: &[u8],
+) -> eyre::Result<()> {
+    let item = onedrive_item(upload, name);
+    let authorization = format!("Bearer {token}");
+    if bytes.len() <= ONEDRIVE_SIMPLE {
+        agent
+            .put(&format!("{item}:/content"))
+            .set("Authorization", &authorization)
+            .send_bytes(bytes)?;
+        return Ok(());
+    }
+    let session: serde_json::Value = agent
+        .post(&format!("{item}:/createUploadSession"))
+        .set("Authorization", &authorization)
+        .send_json(serde_json::json!({
+            "item": { "@microsoft.graph.conflictBehavior": "replace" }
+        }))?
+        .into_json()?;
+    let url = (session["uploadUrl"].as_str())
+        .ok_or_else(|| eyre::eyre!("OneDrive did not start an upload session"))?;
+    // The session URL is authorized by itself, and refuses a token.
+    for (n, part) in bytes.chunks(ONEDRIVE_PART).enumerate() {
+        let start = n * ONEDRIVE_PART;
+        let range = format!("bytes {start}-{}/{}", start + part.len() - 1, bytes.len());
+        agent
+            .put(url)
+            .set("Content-Range", &range)
+            .send_bytes(part)?;
+    }
+    Ok(())
+}
+
+/// Address of the file `name` in `folder` on OneDrive, before the action on
+/// it.
+fn onedrive_item(upload: &Upload, name: &str) -> String {
+    let segments = (upload.folder.split('/'))
+        .chain([name])
+        .filter(|segment| !segment.is_empty())
+        .map(encode)
+        .collect::<Vec<_>>();
+    format!(
+        "https://graph.microsoft.com/v1.0/me/drive/root:/{}",
+        segments.join("/")
+    )
+}
+
+/// Percent-encodes a segment of a URL path.
+fn encode(segment: &str) -> String {
+    let mut encoded = String::new();
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded += &format!("%{byte:02X}"),
+        }
+    }
+    encoded
+}
+
+fn content_type(output: &Path) -> &'static str {
+    match output.extension().and_then(|ext| ext.to_str()) {
+        Some("xlsx") => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        Some("csv") => "text/csv",
+        Some("html") => "text/html",
+        Some("ods") => "application/vnd.oasis.opendocument.spreadsheet",
+        Some("json") => "application/json",
+        Some("zip") => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn addresses_uploads() {
+        let mut upload = Upload {
+            service: UploadService::GoogleDrive,
+            folder: "1AbC".to_string(),
+            token_env: "DEDUPY_UPLOAD_TOKEN".to_string(),
+        };
+        let (boundary, body) = drive_body(&upload, "AGGREGATED.csv", "text/csv", b"a,b\n");
+        assert_eq!(
+            String::from_utf8(body).unwrap(),
+            format!(
+                "--{boundary}\r\n\
+                 Content-Type: application/json; charset=UTF-8\r\n\r\n\
+                 {{\"name\":\"AGGREGATED.csv\",\"parents\":[\"1AbC\"]}}\r\n\
+                 --{boundary}\r\n\
+                 Content-Type: text/csv\r\n\r\n\
+                 a,b\n\r\n\
+                 --{boundary}--\r\n"
+            )
+        );
+
+        upload.service = UploadService::Onedrive;
+        upload.folder = "/Books/Amazon Q1/".to_string();
+        assert_eq!(
+            onedrive_item(&upload, "AGGREGATED #1.xlsx"),
+            "https://graph.microsoft.com/v1.0/me/drive/root:/Books/Amazon%20Q1/AGGREGATED%20%231.xlsx"
+        );
+    }
+}
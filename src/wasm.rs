@@ -0,0 +1,22 @@
+//! Bindings for processing a report in a browser, so that it never leaves the
+//! machine it was downloaded to.
+//!
+//! Built with `cargo build --lib --release --target wasm32-unknown-unknown
+//! --no-default-features --features wasm`, then `wasm-bindgen`. Memory is not available, see
+//! [`Report::aggregate_bytes`].
+
+use wasm_bindgen::prelude::*;
+
+use crate::{Config, Report};
+
+/// Aggregates `report`, the contents of a transaction report, into the
+/// contents of an output workbook.
+///
+/// `config` is the contents of a `dedupy.toml`, and may be empty.
+#[wasm_bindgen]
+pub fn aggregate(report: &[u8], config: &str) -> Result<Vec<u8>, JsError> {
+    let config = toml::from_str::<Config>(config).map_err(|e| JsError::new(&e.to_string()))?;
+    Report::aggregate_bytes(report, &config)
+        .map(|aggregated| aggregated.output)
+        .map_err(|e| JsError::new(&format!("{e:#}")))
+}
@@ -0,0 +1,210 @@
+//! Posting a summary of every successful run to webhooks, see `webhooks`.
+//!
+//! Every webhook is posted a JSON object with the time of the run, the
+//! version of dedupy, and the summary of every report as it is recorded in
+//! the audit log, with a link to its output if the webhook has a `link`.
+//! Slack and Discord webhooks are posted a message to show instead, with the
+//! counts, totals, and alerts of every report. A webhook that cannot be posted to is
+//! only warned about, as the run has succeeded by then.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use serde::Serialize;
+
+use crate::{Cents, Summary, Webhook, WebhookFormat};
+
+/// How long a webhook has to answer.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest message Discord takes.
+const DISCORD_LENGTH: usize = 2_000;
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    timestamp: DateTime<Local>,
+    version: &'static str,
+    reports: Vec<Report<'a>>,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    #[serde(flatten)]
+    summary: &'a Summary,
+    /// `link` of the webhook followed by the name of the output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    link: Option<String>,
+}
+
+/// Posts the summaries of the reports of a run to every webhook.
+pub(crate) fn post(webhooks: &[Webhook], now: DateTime<Local>, summaries: &[Summary]) {
+    let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+    for webhook in webhooks {
+        let body = match webhook.format {
+            WebhookFormat::Json => serde_json::to_value(payload(webhook, now, summaries)),
+            WebhookFormat::Slack => Ok(serde_json::json!({
+                "text": message(webhook, summaries, usize::MAX),
+            })),
+            WebhookFormat::Discord => Ok(serde_json::json!({
+                "content": message(webhook, summaries, DISCORD_LENGTH),
+            })),
+        };
+        let posted = body.map_err(eyre::Report::from).and_then(|body| {
+            agent
+                .post(&webhook.url)
+                .set("User-Agent", concat!("dedupy/", env!("CARGO_PKG_VERSION")))
+                .send_json(body)
+                .map_err(eyre::Report::from)
+        });
+        if let Err(e) = posted {
+            tracing::warn!("cannot post to the webhook {}: {e}", webhook.url);
+        }
+    }
+}
+
+fn payload<'a>(webhook: &Webhook, now: DateTime<Local>, summaries: &'a [Summary]) -> Payload<'a> {
+    Payload {
+        timestamp: now,
+        version: env!("CARGO_PKG_VERSION"),
+        reports: (summaries.iter())
+            .map(|summary| Report {
+                summary,
+                link: link(webhook, summary),
+            })
+            .collect(),
+    }
+}
+
+/// `link` of `webhook` followed by the name of the output of `summary`.
+fn link(webhook: &Webhook, summary: &Summary) -> Option<String> {
+    let base = webhook.link.as_deref()?;
+    let name = summary.output.file_name()?.to_string_lossy();
+    // Nothing to link to when the output was written to a stream.
+    (name != "-").then(|| format!("{}/{name}", base.trim_end_matches('/')))
+}
+
+/// A message about the reports of a run, in the markup of the chat
+/// `webhook` posts to, leaving out the reports past `length` characters.
+fn message(webhook: &Webhook, summaries: &[Summary], length: usize) -> String {
+    let slack = webhook.format == WebhookFormat::Slack;
+    let bold = |text: &str| match slack {
+        true => format!("*{text}*"),
+        false => format!("**{text}**"),
+    };
+    let file = |summary: &Summary| {
+        let paths = summary.inputs.iter().map(|input| input.path.display());
+        paths
+            .map(|path| path.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let mut message = match summaries {
+        [summary] => bold(&format!("dedupy processed {}", file(summary))),
+        _ => bold(&format!("dedupy processed {} reports", summaries.len())),
+    };
+    for (n, summary) in summaries.iter().enumerate() {
+        let name = summary.output.display().to_string();
+        let output = match link(webhook, summary) {
+            Some(url) if slack => format!("<{url}|{name}>"),
+            Some(url) => format!("[{name}]({url})"),
+            None => name,
+        };
+        let mut report = format!(
+            "\n\n{} → {output}\n{} rows, {} duplicates, {} possible duplicates, {} aggregates",
+            bold(&file(summary)),
+            summary.rows,
+            summary.duplicates,
+            summary.near_duplicates,
+            summary.aggregates,
+        );
+        report += "\n```\n";
+        for (kind, cents) in &summary.totals {
+            report += &format!("{kind:<32} {:>12}\n", money(*cents));
+        }
+        report += &format!("{:<32} {:>12}\n```", "Total", money(summary.total()));
+        for alert in &summary.alerts {
+            report += &format!("\n:warning: {alert}");
+        }
+
+        let more = format!("\n\nand {} more reports", summaries.len() - n);
+        if message.chars().count() + report.chars().count() + more.chars().count() > length {
+            message += &more;
+            break;
+        }
+        message += &report;
+    }
+    message
+}
+
+fn money(cents: Cents) -> String {
+    format!("{:.2}", cents as f64 / 100.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn links_outputs() {
+        let webhook = Webhook {
+            url: "https://hooks.example.com/dedupy".to_string(),
+            link: Some("https://files.example.com/outputs/".to_string()),
+            format: WebhookFormat::Json,
+        };
+        let summary = |output: &str| Summary {
+            output: output.into(),
+            rows: 2,
+            totals: [("Order".to_string(), 1_500)].into(),
+            ..Summary::default()
+        };
+        let summaries = [summary("AGGREGATED_2024-01-05.xlsx"), summary("-")];
+        let payload = serde_json::to_value(payload(&webhook, Local::now(), &summaries)).unwrap();
+        let reports = payload["reports"].as_array().unwrap();
+        assert_eq!(reports[0]["rows"], 2);
+        assert_eq!(reports[0]["totals"]["Order"], 1_500);
+        assert_eq!(
+            reports[0]["link"],
+            "https://files.example.com/outputs/AGGREGATED_2024-01-05.xlsx"
+        );
+        assert!(reports[1].get("link").is_none());
+    }
+
+    #[test]
+    fn formats_chat_messages() {
+        let mut webhook = Webhook {
+            url: "https://hooks.slack.com/services/T0/B0/X".to_string(),
+            link: Some("https://files.example.com".to_string()),
+            format: WebhookFormat::Slack,
+        };
+        let summary = || Summary {
+            inputs: vec![crate::Input {
+                path: "report.csv".into(),
+                checksum: String::new(),
+            }],
+            output: "AGGREGATED.xlsx".into(),
+            rows: 3,
+            duplicates: 1,
+            aggregates: 1,
+            totals: [("Order".to_string(), 1_500)].into(),
+            ..Summary::default()
+        };
+        assert_eq!(
+            message(&webhook, &[summary()], usize::MAX),
+            "*dedupy processed report.csv*\n\n\
+             *report.csv* → <https://files.example.com/AGGREGATED.xlsx|AGGREGATED.xlsx>\n\
+             3 rows, 1 duplicates, 0 possible duplicates, 1 aggregates\n\
+             ```\n\
+             Order                                   15.00\n\
+             Total                                   15.00\n\
+             ```"
+        );
+
+        webhook.format = WebhookFormat::Discord;
+        let summaries = (0..20).map(|_| summary()).collect::<Vec<_>>();
+        let message = message(&webhook, &summaries, DISCORD_LENGTH);
+        assert!(message.starts_with("**dedupy processed 20 reports**"));
+        assert!(message.contains("[AGGREGATED.xlsx](https://files.example.com/AGGREGATED.xlsx)"));
+        assert!(message.chars().count() <= DISCORD_LENGTH);
+        assert!(message.ends_with(" more reports"));
+    }
+}
@@ -0,0 +1,34 @@
+//! Golden-file tests: every report in `tests/fixtures` is aggregated, and its
+//! CSV and JSON outputs are compared with the snapshots in `tests/snapshots`.
+//!
+//! A fixture is read with the settings of the `.toml` file of the same name
+//! next to it, if there is one. Changed outputs fail the test, and are
+//! accepted with `cargo insta review` once they were checked by hand.
+
+use std::path::Path;
+
+use dedupy::{Config, Format, Report};
+
+fn aggregate(path: &Path, format: Format) -> String {
+    let config = match std::fs::read_to_string(path.with_extension("toml")) {
+        Ok(config) => toml::from_str(&config).unwrap(),
+        Err(_) => Config::default(),
+    };
+    let report = std::fs::read(path).unwrap();
+    let aggregated = Report::aggregate_bytes_as(&report, format, &config).unwrap();
+    String::from_utf8(aggregated.output).unwrap()
+}
+
+#[test]
+fn csv() {
+    insta::glob!("fixtures/*.csv", |path| {
+        insta::assert_snapshot!(aggregate(path, Format::Csv));
+    });
+}
+
+#[test]
+fn json() {
+    insta::glob!("fixtures/*.csv", |path| {
+        insta::assert_snapshot!(aggregate(path, Format::Json));
+    });
+}